@@ -8,6 +8,9 @@ use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use std::fs::File;
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 use url::Url;
 
 // Low level functions to create unique file names
@@ -35,6 +38,18 @@ mod filename {
         format!("{}/{}", new('1', base), new('4', url))
     }
 
+    pub fn report(digest: &str) -> String {
+        format!("5{}", digest)
+    }
+
+    pub fn artifact(url: &Url) -> String {
+        new('6', url)
+    }
+
+    pub fn archive(digest: &str) -> String {
+        format!("7{}", digest)
+    }
+
     pub fn drop(path: Option<std::path::PathBuf>) -> Result<()> {
         path.map_or_else(
             || Ok(()),
@@ -43,19 +58,140 @@ mod filename {
     }
 }
 
+/// The validators of a cached artifact: the ETag and the Last-Modified headers.
+pub type Validators = (Option<String>, Option<String>);
+
 /// The Cache object to read and write cached content.
 pub struct Cache {
     xdg: xdg::BaseDirectories,
+    quota: Option<u64>,
+    /// The size of the cache directory, computed on the first quota check and updated by the
+    /// written entries.
+    usage: Arc<Mutex<Option<u64>>>,
 }
 
 impl Cache {
     /// Create the cache.
     pub fn new() -> Result<Cache> {
-        xdg::BaseDirectories::with_prefix("logreduce")
-            .map(|xdg| Cache { xdg })
+        Cache::with_prefix("logreduce")
+    }
+
+    /// Create the cache of a tenant, which is not visible from the other tenants.
+    pub fn with_namespace(namespace: &str) -> Result<Cache> {
+        if namespace.is_empty()
+            || !namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(anyhow::anyhow!("Invalid cache namespace: {}", namespace));
+        }
+        Cache::with_prefix(&format!("logreduce/tenants/{}", namespace))
+    }
+
+    /// Create the cache of the LOGREDUCE_TENANT namespace, limited to LOGREDUCE_TENANT_QUOTA bytes.
+    /// The old entries are evicted according to the GcPolicy::from_env, the eviction is best-effort
+    /// and its errors are only logged.
+    pub fn from_env() -> Result<Cache> {
+        let cache = Cache::tenant_from_env()?;
+        match GcPolicy::from_env() {
+            Ok(Some(policy)) => {
+                if let Err(e) = cache.gc(&policy, false) {
+                    tracing::warn!("Can't evict the cache entries: {:#}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!("Skipping the cache eviction: {:#}", e),
+        }
+        Ok(cache)
+    }
+
+    /// Create the cache of the LOGREDUCE_TENANT namespace, without the automatic eviction.
+    pub fn tenant_from_env() -> Result<Cache> {
+        let cache = match std::env::var("LOGREDUCE_TENANT") {
+            Ok(namespace) => Cache::with_namespace(&namespace)?,
+            Err(_) => Cache::new()?,
+        };
+        match std::env::var("LOGREDUCE_TENANT_QUOTA") {
+            Ok(quota) => Ok(cache.with_quota(
+                quota
+                    .parse()
+                    .with_context(|| format!("Invalid LOGREDUCE_TENANT_QUOTA: {}", quota))?,
+            )),
+            Err(_) => Ok(cache),
+        }
+    }
+
+    fn with_prefix(prefix: &str) -> Result<Cache> {
+        xdg::BaseDirectories::with_prefix(prefix)
+            .map(|xdg| Cache {
+                xdg,
+                quota: None,
+                usage: Arc::new(Mutex::new(None)),
+            })
             .context("Failed to get xdg cache directory")
     }
 
+    /// Limit the size of the cache, the new entries are refused once the quota is reached.
+    pub fn with_quota(mut self, bytes: u64) -> Cache {
+        self.quota = Some(bytes);
+        self
+    }
+
+    /// Evict the entries older than the max age, and then the oldest entries until the cache is smaller
+    /// than the max size. Returns the evicted entries, which are kept with dry_run.
+    pub fn gc(&self, policy: &GcPolicy, dry_run: bool) -> Result<Vec<Entry>> {
+        let root = self.xdg.get_cache_home();
+        let mut entries = Vec::new();
+        // The tenants namespaces are collected separately.
+        list_entries(&root, &root.join("tenants"), &mut entries);
+        entries.sort_by_key(|entry| entry.modified);
+        let now = SystemTime::now();
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut evicted = Vec::new();
+        for entry in entries {
+            let age = now.duration_since(entry.modified).unwrap_or_default();
+            let expired = matches!(policy.max_age, Some(max_age) if age > max_age);
+            let oversized = matches!(policy.max_size, Some(max_size) if total > max_size);
+            if !expired && !oversized {
+                continue;
+            }
+            if !dry_run {
+                match std::fs::remove_file(&entry.path) {
+                    Ok(()) => {}
+                    // The entry was already evicted by a concurrent collection.
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        total -= entry.size;
+                        continue;
+                    }
+                    Err(e) => {
+                        return Err(e).with_context(|| format!("Failed to delete {:?}", entry.path))
+                    }
+                }
+                // Remove the directory of the url entries when it is empty.
+                if let Some(parent) = entry.path.parent().filter(|parent| *parent != root) {
+                    std::fs::remove_dir(parent).ok();
+                }
+            }
+            total -= entry.size;
+            evicted.push(entry);
+        }
+        if !dry_run {
+            *self.usage.lock().unwrap() = None;
+        }
+        Ok(evicted)
+    }
+
+    /// Returns true when the cache is over its quota.
+    pub fn is_full(&self) -> bool {
+        match self.quota {
+            Some(quota) => {
+                let mut usage = self.usage.lock().unwrap();
+                *usage.get_or_insert_with(|| dir_size(&self.xdg.get_cache_home())) >= quota
+            }
+            None => false,
+        }
+    }
+
     pub fn head(&self, base: &Url, path: &Url) -> Option<bool> {
         match self.get(&filename::head_success(base, path)) {
             Some(_) => Some(true),
@@ -103,6 +239,64 @@ impl Cache {
         bincode::serialize_into(fp, paths).context("Failed to serialize httpdir save")
     }
 
+    /// Get a cached report path.
+    pub fn report_get(&self, digest: &str) -> Option<std::path::PathBuf> {
+        self.get(&filename::report(digest))
+    }
+
+    /// Create a new report entry in the cache.
+    pub fn report_add(&self, digest: &str) -> Result<CacheFile> {
+        self.create(&filename::report(digest))
+    }
+
+    /// Get a cached artifact, such as a model, with its validators.
+    pub fn artifact_get(&self, url: &Url) -> Option<Result<(Validators, Vec<u8>)>> {
+        self.get(&filename::artifact(url)).map(|buf| {
+            let fp = File::open(buf)?;
+            bincode::deserialize_from(std::io::BufReader::new(fp))
+                .context("Failed to decode cached artifact")
+        })
+    }
+
+    /// Add or replace an artifact in the cache.
+    pub fn artifact_add(&self, url: &Url, validators: &Validators, data: &[u8]) -> Result<()> {
+        self.artifact_drop(url)?;
+        let fp = self.create(&filename::artifact(url))?;
+        bincode::serialize_into(std::io::BufWriter::new(fp), &(validators, data))
+            .context("Failed to serialize artifact")
+    }
+
+    /// Get a spooled archive path.
+    pub fn archive_get(&self, digest: &str) -> Option<std::path::PathBuf> {
+        self.get(&filename::archive(digest))
+    }
+
+    /// Spool a decompressed archive in the cache, the entry only appears once it is complete.
+    pub fn archive_add<R: Read>(&self, digest: &str, mut obj: R) -> Result<std::path::PathBuf> {
+        let tmp = format!("{}.{}.tmp", filename::archive(digest), std::process::id());
+        let tmp_path = self.xdg.get_cache_file(&tmp);
+        // Remove the leftover of an interrupted spool.
+        let _ = std::fs::remove_file(&tmp_path);
+        let mut fp = self.create(&tmp)?;
+        if let Err(e) = std::io::copy(&mut obj, &mut fp) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e).context("Failed to spool archive");
+        }
+        let path = self.xdg.get_cache_file(filename::archive(digest));
+        std::fs::rename(&tmp_path, &path).context("Failed to save archive")?;
+        Ok(path)
+    }
+
+    /// Remove a spooled archive from the cache.
+    pub fn archive_drop(&self, digest: &str) -> Result<()> {
+        filename::drop(self.get(&filename::archive(digest)))
+    }
+
+    /// Remove an artifact from the cache.
+    pub fn artifact_drop(&self, url: &Url) -> Result<()> {
+        filename::drop(self.get(&filename::artifact(url)))
+    }
+
     /// Remove a remote file from the cache.
     pub fn remote_drop(&self, base: &Url, path: &Url) -> Result<()> {
         filename::drop(self.get(&filename::http(base, path)))
@@ -124,23 +318,118 @@ impl Cache {
     }
 
     // creates a new cache entry.
-    fn create(&self, path: &str) -> Result<File> {
+    fn create(&self, path: &str) -> Result<CacheFile> {
         let buf = self.xdg.get_cache_file(path);
         let path = buf.as_path();
-        if path.exists() {
+        if self.is_full() {
+            Err(anyhow::anyhow!("Cache quota exceeded: {:?}", buf))
+        } else if path.exists() {
             Err(anyhow::anyhow!("Cache file already exist: {:?}", buf))
         } else {
             let parent = path.parent().context("Failed to get cache parent")?;
             std::fs::create_dir_all(parent).context("Failed to create parent dir")?;
-            File::create(buf).context("Failed to create local cache file")
+            File::create(buf)
+                .map(|file| CacheFile {
+                    file,
+                    usage: self.usage.clone(),
+                })
+                .context("Failed to create local cache file")
         }
     }
 }
 
+/// The eviction policy of the cache entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcPolicy {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl GcPolicy {
+    /// The automatic eviction, set with the LOGREDUCE_CACHE_MAX_SIZE (bytes) and the LOGREDUCE_CACHE_MAX_AGE (days)
+    /// environment variables.
+    pub fn from_env() -> Result<Option<GcPolicy>> {
+        let max_size = match std::env::var("LOGREDUCE_CACHE_MAX_SIZE") {
+            Ok(size) => Some(
+                size.parse()
+                    .with_context(|| format!("Invalid LOGREDUCE_CACHE_MAX_SIZE: {}", size))?,
+            ),
+            Err(_) => None,
+        };
+        let max_age = match std::env::var("LOGREDUCE_CACHE_MAX_AGE") {
+            Ok(days) => {
+                Some(days_duration(days.parse().with_context(|| {
+                    format!("Invalid LOGREDUCE_CACHE_MAX_AGE: {}", days)
+                })?))
+            }
+            Err(_) => None,
+        };
+        Ok(match (max_size, max_age) {
+            (None, None) => None,
+            _ => Some(GcPolicy { max_size, max_age }),
+        })
+    }
+}
+
+/// The duration of a number of days.
+pub fn days_duration(days: u64) -> Duration {
+    Duration::from_secs(days * 24 * 3600)
+}
+
+/// A cache file.
+#[derive(Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+fn list_entries(path: &Path, skip: &Path, entries: &mut Vec<Entry>) {
+    for entry in std::fs::read_dir(path).into_iter().flatten().flatten() {
+        match entry.metadata() {
+            Ok(_) if entry.path() == skip => {}
+            Ok(meta) if meta.is_dir() => list_entries(&entry.path(), skip, entries),
+            Ok(meta) => entries.push(Entry {
+                path: entry.path(),
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            }),
+            Err(_) => {}
+        }
+    }
+}
+
+/// The total size of the cache files, without the tenants namespaces like the gc.
+fn dir_size(root: &Path) -> u64 {
+    let mut entries = Vec::new();
+    list_entries(root, &root.join("tenants"), &mut entries);
+    entries.iter().map(|entry| entry.size).sum()
+}
+
+/// A new cache file, its written bytes are added to the cache usage.
+pub struct CacheFile {
+    file: File,
+    usage: Arc<Mutex<Option<u64>>>,
+}
+
+impl Write for CacheFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = self.file.write(buf)?;
+        if let Some(usage) = self.usage.lock().unwrap().as_mut() {
+            *usage += size as u64;
+        }
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
 /// A Reader object that saves remote data to a local compressed file.
 pub struct CacheReader<R: Read> {
     remote: R,
-    local: GzEncoder<File>,
+    local: GzEncoder<CacheFile>,
 }
 
 impl<R: Read> Read for CacheReader<R> {
@@ -208,3 +497,128 @@ fn test_remote() {
     );
     assert_eq!(data, new_data);
 }
+
+#[test]
+fn test_artifact() {
+    let cache = Cache::new().unwrap();
+    let url = Url::parse("http://localhost/models/model.bin").unwrap();
+    cache.artifact_drop(&url).unwrap();
+    assert!(cache.artifact_get(&url).is_none());
+
+    let validators = (Some("\"v1\"".to_string()), None);
+    cache.artifact_add(&url, &validators, b"model").unwrap();
+    cache.artifact_add(&url, &validators, b"model-v1").unwrap();
+    let (cached_validators, data) = cache.artifact_get(&url).unwrap().unwrap();
+    assert_eq!(cached_validators, validators);
+    assert_eq!(data, b"model-v1");
+    cache.artifact_drop(&url).unwrap();
+}
+
+#[test]
+fn test_archive() {
+    let cache = Cache::new().unwrap();
+    cache.archive_drop("test").unwrap();
+    assert!(cache.archive_get("test").is_none());
+
+    let path = cache
+        .archive_add("test", std::io::Cursor::new("archive"))
+        .unwrap();
+    assert_eq!(cache.archive_get("test"), Some(path.clone()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "archive");
+    cache.archive_drop("test").unwrap();
+}
+
+#[test]
+fn test_namespace() {
+    assert!(Cache::with_namespace("../other").is_err());
+    let cache = Cache::with_namespace("test-quota").unwrap();
+    filename::drop(cache.report_get("quota")).unwrap();
+    let mut report = cache.report_add("quota").unwrap();
+    report.write_all(b"report").unwrap();
+    assert!(Cache::new().unwrap().report_get("quota").is_none());
+
+    let full = Cache::with_namespace("test-quota").unwrap().with_quota(1);
+    assert!(full.is_full());
+    assert!(full.report_add("other").is_err());
+    std::fs::remove_file(cache.report_get("quota").unwrap()).unwrap();
+}
+
+#[test]
+fn test_quota_usage() {
+    let cache = Cache::with_namespace("test-usage").unwrap();
+    let empty = GcPolicy {
+        max_size: Some(0),
+        max_age: None,
+    };
+    cache.gc(&empty, false).unwrap();
+    let cache = cache.with_quota(64);
+    assert!(!cache.is_full());
+
+    // The written entries are counted without scanning the directory again.
+    let base = Url::parse("http://localhost/builds").unwrap();
+    let path = base.join("job-output.txt").unwrap();
+    let data: Vec<u8> = (0..4096).map(|i| (i * 7 % 251) as u8).collect();
+    let mut reader = cache
+        .remote_add(&base, &path, std::io::Cursor::new(data))
+        .unwrap();
+    std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+    drop(reader);
+    assert!(cache.is_full());
+    assert!(cache.report_add("usage").is_err());
+    cache.gc(&empty, false).unwrap();
+}
+
+#[test]
+fn test_gc() {
+    let cache = Cache::with_namespace("test-gc").unwrap();
+    let empty = GcPolicy {
+        max_size: Some(0),
+        max_age: None,
+    };
+    cache.gc(&empty, false).unwrap();
+    let base = Url::parse("http://localhost/builds").unwrap();
+    for (name, age) in [("old.txt", 10), ("recent.txt", 1), ("new.txt", 0)] {
+        let path = base.join(name).unwrap();
+        let mut reader = cache
+            .remote_add(&base, &path, std::io::Cursor::new("data"))
+            .unwrap();
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        drop(reader);
+        let file = File::options()
+            .write(true)
+            .open(cache.get(&filename::http(&base, &path)).unwrap())
+            .unwrap();
+        file.set_modified(SystemTime::now() - days_duration(age))
+            .unwrap();
+    }
+
+    // The dry run keeps the entries.
+    let old = GcPolicy {
+        max_size: None,
+        max_age: Some(days_duration(5)),
+    };
+    assert_eq!(cache.gc(&old, true).unwrap().len(), 1);
+    assert_eq!(cache.gc(&old, false).unwrap().len(), 1);
+    assert_eq!(cache.gc(&old, false).unwrap().len(), 0);
+
+    // The oldest entries are evicted first.
+    let size = dir_size(&cache.xdg.get_cache_home());
+    let small = GcPolicy {
+        max_size: Some(size - 1),
+        max_age: None,
+    };
+    assert_eq!(cache.gc(&small, false).unwrap().len(), 1);
+    assert!(cache
+        .remote_get(&base, &base.join("new.txt").unwrap())
+        .is_some());
+    assert!(cache
+        .remote_get(&base, &base.join("recent.txt").unwrap())
+        .is_none());
+
+    // The entries deleted by a concurrent collection are skipped.
+    let mut entries = Vec::new();
+    let root = cache.xdg.get_cache_home();
+    list_entries(&root, &root.join("tenants"), &mut entries);
+    std::fs::remove_file(&entries[0].path).unwrap();
+    assert_eq!(cache.gc(&empty, false).unwrap().len(), entries.len() - 1);
+}
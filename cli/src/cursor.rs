@@ -0,0 +1,61 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the resume cursors of the `--stream jsonl` events. Each event has a
+//! `cursor`, and a consumer that disconnected restarts the analysis with `--resume-from` to only
+//! receive the following events: the sources before the cursor are skipped without being read.
+
+use anyhow::Result;
+
+/// The position of an anomaly event, ordered like the events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    /// The position of the source in the target.
+    pub source: usize,
+    /// The position of the anomaly line in the source.
+    pub pos: usize,
+}
+
+impl Cursor {
+    /// Returns true when the event at this position was sent before the resume cursor.
+    pub fn is_sent(&self, resume: Option<Cursor>) -> bool {
+        resume.is_some_and(|resume| *self <= resume)
+    }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.source, self.pos)
+    }
+}
+
+impl std::str::FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split_once(':')
+            .and_then(|(source, pos)| {
+                Some(Cursor {
+                    source: source.parse().ok()?,
+                    pos: pos.parse().ok()?,
+                })
+            })
+            .ok_or_else(|| anyhow::anyhow!("Invalid cursor {}, expected SOURCE:LINE", s))
+    }
+}
+
+#[test]
+fn test_cursor() {
+    let cursor: Cursor = "2:42".parse().unwrap();
+    assert_eq!(cursor, Cursor { source: 2, pos: 42 });
+    assert_eq!(cursor.to_string(), "2:42");
+    assert!("2".parse::<Cursor>().is_err());
+    assert!(Cursor {
+        source: 1,
+        pos: 100
+    }
+    .is_sent(Some(cursor)));
+    assert!(cursor.is_sent(Some(cursor)));
+    assert!(!Cursor { source: 2, pos: 43 }.is_sent(Some(cursor)));
+    assert!(!cursor.is_sent(None));
+}
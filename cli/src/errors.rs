@@ -0,0 +1,113 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module presents the errors: the known failures are shortened with a remediation hint,
+//! and the full chain is printed with `--verbose`.
+
+use logreduce_model::errors::{find_failure, Failure};
+
+/// The access token variables of the remote providers.
+const TOKENS: &[(&str, &str)] = &[
+    ("github.com", "GITHUB_TOKEN"),
+    ("gitlab", "GITLAB_TOKEN"),
+    ("circleci.com", "CIRCLECI_TOKEN"),
+    (
+        "amazonaws.com",
+        "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY",
+    ),
+    ("storage.googleapis.com", "GOOGLE_OAUTH_ACCESS_TOKEN"),
+    ("blob.core.windows.net", "AZURE_STORAGE_SAS_TOKEN"),
+];
+
+fn token_hint(host: &str) -> String {
+    match TOKENS.iter().find(|(domain, _)| host.contains(domain)) {
+        Some((_, token)) => format!("set {} to access {}", token, host),
+        None => format!(
+            "set the access token of the provider, one of: {}",
+            TOKENS
+                .iter()
+                .map(|(_, token)| *token)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Returns the short message and the hint of a known failure.
+fn describe(err: &anyhow::Error, failure: &Failure) -> (String, Option<String>) {
+    match failure {
+        Failure::MissingBaselines => (
+            format!("{}: {}", failure, err.root_cause()),
+            Some("provide the baselines with `logreduce-cli diff BASELINE... TARGET`, or a trained model with `--model FILE`".to_string()),
+        ),
+        Failure::HttpStatus(url, 401) | Failure::HttpStatus(url, 403) => (
+            format!("{} requires authentication", url),
+            Some(token_hint(url.host_str().unwrap_or(""))),
+        ),
+        Failure::HttpStatus(url, 404) | Failure::HttpStatus(url, 410) => (
+            format!("{} was not found", url),
+            Some("check the url, the build artifacts may have expired".to_string()),
+        ),
+        Failure::HttpStatus(_, _) => (format!("{:#}", err), None),
+        Failure::IncompatibleModel(path) => (
+            format!("{}: {}", failure, err.root_cause()),
+            Some(format!(
+                "the model was likely saved by another version, remove {:?} or use a new `--model` path to train it again",
+                path
+            )),
+        ),
+        Failure::UntrustedModel(path) => (
+            format!("{}: {}", failure, err.root_cause()),
+            Some(format!(
+                "download {:?} again from the model publisher, or train it again",
+                path
+            )),
+        ),
+    }
+}
+
+/// Render the error for the terminal.
+pub fn render(err: &anyhow::Error, verbose: bool) -> String {
+    let (message, hint) = match find_failure(err) {
+        Some(failure) => describe(err, failure),
+        None => (format!("{:#}", err), None),
+    };
+    let mut result = if verbose {
+        format!("Error: {:?}", err)
+    } else {
+        format!("Error: {}", message)
+    };
+    if let Some(hint) = hint {
+        result.push_str(&format!("\nHint: {}", hint));
+        if !verbose {
+            result.push_str("\nUse --verbose to show the full error chain");
+        }
+    }
+    result
+}
+
+#[test]
+fn test_render() {
+    use anyhow::Context;
+    let url = "https://api.github.com/repos/org/project/actions/runs/42";
+    let err = Err::<(), _>(anyhow::Error::from(Failure::HttpStatus(
+        url.parse().unwrap(),
+        401,
+    )))
+    .context("Can't get run")
+    .unwrap_err();
+    assert_eq!(
+        render(&err, false),
+        "Error: https://api.github.com/repos/org/project/actions/runs/42 requires authentication\n\
+         Hint: set GITHUB_TOKEN to access api.github.com\n\
+         Use --verbose to show the full error chain"
+    );
+    assert!(render(&err, true).starts_with("Error: Can't get run\n"));
+    assert_eq!(
+        render(
+            &anyhow::anyhow!("Empty sources").context("Bad target"),
+            false
+        ),
+        "Error: Bad target: Empty sources"
+    );
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module fits the printed anomaly lines to the width of the terminal.
+
+use clap::ArgEnum;
+
+/// The minimum width of the line content, below which the lines are printed in full.
+const MIN_WIDTH: usize = 20;
+
+/// How to print the lines wider than the terminal.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum LongLines {
+    /// Cut the line with an ellipsis.
+    Truncate,
+    /// Continue the line on the next rows, indented after the prefix.
+    Wrap,
+    /// Print the line as is.
+    Full,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LineFormat {
+    width: Option<usize>,
+    mode: LongLines,
+}
+
+impl LineFormat {
+    /// Use the width of the terminal, or the COLUMNS environment variable, when stdout is a terminal.
+    pub fn new(mode: LongLines) -> LineFormat {
+        let width = if atty::is(atty::Stream::Stdout) {
+            terminal_size::terminal_size()
+                .map(|(width, _)| width.0 as usize)
+                .or_else(|| {
+                    std::env::var("COLUMNS")
+                        .ok()
+                        .and_then(|columns| columns.parse().ok())
+                })
+        } else {
+            None
+        };
+        LineFormat { width, mode }
+    }
+
+    /// Format a line after its prefix, such as the `   42 | ` position.
+    pub fn format(&self, prefix: &str, line: &str) -> String {
+        let indent = prefix.chars().count();
+        let available = match self.width {
+            Some(width) if width >= indent + MIN_WIDTH => width - indent,
+            _ => return format!("{}{}", prefix, line),
+        };
+        if line.chars().count() <= available {
+            return format!("{}{}", prefix, line);
+        }
+        match self.mode {
+            LongLines::Full => format!("{}{}", prefix, line),
+            LongLines::Truncate => {
+                let line: String = line.chars().take(available - 1).collect();
+                format!("{}{}…", prefix, line)
+            }
+            LongLines::Wrap => {
+                let chars: Vec<char> = line.chars().collect();
+                let rows: Vec<String> = chars
+                    .chunks(available)
+                    .map(|row| row.iter().collect())
+                    .collect();
+                format!(
+                    "{}{}",
+                    prefix,
+                    rows.join(&format!("\n{}", " ".repeat(indent)))
+                )
+            }
+        }
+    }
+}
+
+#[test]
+fn test_line_format() {
+    let line = "ERROR: ".to_string() + &"x".repeat(30);
+    let format = |mode| LineFormat {
+        width: Some(30),
+        mode,
+    };
+    assert_eq!(
+        format(LongLines::Truncate).format("12 | ", &line),
+        format!("12 | ERROR: {}…", "x".repeat(17))
+    );
+    assert_eq!(
+        format(LongLines::Wrap).format("12 | ", &line),
+        format!("12 | ERROR: {}\n     {}", "x".repeat(18), "x".repeat(12))
+    );
+    assert_eq!(
+        format(LongLines::Full).format("12 | ", &line),
+        "12 | ".to_string() + &line
+    );
+    assert_eq!(
+        format(LongLines::Truncate).format("12 | ", "short"),
+        "12 | short"
+    );
+    let narrow = LineFormat {
+        width: Some(10),
+        mode: LongLines::Truncate,
+    };
+    assert_eq!(narrow.format("12 | ", &line), "12 | ".to_string() + &line);
+}
@@ -2,9 +2,35 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use logreduce_model::{Content, Input, Model, OutputMode};
-use std::path::PathBuf;
+use clap::{ArgEnum, Parser, Subcommand};
+use logreduce_model::accepted::AcceptedAnomalies;
+use logreduce_model::annotations::{Annotation, Annotations};
+use logreduce_model::changes::IndexChange;
+use logreduce_model::compression::{Codec, Compression};
+use logreduce_model::journal::Query;
+use logreduce_model::levels::Level;
+use logreduce_model::params::TrainParams;
+use logreduce_model::process::LearningWindow;
+use logreduce_model::ranges::Ranges;
+use logreduce_model::sampling::Sampling;
+use logreduce_model::severity::Keywords;
+use logreduce_model::signing::PublicKey;
+use logreduce_model::warmup::Warmup;
+use logreduce_model::{
+    ChunkIndex, Content, IndexName, Input, Model, OutputMode, Report, ReportOptions,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+mod cursor;
+mod errors;
+mod lines;
+mod perf;
+mod status;
+
+use cursor::Cursor;
+use lines::{LineFormat, LongLines};
 
 #[derive(Parser)]
 #[clap(version, about, long_about = None)]
@@ -13,18 +39,434 @@ struct Cli {
     #[clap(long, parse(from_os_str), help = "Create an html report")]
     report: Option<PathBuf>,
 
+    #[clap(
+        long,
+        arg_enum,
+        help = "The report format, written to the --report file or to stdout"
+    )]
+    format: Option<Format>,
+
+    #[clap(
+        long,
+        arg_enum,
+        conflicts_with_all = &["format", "report"],
+        help = "Write the anomalies to stdout as they are found"
+    )]
+    stream: Option<Stream>,
+
+    #[clap(
+        long,
+        requires = "stream",
+        help = "Only write the anomalies after the cursor of the last received event",
+        value_name = "CURSOR"
+    )]
+    resume_from: Option<Cursor>,
+
+    #[clap(
+        long,
+        conflicts_with_all = &["format", "report"],
+        help = "Learn the lines close to the baselines while printing the anomalies"
+    )]
+    learn: bool,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        requires = "learn",
+        help = "Save the model adapted with --learn on exit",
+        value_name = "FILE"
+    )]
+    save_learned: Option<PathBuf>,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "truncate",
+        help = "How to print the lines wider than the terminal"
+    )]
+    long_lines: LongLines,
+
     #[clap(
         long,
         parse(from_os_str),
-        help = "Load or save the model",
+        help = "Load or save the model, a local path or a s3:// or http url",
         value_name = "FILE"
     )]
     model: Option<PathBuf>,
 
+    #[clap(
+        long,
+        help = "Train the --model again when it is older than DAYS and the baselines can be discovered",
+        value_name = "DAYS"
+    )]
+    max_model_age: Option<u64>,
+
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "hashing",
+        help = "The index of the trained models, tfidf lowers the weight of the common tokens and lsh is faster with many baselines"
+    )]
+    index: IndexKind,
+
+    #[clap(
+        long,
+        help = "The number of nearest lines searched with the hnsw index, a larger value is slower and more accurate",
+        value_name = "N"
+    )]
+    hnsw_ef: Option<usize>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The directory of the embedding model, with the model.onnx and the tokenizer.json, for the embedding index",
+        value_name = "DIR"
+    )]
+    embedding_model: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The accepted anomalies, defaults to accepted-anomalies.yaml when present",
+        value_name = "FILE"
+    )]
+    accepted: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The owners and runbooks of the logs",
+        value_name = "FILE"
+    )]
+    annotations: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The keyword weights used to classify the anomalies",
+        value_name = "FILE"
+    )]
+    keywords: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The regular expressions per token added to the tokenizer masking patterns",
+        value_name = "FILE"
+    )]
+    patterns: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Hide the anomalies logged below the level, such as warning",
+        value_name = "LEVEL"
+    )]
+    min_level: Option<Level>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The line or time ranges to inspect per source path",
+        value_name = "FILE"
+    )]
+    ranges: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "The number of baseline lines under which the results are low-confidence [default: 100]",
+        value_name = "LINES"
+    )]
+    min_baseline_lines: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Print the performance counters of the processing phases on exit"
+    )]
+    debug_perf: bool,
+
+    #[clap(
+        long,
+        help = "Report the lines matched by each baseline, when the model is trained with many"
+    )]
+    attribute_baselines: bool,
+
+    #[clap(
+        long,
+        help = "Fold the indented and the stack trace lines into the previous line, to report a trace as one anomaly"
+    )]
+    multiline: bool,
+
+    #[clap(
+        long,
+        help = "Tokenize the lines that are JSON objects from their level, message and fields"
+    )]
+    json_lines: bool,
+
+    #[clap(
+        long,
+        help = "Keep the ANSI escape sequences of the lines, such as the colors, they are removed by default"
+    )]
+    keep_ansi: bool,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "The multiline, JSON lines and ANSI modes per filename pattern",
+        value_name = "FILE"
+    )]
+    profiles: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Remove the baselines that are dissimilar from the others before the training"
+    )]
+    auto_drop_bad_baselines: bool,
+
+    #[clap(
+        long,
+        help = "Count the runs in the --model file, to prune the indexes that no longer match a target"
+    )]
+    record_runs: bool,
+
+    #[clap(
+        long,
+        help = "Only load the models signed by the minisign public key, a file or its base64 line",
+        value_name = "KEY"
+    )]
+    model_pubkey: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "gzip",
+        help = "The compression of the saved models: gzip, zstd or none",
+        value_name = "CODEC"
+    )]
+    model_codec: Codec,
+
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        help = "The compression level of the saved models, 1 for gzip and 3 for zstd by default",
+        value_name = "N"
+    )]
+    model_level: Option<i32>,
+
+    #[clap(
+        long,
+        help = "Store the saved model matrices with N-bit values (2 to 8) instead of floats, a lower value gives a smaller and less accurate model",
+        value_name = "N"
+    )]
+    quantize_bits: Option<u8>,
+
+    #[clap(
+        long,
+        help = "The number of hashed features of the trained models, 260000 by default, a lower value uses less memory",
+        value_name = "N"
+    )]
+    hashing_dimension: Option<usize>,
+
+    #[clap(
+        long,
+        help = "The number of unique lines indexed and searched together, 512 by default",
+        value_name = "N"
+    )]
+    chunk_size: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Only read the last bytes of the remote files, using range requests",
+        value_name = "N"
+    )]
+    tail_bytes: Option<u64>,
+
+    #[clap(
+        long,
+        help = "Only analyze the head and tail of the larger sources",
+        value_name = "N"
+    )]
+    max_source_bytes: Option<u64>,
+
+    #[clap(
+        long,
+        requires = "max-source-bytes",
+        help = "The head bytes of the sampled sources, half of --max-source-bytes by default",
+        value_name = "N"
+    )]
+    sample_head_bytes: Option<u64>,
+
+    #[clap(
+        long,
+        requires = "max-source-bytes",
+        help = "The tail bytes of the sampled sources, half of --max-source-bytes by default",
+        value_name = "N"
+    )]
+    sample_tail_bytes: Option<u64>,
+
+    #[clap(long, help = "Print the full error chain on failure")]
+    verbose: bool,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        help = "Write a JSON summary of the run, also when it fails",
+        value_name = "FILE"
+    )]
+    status_file: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Select the target sources in a checklist before the analysis"
+    )]
+    interactive: bool,
+
     #[clap(subcommand)]
     command: Commands,
 }
 
+/// The accepted anomalies file, used when present in the current directory.
+const ACCEPTED_FILE: &str = "accepted-anomalies.yaml";
+
+/// The user configuration applied to the anomalies.
+#[derive(Debug)]
+struct Triage {
+    accepted: AcceptedAnomalies,
+    annotations: Annotations,
+    keywords: Keywords,
+    min_level: Option<Level>,
+}
+
+impl Triage {
+    fn apply(&self, report: &mut Report) {
+        if let Some(min_level) = self.min_level {
+            report.filter_level(min_level);
+        }
+        report.accept(&self.accepted);
+        report.annotate(&self.annotations);
+        report.classify(&self.keywords);
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Format {
+    Html,
+    StandaloneHtml,
+    Json,
+    Sarif,
+    Csv,
+    Markdown,
+    Pretty,
+}
+
+impl Format {
+    fn render(&self, report: &Report, writer: &mut dyn std::io::Write) -> Result<()> {
+        let rendered = match self {
+            Format::Html => logreduce_report::render(report),
+            Format::StandaloneHtml => logreduce_report::render_standalone(report),
+            Format::Csv => logreduce_report::render_csv(report),
+            Format::Markdown => logreduce_report::render_markdown(report),
+            Format::Pretty => logreduce_report::render_pretty(report),
+            Format::Sarif => Ok(format!("{:#}", report.to_sarif())),
+            Format::Json => return report.write_json(writer),
+        }
+        .context("Error rendering the report")?;
+        writer
+            .write_all(rendered.as_bytes())
+            .context("Failed to write the report")
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Stream {
+    Jsonl,
+}
+
+/// The ChunkIndex implementations.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+enum IndexKind {
+    Hashing,
+    Tfidf,
+    Lsh,
+    Hnsw,
+    Embedding,
+}
+
+impl IndexKind {
+    fn factory(self) -> fn() -> ChunkIndex {
+        match self {
+            IndexKind::Hashing => logreduce_model::hashing_index::new,
+            IndexKind::Tfidf => logreduce_model::tfidf_index::new,
+            IndexKind::Lsh => logreduce_model::lsh_index::new,
+            IndexKind::Hnsw => logreduce_model::hnsw_index::new,
+            #[cfg(feature = "embedding")]
+            IndexKind::Embedding => logreduce_model::embedding_index::new,
+            // Refused by Cli::run.
+            #[cfg(not(feature = "embedding"))]
+            IndexKind::Embedding => unreachable!(),
+        }
+    }
+}
+
+/// Learn the lines close to the baselines during a live session.
+#[derive(Debug)]
+struct Learn {
+    /// Where to save the adapted model.
+    save: Option<PathBuf>,
+}
+
+/// Where to write the anomalies.
+#[derive(Debug)]
+enum Output {
+    /// Print the anomalies as they are found.
+    Live {
+        stream: Option<Stream>,
+        /// Skip the events before the cursor, see cursor::Cursor.
+        resume: Option<Cursor>,
+        learn: Option<Learn>,
+        lines: LineFormat,
+    },
+    /// Write the report once all the anomalies are found.
+    Report {
+        format: Format,
+        file: Option<PathBuf>,
+    },
+}
+
+impl Output {
+    /// When no format is requested, the --report file is html, otherwise anomalies are printed live.
+    fn new(
+        format: Option<Format>,
+        file: Option<PathBuf>,
+        stream: Option<Stream>,
+        resume: Option<Cursor>,
+        learn: Option<Learn>,
+        lines: LineFormat,
+    ) -> Output {
+        match (format, file) {
+            (None, None) => Output::Live {
+                stream,
+                resume,
+                learn,
+                lines,
+            },
+            (format, file) => Output::Report {
+                format: format.unwrap_or(Format::Html),
+                file,
+            },
+        }
+    }
+
+    /// Returns true when the anomalies are written to stdout.
+    fn use_stdout(&self) -> bool {
+        matches!(
+            self,
+            Output::Live {
+                stream: Some(_),
+                ..
+            } | Output::Report { file: None, .. }
+        )
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     #[clap(about = "Compare targets", allow_missing_positional = true)]
@@ -34,23 +476,79 @@ enum Commands {
     Path { path: String },
 
     #[clap(about = "Analyze a url")]
-    Url { url: String },
+    Url {
+        url: String,
+
+        #[clap(
+            long,
+            help = "The baseline urls, instead of the discovered ones",
+            multiple_occurrences = true
+        )]
+        baseline: Vec<String>,
+    },
 
     #[clap(about = "Analyze systemd-journal", allow_missing_positional = true)]
     Journald {
+        #[clap(
+            help = "The start time, e.g. `2022-03-15 10:15:00` [default: the range before now]"
+        )]
         start: Option<String>,
+
+        #[clap(help = "The time span, e.g. `1h` or `30min`")]
         range: String,
     },
 
     #[clap(about = "When running in CI, analyze the current build")]
     CurrentBuild,
 
+    #[clap(about = "Analyze a log against its own beginning, when no baseline exists")]
+    SelfBaseline {
+        target: String,
+
+        #[clap(
+            long,
+            help = "The number of lines used as the baseline [default: 1000]"
+        )]
+        warmup_lines: Option<usize>,
+
+        #[clap(
+            long,
+            conflicts_with = "warmup-lines",
+            help = "The minutes of logs used as the baseline"
+        )]
+        warmup_minutes: Option<u64>,
+    },
+
     #[clap(about = "Train a model")]
     Train {
+        #[clap(
+            long,
+            help = "Only keep the lines and the tokens found in at least K baselines, to share the model"
+        )]
+        k_anonymity: Option<usize>,
+
         #[clap(required = true)]
         baselines: Vec<String>,
     },
 
+    #[clap(about = "Manage the trained models")]
+    Model {
+        #[clap(subcommand)]
+        command: ModelCommands,
+    },
+
+    #[clap(about = "Evict the old cache entries, such as the downloads and the reports")]
+    Gc {
+        #[clap(long, help = "The maximum size of the cache in bytes")]
+        max_size: Option<u64>,
+
+        #[clap(long, help = "The maximum age of the entries in days")]
+        max_age: Option<u64>,
+
+        #[clap(long, help = "List the entries without deleting them")]
+        dry_run: bool,
+    },
+
     // Secret options to debug specific part of the process
     #[clap(hide = true, about = "List source groups")]
     DebugGroups { target: String },
@@ -60,46 +558,313 @@ enum Commands {
     DebugTokenizer { line: String },
 }
 
+#[derive(Subcommand)]
+enum ModelCommands {
+    #[clap(about = "Combine the baselines and the indexes of several models")]
+    Merge {
+        #[clap(required = true)]
+        models: Vec<String>,
+
+        #[clap(short, long, help = "The merged model location", value_name = "FILE")]
+        output: String,
+    },
+
+    #[clap(about = "Show the indexes added, removed or grown between two models")]
+    Diff { old: String, new: String },
+
+    #[clap(about = "Write a model in the portable JSON format")]
+    Export {
+        model: String,
+
+        #[clap(short, long, parse(from_os_str), value_name = "FILE")]
+        output: PathBuf,
+    },
+
+    #[clap(about = "Read a model from the portable JSON format")]
+    Import {
+        #[clap(parse(from_os_str))]
+        json: PathBuf,
+
+        #[clap(short, long, help = "The model location", value_name = "FILE")]
+        output: String,
+    },
+
+    #[clap(about = "Remove the stale indexes of a model")]
+    Prune {
+        model: String,
+
+        #[clap(
+            long,
+            help = "Remove the indexes that didn't match a target in the last N recorded runs",
+            value_name = "N"
+        )]
+        idle_runs: Option<usize>,
+
+        #[clap(
+            long,
+            help = "Remove the indexes trained more than DAYS ago",
+            value_name = "DAYS"
+        )]
+        older_than: Option<u64>,
+
+        #[clap(
+            short,
+            long,
+            help = "The pruned model location, defaults to the model",
+            value_name = "FILE"
+        )]
+        output: Option<String>,
+    },
+}
+
 impl Cli {
     fn run(self, progress: OutputMode) -> Result<()> {
+        let learn = if self.learn {
+            Some(Learn {
+                save: self.save_learned,
+            })
+        } else {
+            None
+        };
+        let output = Output::new(
+            self.format,
+            self.report,
+            self.stream,
+            self.resume_from,
+            learn,
+            LineFormat::new(self.long_lines),
+        );
+        // Check the cache settings before the cache is used, it is created on the first read.
+        logreduce_cache::GcPolicy::from_env()?;
+        logreduce_cache::Cache::tenant_from_env()?;
+        logreduce_model::urls::set_tail_bytes(self.tail_bytes);
+        logreduce_model::process::set_attribution(self.attribute_baselines);
+        logreduce_model::multiline::set_multiline(self.multiline);
+        logreduce_model::jsonlines::set_json_lines(self.json_lines);
+        logreduce_model::ansi::set_strip_ansi(!self.keep_ansi);
+        if let Some(path) = &self.patterns {
+            logreduce_model::patterns::load_patterns(path)?;
+        }
+        if let Some(path) = &self.profiles {
+            logreduce_model::profiles::load_profiles(path)?;
+        }
+        logreduce_model::outliers::set_auto_drop(self.auto_drop_bad_baselines);
+        if let Some(ef) = self.hnsw_ef {
+            logreduce_model::hnsw_index::set_ef_search(ef);
+        }
+        #[cfg(feature = "embedding")]
+        if let Some(dir) = &self.embedding_model {
+            logreduce_model::embedding_index::set_encoder(dir)?;
+        }
+        if self.index == IndexKind::Embedding
+            && (!cfg!(feature = "embedding") || self.embedding_model.is_none())
+        {
+            return Err(anyhow::anyhow!(
+                "The embedding index needs --embedding-model, and logreduce built with the embedding feature"
+            ));
+        }
+        logreduce_model::compression::set_compression(Compression::new(
+            self.model_codec,
+            self.model_level,
+        )?);
+        logreduce_model::quantization::set_quantization(self.quantize_bits)?;
+        logreduce_model::params::set_train_params(TrainParams::new(
+            self.hashing_dimension,
+            self.chunk_size,
+        )?);
+        let (head_bytes, tail_bytes) = (self.sample_head_bytes, self.sample_tail_bytes);
+        logreduce_model::sampling::set_sampling(self.max_source_bytes.map(|max_bytes| {
+            let sampling = Sampling::new(max_bytes);
+            let head_bytes = head_bytes.unwrap_or(sampling.head_bytes);
+            let tail_bytes = tail_bytes.unwrap_or(sampling.tail_bytes);
+            sampling
+                .with_head_bytes(head_bytes)
+                .with_tail_bytes(tail_bytes)
+        }));
+        let options = ReportOptions {
+            ranges: match &self.ranges {
+                Some(path) => Ranges::load(path)?,
+                None => Ranges::default(),
+            },
+            min_baseline_lines: self
+                .min_baseline_lines
+                .unwrap_or(logreduce_model::DEFAULT_MIN_BASELINE_LINES),
+            ..ReportOptions::default()
+        };
+        if let Some(key) = &self.model_pubkey {
+            let content = match Path::new(key).exists() {
+                true => std::fs::read_to_string(key).context("Can't read the public key")?,
+                false => key.clone(),
+            };
+            logreduce_model::signing::set_trusted_key(Some(PublicKey::parse(&content)?));
+        }
+        let accepted = match self.accepted {
+            Some(path) => AcceptedAnomalies::load(&path)?,
+            None if Path::new(ACCEPTED_FILE).exists() => {
+                AcceptedAnomalies::load(Path::new(ACCEPTED_FILE))?
+            }
+            None => AcceptedAnomalies::default(),
+        };
+        let triage = Triage {
+            accepted,
+            annotations: match self.annotations {
+                Some(path) => Annotations::load(&path)?,
+                None => Annotations::default(),
+            },
+            keywords: match self.keywords {
+                Some(path) => Keywords::load(&path)?,
+                None => Keywords::default(),
+            },
+            min_level: self.min_level,
+        };
+        let progress = if output.use_stdout() && progress.inlined() {
+            // Keep stdout clean for the report
+            OutputMode::Quiet
+        } else {
+            progress
+        };
         match self.command {
             // Discovery commands
-            Commands::Path { path } => {
-                process(progress, self.report, self.model, None, Input::Path(path))
-            }
-            Commands::Url { url } => {
-                process(progress, self.report, self.model, None, Input::Url(url))
-            }
-            Commands::Journald { .. } => todo!(),
+            Commands::Path { path } => process(
+                progress,
+                output,
+                self.model,
+                self.max_model_age.map(logreduce_cache::days_duration),
+                self.index.factory(),
+                self.record_runs,
+                &triage,
+                self.interactive,
+                options,
+                None,
+                Input::Path(path),
+            ),
+            Commands::Url { url, baseline } => process(
+                progress,
+                output,
+                self.model,
+                self.max_model_age.map(logreduce_cache::days_duration),
+                self.index.factory(),
+                self.record_runs,
+                &triage,
+                self.interactive,
+                options,
+                match baseline.is_empty() {
+                    true => None,
+                    false => Some(baseline.into_iter().map(Input::from_string).collect()),
+                },
+                Input::Url(url),
+            ),
+            Commands::Journald { start, range } => process(
+                progress,
+                output,
+                self.model,
+                self.max_model_age.map(logreduce_cache::days_duration),
+                self.index.factory(),
+                self.record_runs,
+                &triage,
+                self.interactive,
+                options,
+                None,
+                Input::Url(Query::from_range(start.as_deref(), &range)?.to_string()),
+            ),
             Commands::CurrentBuild => todo!(),
 
             // Manual commands
             Commands::Diff { src, dst } => process(
                 progress,
-                self.report,
+                output,
                 self.model,
+                self.max_model_age.map(logreduce_cache::days_duration),
+                self.index.factory(),
+                self.record_runs,
+                &triage,
+                self.interactive,
+                options,
                 Some(src.into_iter().map(Input::from_string).collect()),
                 Input::from_string(dst),
             ),
-            Commands::Train { baselines } => {
+            Commands::SelfBaseline {
+                target,
+                warmup_lines,
+                warmup_minutes,
+            } => {
+                let warmup = match (warmup_lines, warmup_minutes) {
+                    (_, Some(minutes)) => Warmup::Duration(Duration::from_secs(minutes * 60)),
+                    (Some(lines), None) => Warmup::Lines(lines),
+                    (None, None) => Warmup::default(),
+                };
+                let mut report = Model::self_baseline(
+                    progress,
+                    Content::from_input(Input::from_string(target))?,
+                    warmup,
+                    self.index.factory(),
+                )?;
+                triage.apply(&mut report);
+                if progress.inlined() {
+                    println!();
+                }
+                match output {
+                    Output::Live {
+                        stream,
+                        resume,
+                        lines,
+                        ..
+                    } => print_report(&report, stream, resume, &lines),
+                    Output::Report { format, file } => write_report(format, file, &report),
+                }
+            }
+            Commands::Train {
+                k_anonymity,
+                baselines,
+            } => {
                 let model_path = self.model.ok_or_else(|| {
                     anyhow::anyhow!(
                         "A output file path is required, please add a `--model FILE` argument"
                     )
                 })?;
-                let model = Model::train(
-                    progress,
-                    baselines
-                        .into_iter()
-                        .map(Input::from_string)
-                        .map(Content::from_input)
-                        .collect::<Result<Vec<_>>>()?,
-                    logreduce_model::hashing_index::new,
-                )?;
-                model.save(&model_path)
+                let baselines = baselines
+                    .into_iter()
+                    .map(Input::from_string)
+                    .map(Content::from_input)
+                    .collect::<Result<Vec<_>>>()?;
+                let model = match k_anonymity {
+                    Some(k) => {
+                        Model::train_anonymous(progress, baselines, self.index.factory(), k)?
+                    }
+                    None => Model::train(progress, baselines, self.index.factory())?,
+                };
+                model.save_location(&model_path.to_string_lossy())
             }
 
+            Commands::Model { command } => match command {
+                ModelCommands::Merge { models, output } => merge_models(&models, &output),
+                ModelCommands::Diff { old, new } => diff_models(&old, &new),
+                ModelCommands::Export { model, output } => {
+                    load_model(&model)?.export_json(std::io::BufWriter::new(
+                        std::fs::File::create(output).context("Can't create export file")?,
+                    ))
+                }
+                ModelCommands::Import { json, output } => {
+                    Model::import_json(std::io::BufReader::new(
+                        std::fs::File::open(json).context("Can't open import file")?,
+                    ))?
+                    .save_location(&output)
+                }
+                ModelCommands::Prune {
+                    model,
+                    idle_runs,
+                    older_than,
+                    output,
+                } => prune_model(&model, idle_runs, older_than, output.as_deref()),
+            },
+
             // Debug handlers
+            Commands::Gc {
+                max_size,
+                max_age,
+                dry_run,
+            } => gc(max_size, max_age, dry_run),
+
             Commands::DebugGroups { target } => debug_groups(Input::from_string(target)),
             Commands::DebugTokenizer { line } => {
                 println!("{}\n", logreduce_tokenizer::process(&line));
@@ -109,11 +874,38 @@ impl Cli {
     }
 }
 
-fn main() -> Result<()> {
+fn main() {
+    let cli = Cli::parse();
+    let verbose = cli.verbose;
+    let status_file = cli.status_file.clone();
+    let result = run_cli(cli);
+    if let Some(path) = status_file {
+        if let Err(err) = status::write(&path, &result) {
+            eprintln!("{}", errors::render(&err, verbose));
+        }
+    }
+    if let Err(err) = result {
+        eprintln!("{}", errors::render(&err, verbose));
+        std::process::exit(1);
+    }
+}
+
+fn run_cli(cli: Cli) -> Result<()> {
     use std::str::FromStr;
     use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
-    let logger = tracing_subscriber::Registry::default();
+    let perf = if cli.debug_perf {
+        Some(perf::PerfLayer::default())
+    } else {
+        None
+    };
+
+    let logger = tracing_subscriber::Registry::default().with(perf.clone().map(|perf| {
+        perf.with_filter(tracing_subscriber::filter::Targets::new().with_target(
+            logreduce_model::perf::TARGET,
+            tracing_subscriber::filter::LevelFilter::DEBUG,
+        ))
+    }));
 
     let (_flush, debug) = match std::env::var("LOGREDUCE_LOG") {
         Err(_) => {
@@ -121,6 +913,7 @@ fn main() -> Result<()> {
             logger
                 .with(
                     tracing_subscriber::fmt::layer()
+                        .with_writer(std::io::stderr)
                         .with_target(false)
                         .compact()
                         .with_filter(tracing_subscriber::filter::LevelFilter::INFO),
@@ -158,118 +951,327 @@ fn main() -> Result<()> {
     } else {
         OutputMode::Quiet
     };
-    Cli::parse().run(output_mode).map_err(|e| {
+    logreduce_model::version::check_update();
+    let result = cli.run(output_mode).map_err(|e| {
         // Ensure the exception happens on a new line
         if output_mode.inlined() {
             println!();
         }
         e
-    })
+    });
+    if let Some(perf) = perf {
+        eprintln!("{}", perf.summary());
+    }
+    result
 }
 
+#[allow(clippy::too_many_arguments)]
 #[tracing::instrument(level = "debug", skip(output_mode))]
 fn process(
     output_mode: OutputMode,
-    report: Option<PathBuf>,
+    output: Output,
     model_path: Option<PathBuf>,
+    max_model_age: Option<Duration>,
+    mk_index: fn() -> ChunkIndex,
+    record_runs: bool,
+    triage: &Triage,
+    interactive: bool,
+    mut options: ReportOptions,
     baselines: Option<Vec<Input>>,
     input: Input,
 ) -> Result<()> {
     // Convert user Input to target Content.
     let content = Content::from_input(input)?;
+    if interactive {
+        options.skipped = select_sources(&content)?;
+    }
 
-    let model = match model_path {
-        Some(ref path) if path.exists() => match baselines {
-            None => Model::load(path),
+    let location = model_path.map(|path| path.to_string_lossy().into_owned());
+    // The model is only read when it isn't saved again, so that its indexes are loaded on demand.
+    let is_updated = record_runs
+        || matches!(
+            output,
+            Output::Live {
+                learn: Some(Learn { save: Some(_) }),
+                ..
+            }
+        );
+    let stored = match &location {
+        Some(location) if is_updated => Model::load_location(location)?,
+        Some(location) => Model::load_location_lazy(location)?,
+        None => None,
+    };
+    let (stored, discovered) = match stored {
+        Some(model)
+            if baselines.is_none() && max_model_age.is_some_and(|age| model.is_stale(age)) =>
+        {
+            match content.discover_baselines() {
+                Ok(discovered) => {
+                    tracing::warn!("The model is too old, training it again");
+                    (None, Some(discovered))
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "The model is too old, but the baselines can't be discovered: {:#}",
+                        err
+                    );
+                    (Some(model), None)
+                }
+            }
+        }
+        stored => (stored, None),
+    };
+    let is_stored = stored.is_some();
+    if let Some(model) = &stored {
+        // The learned lines are indexed with the parameters of the model.
+        logreduce_model::params::set_train_params(model.params());
+    }
+    let mut model = match stored {
+        Some(model) => match baselines {
+            None => Ok(model),
             Some(_) => Err(anyhow::anyhow!("Ambiguous baselines and model provided")),
         },
-        _ => {
+        None => {
             // Lookup baselines.
             tracing::debug!("Finding baselines");
-            let baselines = match baselines {
-                None => content.discover_baselines(),
-                Some(baselines) => baselines
+            let baselines = match (discovered, baselines) {
+                (Some(discovered), _) => Ok(discovered),
+                (None, None) => content.discover_baselines(),
+                (None, Some(baselines)) => baselines
                     .into_iter()
                     .map(Content::from_input)
                     .collect::<Result<Vec<_>>>(),
             }?;
 
-            // Create the model. TODO: enable custom index.
             tracing::debug!("Building model");
-            Model::train(output_mode, baselines, logreduce_model::hashing_index::new)
+            Model::train(output_mode, baselines, mk_index)
         }
     }?;
 
-    match model_path {
-        Some(ref path) if !path.exists() => model.save(path),
+    match location {
+        Some(ref location) if !is_stored => model.save_location(location),
         _ => Ok(()),
     }?;
 
     tracing::debug!("Inspecting");
-    match report {
-        None => process_live(output_mode, &content, &model),
-        Some(file) => {
-            let report = model.report(output_mode, content)?;
+    let inspected = match output {
+        Output::Live {
+            stream,
+            resume,
+            learn,
+            lines,
+        } => {
+            let (windows, inspected) = process_live(
+                output_mode,
+                &content,
+                &model,
+                &options,
+                stream,
+                resume,
+                learn.is_some(),
+                triage,
+                &lines,
+            )?;
+            if output_mode.inlined() {
+                // Keep the final progress message
+                println!();
+            }
+            if let Some(Learn { save: Some(path) }) = learn {
+                for (index_name, window) in windows {
+                    tracing::info!("{}: learned {} lines", index_name, window.line_count());
+                    model.learn(&index_name, window)?;
+                }
+                model.save_location(&path.to_string_lossy())?;
+            }
+            inspected
+        }
+        Output::Report { format, file } => {
+            let use_cache = std::env::var("LOGREDUCE_CACHE").is_ok();
+            let mut report = if use_cache {
+                model.report_cached_with(output_mode, content, &options)?
+            } else {
+                model.report_with(output_mode, content, &options)?
+            };
+            triage.apply(&mut report);
 
             // Save raw report for debug purpose
-            if std::env::var("LOGREDUCE_CACHE").is_ok() {
+            if let (true, Some(file)) = (use_cache, &file) {
                 let mut report_json = file.clone();
                 report_json.set_extension("json");
                 report.save(&report_json)?;
             }
+            write_report(format, file, &report)?;
+            report.inspected_indexes
+        }
+    };
+    if record_runs {
+        match &location {
+            Some(location) => {
+                model.record_run(&inspected);
+                model.save_location(location)?;
+            }
+            None => tracing::warn!("The runs are only recorded with a --model location"),
+        }
+    }
+    tracing::info!(
+        "Resource usage: {}",
+        logreduce_model::usage::ResourceUsage::collect()
+    );
+    Ok(())
+}
 
+fn write_report(format: Format, file: Option<PathBuf>, report: &Report) -> Result<()> {
+    status::record(report.total_line_count, report.total_anomaly_count);
+    match file {
+        Some(file) => {
             println!("{:?}: Writing report...", file);
-            std::fs::write(
-                file,
-                logreduce_report::render(&report).context("Error rendering the report")?,
-            )
-            .context("Failed to write the report")
+            let mut data = Vec::new();
+            format.render(report, &mut data)?;
+            logreduce_model::storage::put_location(&file.to_string_lossy(), data)
+                .context("Failed to write the report")
         }
+        None => format.render(report, &mut std::io::stdout().lock()),
     }
 }
 
-fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Result<()> {
-    let print_context = |pos: usize, xs: &[String]| {
-        xs.iter()
-            .enumerate()
-            .for_each(|(idx, line)| println!("   {} | {}", pos + idx, line))
+/// Print the anomalies of a report, like the live output.
+fn print_report(
+    report: &Report,
+    stream: Option<Stream>,
+    resume: Option<Cursor>,
+    lines: &LineFormat,
+) -> Result<()> {
+    status::record(report.total_line_count, report.total_anomaly_count);
+    if stream.is_none() {
+        println!("{}", logreduce_report::render_summary(report)?);
+    }
+    for (source, log_report) in report.log_reports.iter().enumerate() {
+        let mut last_pos = None;
+        if stream.is_none() {
+            println!("{}:", log_report.source);
+        }
+        for anomaly in &log_report.anomalies {
+            let cursor = Cursor {
+                source,
+                pos: anomaly.anomaly.pos,
+            };
+            match stream {
+                Some(Stream::Jsonl) if cursor.is_sent(resume) => {}
+                Some(Stream::Jsonl) => print_jsonl(
+                    &log_report.source,
+                    &log_report.index_name,
+                    log_report.annotation.as_ref(),
+                    anomaly,
+                    cursor,
+                )?,
+                None => print_text(lines, &mut last_pos, anomaly),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_live(
+    output_mode: OutputMode,
+    content: &Content,
+    model: &Model,
+    options: &ReportOptions,
+    stream: Option<Stream>,
+    resume: Option<Cursor>,
+    learn: bool,
+    triage: &Triage,
+    lines: &LineFormat,
+) -> Result<(HashMap<IndexName, LearningWindow>, Vec<IndexName>)> {
+    // When streaming, the messages are logged to keep stdout parsable.
+    let notice = |msg: String| match stream {
+        Some(_) => tracing::warn!("{}", msg),
+        None => println!("{}", msg),
     };
 
     let mut progress_sep_shown = false;
+    let mut warned = std::collections::HashSet::new();
+    // The lines learned per index, shared by the sources of the session.
+    let mut windows = HashMap::new();
+    // The target indexes found in the model, see Model::record_run.
+    let mut inspected = Vec::new();
+    let today = logreduce_model::accepted::today();
+    let mut accepted_count = 0;
+    for entry in triage.accepted.expired(today) {
+        notice(format!(
+            "Warning: accepted anomaly expired: {}",
+            entry.comment.as_deref().unwrap_or("no comment")
+        ));
+    }
     let mut total_line_count = 0;
     let mut total_anomaly_count = 0;
-    for source in content.get_sources()? {
+    for (source_pos, source) in content.get_sources()?.into_iter().enumerate() {
+        if options.is_skipped(&source) {
+            continue;
+        }
+        if resume.is_some_and(|resume| source_pos < resume.source) {
+            // The events of the source were already sent.
+            continue;
+        }
         let index_name = logreduce_model::IndexName::from_source(&source);
         match model.get_index(&index_name) {
             Some(index) => {
+                if !inspected.contains(&index_name) {
+                    inspected.push(index_name.clone());
+                }
+                if let Some(warning) =
+                    index.check_confidence(&index_name, options.min_baseline_lines)
+                {
+                    if warned.insert(index_name.clone()) {
+                        if output_mode.inlined() {
+                            println!();
+                        }
+                        notice(format!("Warning: {}", warning));
+                    }
+                }
                 let mut last_pos = None;
-                let mut print_anomaly = |anomaly: logreduce_model::AnomalyContext| {
+                let annotation = triage.annotations.find(&index_name, &source);
+                let mut print_anomaly = |mut anomaly: logreduce_model::AnomalyContext| {
+                    if matches!((anomaly.anomaly.level, triage.min_level), (Some(level), Some(min)) if level < min)
+                    {
+                        return Ok(());
+                    }
+                    if triage.accepted.find(&anomaly.anomaly, today).is_some() {
+                        accepted_count += 1;
+                        return Ok(());
+                    }
+                    anomaly.anomaly.severity = triage.keywords.severity(&anomaly.anomaly);
                     total_anomaly_count += 1;
-                    let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
-                    if let Some(last_pos) = last_pos {
-                        if last_pos != starting_pos {
-                            println!("--");
-                        }
+                    let cursor = Cursor {
+                        source: source_pos,
+                        pos: anomaly.anomaly.pos,
+                    };
+                    if cursor.is_sent(resume) {
+                        return Ok(());
                     }
-
-                    print_context(starting_pos, &anomaly.before);
-                    println!(
-                        "{:02.0} {} | {}",
-                        anomaly.anomaly.distance * 99.0,
-                        anomaly.anomaly.pos,
-                        anomaly.anomaly.line
-                    );
-                    print_context(anomaly.anomaly.pos, &anomaly.after);
-
-                    last_pos = Some(anomaly.anomaly.pos + anomaly.after.len());
+                    if let Some(Stream::Jsonl) = stream {
+                        return print_jsonl(&source, &index_name, annotation, &anomaly, cursor);
+                    }
+                    print_text(lines, &mut last_pos, &anomaly);
+                    Ok(())
                 };
                 progress_sep_shown = false;
                 match index.get_processor(
                     output_mode,
                     &source,
                     &mut std::collections::HashSet::new(),
+                    &options.ranges,
                 ) {
-                    Ok(mut processor) => {
+                    Ok(processor) => {
+                        let mut processor = if learn {
+                            processor.with_learning(
+                                windows
+                                    .entry(index_name.clone())
+                                    .or_insert_with(|| index.learning_window()),
+                            )
+                        } else {
+                            processor
+                        };
                         for anomaly in processor.by_ref() {
                             if output_mode.inlined() && !progress_sep_shown {
                                 // Show a progress separator for the first anomaly.
@@ -277,24 +1279,28 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
                                 progress_sep_shown = true;
                             }
                             match anomaly {
-                                Ok(anomaly) => print_anomaly(anomaly),
+                                Ok(anomaly) => print_anomaly(anomaly)?,
                                 Err(err) => {
-                                    println!("Could not read {}: {}", &source, err);
+                                    notice(format!("Could not read {}: {}", &source, err));
                                     break;
                                 }
                             }
                         }
                         total_line_count += processor.line_count;
+                        if processor.is_empty() {
+                            progress_sep_shown = true;
+                            notice(format!(" -> {}: skipped: empty", source))
+                        }
                     }
                     Err(err) => {
-                        println!("Could not read {}: {}", &source, err);
+                        notice(format!("Could not read {}: {}", &source, err));
                         break;
                     }
                 }
             }
             None => {
                 progress_sep_shown = true;
-                println!(" -> No baselines for {}", source)
+                notice(format!(" -> No baselines for {}", source))
             }
         }
     }
@@ -302,6 +1308,10 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
         // If the last source didn't had an anomaly, then erase the current progress
         print!("\r\x1b[K");
     }
+    if accepted_count > 0 {
+        notice(format!("{} accepted anomalies hidden", accepted_count));
+    }
+    status::record(total_line_count, total_anomaly_count);
     logreduce_model::debug_or_progress(
         output_mode,
         &format!(
@@ -309,9 +1319,185 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
             content, total_line_count, total_anomaly_count
         ),
     );
+    Ok((windows, inspected))
+}
+
+/// Print a single anomaly with its context, separating the non contiguous contexts.
+fn print_text(
+    lines: &LineFormat,
+    last_pos: &mut Option<usize>,
+    anomaly: &logreduce_model::AnomalyContext,
+) {
+    let print_context = |pos: usize, xs: &[String]| {
+        xs.iter().enumerate().for_each(|(idx, line)| {
+            println!("{}", lines.format(&format!("   {} | ", pos + idx), line))
+        })
+    };
+    let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
+    if let Some(last_pos) = last_pos {
+        if *last_pos != starting_pos {
+            println!("--");
+        }
+    }
+
+    print_context(starting_pos, &anomaly.before);
+    let prefix = format!(
+        "{:02.0} {} | ",
+        anomaly.anomaly.distance * 99.0,
+        anomaly.anomaly.pos
+    );
+    println!("{}", lines.format(&prefix, &anomaly.anomaly.line));
+    print_context(anomaly.anomaly.end_pos(), &anomaly.after);
+
+    *last_pos = Some(anomaly.anomaly.end_pos() + anomaly.after.len());
+}
+
+/// Write a single anomaly as a JSON line.
+fn print_jsonl(
+    source: &logreduce_model::Source,
+    index_name: &logreduce_model::IndexName,
+    annotation: Option<&Annotation>,
+    anomaly: &logreduce_model::AnomalyContext,
+    cursor: Cursor,
+) -> Result<()> {
+    let mut value = serde_json::to_value(anomaly)?;
+    value["cursor"] = cursor.to_string().into();
+    value["source"] = source.as_str().into();
+    value["index_name"] = index_name.as_str().into();
+    if let Some(annotation) = annotation {
+        value["annotation"] = serde_json::to_value(annotation)?;
+    }
+    println!("{}", value);
     Ok(())
 }
 
+fn load_model(location: &str) -> Result<Model> {
+    Model::load_location(location)?.ok_or_else(|| anyhow::anyhow!("Model not found: {}", location))
+}
+
+/// Combine the models into a single one, for example the models trained per job.
+fn merge_models(models: &[String], output: &str) -> Result<()> {
+    let mut merged: Option<Model> = None;
+    for location in models {
+        let model = load_model(location)?;
+        match &mut merged {
+            Some(merged) => merged
+                .merge(model)
+                .with_context(|| format!("{}: can't merge the model", location))?,
+            None => merged = Some(model),
+        }
+    }
+    match merged {
+        Some(model) => model.save_location(output),
+        None => Err(anyhow::anyhow!("No model to merge")),
+    }
+}
+
+/// Print the changes of the indexes between two models, for example two weekly retrains.
+fn diff_models(old: &str, new: &str) -> Result<()> {
+    let changes = load_model(old)?.diff(&load_model(new)?);
+    for (index_name, change) in &changes {
+        println!("{}: {}", index_name, change);
+    }
+    let count = |f: fn(&IndexChange) -> bool| changes.iter().filter(|(_, c)| f(c)).count();
+    println!(
+        "{} added, {} removed, {} changed indexes",
+        count(|c| matches!(c, IndexChange::Added(_))),
+        count(|c| matches!(c, IndexChange::Removed(_))),
+        count(|c| matches!(c, IndexChange::Changed(_, _)))
+    );
+    Ok(())
+}
+
+/// Remove the indexes that are idle for too many runs, or that are too old.
+fn prune_model(
+    location: &str,
+    idle_runs: Option<usize>,
+    older_than: Option<u64>,
+    output: Option<&str>,
+) -> Result<()> {
+    if idle_runs.is_none() && older_than.is_none() {
+        return Err(anyhow::anyhow!(
+            "A policy is required, please add a `--idle-runs` or `--older-than` argument"
+        ));
+    }
+    let cutoff = older_than
+        .and_then(|days| SystemTime::now().checked_sub(logreduce_cache::days_duration(days)))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    let mut model = load_model(location)?;
+    let removed = model.prune(|_, index| {
+        idle_runs.is_some_and(|max| index.idle_runs() >= max) || index.created_at() < cutoff
+    });
+    for index_name in &removed {
+        println!("{}: removed", index_name);
+    }
+    println!("{} indexes removed", removed.len());
+    model.save_location(output.unwrap_or(location))
+}
+
+/// Evict the cache entries with the given policy, or the LOGREDUCE_CACHE_MAX_SIZE and LOGREDUCE_CACHE_MAX_AGE one.
+fn gc(max_size: Option<u64>, max_age: Option<u64>, dry_run: bool) -> Result<()> {
+    use logreduce_cache::{days_duration, Cache, GcPolicy};
+    let policy = match (max_size, max_age) {
+        (None, None) => GcPolicy::from_env()?.ok_or_else(|| {
+            anyhow::anyhow!(
+                "A policy is required, please add a `--max-size` or `--max-age` argument"
+            )
+        })?,
+        _ => GcPolicy {
+            max_size,
+            max_age: max_age.map(days_duration),
+        },
+    };
+    let evicted = Cache::tenant_from_env()?.gc(&policy, dry_run)?;
+    for entry in &evicted {
+        println!("{} {}", entry.size, entry.path.display());
+    }
+    println!(
+        "{} {} entries, {} bytes",
+        if dry_run { "Would evict" } else { "Evicted" },
+        evicted.len(),
+        evicted.iter().map(|entry| entry.size).sum::<u64>()
+    );
+    Ok(())
+}
+
+/// Let the user deselect the irrelevant target sources, returns the names of the skipped sources.
+fn select_sources(content: &Content) -> Result<BTreeSet<String>> {
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stdout) {
+        return Err(anyhow::anyhow!(
+            "The --interactive option requires a terminal"
+        ));
+    }
+    let sources = content.get_sources()?;
+    let items = sources
+        .iter()
+        .map(|source| match source.size_hint() {
+            Some(size) => format!(
+                "{} ({})",
+                source.get_relative(),
+                logreduce_model::usage::human_bytes(size)
+            ),
+            None => source.get_relative().to_string(),
+        })
+        .collect::<Vec<_>>();
+    let selected = dialoguer::MultiSelect::new()
+        .with_prompt("Select the sources to analyze (space to toggle, enter to start)")
+        .items(&items)
+        .defaults(&vec![true; items.len()])
+        .interact()
+        .context("Can't read the selection")?;
+    if selected.is_empty() {
+        return Err(anyhow::anyhow!("No source selected"));
+    }
+    Ok(sources
+        .iter()
+        .enumerate()
+        .filter(|(idx, _)| !selected.contains(idx))
+        .map(|(_, source)| source.get_relative().to_string())
+        .collect())
+}
+
 fn debug_groups(input: Input) -> Result<()> {
     let content = Content::from_input(input)?;
     for (index_name, sources) in Content::group_sources(&[content])?.drain() {
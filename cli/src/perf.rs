@@ -0,0 +1,95 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module collects the performance events of the model to print a summary for the
+//! `--debug-perf` option.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::field::{Field, Visit};
+use tracing_subscriber::layer::{Context, Layer};
+
+use logreduce_model::usage::{human_bytes, ResourceUsage};
+
+#[derive(Debug, Default)]
+struct Counters {
+    lines: u64,
+    bytes: u64,
+    chunks: u64,
+    elapsed_us: u64,
+}
+
+#[derive(Default)]
+struct Event {
+    phase: String,
+    counters: Counters,
+}
+
+impl Visit for Event {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "phase" {
+            self.phase = value.to_string();
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "lines" => self.counters.lines = value,
+            "bytes" => self.counters.bytes = value,
+            "chunks" => self.counters.chunks = value,
+            "elapsed_us" => self.counters.elapsed_us = value,
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// A tracing layer that sums the counters per phase.
+#[derive(Clone, Debug, Default)]
+pub struct PerfLayer {
+    phases: Arc<Mutex<BTreeMap<String, Counters>>>,
+}
+
+impl<S: tracing::Subscriber> Layer<S> for PerfLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != logreduce_model::perf::TARGET {
+            return;
+        }
+        let mut perf = Event::default();
+        event.record(&mut perf);
+        if let Ok(mut phases) = self.phases.lock() {
+            let counters = phases.entry(perf.phase).or_default();
+            counters.lines += perf.counters.lines;
+            counters.bytes += perf.counters.bytes;
+            counters.chunks += perf.counters.chunks;
+            counters.elapsed_us += perf.counters.elapsed_us;
+        }
+    }
+}
+
+impl PerfLayer {
+    /// Render the counters of each phase, followed by the process resource usage.
+    pub fn summary(&self) -> String {
+        let mut out = String::from("Performance counters:\n");
+        if let Ok(phases) = self.phases.lock() {
+            for (phase, counters) in phases.iter() {
+                let elapsed = Duration::from_micros(counters.elapsed_us);
+                let _ = writeln!(
+                    out,
+                    "  {}: {} lines ({}) in {:.2} sec, {:.0} lines/sec, {} chunks",
+                    phase,
+                    counters.lines,
+                    human_bytes(counters.bytes),
+                    elapsed.as_secs_f32(),
+                    counters.lines as f32 / elapsed.as_secs_f32().max(1e-6),
+                    counters.chunks
+                );
+            }
+        }
+        let _ = write!(out, "  {}", ResourceUsage::collect());
+        out
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module writes the `--status-file`, a JSON summary of the run that is also written when
+//! logreduce fails, so that the CI wrappers can tell "no anomalies" from a crash.
+
+use anyhow::{Context, Result};
+use logreduce_model::errors::{find_failure, Failure};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static ANALYZED: AtomicBool = AtomicBool::new(false);
+static LINE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static ANOMALY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record the totals of the analysis.
+pub fn record(line_count: usize, anomaly_count: usize) {
+    LINE_COUNT.store(line_count, Ordering::Relaxed);
+    ANOMALY_COUNT.store(anomaly_count, Ordering::Relaxed);
+    ANALYZED.store(true, Ordering::Relaxed);
+}
+
+/// The stable name of the failure kind.
+fn category(err: &anyhow::Error) -> &'static str {
+    match find_failure(err) {
+        Some(Failure::MissingBaselines) => "missing-baselines",
+        Some(Failure::HttpStatus(_, _)) => "http-status",
+        Some(Failure::IncompatibleModel(_)) => "incompatible-model",
+        Some(Failure::UntrustedModel(_)) => "untrusted-model",
+        None => "error",
+    }
+}
+
+fn summary(result: &Result<()>) -> serde_json::Value {
+    let analyzed = ANALYZED.load(Ordering::Relaxed);
+    let count = |value: &AtomicUsize| match analyzed {
+        true => Some(value.load(Ordering::Relaxed)),
+        false => None,
+    };
+    serde_json::json!({
+        "success": result.is_ok(),
+        "line_count": count(&LINE_COUNT),
+        "anomaly_count": count(&ANOMALY_COUNT),
+        "error_category": result.as_ref().err().map(category),
+        "error": result.as_ref().err().map(|err| format!("{:#}", err)),
+    })
+}
+
+/// Write the summary of the run result.
+pub fn write(path: &Path, result: &Result<()>) -> Result<()> {
+    let file = std::fs::File::create(path).context("Can't create status file")?;
+    serde_json::to_writer_pretty(file, &summary(result)).context("Can't write status file")
+}
+
+#[test]
+fn test_summary() {
+    let err = Err(anyhow::Error::from(Failure::MissingBaselines).context("Bad target"));
+    assert_eq!(
+        summary(&err),
+        serde_json::json!({
+            "success": false,
+            "line_count": null,
+            "anomaly_count": null,
+            "error_category": "missing-baselines",
+            "error": "Bad target: Can't discover the baselines",
+        })
+    );
+    record(42, 2);
+    let status = summary(&Ok(()));
+    assert_eq!(status["success"], true);
+    assert_eq!(status["anomaly_count"], 2);
+    assert_eq!(status["error_category"], serde_json::Value::Null);
+}
@@ -24,13 +24,32 @@
 //! }
 //! # Ok(()) }
 //! ```
+//!
+//! Some servers require crawlers to identify themselves and to honor the robots.txt rules:
+//!
+//! ```no_run
+//! # fn main() -> std::io::Result<()> {
+//! # use url::Url;
+//! # let url = Url::parse("http://localhost/logs/").unwrap();
+//! let client = reqwest::blocking::Client::builder()
+//!     .user_agent("my-bot/1.0 (+https://example.com/contact)")
+//!     .build()
+//!     .unwrap();
+//! let files = httpdir::Crawler::new()
+//!     .with_client(client, "my-bot")
+//!     .with_robots()
+//!     .list(url)?;
+//! # Ok(()) }
+//! ```
 
 use lazy_static::lazy_static;
 use regex::Regex;
 use reqwest::blocking::Client;
+use std::collections::HashMap;
 use std::io::Result;
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::time::Duration;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use threadpool::ThreadPool;
 use url::Url;
 
@@ -61,6 +80,190 @@ impl Visitor {
     }
 }
 
+/// Per host request limits, to avoid overloading small log servers.
+pub struct HostLimiter {
+    // The minimum delay between two requests to the same host.
+    min_interval: Option<Duration>,
+    // The maximum number of concurrent requests to the same host.
+    max_connections: usize,
+    hosts: Mutex<HashMap<String, HostState>>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct HostState {
+    active: usize,
+    next_request: Option<Instant>,
+}
+
+/// A permit to perform a request, the host slot is released when it is dropped.
+pub struct Permit<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+}
+
+impl HostLimiter {
+    /// Create a limiter, use `None` and `usize::MAX` to disable the limits.
+    pub fn new(requests_per_second: Option<f32>, max_connections: usize) -> HostLimiter {
+        HostLimiter {
+            min_interval: requests_per_second
+                .filter(|rate| *rate > 0.0)
+                .map(|rate| Duration::from_secs_f32(1.0 / rate)),
+            max_connections: max_connections.max(1),
+            hosts: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until a request to the url's host is allowed.
+    pub fn acquire(&self, url: &Url) -> Permit<'_> {
+        let host = url.host_str().unwrap_or("").to_string();
+        let mut hosts = self.hosts.lock().unwrap();
+        loop {
+            let state = hosts.entry(host.clone()).or_default();
+            if state.active >= self.max_connections {
+                hosts = self.released.wait(hosts).unwrap();
+                continue;
+            }
+            let now = Instant::now();
+            match state.next_request {
+                Some(next_request) if next_request > now => {
+                    hosts = self
+                        .released
+                        .wait_timeout(hosts, next_request - now)
+                        .unwrap()
+                        .0;
+                }
+                _ => {
+                    state.active += 1;
+                    state.next_request = self.min_interval.map(|interval| now + interval);
+                    return Permit {
+                        limiter: self,
+                        host,
+                    };
+                }
+            }
+        }
+    }
+
+    /// The number of requests in progress to the url's host.
+    pub fn active(&self, url: &Url) -> usize {
+        let host = url.host_str().unwrap_or("");
+        self.hosts
+            .lock()
+            .unwrap()
+            .get(host)
+            .map_or(0, |state| state.active)
+    }
+}
+
+impl Default for HostLimiter {
+    fn default() -> Self {
+        Self::new(None, usize::MAX)
+    }
+}
+
+impl<'a> Drop for Permit<'a> {
+    fn drop(&mut self) {
+        if let Some(state) = self.limiter.hosts.lock().unwrap().get_mut(&self.host) {
+            state.active -= 1;
+        }
+        self.limiter.released.notify_all();
+    }
+}
+
+/// The robots.txt rules of a host, used by the crawler and to check the fetched urls.
+#[derive(Debug, Default)]
+pub struct Robots {
+    // The rules origin, other hosts are not restricted.
+    origin: Option<url::Origin>,
+    // The list of (allow, path prefix).
+    rules: Vec<(bool, String)>,
+}
+
+impl Robots {
+    /// Fetch the rules of the url's host, missing or unavailable robots.txt allows everything.
+    pub fn fetch(client: &Client, limiter: &HostLimiter, agent: &str, url: &Url) -> Robots {
+        let robots_url = match url.join("/robots.txt") {
+            Ok(robots_url) => robots_url,
+            Err(_) => return Robots::default(),
+        };
+        let _permit = limiter.acquire(&robots_url);
+        match client.get(robots_url).send() {
+            Ok(resp) if resp.status().is_success() => match resp.text() {
+                Ok(text) => Robots::parse(url.origin(), agent, &text),
+                Err(_) => Robots::default(),
+            },
+            _ => Robots::default(),
+        }
+    }
+
+    // Collect the rules of the groups matching the agent name, or of the '*' groups.
+    fn parse(origin: url::Origin, agent: &str, content: &str) -> Robots {
+        let agent = agent.to_lowercase();
+        let mut agent_rules = Vec::new();
+        let mut default_rules = Vec::new();
+        // The state of the current group: (is agent group, is default group, reading agents).
+        let mut group = (false, false, false);
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+            match key.as_str() {
+                "user-agent" => {
+                    if !group.2 {
+                        // A new group starts
+                        group = (false, false, true);
+                    }
+                    let name = value.to_lowercase();
+                    if name == "*" {
+                        group.1 = true;
+                    } else if !name.is_empty() && agent.contains(&name) {
+                        group.0 = true;
+                    }
+                }
+                "allow" | "disallow" => {
+                    group.2 = false;
+                    // An empty disallow value means everything is allowed.
+                    if !value.is_empty() {
+                        let rule = (key == "allow", value.to_string());
+                        if group.0 {
+                            agent_rules.push(rule);
+                        } else if group.1 {
+                            default_rules.push(rule);
+                        }
+                    }
+                }
+                _ => group.2 = false,
+            }
+        }
+        Robots {
+            origin: Some(origin),
+            rules: if agent_rules.is_empty() {
+                default_rules
+            } else {
+                agent_rules
+            },
+        }
+    }
+
+    /// Check if the url can be fetched: the longest matching prefix decides, allow wins on ties.
+    pub fn allowed(&self, url: &Url) -> bool {
+        if self.origin.as_ref() != Some(&url.origin()) {
+            return true;
+        }
+        let path = url.path();
+        self.rules
+            .iter()
+            .filter(|(_, prefix)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(allow, prefix)| (prefix.len(), *allow))
+            .map(|(allow, _)| *allow)
+            .unwrap_or(true)
+    }
+}
+
 // The message type produced by a worker:
 // - found a file url:  Some(url)
 // - got an error:      Some(error)
@@ -70,6 +273,12 @@ type Message = Option<Result<Url>>;
 /// The state of the crawler, created calling `Crawler::new()`.
 pub struct Crawler {
     client: Client,
+    // The name used to match the robots.txt rules.
+    agent: String,
+    // Honor the robots.txt rules.
+    robots: bool,
+    // The requests limits.
+    limiter: Arc<HostLimiter>,
     // A worker pool.
     workers: ThreadPool,
     // The mpsc channel.
@@ -91,11 +300,34 @@ impl Crawler {
         Crawler {
             workers,
             client,
+            agent: "logreduce".to_string(),
+            robots: false,
+            limiter: Arc::new(HostLimiter::default()),
             tx,
             rx,
         }
     }
 
+    /// Use a custom client, for example to set the User-Agent.
+    /// The agent name is used to match the robots.txt rules.
+    pub fn with_client(mut self, client: Client, agent: &str) -> Crawler {
+        self.client = client;
+        self.agent = agent.to_string();
+        self
+    }
+
+    /// Honor the robots.txt rules of the crawled host.
+    pub fn with_robots(mut self) -> Crawler {
+        self.robots = true;
+        self
+    }
+
+    /// Share a requests limiter with the crawler.
+    pub fn with_limiter(mut self, limiter: Arc<HostLimiter>) -> Crawler {
+        self.limiter = limiter;
+        self
+    }
+
     /// A simple implementation to list all the available files.
     pub fn list(&self, url: Url) -> Result<Vec<Url>> {
         // Submit the initial task.
@@ -123,14 +355,34 @@ impl Crawler {
     }
 
     fn start(&self, url: Url) {
+        let robots = Arc::new(if self.robots {
+            Robots::fetch(&self.client, &self.limiter, &self.agent, &url)
+        } else {
+            Robots::default()
+        });
+        if !robots.allowed(&url) {
+            let msg = format!("{} is disallowed by robots.txt", url);
+            self.tx.send(Some(Err(mk_error(&msg)))).unwrap();
+            return;
+        }
         // Here we pass all the requirements by reference to avoid lifetime issues.
-        Crawler::process(&Visitor::new(), &self.client, &self.workers, &self.tx, url);
+        Crawler::process(
+            &Visitor::new(),
+            &self.client,
+            &self.limiter,
+            &robots,
+            &self.workers,
+            &self.tx,
+            url,
+        );
     }
 
     // Helper function to handle a single url.
     fn process(
         visitor: &Visitor,
         client: &Client,
+        limiter: &Arc<HostLimiter>,
+        robots: &Arc<Robots>,
         pool: &ThreadPool,
         tx: &Sender<Message>,
         url: Url,
@@ -140,18 +392,25 @@ impl Crawler {
             let tx = tx.clone();
             let sub_pool = pool.clone();
             let client = client.clone();
+            let limiter = limiter.clone();
+            let robots = robots.clone();
 
             // Submit the work.
-            pool.execute(move || match http_list(&client, url) {
+            pool.execute(move || match limited_list(&client, &limiter, url) {
                 // We decoded some urls.
                 Ok(urls) => {
                     for url in urls {
-                        if url.path().ends_with("/etc/") {
+                        if !robots.allowed(&url) {
+                            // The url is excluded by the robots.txt rules.
+                            continue;
+                        } else if url.path().ends_with("/etc/") {
                             // Special case to avoid system config directory
                             continue;
                         } else if let Some(url) = path_dir(&url) {
                             // Recursively call the handler on sub directory.
-                            Crawler::process(&visitor, &client, &sub_pool, &tx, url)
+                            Crawler::process(
+                                &visitor, &client, &limiter, &robots, &sub_pool, &tx, url,
+                            )
                         } else {
                             // Send file location to the mpsc channel.
                             tx.send(Some(Ok(url))).unwrap()
@@ -236,6 +495,11 @@ fn path_dir(url: &Url) -> Option<Url> {
     }
 }
 
+fn limited_list(client: &Client, limiter: &HostLimiter, url: Url) -> Result<Vec<Url>> {
+    let _permit = limiter.acquire(&url);
+    http_list(client, url)
+}
+
 /// List the files and directories of a single url.
 pub fn http_list(client: &Client, url: Url) -> Result<Vec<Url>> {
     // dbg!(&url);
@@ -323,3 +587,67 @@ fn test_httpdir() {
     info_mock.assert();
     base_mock.assert();
 }
+
+#[test]
+fn test_host_limiter() {
+    let url = Url::parse("http://localhost/logs/").unwrap();
+    let limiter = HostLimiter::new(Some(20.0), 1);
+    let start = Instant::now();
+    for _ in 0..3 {
+        limiter.acquire(&url);
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    // Other hosts are not limited
+    let permit = limiter.acquire(&url);
+    let start = Instant::now();
+    limiter.acquire(&Url::parse("http://example.com/").unwrap());
+    assert!(start.elapsed() < Duration::from_millis(50));
+    drop(permit);
+
+    // The concurrency cap blocks until the permit is released
+    let limiter = Arc::new(HostLimiter::new(None, 1));
+    let permit = limiter.acquire(&url);
+    let thread_limiter = limiter.clone();
+    let thread_url = url.clone();
+    let waiter = std::thread::spawn(move || {
+        let start = Instant::now();
+        thread_limiter.acquire(&thread_url);
+        start.elapsed()
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    drop(permit);
+    assert!(waiter.join().unwrap() >= Duration::from_millis(90));
+}
+
+#[test]
+fn test_robots() {
+    let origin = Url::parse("http://localhost/").unwrap().origin();
+    let robots = Robots::parse(
+        origin.clone(),
+        "logreduce",
+        r#"
+# Comments are ignored
+User-agent: *
+Disallow: /
+
+User-agent: googlebot
+User-agent: logreduce
+Disallow: /logs/private/
+Allow: /logs/private/job-output.txt
+Disallow: /tmp # trailing comment
+"#,
+    );
+    let allowed =
+        |path: &str| robots.allowed(&Url::parse("http://localhost").unwrap().join(path).unwrap());
+    assert!(allowed("/logs/"));
+    assert!(!allowed("/logs/private/"));
+    assert!(allowed("/logs/private/job-output.txt"));
+    assert!(!allowed("/tmp/job-output.txt"));
+    assert!(robots.allowed(&Url::parse("http://example.com/tmp/").unwrap()));
+
+    // Other agents use the '*' group
+    let robots = Robots::parse(origin, "other-bot", "User-agent: *\nDisallow: /\n");
+    assert!(!robots.allowed(&Url::parse("http://localhost/logs/").unwrap()));
+    assert!(Robots::default().allowed(&Url::parse("http://localhost/logs/").unwrap()));
+}
@@ -9,6 +9,7 @@ use fxhash::hash32;
 // use fasthash::murmur3::hash32;
 use bincode::{deserialize, serialize};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use sprs::*;
 use std::collections::HashMap;
 
@@ -24,7 +25,7 @@ pub struct Features {
 }
 
 fn into_feature(line: &str) -> Features {
-    let vector = vectorize(line);
+    let vector = vectorize(line, DEFAULT_DIMENSION);
     Features {
         norm: vector.dot(&vector),
         vector,
@@ -55,31 +56,690 @@ pub fn load_mat(buf: &[u8]) -> FeaturesMatrix {
 
 /// Another implementation for index using a matrix storage
 pub fn index_mat(lines: &[String]) -> FeaturesMatrix {
-    create_mat(&lines.iter().map(|s| vectorize(s)).collect::<Vec<_>>())
+    index_mat_dim(lines, DEFAULT_DIMENSION)
+}
+
+/// Like index_mat, with dim hashed features. A smaller dimension uses less memory, but more tokens
+/// share the same feature.
+pub fn index_mat_dim(lines: &[String], dim: usize) -> FeaturesMatrix {
+    create_mat(&vectorize_lines(lines, dim), dim)
+}
+
+/// The dimension of the baselines, the lines are hashed with the same dimension.
+fn dim_of(baselines: &[FeaturesMatrix]) -> usize {
+    baselines
+        .first()
+        .map_or(DEFAULT_DIMENSION, |mat| mat.cols())
+}
+
+fn vectorize_lines(lines: &[String], dim: usize) -> Vec<SparseVec> {
+    lines.iter().map(|s| vectorize(s, dim)).collect()
 }
 
 /// Another implementation for search using a matrix product
 pub fn search_mat(baselines: &FeaturesMatrix, lines: &[String]) -> Vec<F> {
-    let target_vectors = lines.iter().map(|s| vectorize(s)).collect::<Vec<_>>();
-    let mut targets = create_mat(&target_vectors);
+    let mut targets = index_mat_dim(lines, baselines.cols());
     targets.transpose_mut();
     cosine_distance(baselines, &targets)
 }
 
 /// Another impementation using baselines chunk
 pub fn search_mat_chunk(baselines: &[FeaturesMatrix], lines: &[String]) -> Vec<F> {
-    let target_vectors = lines.iter().map(|s| vectorize(s)).collect::<Vec<_>>();
-    let mut targets = create_mat(&target_vectors);
+    let mut targets = index_mat_dim(lines, dim_of(baselines));
+    targets.transpose_mut();
+    cosine_distance_chunk(&baselines.iter().collect::<Vec<_>>(), &targets)
+}
+
+/// Like search_mat_chunk, but returns the distances of each line to each group of baseline rows.
+/// The `group_of` function returns the group of a row, counting the rows of all the baselines.
+pub fn search_mat_chunk_groups(
+    baselines: &[FeaturesMatrix],
+    group_of: impl Fn(usize) -> Option<usize>,
+    group_count: usize,
+    lines: &[String],
+) -> Vec<Vec<F>> {
+    let mut targets = index_mat_dim(lines, dim_of(baselines));
     targets.transpose_mut();
-    cosine_distance_chunk(baselines, &targets)
+    cosine_distance_groups(baselines, group_of, group_count, &targets)
 }
 
-fn cosine_distance_chunk(baselines: &[FeaturesMatrix], targets: &FeaturesMatrix) -> Vec<F> {
+/// Create a normalized matrix of dense vectors of dim values, such as the embeddings of the lines.
+/// The vectors of another size and the null vectors give empty rows, which are far from every line.
+pub fn vectors_mat(vectors: &[Vec<F>], dim: usize) -> FeaturesMatrix {
+    let mut mat = TriMat::new((vectors.len(), dim));
+    for (row, vector) in vectors.iter().enumerate() {
+        let l2_norm = vector.iter().map(|v| v * v).sum::<F>().sqrt();
+        if vector.len() == dim && l2_norm > 0.0 {
+            for (col, val) in vector.iter().enumerate() {
+                mat.add_triplet(row, col, *val / l2_norm);
+            }
+        }
+    }
+    mat.to_csr()
+}
+
+fn vectors_dim(baselines: &[FeaturesMatrix]) -> usize {
+    baselines.first().map_or(0, |mat| mat.cols())
+}
+
+/// Like search_mat_chunk, for the dense vectors of the lines.
+pub fn search_vectors_chunk(baselines: &[FeaturesMatrix], vectors: &[Vec<F>]) -> Vec<F> {
+    let mut targets = vectors_mat(vectors, vectors_dim(baselines));
+    targets.transpose_mut();
+    cosine_distance_chunk(&baselines.iter().collect::<Vec<_>>(), &targets)
+}
+
+/// Like search_mat_chunk_groups, for the dense vectors of the lines.
+pub fn search_vectors_chunk_groups(
+    baselines: &[FeaturesMatrix],
+    group_of: impl Fn(usize) -> Option<usize>,
+    group_count: usize,
+    vectors: &[Vec<F>],
+) -> Vec<Vec<F>> {
+    let mut targets = vectors_mat(vectors, vectors_dim(baselines));
+    targets.transpose_mut();
+    cosine_distance_groups(baselines, group_of, group_count, &targets)
+}
+
+fn cosine_distance_groups(
+    baselines: &[FeaturesMatrix],
+    group_of: impl Fn(usize) -> Option<usize>,
+    group_count: usize,
+    targets: &FeaturesMatrix,
+) -> Vec<Vec<F>> {
+    // The targets are transposed, the column is the log line number.
+    let mut result = vec![vec![1.0; group_count]; targets.cols()];
+    let mut offset = 0;
+    for baseline in baselines {
+        let distances_mat = baseline * targets;
+        for (v, (row, col)) in distances_mat.iter() {
+            if let Some(distance) =
+                group_of(offset + row).and_then(|group| result[col].get_mut(group))
+            {
+                *distance = (1.0 - v).min(*distance);
+            }
+        }
+        offset += baseline.rows();
+    }
+    result
+}
+
+/// The set of the features used by a matrix, one bit per column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureFilter(Vec<u64>);
+
+impl FeatureFilter {
+    pub fn new(mat: &FeaturesMatrix) -> FeatureFilter {
+        let mut filter = FeatureFilter::empty(mat.cols());
+        mat.indices().iter().for_each(|col| filter.insert(*col));
+        filter
+    }
+
+    fn empty(dim: usize) -> FeatureFilter {
+        FeatureFilter(vec![0; dim / 64 + 1])
+    }
+
+    fn from_vectors(vectors: &[SparseVec], dim: usize) -> FeatureFilter {
+        let mut filter = FeatureFilter::empty(dim);
+        vectors
+            .iter()
+            .for_each(|vector| vector.indices().iter().for_each(|col| filter.insert(*col)));
+        filter
+    }
+
+    fn insert(&mut self, col: usize) {
+        self.0[col / 64] |= 1 << (col % 64);
+    }
+
+    fn intersects(&self, other: &FeatureFilter) -> bool {
+        self.0.iter().zip(&other.0).any(|(a, b)| a & b != 0)
+    }
+}
+
+/// Like search_mat_chunk, but skip the baselines chunk without a feature in common with the lines.
+/// The filters are the pre-computed FeatureFilter of each baseline.
+pub fn search_mat_chunk_filtered(
+    baselines: &[FeaturesMatrix],
+    filters: &[FeatureFilter],
+    lines: &[String],
+) -> Vec<F> {
+    let dim = dim_of(baselines);
+    let target_vectors = vectorize_lines(lines, dim);
+    let target_filter = FeatureFilter::from_vectors(&target_vectors, dim);
+    let baselines = baselines
+        .iter()
+        .zip(filters)
+        .filter(|(_, filter)| filter.intersects(&target_filter))
+        .map(|(baseline, _)| baseline)
+        .collect::<Vec<_>>();
+    let mut targets = create_mat(&target_vectors, dim);
+    targets.transpose_mut();
+    cosine_distance_chunk(&baselines, &targets)
+}
+
+/// Merge the consecutive matrices into fewer matrices of at most max_rows rows.
+/// The rows are already normalized, thus the search distances are unchanged.
+pub fn compact_mats(mats: Vec<FeaturesMatrix>, max_rows: usize) -> Vec<FeaturesMatrix> {
+    fn flush(group: &mut Vec<FeaturesMatrix>, result: &mut Vec<FeaturesMatrix>) {
+        match group.len() {
+            0 => {}
+            1 => result.append(group),
+            _ => {
+                result.push(vstack(
+                    &group.iter().map(|mat| mat.view()).collect::<Vec<_>>(),
+                ));
+                group.clear();
+            }
+        }
+    }
+    let mut result = Vec::new();
+    let mut group = Vec::new();
+    let mut rows = 0;
+    for mat in mats {
+        if rows + mat.rows() > max_rows {
+            flush(&mut group, &mut result);
+            rows = 0;
+        }
+        rows += mat.rows();
+        group.push(mat);
+    }
+    flush(&mut group, &mut result);
+    result
+}
+
+/// A FeaturesMatrix whose values are stored as signed integers of a few bits, with a scale per row.
+/// The column indices are stored as u32, as the dimension of the matrices is much lower.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuantizedMatrix {
+    cols: usize,
+    indptr: Vec<u32>,
+    indices: Vec<u32>,
+    values: Vec<i8>,
+    scales: Vec<F>,
+}
+
+impl QuantizedMatrix {
+    /// Quantize the values of each row relative to the row maximum, using between 2 and 8 bits.
+    /// The hashing trick rows have the same absolute values, thus they are not altered.
+    pub fn new(mat: &FeaturesMatrix, bits: u8) -> QuantizedMatrix {
+        let levels = ((1 << (bits.clamp(2, 8) - 1)) - 1) as F;
+        let mut quantized = QuantizedMatrix {
+            cols: mat.cols(),
+            indptr: Vec::with_capacity(mat.rows() + 1),
+            indices: Vec::with_capacity(mat.nnz()),
+            values: Vec::with_capacity(mat.nnz()),
+            scales: Vec::with_capacity(mat.rows()),
+        };
+        quantized.indptr.push(0);
+        for row in mat.outer_iterator() {
+            let max = row.data().iter().fold(0.0 as F, |acc, v| acc.max(v.abs()));
+            let scale = if max > 0.0 { max / levels } else { 1.0 };
+            for (col, value) in row.iter() {
+                quantized.indices.push(col as u32);
+                quantized.values.push((value / scale).round() as i8);
+            }
+            quantized.scales.push(scale);
+            quantized.indptr.push(quantized.indices.len() as u32);
+        }
+        quantized
+    }
+
+    /// Restore the matrix. The values rounded to zero are dropped.
+    pub fn to_mat(&self) -> FeaturesMatrix {
+        let mut indptr = Vec::with_capacity(self.indptr.len());
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut data = Vec::with_capacity(self.values.len());
+        indptr.push(0);
+        for (row, scale) in self.scales.iter().enumerate() {
+            let range = self.indptr[row] as usize..self.indptr[row + 1] as usize;
+            for (col, value) in self.indices[range.clone()].iter().zip(&self.values[range]) {
+                if *value != 0 {
+                    indices.push(*col as usize);
+                    data.push(*value as F * scale);
+                }
+            }
+            indptr.push(indices.len());
+        }
+        CsMat::new((self.scales.len(), self.cols), indptr, indices, data)
+    }
+}
+
+/// The inverse document frequency of the features, each matrix row being a document.
+/// The features shared by most of the rows, such as the boilerplate tokens, get a lower weight.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Idf {
+    doc_count: usize,
+    doc_freqs: HashMap<usize, u32>,
+}
+
+impl Idf {
+    /// Count the features of the matrices rows.
+    pub fn new(mats: &[FeaturesMatrix]) -> Idf {
+        let mut idf = Idf::default();
+        for mat in mats {
+            idf.doc_count += mat.rows();
+            // The columns of a row are unique.
+            for col in mat.indices() {
+                *idf.doc_freqs.entry(*col).or_insert(0) += 1;
+            }
+        }
+        idf
+    }
+
+    /// The smoothed idf of a feature, the unknown features have the highest weight.
+    pub fn weight(&self, col: usize) -> F {
+        let doc_freq = self.doc_freqs.get(&col).copied().unwrap_or(0);
+        ((1 + self.doc_count) as F / (1 + doc_freq) as F).ln() + 1.0
+    }
+
+    /// Returns the matrix of the weighted features, with the rows normalized.
+    pub fn weigh_mat(&self, mat: &FeaturesMatrix) -> FeaturesMatrix {
+        let mut result = TriMat::new(mat.shape());
+        for (row, vector) in mat.outer_iterator().enumerate() {
+            let weighted = vector
+                .iter()
+                .map(|(col, val)| (col, val * self.weight(col)))
+                .collect::<Vec<_>>();
+            let l2_norm = weighted.iter().map(|(_, val)| val * val).sum::<F>().sqrt();
+            for (col, val) in weighted {
+                result.add_triplet(row, col, val / l2_norm);
+            }
+        }
+        result.to_csr()
+    }
+
+    fn targets_mat(&self, lines: &[String], dim: usize) -> FeaturesMatrix {
+        let mut targets = self.weigh_mat(&index_mat_dim(lines, dim));
+        targets.transpose_mut();
+        targets
+    }
+
+    /// Like search_mat_chunk, using the baselines weighted with weigh_mat.
+    pub fn search_mat_chunk(&self, baselines: &[FeaturesMatrix], lines: &[String]) -> Vec<F> {
+        cosine_distance_chunk(
+            &baselines.iter().collect::<Vec<_>>(),
+            &self.targets_mat(lines, dim_of(baselines)),
+        )
+    }
+
+    /// Like search_mat_chunk_groups, using the baselines weighted with weigh_mat.
+    pub fn search_mat_chunk_groups(
+        &self,
+        baselines: &[FeaturesMatrix],
+        group_of: impl Fn(usize) -> Option<usize>,
+        group_count: usize,
+        lines: &[String],
+    ) -> Vec<Vec<F>> {
+        cosine_distance_groups(
+            baselines,
+            group_of,
+            group_count,
+            &self.targets_mat(lines, dim_of(baselines)),
+        )
+    }
+}
+
+/// The number of bands of the MinHash signatures.
+const LSH_BANDS: usize = 20;
+
+/// The number of hashes per band: the rows sharing all the hashes of a band are candidates.
+const LSH_BAND_ROWS: usize = 3;
+
+/// A locality sensitive hashing table of the matrices rows, using the MinHash of their features,
+/// to only compare a line with the rows that likely share most of its features.
+/// The rows whose Jaccard similarity with the line is above 0.5 are found with a 93% probability.
+#[derive(Debug, Default)]
+pub struct Lsh {
+    /// The (matrix, row) of each band hash.
+    buckets: HashMap<u64, Vec<(u32, u32)>>,
+}
+
+fn minhash_bands(cols: &[usize]) -> impl Iterator<Item = u64> + '_ {
+    (0..LSH_BANDS).map(move |band| {
+        let signature = (0..LSH_BAND_ROWS)
+            .map(|row| {
+                let seed = band * LSH_BAND_ROWS + row;
+                cols.iter()
+                    .map(|col| hash32(&(seed, col)))
+                    .min()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        // The band position is part of the key, the bands are separate tables.
+        fxhash::hash64(&(band, signature))
+    })
+}
+
+impl Lsh {
+    pub fn new(mats: &[FeaturesMatrix]) -> Lsh {
+        let mut buckets: HashMap<u64, Vec<(u32, u32)>> = HashMap::new();
+        for (pos, mat) in mats.iter().enumerate() {
+            for (row, vector) in mat.outer_iterator().enumerate() {
+                for key in minhash_bands(vector.indices()) {
+                    buckets
+                        .entry(key)
+                        .or_default()
+                        .push((pos as u32, row as u32));
+                }
+            }
+        }
+        Lsh { buckets }
+    }
+
+    /// Call the function with the distance of each line to each candidate row, that is the matrix and the row position.
+    fn search_candidates(
+        &self,
+        mats: &[FeaturesMatrix],
+        lines: &[String],
+        mut f: impl FnMut(usize, (usize, usize), F),
+    ) {
+        let targets = index_mat_dim(lines, dim_of(mats));
+        for (line, target) in targets.outer_iterator().enumerate() {
+            let candidates = minhash_bands(target.indices())
+                .filter_map(|key| self.buckets.get(&key))
+                .flatten()
+                .unique();
+            for (pos, row) in candidates {
+                let (pos, row) = (*pos as usize, *row as usize);
+                if let Some(baseline) = mats.get(pos).and_then(|mat| mat.outer_view(row)) {
+                    f(line, (pos, row), 1.0 - baseline.dot(&target));
+                }
+            }
+        }
+    }
+
+    /// Like search_mat_chunk, but the lines are only compared with the candidate rows,
+    /// the lines without a candidate have the 1.0 distance.
+    pub fn search_mat_chunk(&self, baselines: &[FeaturesMatrix], lines: &[String]) -> Vec<F> {
+        let mut result = vec![1.0; lines.len()];
+        self.search_candidates(baselines, lines, |line, _, distance| {
+            result[line] = distance.min(result[line])
+        });
+        result
+    }
+
+    /// Like search_mat_chunk_groups, but the lines are only compared with the candidate rows.
+    pub fn search_mat_chunk_groups(
+        &self,
+        baselines: &[FeaturesMatrix],
+        group_of: impl Fn(usize) -> Option<usize>,
+        group_count: usize,
+        lines: &[String],
+    ) -> Vec<Vec<F>> {
+        let offsets = baselines
+            .iter()
+            .scan(0, |offset, mat| {
+                let start = *offset;
+                *offset += mat.rows();
+                Some(start)
+            })
+            .collect::<Vec<_>>();
+        let mut result = vec![vec![1.0; group_count]; lines.len()];
+        self.search_candidates(baselines, lines, |line, (pos, row), distance| {
+            if let Some(current) =
+                group_of(offsets[pos] + row).and_then(|group| result[line].get_mut(group))
+            {
+                *current = distance.min(*current);
+            }
+        });
+        result
+    }
+}
+
+/// The number of links per node of the HNSW upper layers.
+const HNSW_M: usize = 12;
+
+/// The number of links per node of the HNSW bottom layer.
+const HNSW_M0: usize = 2 * HNSW_M;
+
+/// The number of candidates searched when inserting a node.
+const HNSW_EF_CONSTRUCTION: usize = 64;
+
+const HNSW_MAX_LEVEL: usize = 16;
+
+/// The rows of the matrices, by their position in all the matrices.
+struct Rows<'a> {
+    mats: &'a [FeaturesMatrix],
+    offsets: Vec<usize>,
+    len: usize,
+}
+
+impl<'a> Rows<'a> {
+    fn new(mats: &'a [FeaturesMatrix]) -> Rows<'a> {
+        let mut offsets = Vec::with_capacity(mats.len());
+        let mut len = 0;
+        for mat in mats {
+            offsets.push(len);
+            len += mat.rows();
+        }
+        Rows { mats, offsets, len }
+    }
+
+    fn get(&self, id: usize) -> CsVecViewI<'a, F, usize> {
+        // The last matrix starting before the row, skipping the empty matrices.
+        let pos = self.offsets.partition_point(|offset| *offset <= id) - 1;
+        self.mats[pos].outer_view(id - self.offsets[pos]).unwrap()
+    }
+}
+
+/// A node of the graph with its distance to the searched vector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Near(F, u32);
+
+impl Eq for Near {}
+
+impl Ord for Near {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Near {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A Hierarchical Navigable Small World graph of the matrices rows, to find the nearest rows of a line
+/// by walking the graph instead of comparing the line with every row. The nodes are the positions of
+/// the rows in all the matrices, so the graph stays valid when the matrices are compacted.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Hnsw {
+    /// The neighbors of each node, per layer.
+    links: Vec<Vec<Vec<u32>>>,
+    entry: Option<u32>,
+    max_level: usize,
+}
+
+/// A pseudo random number from a seed, to build the same graph from the same rows.
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Hnsw {
+    /// The number of rows in the graph.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Insert the rows of the matrices that are not in the graph yet.
+    pub fn extend(&mut self, mats: &[FeaturesMatrix]) {
+        let rows = Rows::new(mats);
+        for id in self.links.len()..rows.len {
+            self.insert(&rows, id);
+        }
+    }
+
+    fn random_level(id: usize) -> usize {
+        let uniform = (splitmix64(id as u64) >> 11) as f64 / (1u64 << 53) as f64;
+        let level = -(uniform.max(f64::MIN_POSITIVE)).ln() / (HNSW_M as f64).ln();
+        (level as usize).min(HNSW_MAX_LEVEL)
+    }
+
+    /// Returns the ef nodes of the layer nearest to the query, starting from the entries, sorted by distance.
+    fn search_layer(
+        &self,
+        rows: &Rows,
+        query: &CsVecViewI<F, usize>,
+        entries: &[Near],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Near> {
+        use std::cmp::Reverse;
+        use std::collections::{BinaryHeap, HashSet};
+        let mut visited: HashSet<u32> = entries.iter().map(|near| near.1).collect();
+        let mut candidates: BinaryHeap<Reverse<Near>> =
+            entries.iter().copied().map(Reverse).collect();
+        let mut results: BinaryHeap<Near> = entries.iter().copied().collect();
+        while let Some(Reverse(candidate)) = candidates.pop() {
+            match results.peek() {
+                Some(furthest) if results.len() >= ef && candidate.0 > furthest.0 => break,
+                _ => {}
+            }
+            for node in &self.links[candidate.1 as usize][level] {
+                if !visited.insert(*node) {
+                    continue;
+                }
+                let near = Near(1.0 - rows.get(*node as usize).dot(query), *node);
+                if results.len() < ef || results.peek().is_some_and(|furthest| near.0 < furthest.0)
+                {
+                    candidates.push(Reverse(near));
+                    results.push(near);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    /// Returns the ef nodes nearest to the query.
+    fn search(&self, rows: &Rows, query: &CsVecViewI<F, usize>, ef: usize) -> Vec<Near> {
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        let mut nearest = vec![Near(1.0 - rows.get(entry as usize).dot(query), entry)];
+        for level in (1..=self.max_level).rev() {
+            nearest = self.search_layer(rows, query, &nearest, 1, level);
+        }
+        self.search_layer(rows, query, &nearest, ef.max(1), 0)
+    }
+
+    fn insert(&mut self, rows: &Rows, id: usize) {
+        let level = Hnsw::random_level(id);
+        self.links.push(vec![Vec::new(); level + 1]);
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => {
+                self.entry = Some(id as u32);
+                self.max_level = level;
+                return;
+            }
+        };
+        let query = rows.get(id);
+        let mut nearest = vec![Near(1.0 - rows.get(entry as usize).dot(&query), entry)];
+        for layer in (level + 1..=self.max_level).rev() {
+            nearest = self.search_layer(rows, &query, &nearest, 1, layer);
+        }
+        for layer in (0..=level.min(self.max_level)).rev() {
+            nearest = self.search_layer(rows, &query, &nearest, HNSW_EF_CONSTRUCTION, layer);
+            let max_links = if layer == 0 { HNSW_M0 } else { HNSW_M };
+            let neighbors = nearest
+                .iter()
+                .take(HNSW_M)
+                .map(|near| near.1)
+                .collect::<Vec<_>>();
+            for neighbor in &neighbors {
+                let links = &mut self.links[*neighbor as usize][layer];
+                links.push(id as u32);
+                if links.len() > max_links {
+                    // Keep the nearest links of the neighbor.
+                    let row = rows.get(*neighbor as usize);
+                    let mut nears = links
+                        .iter()
+                        .map(|node| Near(1.0 - rows.get(*node as usize).dot(&row), *node))
+                        .collect::<Vec<_>>();
+                    nears.sort();
+                    *links = nears.iter().take(max_links).map(|near| near.1).collect();
+                }
+            }
+            self.links[id][layer] = neighbors;
+        }
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(id as u32);
+        }
+    }
+
+    /// Like search_mat_chunk, using the ef nearest rows found in the graph.
+    /// A larger ef improves the recall, at the cost of a slower search.
+    pub fn search_mat_chunk(
+        &self,
+        baselines: &[FeaturesMatrix],
+        lines: &[String],
+        ef: usize,
+    ) -> Vec<F> {
+        let rows = Rows::new(baselines);
+        let targets = index_mat_dim(lines, dim_of(baselines));
+        targets
+            .outer_iterator()
+            .map(|target| {
+                self.search(&rows, &target, ef)
+                    .first()
+                    .map_or(1.0, |near| near.0.min(1.0))
+            })
+            .collect()
+    }
+
+    /// Like search_mat_chunk_groups, using the ef nearest rows found in the graph.
+    pub fn search_mat_chunk_groups(
+        &self,
+        baselines: &[FeaturesMatrix],
+        group_of: impl Fn(usize) -> Option<usize>,
+        group_count: usize,
+        lines: &[String],
+        ef: usize,
+    ) -> Vec<Vec<F>> {
+        let rows = Rows::new(baselines);
+        let targets = index_mat_dim(lines, dim_of(baselines));
+        targets
+            .outer_iterator()
+            .map(|target| {
+                let mut distances = vec![1.0; group_count];
+                for near in self.search(&rows, &target, ef) {
+                    if let Some(distance) =
+                        group_of(near.1 as usize).and_then(|group| distances.get_mut(group))
+                    {
+                        *distance = near.0.min(*distance);
+                    }
+                }
+                distances
+            })
+            .collect()
+    }
+}
+
+/// The number of baseline chunks from which the search is done in parallel.
+const PARALLEL_MIN_CHUNKS: usize = 4;
+
+fn cosine_distance_chunk(baselines: &[&FeaturesMatrix], targets: &FeaturesMatrix) -> Vec<F> {
+    if baselines.len() >= PARALLEL_MIN_CHUNKS {
+        return cosine_distance_chunk_par(baselines, targets);
+    }
     // The targets are transposed, the column is the log line number.
     let mut result = vec![1.0; targets.cols()];
 
     baselines.iter().for_each(|baseline| {
-        let distances_mat = baseline * targets;
+        let distances_mat = *baseline * targets;
 
         distances_mat
             .iter()
@@ -88,9 +748,27 @@ fn cosine_distance_chunk(baselines: &[FeaturesMatrix], targets: &FeaturesMatrix)
     result
 }
 
+/// Search each baseline chunk on the rayon thread pool, and keep the minimum distances.
+fn cosine_distance_chunk_par(baselines: &[&FeaturesMatrix], targets: &FeaturesMatrix) -> Vec<F> {
+    use rayon::prelude::*;
+    baselines
+        .par_iter()
+        .map(|baseline| cosine_distance(baseline, targets))
+        .reduce(
+            || vec![1.0; targets.cols()],
+            |mut result, distances| {
+                result
+                    .iter_mut()
+                    .zip(distances)
+                    .for_each(|(r, d)| *r = d.min(*r));
+                result
+            },
+        )
+}
+
 /// Create a normalized matrix
-fn create_mat(vectors: &[SparseVec]) -> FeaturesMatrix {
-    let mut mat = TriMat::new((vectors.len(), SIZE));
+fn create_mat(vectors: &[SparseVec], dim: usize) -> FeaturesMatrix {
+    let mut mat = TriMat::new((vectors.len(), dim));
     for (row, vector) in vectors.iter().enumerate() {
         let l2_norm = vector.l2_norm();
         for (col, val) in vector.iter() {
@@ -111,20 +789,21 @@ fn cosine_distance(baselines: &FeaturesMatrix, targets: &FeaturesMatrix) -> Vec<
     result
 }
 
-const SIZE: usize = 260000;
+/// The default number of hashed features.
+pub const DEFAULT_DIMENSION: usize = 260000;
 
 // result = vector()
 // for each word:
 //    result[hash(word)] = 1
 // TODO: vectorize directly into a FeaturesMatrix
-fn vectorize(line: &str) -> SparseVec {
+fn vectorize(line: &str, dim: usize) -> SparseVec {
     let (keys, values) = line
         .split(' ')
         .map(|word| {
             let hash = hash32(word);
             // alternate sign to improve inner product preservation in the hashed space
             let sign = if hash >= 2147483648 { 1.0 } else { -1.0 };
-            ((hash as usize) % SIZE, sign)
+            ((hash as usize) % dim, sign)
         })
         .sorted_by(|a, b| Ord::cmp(&a.0, &b.0))
         // Here we sum the duplicate, but turns out,
@@ -136,7 +815,7 @@ fn vectorize(line: &str) -> SparseVec {
         .map(|(value, (key, sign))| (key, sign * value as F))*/
         .dedup_by(|a, b| a.0 == b.0)
         .unzip();
-    CsVec::new(SIZE, keys, values)
+    CsVec::new(dim, keys, values)
 }
 
 /// Returns a number between 1.0 and 0.0, 0.0 being the closest value.
@@ -189,19 +868,246 @@ mod tests {
         assert_eq!(distances, expected);
     }
 
+    #[test]
+    fn test_index_mat_dim() {
+        let baselines = vec![index_mat_dim(
+            &["the first line".to_string(), "service started".to_string()],
+            1024,
+        )];
+        assert_eq!(baselines[0].cols(), 1024);
+        let targets = vec!["service started".to_string(), "a new error".to_string()];
+        // The targets are hashed with the dimension of the baselines.
+        let distances = search_mat_chunk(&baselines, &targets);
+        assert!(distances[0].abs() < 1e-6);
+        assert!(distances[1] > 0.5);
+        let filters = vec![FeatureFilter::new(&baselines[0])];
+        assert_eq!(
+            search_mat_chunk_filtered(&baselines, &filters, &targets),
+            distances
+        );
+    }
+
+    #[test]
+    fn test_compact_mats() {
+        let baselines = (0..10)
+            .map(|chunk| index_mat(&[format!("the line {}", chunk), format!("chunk {}", chunk)]))
+            .collect::<Vec<_>>();
+        let targets = vec!["the line 5".to_string(), "a new error".to_string()];
+        let distances = search_mat_chunk(&baselines, &targets);
+        let compacted = compact_mats(baselines, 6);
+        assert_eq!(
+            compacted.iter().map(|mat| mat.rows()).collect::<Vec<_>>(),
+            vec![6, 6, 6, 2]
+        );
+        assert_eq!(search_mat_chunk(&compacted, &targets), distances);
+    }
+
+    #[test]
+    fn test_quantized_mat() {
+        let baseline = index_mat(&[
+            "the first test is the 42".to_string(),
+            "another line".to_string(),
+        ]);
+        let targets = vec![
+            "the second test is the 42".to_string(),
+            "a new error".to_string(),
+        ];
+        let distances = search_mat(&baseline, &targets);
+        let quantized = QuantizedMatrix::new(&baseline, 8);
+        assert_eq!(quantized.values.len(), baseline.nnz());
+        let restored = quantized.to_mat();
+        assert_eq!(restored.shape(), baseline.shape());
+        for (distance, expected) in search_mat(&restored, &targets).iter().zip(&distances) {
+            assert!((distance - expected).abs() < 1e-3);
+        }
+
+        // The values are relative to the row maximum.
+        let mut mat = TriMat::new((1, 4));
+        mat.add_triplet(0, 0, 0.8);
+        mat.add_triplet(0, 1, -0.5);
+        mat.add_triplet(0, 3, 0.05);
+        let mat: FeaturesMatrix = mat.to_csr();
+        let restored = QuantizedMatrix::new(&mat, 3).to_mat();
+        assert_eq!(restored.indices(), &[0, 1]);
+        assert!((restored.data()[1] + 0.53).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_search_vectors() {
+        let baselines = vec![
+            vectors_mat(&[vec![1.0, 0.0, 0.0], vec![0.0, 2.0, 0.0]], 3),
+            vectors_mat(&[vec![0.0, 0.0, 3.0]], 3),
+        ];
+        let targets = vec![vec![0.0, 1.0, 0.0], vec![1.0, 0.0, 1.0], vec![]];
+        let distances = search_vectors_chunk(&baselines, &targets);
+        assert!(distances[0].abs() < 1e-6);
+        assert!((distances[1] - (1.0 - 0.5_f32.sqrt())).abs() < 1e-6);
+        assert_eq!(distances[2], 1.0);
+        let groups = search_vectors_chunk_groups(&baselines, |row| Some(row / 2), 2, &targets);
+        assert_eq!(groups[0][1], 1.0);
+        assert!((groups[1][1] - (1.0 - 0.5_f32.sqrt())).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_search_mat_chunk_par() {
+        let baselines = (0..PARALLEL_MIN_CHUNKS * 2)
+            .map(|chunk| index_mat(&[format!("the line {}", chunk), format!("chunk {}", chunk)]))
+            .collect::<Vec<_>>();
+        let targets = vec![
+            "the line 5".to_string(),
+            "chunk 3".to_string(),
+            "a new error".to_string(),
+        ];
+        let mut targets_mat = index_mat(&targets);
+        targets_mat.transpose_mut();
+        let sequential = baselines
+            .iter()
+            .map(|baseline| cosine_distance(baseline, &targets_mat))
+            .fold(vec![1.0; targets.len()], |acc, distances| {
+                acc.iter().zip(distances).map(|(a, d)| d.min(*a)).collect()
+            });
+        assert_eq!(search_mat_chunk(&baselines, &targets), sequential);
+        assert!(sequential[0] < 0.01 && sequential[1] < 0.01 && sequential[2] > 0.5);
+    }
+
+    #[test]
+    fn test_search_mat_chunk_filtered() {
+        let baselines = vec![
+            index_mat(&["the first line".to_string()]),
+            index_mat(&["service started".to_string()]),
+        ];
+        let filters = baselines.iter().map(FeatureFilter::new).collect::<Vec<_>>();
+        assert!(!filters[0].intersects(&filters[1]));
+        let targets = vec!["the second line".to_string(), "a new error".to_string()];
+        assert_eq!(
+            search_mat_chunk_filtered(&baselines, &filters, &targets),
+            search_mat_chunk(&baselines, &targets)
+        );
+    }
+
+    #[test]
+    fn test_search_mat_chunk_groups() {
+        let baselines = vec![
+            index_mat(&["the first line".to_string(), "service started".to_string()]),
+            index_mat(&["the first line".to_string()]),
+        ];
+        let targets = vec!["service started".to_string(), "the first line".to_string()];
+        let groups =
+            search_mat_chunk_groups(&baselines, |row| [0, 0, 1].get(row).copied(), 2, &targets);
+        assert!(groups[0][0] < 0.01 && groups[0][1] == 1.0);
+        assert!(groups[1][0] < 0.01 && groups[1][1] < 0.01);
+        let distances = search_mat_chunk(&baselines, &targets);
+        for (line, distance) in groups.iter().zip(distances) {
+            assert_eq!(line.iter().fold(1.0, |acc: F, d| acc.min(*d)), distance);
+        }
+    }
+
+    #[test]
+    fn test_idf() {
+        let baselines = vec![index_mat(&[
+            "INFO task started".to_string(),
+            "INFO task running".to_string(),
+            "INFO task done".to_string(),
+            "INFO disk usage".to_string(),
+        ])];
+        let idf = Idf::new(&baselines);
+        let col = |word: &str| vectorize(word, DEFAULT_DIMENSION).indices()[0];
+        assert!(idf.weight(col("INFO")) < idf.weight(col("task")));
+        assert!(idf.weight(col("task")) < idf.weight(col("disk")));
+        assert!(idf.weight(col("disk")) < idf.weight(col("unknown")));
+
+        let weighted = baselines
+            .iter()
+            .map(|mat| idf.weigh_mat(mat))
+            .collect::<Vec<_>>();
+        let targets = vec![
+            "INFO task done".to_string(),
+            "INFO error raised".to_string(),
+        ];
+        let distances = idf.search_mat_chunk(&weighted, &targets);
+        assert!(distances[0] < 0.01);
+        // The boilerplate INFO token matters less than with the plain hashing trick.
+        assert!(distances[1] > search_mat_chunk(&baselines, &targets)[1]);
+        let groups = idf.search_mat_chunk_groups(&weighted, |_| Some(0), 1, &targets);
+        assert_eq!(groups[0][0], distances[0]);
+    }
+
+    #[test]
+    fn test_lsh() {
+        let baselines = (0..10)
+            .map(|chunk| {
+                index_mat(&[
+                    format!("the service {} started on the node", chunk),
+                    format!("the worker {} is ready", chunk),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let lsh = Lsh::new(&baselines);
+        let targets = vec![
+            "the service 5 started on the node".to_string(),
+            "the worker 3 is ready".to_string(),
+            "a new error".to_string(),
+        ];
+        let exact = search_mat_chunk(&baselines, &targets);
+        let distances = lsh.search_mat_chunk(&baselines, &targets);
+        assert_eq!(distances[..2], exact[..2]);
+        assert_eq!(distances[2], 1.0);
+        // The service lines are the first group, and the worker lines the second one.
+        let groups = lsh.search_mat_chunk_groups(&baselines, |row| Some(row % 2), 2, &targets);
+        assert!(groups[0][0] < 0.01 && groups[1][1] < 0.01);
+        assert_eq!(groups[2], vec![1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_hnsw() {
+        let line = |idx: usize| {
+            format!(
+                "the service{} started on the node{} with the worker{}",
+                idx % 7,
+                idx % 11,
+                idx
+            )
+        };
+        let mut baselines = (0..10)
+            .map(|chunk| {
+                index_mat(
+                    &(0..30)
+                        .map(|idx| line(chunk * 30 + idx))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut hnsw = Hnsw::default();
+        hnsw.extend(&baselines);
+        assert_eq!(hnsw.len(), 300);
+        let targets = vec![line(42), line(299), "a new error".to_string()];
+        let exact = search_mat_chunk(&baselines, &targets);
+        let distances = hnsw.search_mat_chunk(&baselines, &targets, 32);
+        assert!(distances[0] < 0.01 && distances[1] < 0.01);
+        assert_eq!(distances[2], exact[2]);
+
+        // The graph nodes are the rows positions, which are kept by the compaction.
+        baselines = compact_mats(baselines, 100);
+        assert_eq!(hnsw.search_mat_chunk(&baselines, &targets, 32), distances);
+
+        let groups =
+            hnsw.search_mat_chunk_groups(&baselines, |row| Some(row / 150), 2, &targets, 32);
+        assert!(groups[0][0] < 0.01 && groups[1][1] < 0.01);
+    }
+
     // A test playground that was used for the search_mat implementation
     #[test]
     fn test_matrix() {
         let baselines =
             IntoIterator::into_iter(["the", "the second line", "the third line is a warning"])
-                .map(|s| vectorize(s))
+                .map(|s| vectorize(s, DEFAULT_DIMENSION))
                 .collect::<Vec<SparseVec>>();
-        let baselines_mat = dbg!(create_mat(&baselines));
+        let baselines_mat = dbg!(create_mat(&baselines, DEFAULT_DIMENSION));
 
         let targets = IntoIterator::into_iter(["the second line", "a error"])
-            .map(|s| vectorize(s))
+            .map(|s| vectorize(s, DEFAULT_DIMENSION))
             .collect::<Vec<SparseVec>>();
-        let mut targets_mat = dbg!(create_mat(&targets));
+        let mut targets_mat = dbg!(create_mat(&targets, DEFAULT_DIMENSION));
         targets_mat.transpose_mut();
         dbg!(&targets_mat);
 
@@ -0,0 +1,219 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the accepted anomalies, a reviewable list of known issues that are
+//! demoted to the accepted section of the report. The list is a yaml file such as:
+//!
+//! ```yaml
+//! - pattern: "Connection reset by peer"
+//!   comment: Known network flakiness
+//!   expires: 2022-12-31
+//! - fingerprint: 3f2a9c0e1b7d4a56
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::{Anomaly, AnomalyContext, Report, Source};
+
+/// A known issue.
+#[derive(Debug, Deserialize)]
+pub struct Acceptance {
+    /// The anomaly fingerprint, see Anomaly::fingerprint.
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// A regex matching the anomaly line.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// The date after which the anomaly is no longer accepted.
+    #[serde(default)]
+    pub expires: Option<chrono::NaiveDate>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// The list of accepted anomalies, loaded from an accepted-anomalies.yaml file.
+#[derive(Debug, Default)]
+pub struct AcceptedAnomalies {
+    entries: Vec<(Acceptance, Option<regex::Regex>)>,
+}
+
+/// An anomaly that matched an accepted entry.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AcceptedAnomaly {
+    pub source: Source,
+    pub anomaly: AnomalyContext,
+    pub comment: Option<String>,
+}
+
+impl Anomaly {
+    /// A stable identifier of the anomaly, computed from the tokenized line so that it does not
+    /// change with the dates, numbers or ids of the line.
+    pub fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(logreduce_tokenizer::process(&self.line).as_bytes());
+        digest[..8].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+impl AcceptedAnomalies {
+    pub fn load(path: &Path) -> Result<AcceptedAnomalies> {
+        let file = std::fs::File::open(path).context("Can't open accepted anomalies")?;
+        let entries: Vec<Acceptance> =
+            serde_yaml::from_reader(file).context("Can't read accepted anomalies")?;
+        AcceptedAnomalies::new(entries)
+    }
+
+    pub fn new(entries: Vec<Acceptance>) -> Result<AcceptedAnomalies> {
+        let entries = entries
+            .into_iter()
+            .map(|entry| match (&entry.fingerprint, &entry.pattern) {
+                (None, None) => Err(anyhow::anyhow!(
+                    "Accepted anomaly needs a fingerprint or a pattern: {:?}",
+                    entry
+                )),
+                (_, Some(pattern)) => {
+                    let re = regex::Regex::new(pattern)
+                        .with_context(|| format!("Invalid accepted pattern {}", pattern))?;
+                    Ok((entry, Some(re)))
+                }
+                (_, None) => Ok((entry, None)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(AcceptedAnomalies { entries })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the entries that are expired at the given date.
+    pub fn expired(&self, today: chrono::NaiveDate) -> impl Iterator<Item = &Acceptance> {
+        self.entries
+            .iter()
+            .map(|(entry, _)| entry)
+            .filter(move |entry| entry.expires.map(|date| date < today).unwrap_or(false))
+    }
+
+    /// Find the entry accepting the anomaly, ignoring the expired entries.
+    pub fn find(&self, anomaly: &Anomaly, today: chrono::NaiveDate) -> Option<&Acceptance> {
+        let mut fingerprint = None;
+        self.entries
+            .iter()
+            .filter(|(entry, _)| entry.expires.map(|date| date >= today).unwrap_or(true))
+            .find(|(entry, re)| {
+                re.as_ref()
+                    .map(|re| re.is_match(&anomaly.line))
+                    .unwrap_or(false)
+                    || entry
+                        .fingerprint
+                        .as_ref()
+                        .map(|expected| {
+                            expected == fingerprint.get_or_insert_with(|| anomaly.fingerprint())
+                        })
+                        .unwrap_or(false)
+            })
+            .map(|(entry, _)| entry)
+    }
+}
+
+/// The current date, to check the expiry dates.
+pub fn today() -> chrono::NaiveDate {
+    chrono::Utc::now().naive_utc().date()
+}
+
+impl Report {
+    /// Move the accepted anomalies to the accepted section.
+    pub fn accept(&mut self, accepted: &AcceptedAnomalies) {
+        let today = today();
+        for entry in accepted.expired(today) {
+            self.warnings.push(format!(
+                "Accepted anomaly {} expired: {}",
+                entry
+                    .pattern
+                    .as_ref()
+                    .or(entry.fingerprint.as_ref())
+                    .map(|s| s.as_str())
+                    .unwrap_or(""),
+                entry.comment.as_deref().unwrap_or("")
+            ));
+        }
+        for log_report in &mut self.log_reports {
+            let (found, anomalies): (Vec<_>, Vec<_>) = log_report
+                .anomalies
+                .drain(..)
+                .map(|anomaly| {
+                    let comment = accepted
+                        .find(&anomaly.anomaly, today)
+                        .map(|entry| entry.comment.clone());
+                    (anomaly, comment)
+                })
+                .partition(|(_, comment)| comment.is_some());
+            log_report.anomalies = anomalies.into_iter().map(|(anomaly, _)| anomaly).collect();
+            self.total_anomaly_count -= found.len();
+            for (anomaly, comment) in found {
+                self.accepted_anomalies.push(AcceptedAnomaly {
+                    source: log_report.source.clone(),
+                    anomaly,
+                    comment: comment.flatten(),
+                });
+            }
+        }
+        self.log_reports
+            .retain(|log_report| !log_report.anomalies.is_empty());
+    }
+}
+
+#[test]
+fn test_accepted_anomalies() {
+    let anomaly = |line: &str| Anomaly {
+        distance: 1.0,
+        severity: 1.0,
+        pos: 1,
+        line: line.to_string(),
+        repeats: None,
+        level: None,
+    };
+    let today = chrono::NaiveDate::parse_from_str("2022-06-01", "%Y-%m-%d").unwrap();
+    assert_eq!(
+        anomaly("ERROR 2022-06-01 request 42 failed").fingerprint(),
+        anomaly("ERROR 2022-06-02 request 43 failed").fingerprint(),
+        "The fingerprint ignores the dates and numbers"
+    );
+    let fingerprint = anomaly("Traceback (most recent call last)").fingerprint();
+    let accepted = AcceptedAnomalies::new(
+        serde_yaml::from_str(&format!(
+            r#"
+- pattern: "Connection reset"
+  comment: flaky network
+  expires: 2022-12-31
+- pattern: "timeout"
+  expires: 2022-01-01
+- fingerprint: {}
+"#,
+            fingerprint
+        ))
+        .unwrap(),
+    )
+    .unwrap();
+
+    let comment = |line: &str| {
+        accepted
+            .find(&anomaly(line), today)
+            .map(|entry| entry.comment.clone())
+    };
+    assert_eq!(
+        comment("read: Connection reset by peer"),
+        Some(Some("flaky network".to_string()))
+    );
+    assert_eq!(comment("Traceback (most recent call last)"), Some(None));
+    assert_eq!(
+        comment("request timeout on worker"),
+        None,
+        "Expired entries are ignored"
+    );
+    assert_eq!(accepted.expired(today).count(), 1);
+
+    assert!(AcceptedAnomalies::new(serde_yaml::from_str("- comment: oops").unwrap()).is_err());
+}
@@ -0,0 +1,138 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the report annotations, to route the readers to the owner of a log.
+//! The annotations are a yaml file where each rule matches an index name or a path regex:
+//!
+//! ```yaml
+//! - index: nova-compute.log
+//!   team: compute
+//!   runbook: https://wiki.example.com/runbooks/nova
+//! - path: "^logs/controller/.*/neutron/"
+//!   team: networking
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{IndexName, Report, Source};
+
+/// The metadata displayed next to the anomalies of a log.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default)]
+    pub team: Option<String>,
+    #[serde(default)]
+    pub runbook: Option<String>,
+}
+
+impl std::fmt::Display for Annotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.team, &self.runbook) {
+            (Some(team), Some(runbook)) => write!(f, "owner: {}, runbook: {}", team, runbook),
+            (Some(team), None) => write!(f, "owner: {}", team),
+            (None, Some(runbook)) => write!(f, "runbook: {}", runbook),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Rule {
+    #[serde(default)]
+    index: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(flatten)]
+    annotation: Annotation,
+}
+
+/// The list of annotation rules, the first matching rule is used.
+#[derive(Debug, Default)]
+pub struct Annotations {
+    rules: Vec<(Rule, Option<regex::Regex>)>,
+}
+
+impl Annotations {
+    pub fn load(path: &Path) -> Result<Annotations> {
+        let file = std::fs::File::open(path).context("Can't open annotations")?;
+        Annotations::from_reader(file)
+    }
+
+    fn from_reader<R: std::io::Read>(reader: R) -> Result<Annotations> {
+        let rules: Vec<Rule> = serde_yaml::from_reader(reader).context("Can't read annotations")?;
+        let rules = rules
+            .into_iter()
+            .map(|rule| match (&rule.index, &rule.path) {
+                (None, None) => Err(anyhow::anyhow!(
+                    "Annotation needs an index or a path: {:?}",
+                    rule
+                )),
+                (_, Some(path)) => {
+                    let re = regex::Regex::new(path)
+                        .with_context(|| format!("Invalid annotation path {}", path))?;
+                    Ok((rule, Some(re)))
+                }
+                (_, None) => Ok((rule, None)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Annotations { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Find the annotation of a source.
+    pub fn find(&self, index_name: &IndexName, source: &Source) -> Option<&Annotation> {
+        self.rules
+            .iter()
+            .find(|(rule, re)| {
+                rule.index.as_deref() == Some(index_name.as_str())
+                    || re
+                        .as_ref()
+                        .map(|re| re.is_match(source.get_relative()))
+                        .unwrap_or(false)
+            })
+            .map(|(rule, _)| &rule.annotation)
+    }
+}
+
+impl Report {
+    /// Set the annotation of each log report.
+    pub fn annotate(&mut self, annotations: &Annotations) {
+        for log_report in &mut self.log_reports {
+            log_report.annotation = annotations
+                .find(&log_report.index_name, &log_report.source)
+                .cloned();
+        }
+    }
+}
+
+#[test]
+fn test_annotations() {
+    let annotations = Annotations::from_reader(
+        r#"
+- index: nova-compute.log
+  team: compute
+  runbook: https://wiki.example.com/nova
+- path: "^controller/.*neutron"
+  team: networking
+"#
+        .as_bytes(),
+    )
+    .unwrap();
+    let team = |path: &str| {
+        annotations
+            .find(&IndexName::from_path(path), &Source::Local(0, path.into()))
+            .and_then(|annotation| annotation.team.clone())
+    };
+    assert_eq!(team("nova-compute.log"), Some("compute".to_string()));
+    assert_eq!(
+        team("controller/logs/neutron/server.log"),
+        Some("networking".to_string())
+    );
+    assert_eq!(team("controller/logs/keystone.log"), None);
+    assert!(Annotations::from_reader("- team: oops".as_bytes()).is_err());
+}
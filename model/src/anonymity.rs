@@ -0,0 +1,229 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the k-anonymity training, to share the models trained on internal logs:
+//! the tokens found in fewer than k distinct baselines are masked, and then the lines found in
+//! fewer than k distinct baselines are not added to the model.
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::time::{Instant, SystemTime};
+
+use crate::process::{tokenize_line, ChunkTrainer};
+use crate::{
+    debug_or_progress, Baselines, ChunkIndex, Index, IndexName, Model, OutputMode, Source,
+};
+
+/// The replacement of the rare tokens.
+pub const MASK: &str = "%MASK";
+
+/// The tokenized lines of an index name, for each baseline.
+#[derive(Default)]
+struct Group {
+    sources: Vec<Source>,
+    builds: Vec<Vec<String>>,
+    line_count: usize,
+    byte_count: usize,
+}
+
+fn read_tokens(index: &ChunkIndex, source: &Source, group: &mut Group) -> Result<Vec<String>> {
+    let reader = source.open()?;
+    let mut lines = Vec::new();
+    let profile = crate::profiles::profile_of(&IndexName::from_source(source));
+    for line in crate::multiline::read_lines(reader, profile) {
+        let line = line?;
+        group.line_count += 1;
+        group.byte_count += line.0.len();
+        if !String::from_utf8_lossy(&line.0[..]).trim().is_empty() {
+            lines.push(tokenize_line(index, &line.0[..], profile.json_lines));
+        }
+    }
+    Ok(lines)
+}
+
+/// Count the number of distinct builds of each item.
+fn count_builds<'a, I, J>(builds: I) -> HashMap<&'a str, usize>
+where
+    I: Iterator<Item = J>,
+    J: Iterator<Item = &'a str>,
+{
+    let mut counts = HashMap::new();
+    for build in builds {
+        for item in build.collect::<HashSet<_>>() {
+            *counts.entry(item).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+/// Returns the lines found in at least k builds, after masking the rare tokens.
+fn anonymize(builds: &[Vec<String>], token_builds: &HashMap<&str, usize>, k: usize) -> Vec<String> {
+    let masked = builds
+        .iter()
+        .map(|lines| {
+            lines
+                .iter()
+                .map(|line| {
+                    line.split(' ')
+                        .map(|token| match token_builds.get(token) {
+                            Some(count) if *count >= k => token,
+                            _ => MASK,
+                        })
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let line_builds = count_builds(
+        masked
+            .iter()
+            .map(|lines| lines.iter().map(|line| line.as_str())),
+    );
+    masked
+        .iter()
+        .flatten()
+        .filter(|line| matches!(line_builds.get(line.as_str()), Some(count) if *count >= k))
+        .unique()
+        .cloned()
+        .collect()
+}
+
+impl Model {
+    /// Create a Model that only contains the lines shared by at least k distinct baselines.
+    #[tracing::instrument(level = "debug", skip(mk_index, output_mode))]
+    pub fn train_anonymous(
+        output_mode: OutputMode,
+        baselines: Baselines,
+        mk_index: fn() -> ChunkIndex,
+        k: usize,
+    ) -> Result<Model> {
+        if k == 0 || baselines.len() < k {
+            return Err(anyhow::anyhow!(
+                "The k-anonymity needs at least {} baselines, got {}",
+                k.max(1),
+                baselines.len()
+            ));
+        }
+        let created_at = SystemTime::now();
+        let start_time = Instant::now();
+        let tokenizer = mk_index();
+        let mut groups: HashMap<IndexName, Group> = HashMap::new();
+        for (build, baseline) in baselines.iter().enumerate() {
+            debug_or_progress(output_mode, &format!("Reading {}", baseline));
+            for source in baseline.collect_sources()? {
+                let group = groups.entry(IndexName::from_source(&source)).or_default();
+                let lines = read_tokens(&tokenizer, &source, group)
+                    .with_context(|| format!("Failed to load {}", source))?;
+                group.builds.resize_with(build + 1, Vec::new);
+                group.builds[build].extend(lines);
+                group.sources.push(source);
+            }
+        }
+
+        let token_builds = count_builds((0..baselines.len()).map(|build| {
+            groups
+                .values()
+                .filter_map(move |group| group.builds.get(build))
+                .flatten()
+                .flat_map(|line| line.split(' '))
+        }));
+        let mut indexes = HashMap::new();
+        for (index_name, group) in &groups {
+            let mut index = mk_index();
+            let mut trainer = ChunkTrainer::new(&mut index);
+            let lines = anonymize(&group.builds, &token_builds, k);
+            if lines.is_empty() {
+                tracing::warn!("{}: skipped: no line found in {} baselines", index_name, k);
+                continue;
+            }
+            lines.into_iter().for_each(|line| trainer.add_tokens(line));
+            trainer.complete();
+            indexes.insert(
+                index_name.clone(),
+                Index {
+                    created_at,
+                    train_time: start_time.elapsed(),
+                    sources: group.sources.clone(),
+                    index,
+                    line_count: group.line_count,
+                    byte_count: group.byte_count,
+                    baseline_rows: Vec::new(),
+                    idle_runs: 0,
+                },
+            );
+        }
+        if indexes.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Empty baselines, no line is shared by {} baselines",
+                k
+            ));
+        }
+        Ok(Model {
+            created_at,
+            version: crate::version::VERSION.to_string(),
+            baselines,
+            indexes,
+            params: crate::params::get_train_params(),
+            lazy: None,
+        })
+    }
+}
+
+#[test]
+fn test_anonymize() {
+    let builds = [
+        vec!["user alice logged in", "service started", "secret token"],
+        vec!["user bob logged in", "service started"],
+        vec!["service started", "service stopped"],
+    ]
+    .iter()
+    .map(|lines| lines.iter().map(|line| line.to_string()).collect())
+    .collect::<Vec<Vec<String>>>();
+    let token_builds = count_builds(
+        builds
+            .iter()
+            .map(|lines| lines.iter().flat_map(|line| line.split(' '))),
+    );
+    assert_eq!(token_builds.get("started"), Some(&3));
+    assert_eq!(
+        anonymize(&builds, &token_builds, 2),
+        vec!["user %MASK logged in", "service started"]
+    );
+    assert_eq!(
+        anonymize(&builds, &token_builds, 3),
+        vec!["service started"]
+    );
+}
+
+#[test]
+fn test_train_anonymous() -> Result<()> {
+    use crate::Content;
+    let baseline = |user: &str| {
+        Content::from_memory(vec![(
+            "service.log",
+            format!("service started\nsecret {} token\n", user),
+        )])
+    };
+    let baselines = vec![baseline("alice"), baseline("bob")];
+    assert!(
+        Model::train_anonymous(OutputMode::Quiet, baselines, crate::hashing_index::new, 3).is_err()
+    );
+
+    let baselines = vec![baseline("alice"), baseline("bob"), baseline("carol")];
+    let model = Model::train_anonymous(OutputMode::Quiet, baselines, crate::hashing_index::new, 2)?;
+    let report = model.report(
+        OutputMode::Quiet,
+        Content::from_memory(vec![(
+            "service.log",
+            "service started\nsecret alice token\n",
+        )]),
+    )?;
+    // The line is anonymized, thus the secret is not found in the model.
+    assert_eq!(report.total_anomaly_count, 1);
+    assert_eq!(
+        report.log_reports[0].anomalies[0].anomaly.line,
+        "secret alice token"
+    );
+    Ok(())
+}
@@ -0,0 +1,122 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module removes the ANSI escape sequences of the lines, such as the colors of the Ansible
+//! and pytest console logs, so that they are neither tokenized nor displayed in the reports.
+
+use bytes::Bytes;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use logreduce_iterator::LogLine;
+
+const ESC: u8 = 0x1b;
+
+/// The end of an operating system command, the other end is `ESC \`.
+const BEL: u8 = 0x07;
+
+static STRIP_ANSI: AtomicBool = AtomicBool::new(true);
+
+/// Remove the ANSI escape sequences when reading the sources, this is enabled by default.
+pub fn set_strip_ansi(enabled: bool) {
+    STRIP_ANSI.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    STRIP_ANSI.load(Ordering::Relaxed)
+}
+
+/// Remove the escape sequences of the line, without copy when there are none.
+pub(crate) fn strip_line((line, pos): LogLine) -> LogLine {
+    if line.contains(&ESC) {
+        (Bytes::from(strip(&line)), pos)
+    } else {
+        (line, pos)
+    }
+}
+
+fn strip(line: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(line.len());
+    let mut pos = 0;
+    while pos < line.len() {
+        if line[pos] != ESC {
+            result.push(line[pos]);
+            pos += 1;
+            continue;
+        }
+        pos += 1;
+        match line.get(pos) {
+            // A control sequence, such as a color: the parameters, and the final byte.
+            Some(b'[') => {
+                pos += 1;
+                while let Some(c) = line.get(pos) {
+                    pos += 1;
+                    if !(0x20..=0x3f).contains(c) {
+                        break;
+                    }
+                }
+            }
+            // An operating system command, such as a hyperlink or a window title.
+            Some(b']') => {
+                pos += 1;
+                while pos < line.len() {
+                    if line[pos] == BEL {
+                        pos += 1;
+                        break;
+                    } else if line[pos] == ESC && line.get(pos + 1) == Some(&b'\\') {
+                        pos += 2;
+                        break;
+                    }
+                    pos += 1;
+                }
+            }
+            // An escape sequence, with the optional intermediate bytes and the final byte.
+            Some(_) => {
+                while line.get(pos).is_some_and(|c| (0x20..=0x2f).contains(c)) {
+                    pos += 1;
+                }
+                pos += 1;
+            }
+            None => {}
+        }
+    }
+    result
+}
+
+#[test]
+fn test_strip_ansi() {
+    let strip_str = |line: &str| String::from_utf8(strip(line.as_bytes())).unwrap();
+    assert_eq!(
+        strip_str("\x1b[0;32mok: [localhost]\x1b[0m"),
+        "ok: [localhost]"
+    );
+    assert_eq!(
+        strip_str("\x1b[1m\x1b[31mFAILED\x1b[0m tests/test_a.py::test_b"),
+        "FAILED tests/test_a.py::test_b"
+    );
+    assert_eq!(
+        strip_str("see \x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07 \x1b[2K\x1b(Bdone"),
+        "see link done"
+    );
+    assert_eq!(strip_str("truncated \x1b[0;3"), "truncated ");
+
+    let line = (Bytes::from("plain"), 42);
+    assert_eq!(strip_line(line.clone()), line);
+}
+
+#[test]
+fn test_ansi_anomalies() {
+    use crate::process::{ChunkProcessor, ChunkTrainer};
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(
+        &mut index,
+        std::io::Cursor::new("ok: [controller]\nchanged: [controller]\n"),
+    )
+    .unwrap();
+    let data = "\x1b[0;32mok: [controller]\x1b[0m\n\x1b[0;31mfatal: [controller]: FAILED!\x1b[0m\n";
+    let anomalies = ChunkProcessor::single(std::io::Cursor::new(data), &index)
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.line, "fatal: [controller]: FAILED!");
+    assert_eq!(anomalies[0].before, vec!["ok: [controller]".to_string()]);
+}
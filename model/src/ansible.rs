@@ -0,0 +1,289 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the detection of the failing Ansible task, using the job-output.json
+//! of the Zuul builds. The anomalies logged during the task are the most likely cause of the
+//! failure, and their severity is raised.
+//! The job-output.json is also loaded as one source per task name, so that the task outputs are
+//! compared with the same task of the baselines. It is read once for both, see read_job_output.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{Anomaly, Source};
+
+/// The severity added to the anomalies logged during the failed task.
+pub const FAILED_TASK_WEIGHT: f32 = 0.3;
+
+/// The task which failed the build.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailedTask {
+    pub name: String,
+    pub playbook: String,
+    pub hosts: Vec<String>,
+    pub msg: Option<String>,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl std::fmt::Display for FailedTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} on {} ({} to {})",
+            self.name,
+            self.hosts.join(", "),
+            self.start.format("%H:%M:%S"),
+            self.end.format("%H:%M:%S")
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Playbook {
+    playbook: String,
+    #[serde(default)]
+    plays: Vec<Play>,
+}
+
+#[derive(Deserialize)]
+struct Play {
+    #[serde(default)]
+    tasks: Vec<Task>,
+}
+
+#[derive(Deserialize)]
+struct Task {
+    task: TaskInfo,
+    #[serde(default)]
+    hosts: std::collections::BTreeMap<String, HostResult>,
+}
+
+#[derive(Deserialize)]
+struct TaskInfo {
+    name: String,
+    duration: Duration,
+}
+
+#[derive(Deserialize)]
+struct Duration {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct HostResult {
+    #[serde(default)]
+    failed: bool,
+    #[serde(default)]
+    ignore_errors: bool,
+    #[serde(default)]
+    msg: Option<serde_json::Value>,
+    #[serde(default)]
+    stdout: Option<String>,
+    #[serde(default)]
+    stderr: Option<String>,
+    /// The results of the loop items.
+    #[serde(default)]
+    results: Vec<serde_json::Value>,
+}
+
+impl HostResult {
+    fn write_output(&self, out: &mut String) {
+        let msg = match &self.msg {
+            Some(serde_json::Value::String(msg)) => Some(msg),
+            _ => None,
+        };
+        for text in [&self.stdout, &self.stderr]
+            .iter()
+            .copied()
+            .flatten()
+            .chain(msg)
+            .filter(|text| !text.is_empty())
+        {
+            out.push_str(text);
+            if !text.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+        for item in &self.results {
+            if let Ok(result) = serde_json::from_value::<HostResult>(item.clone()) {
+                result.write_output(out);
+            }
+        }
+    }
+}
+
+impl FailedTask {
+    /// Find the last failed task of a job-output.json, ignoring the failures of ignore_errors tasks.
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Option<FailedTask>> {
+        let playbooks: Vec<Playbook> =
+            serde_json::from_reader(reader).context("Can't decode job-output.json")?;
+        Ok(FailedTask::from_playbooks(&playbooks))
+    }
+
+    fn from_playbooks(playbooks: &[Playbook]) -> Option<FailedTask> {
+        let mut failed_task = None;
+        for playbook in playbooks {
+            for task in playbook.plays.iter().flat_map(|play| &play.tasks) {
+                let failed = task
+                    .hosts
+                    .iter()
+                    .filter(|(_, result)| result.failed && !result.ignore_errors)
+                    .collect::<Vec<_>>();
+                if let Some((_, result)) = failed.first() {
+                    failed_task = Some(FailedTask {
+                        name: task.task.name.clone(),
+                        playbook: playbook.playbook.clone(),
+                        hosts: failed.iter().map(|(host, _)| host.to_string()).collect(),
+                        msg: result.msg.as_ref().map(|msg| match msg {
+                            serde_json::Value::String(msg) => msg.clone(),
+                            msg => msg.to_string(),
+                        }),
+                        start: task.task.duration.start,
+                        end: task.task.duration.end,
+                    });
+                }
+            }
+        }
+        failed_task
+    }
+
+    /// Returns true when the anomaly line is timestamped during the task.
+    /// The lines with only a time are assumed to be logged on the day the task started.
+    pub fn contains(&self, anomaly: &Anomaly) -> bool {
+        match crate::warmup::parse_timestamp(&anomaly.line) {
+            Some(ts) => {
+                let ts = if ts.year() == 1970 {
+                    self.start.naive_utc().date().and_time(ts.time())
+                } else {
+                    ts
+                };
+                self.start.naive_utc() <= ts && ts <= self.end.naive_utc()
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns true when the source is a job-output.json.
+pub fn is_job_output(source: &Source) -> bool {
+    let s = source.as_str();
+    s.ends_with("job-output.json") || s.ends_with("job-output.json.gz")
+}
+
+/// Split a job-output.json into one memory source per task name, named `job-output.json/<task>`,
+/// and find its failed task.
+pub fn read_job_output(source: &Source) -> Result<(Vec<Source>, Option<FailedTask>)> {
+    let reader = source.open()?;
+    let playbooks: Vec<Playbook> =
+        serde_json::from_reader(reader).context("Can't decode job-output.json")?;
+    let mut outputs = std::collections::BTreeMap::new();
+    for task in playbooks
+        .iter()
+        .flat_map(|playbook| &playbook.plays)
+        .flat_map(|play| &play.tasks)
+    {
+        let output = outputs
+            .entry(task.task.name.replace('/', "_"))
+            .or_insert_with(String::new);
+        for result in task.hosts.values() {
+            result.write_output(output);
+        }
+    }
+    let source_name = source.get_relative().trim_end_matches(".gz");
+    let sources = outputs
+        .into_iter()
+        .filter(|(_, output)| !output.is_empty())
+        .map(|(name, output)| {
+            Source::Memory(
+                format!("{}/{}", source_name, name),
+                crate::memory::MemoryData(output.into()),
+            )
+        })
+        .collect();
+    Ok((sources, FailedTask::from_playbooks(&playbooks)))
+}
+
+#[test]
+fn test_failed_task() {
+    let job_output = r#"[
+      {"playbook": "pre.yaml", "plays": [{"tasks": [
+        {"task": {"name": "Check optional", "duration": {"start": "2022-04-01T10:00:00.000000Z", "end": "2022-04-01T10:00:01.000000Z"}},
+         "hosts": {"controller": {"failed": true, "ignore_errors": true}}}
+      ]}]},
+      {"playbook": "run.yaml", "plays": [{"tasks": [
+        {"task": {"name": "Setup", "duration": {"start": "2022-04-01T10:01:00.000000Z", "end": "2022-04-01T10:02:00.000000Z"}},
+         "hosts": {"controller": {"changed": true}}},
+        {"task": {"name": "Run tests", "duration": {"start": "2022-04-01T10:02:00.000000Z", "end": "2022-04-01T10:05:00.000000Z"}},
+         "hosts": {"compute1": {"failed": false}, "controller": {"failed": true, "msg": "non-zero return code"}}}
+      ]}]}
+    ]"#;
+    let task = FailedTask::from_reader(job_output.as_bytes())
+        .unwrap()
+        .unwrap();
+    assert_eq!(task.name, "Run tests");
+    assert_eq!(task.playbook, "run.yaml");
+    assert_eq!(task.hosts, vec!["controller"]);
+    assert_eq!(task.msg.as_deref(), Some("non-zero return code"));
+
+    let anomaly = |line: &str| Anomaly {
+        distance: 0.5,
+        severity: 0.5,
+        pos: 1,
+        line: line.to_string(),
+        repeats: None,
+        level: None,
+    };
+    assert!(task.contains(&anomaly("2022-04-01 10:03:00.123 | ERROR test failed")));
+    assert!(task.contains(&anomaly("[10:04:00] ERROR test failed")));
+    assert!(!task.contains(&anomaly("2022-04-01 10:01:30 ERROR setup warning")));
+    assert!(!task.contains(&anomaly("ERROR without timestamp")));
+
+    assert_eq!(FailedTask::from_reader("[]".as_bytes()).unwrap(), None);
+}
+
+#[test]
+fn test_task_sources() {
+    let job_output = r#"[
+      {"playbook": "run.yaml", "plays": [{"tasks": [
+        {"task": {"name": "Setup", "duration": {"start": "2022-04-01T10:01:00.000000Z", "end": "2022-04-01T10:02:00.000000Z"}},
+         "hosts": {"controller": {"changed": true}}},
+        {"task": {"name": "tox : Run tests", "duration": {"start": "2022-04-01T10:02:00.000000Z", "end": "2022-04-01T10:05:00.000000Z"}},
+         "hosts": {"controller": {"failed": true, "stdout": "test_a ok\ntest_b FAILED", "stderr": "", "msg": "non-zero return code"}}},
+        {"task": {"name": "Collect/logs", "duration": {"start": "2022-04-01T10:05:00.000000Z", "end": "2022-04-01T10:06:00.000000Z"}},
+         "hosts": {"controller": {"results": [{"stdout": "copied a.log"}, "skipped"]}}}
+      ]}]}
+    ]"#;
+    let source = Source::Memory(
+        "job-output.json".to_string(),
+        crate::memory::MemoryData(job_output.into()),
+    );
+    assert!(is_job_output(&source));
+    let (sources, failed_task) = read_job_output(&source).unwrap();
+    assert_eq!(
+        failed_task.map(|task| task.name).as_deref(),
+        Some("tox : Run tests")
+    );
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec![
+            "job-output.json/Collect_logs",
+            "job-output.json/tox : Run tests"
+        ]
+    );
+    match &sources[1] {
+        Source::Memory(_, data) => {
+            assert_eq!(data.0, "test_a ok\ntest_b FAILED\nnon-zero return code\n")
+        }
+        source => panic!("Unexpected source {:?}", source),
+    }
+    assert_eq!(
+        crate::IndexName::from_source(&sources[1]).as_str(),
+        "job-output.json/toxRuntests"
+    );
+}
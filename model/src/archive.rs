@@ -0,0 +1,236 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the tar archives support: a `logs.tar.gz` is read like a directory,
+//! each member becomes a source named `logs.tar.gz/<member path>`.
+//! The archive is decompressed once to the cache when its members are listed, and each member
+//! is read by seeking to its offset in the spooled archive.
+//! When the archive can't be spooled, for example when the cache quota is reached, each member
+//! is read by streaming the archive up to its header.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::reader::DecompressReader;
+use crate::Source;
+
+const EXTS: &[&str] = &[".tar", ".tar.gz", ".tgz", ".tar.zst", ".tar.xz", ".tar.bz2"];
+
+lazy_static::lazy_static! {
+    /// The spooled archives per digest, indexed once per process.
+    static ref SPOOLS: Mutex<HashMap<String, Arc<Spool>>> = Mutex::new(HashMap::new());
+}
+
+/// A decompressed archive in the cache, with the offset and size of its members.
+struct Spool {
+    path: PathBuf,
+    names: Vec<String>,
+    members: HashMap<String, (u64, u64)>,
+}
+
+/// Returns true when the source is a tar archive.
+pub fn is_archive(source: &Source) -> bool {
+    let s = source.as_str();
+    EXTS.iter().any(|ext| s.ends_with(ext))
+}
+
+/// The decompressed archive content.
+fn archive_reader(source: &Source) -> Result<DecompressReader> {
+    let reader = source.open()?;
+    // The remote compressed archives may already be decompressed by the reader.
+    crate::reader::maybe_decompress(reader).context("Can't read archive")
+}
+
+fn open_archive(source: &Source) -> Result<tar::Archive<DecompressReader>> {
+    Ok(tar::Archive::new(archive_reader(source)?))
+}
+
+/// The path of the regular file entries, without the leading `./`.
+fn member_path<R: Read>(entry: &tar::Entry<R>) -> Result<Option<String>> {
+    if !entry.header().entry_type().is_file() {
+        return Ok(None);
+    }
+    let path = entry.path().context("Invalid archive entry path")?;
+    Ok(Some(
+        path.to_string_lossy().trim_start_matches("./").to_string(),
+    ))
+}
+
+/// Get the spooled archive, the archive is decompressed to the cache on the first call.
+/// Returns None when the archive can't be identified or spooled.
+fn get_spool(source: &Source) -> Result<Option<Arc<Spool>>> {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    if source.digest(&mut hasher).is_err() {
+        return Ok(None);
+    }
+    let digest = format!("{:X}", hasher.finalize());
+    // The lock is held while spooling to decompress each archive only once.
+    let mut spools = SPOOLS.lock().unwrap();
+    if let Some(spool) = spools.get(&digest) {
+        return Ok(Some(spool.clone()));
+    }
+    let path = match crate::reader::CACHE.archive_get(&digest) {
+        Some(path) => path,
+        None => match crate::reader::CACHE.archive_add(&digest, archive_reader(source)?) {
+            Ok(path) => path,
+            Err(e) => {
+                tracing::debug!("{}: streaming the members: {:#}", source, e);
+                return Ok(None);
+            }
+        },
+    };
+    let mut archive = tar::Archive::new(std::fs::File::open(&path).context("Can't open spool")?);
+    let mut names = Vec::new();
+    let mut members = HashMap::new();
+    for entry in archive.entries().context("Can't read archive")? {
+        let entry = entry.context("Can't read archive entry")?;
+        if let Some(path) = member_path(&entry)? {
+            members.insert(path.clone(), (entry.raw_file_position(), entry.size()));
+            names.push(path);
+        }
+    }
+    let spool = Arc::new(Spool {
+        path,
+        names,
+        members,
+    });
+    spools.insert(digest, spool.clone());
+    Ok(Some(spool))
+}
+
+/// The archive member paths, in the archive order.
+fn member_paths(source: &Source) -> Result<Vec<String>> {
+    if let Some(spool) = get_spool(source)? {
+        return Ok(spool.names.clone());
+    }
+    let mut archive = open_archive(source)?;
+    let mut paths = Vec::new();
+    for entry in archive.entries().context("Can't read archive")? {
+        let entry = entry.context("Can't read archive entry")?;
+        if let Some(path) = member_path(&entry)? {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Expand the archive into one source per valid member, the members content is not read.
+pub fn member_sources(source: &Source) -> Result<Vec<Source>> {
+    Ok(member_paths(source)?
+        .into_iter()
+        .map(|path| {
+            Source::Member(
+                Box::new(source.clone()),
+                format!("{}/{}", source.get_relative(), path),
+            )
+        })
+        .filter(|source| source.is_valid())
+        .collect())
+}
+
+/// Read a member, the name is the relative name of the source returned by member_sources.
+pub(crate) fn member_open(archive_source: &Source, name: &str) -> Result<DecompressReader> {
+    let path = name
+        .get(archive_source.get_relative().len() + 1..)
+        .ok_or_else(|| anyhow::anyhow!("Invalid archive member {}", name))?;
+    if let Some(spool) = get_spool(archive_source)? {
+        let (offset, size) = spool
+            .members
+            .get(path)
+            .ok_or_else(|| anyhow::anyhow!("{}: member not found", name))?;
+        let mut file = std::fs::File::open(&spool.path).context("Can't open spool")?;
+        file.seek(SeekFrom::Start(*offset))?;
+        return Ok(DecompressReader::Member(Box::new(
+            DecompressReader::Flat(file).take(*size),
+        )));
+    }
+    stream_member(archive_source, path, name)
+}
+
+/// Read a member by streaming the archive up to its header.
+fn stream_member(archive_source: &Source, path: &str, name: &str) -> Result<DecompressReader> {
+    let mut archive = open_archive(archive_source)?;
+    let mut size = None;
+    for entry in archive.entries().context("Can't read archive")? {
+        let entry = entry.context("Can't read archive entry")?;
+        if member_path(&entry)?.as_deref() == Some(path) {
+            size = Some(entry.size());
+            break;
+        }
+    }
+    // The archive reader is left at the start of the member content.
+    match size {
+        Some(size) => Ok(DecompressReader::Member(Box::new(
+            archive.into_inner().take(size),
+        ))),
+        None => Err(anyhow::anyhow!("{}: member not found", name)),
+    }
+}
+
+#[test]
+fn test_archive_members() -> Result<()> {
+    // The long paths use a separate GNU header entry.
+    let long_path = format!("./controller/{}/neutron.log", "d".repeat(120));
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, content) in [
+        ("./controller/nova.log", "INFO nova started\n"),
+        ("./controller/screenshot.png", "binary"),
+        (long_path.as_str(), "INFO neutron started\n"),
+        ("./job-output.txt", "TASK [run]\n"),
+    ] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, content.as_bytes())?;
+    }
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    std::io::Write::write_all(&mut gz, &builder.into_inner()?)?;
+    let archive = Source::Memory(
+        "logs.tar.gz".to_string(),
+        crate::memory::MemoryData(gz.finish()?.into()),
+    );
+    assert!(is_archive(&archive));
+
+    let sources = member_sources(&archive)?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec![
+            "logs.tar.gz/controller/nova.log".to_string(),
+            format!("logs.tar.gz{}", &long_path[1..]),
+            "logs.tar.gz/job-output.txt".to_string(),
+        ]
+    );
+    assert_eq!(
+        crate::IndexName::from_source(&sources[0]),
+        crate::IndexName::from_path("controller/nova.log")
+    );
+    let read = |source: &Source| -> Result<String> {
+        let mut content = String::new();
+        match source {
+            Source::Member(archive, name) => member_open(archive, name)?,
+            source => panic!("Unexpected source {:?}", source),
+        }
+        .read_to_string(&mut content)?;
+        Ok(content)
+    };
+    // The archive is decompressed once, the members are read from the spool.
+    assert!(get_spool(&archive)?.is_some());
+    assert_eq!(read(&sources[0])?, "INFO nova started\n");
+    assert_eq!(read(&sources[1])?, "INFO neutron started\n");
+    assert_eq!(read(&sources[2])?, "TASK [run]\n");
+
+    // The members can still be streamed when the archive is not spooled.
+    let mut content = String::new();
+    stream_member(&archive, "job-output.txt", "logs.tar.gz/job-output.txt")?
+        .read_to_string(&mut content)?;
+    assert_eq!(content, "TASK [run]\n");
+    Ok(())
+}
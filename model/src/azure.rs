@@ -0,0 +1,267 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Azure Blob Storage support: the container urls such as
+//! `https://<account>.blob.core.windows.net/<container>/<prefix>` are listed like a directory.
+//! Set AZURE_STORAGE_SAS_TOKEN to use a shared access signature, or AZURE_STORAGE_KEY to
+//! sign the requests with the key of the AZURE_STORAGE_ACCOUNT.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::{Content, Source};
+
+/// The version of the blob service api.
+const API_VERSION: &str = "2021-08-06";
+
+lazy_static::lazy_static! {
+    static ref SAS_TOKEN: Option<Vec<(String, String)>> = std::env::var("AZURE_STORAGE_SAS_TOKEN")
+        .ok()
+        .map(|token| parse_sas(&token));
+    static ref ACCOUNT_KEY: Option<Vec<u8>> = std::env::var("AZURE_STORAGE_KEY").ok().and_then(|key| {
+        match base64::engine::general_purpose::STANDARD.decode(key.trim()) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid AZURE_STORAGE_KEY: {}", e);
+                None
+            }
+        }
+    });
+    static ref ACCOUNT: Option<String> = std::env::var("AZURE_STORAGE_ACCOUNT").ok();
+}
+
+fn parse_sas(token: &str) -> Vec<(String, String)> {
+    url::form_urlencoded::parse(token.trim_start_matches('?').as_bytes())
+        .into_owned()
+        .collect()
+}
+
+/// Returns true for the blob service urls.
+pub fn is_blob_url(url: &Url) -> bool {
+    url.host_str()
+        .map(|host| host.ends_with(".blob.core.windows.net"))
+        .unwrap_or(false)
+}
+
+/// The storage account name, the first label of the host.
+fn account_name(url: &Url) -> &str {
+    url.host_str()
+        .and_then(|host| host.split('.').next())
+        .unwrap_or("")
+}
+
+/// Returns the container url and the blob prefix of a blob service url.
+fn get_container(url: &Url) -> Option<(Url, String)> {
+    let mut segments = url.path_segments()?;
+    let container = segments.next().filter(|container| !container.is_empty())?;
+    let prefix = crate::urls::decode(&segments.collect::<Vec<_>>().join("/"));
+    let mut container_url = url.join(&format!("/{}/", container)).ok()?;
+    container_url.set_query(None);
+    Some((container_url, prefix))
+}
+
+pub fn sources_iter(url: &Url) -> Box<dyn Iterator<Item = Result<Source>>> {
+    match get_sources(url) {
+        Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+        Err(e) => Box::new(std::iter::once(Err(e))),
+    }
+}
+
+fn get_sources(url: &Url) -> Result<Vec<Source>> {
+    let (container, prefix) =
+        get_container(url).ok_or_else(|| anyhow::anyhow!("Missing container name: {}", url))?;
+    // The relative paths start after the last directory of the prefix.
+    let dir = match prefix.rfind('/') {
+        Some(pos) => &prefix[..pos + 1],
+        None => "",
+    };
+    let base_len = container
+        .join(dir)
+        .context("Can't create prefix url")?
+        .as_str()
+        .len();
+    let mut sources = Vec::new();
+    let mut marker: Option<String> = None;
+    loop {
+        let mut list_url = container.clone();
+        {
+            let mut query = list_url.query_pairs_mut();
+            query.append_pair("restype", "container");
+            query.append_pair("comp", "list");
+            query.append_pair("prefix", &prefix);
+            if let Some(marker) = &marker {
+                query.append_pair("marker", marker);
+            }
+        }
+        let mut body = String::new();
+        std::io::Read::read_to_string(
+            &mut crate::reader::from_url(&container, &list_url)?,
+            &mut body,
+        )
+        .context("Can't read the blobs list")?;
+        let (names, next) = parse_list(&body);
+        for name in names.iter().filter(|name| !name.ends_with('/')) {
+            let url = container
+                .join(&crate::s3::encode(name, false))
+                .context("Can't create blob url")?;
+            sources.push(Source::Remote(base_len, url));
+        }
+        match next {
+            Some(next) => marker = Some(next),
+            None => return Ok(sources),
+        }
+    }
+}
+
+/// Returns the blob names and the next marker of a List Blobs response.
+fn parse_list(body: &str) -> (Vec<String>, Option<String>) {
+    lazy_static::lazy_static! {
+        static ref NAME: regex::Regex = regex::Regex::new("<Blob>\\s*<Name>([^<]*)</Name>").expect("Name regex");
+        static ref NEXT: regex::Regex =
+            regex::Regex::new("<NextMarker>([^<]+)</NextMarker>").expect("Marker regex");
+    }
+    let names = NAME
+        .captures_iter(body)
+        .map(|captures| crate::s3::unescape(&captures[1]))
+        .collect();
+    let next = NEXT
+        .captures(body)
+        .map(|captures| crate::s3::unescape(&captures[1]));
+    (names, next)
+}
+
+/// The Shared Key string to sign of a request without body.
+fn string_to_sign(method: &str, url: &Url, date: &str) -> String {
+    let mut query: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (key, value) in url.query_pairs() {
+        query
+            .entry(key.to_lowercase())
+            .or_default()
+            .push(value.into_owned());
+    }
+    let mut resource = format!("/{}{}", account_name(url), url.path());
+    for (key, mut values) in query {
+        values.sort();
+        resource.push_str(&format!("\n{}:{}", key, values.join(",")));
+    }
+    format!(
+        "{}\n\n\n\n\n\n\n\n\n\n\n\nx-ms-date:{}\nx-ms-version:{}\n{}",
+        method, date, API_VERSION, resource
+    )
+}
+
+/// Returns the signed headers of a GET or HEAD request.
+fn sign(key: &[u8], method: &str, url: &Url, date: &str) -> Vec<(&'static str, String)> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(string_to_sign(method, url, date).as_bytes());
+    let signature = base64::engine::general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+    vec![
+        ("x-ms-date", date.to_string()),
+        ("x-ms-version", API_VERSION.to_string()),
+        (
+            "authorization",
+            format!("SharedKey {}:{}", account_name(url), signature),
+        ),
+    ]
+}
+
+/// Add the SAS token, or the Shared Key signature, to the blob service requests.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    if !is_blob_url(url) {
+        return request;
+    }
+    match (&*SAS_TOKEN, &*ACCOUNT_KEY) {
+        (Some(sas), _) => request.query(sas),
+        (None, Some(key)) if ACCOUNT.iter().all(|account| account == account_name(url)) => {
+            let method = crate::urls::request_method(&request);
+            let date = chrono::Utc::now()
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string();
+            sign(key, &method, url, &date)
+                .into_iter()
+                .fold(request, |request, (name, value)| {
+                    request.header(name, value)
+                })
+        }
+        _ => request,
+    }
+}
+
+impl Content {
+    /// The blob service urls are analyzed as a directory, even without a trailing slash.
+    pub fn from_azure_url(url: &Url) -> Option<Result<Content>> {
+        if is_blob_url(url) && get_container(url).is_some() {
+            Some(Ok(Content::Directory(Source::Remote(0, url.clone()))))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn test_azure_url() {
+    let url = Url::parse("https://ci.blob.core.windows.net/logs/builds/42/a%20b").unwrap();
+    assert!(is_blob_url(&url));
+    assert_eq!(account_name(&url), "ci");
+    let (container, prefix) = get_container(&url).unwrap();
+    assert_eq!(container.as_str(), "https://ci.blob.core.windows.net/logs/");
+    assert_eq!(prefix, "builds/42/a b");
+    assert!(get_container(&Url::parse("https://ci.blob.core.windows.net/").unwrap()).is_none());
+    assert!(matches!(
+        Content::from_azure_url(&url),
+        Some(Ok(Content::Directory(_)))
+    ));
+    assert!(Content::from_azure_url(&Url::parse("https://example.com/logs").unwrap()).is_none());
+    assert_eq!(
+        parse_sas("?sv=2021-08-06&sig=a%2Bb"),
+        vec![
+            ("sv".to_string(), "2021-08-06".to_string()),
+            ("sig".to_string(), "a+b".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_azure_list() {
+    let (names, next) = parse_list(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<EnumerationResults ServiceEndpoint="https://ci.blob.core.windows.net/" ContainerName="logs">
+  <Prefix>builds/42/</Prefix>
+  <Blobs>
+    <Blob><Name>builds/42/job-output.txt</Name><Properties /></Blob>
+    <Blob><Name>builds/42/logs/a&amp;b.log</Name><Properties /></Blob>
+  </Blobs>
+  <NextMarker>2!96!MDAwMDQ</NextMarker>
+</EnumerationResults>"#,
+    );
+    assert_eq!(
+        names,
+        vec!["builds/42/job-output.txt", "builds/42/logs/a&b.log"]
+    );
+    assert_eq!(next.as_deref(), Some("2!96!MDAwMDQ"));
+    assert_eq!(
+        parse_list("<EnumerationResults><Blobs /><NextMarker /></EnumerationResults>"),
+        (Vec::new(), None)
+    );
+
+    let url = Url::parse(
+        "https://ci.blob.core.windows.net/logs/?restype=container&comp=list&prefix=builds%2F42%2F",
+    )
+    .unwrap();
+    let date = "Fri, 01 Apr 2022 10:00:00 GMT";
+    assert_eq!(
+        string_to_sign("GET", &url, date),
+        "GET\n\n\n\n\n\n\n\n\n\n\n\n\
+         x-ms-date:Fri, 01 Apr 2022 10:00:00 GMT\nx-ms-version:2021-08-06\n\
+         /ci/logs/\ncomp:list\nprefix:builds/42/\nrestype:container"
+    );
+    let headers = sign(b"secret", "GET", &url, date);
+    assert!(headers[2].1.starts_with("SharedKey ci:"));
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module compares the indexes of two models, to audit what changed between the retrains.
+
+use crate::{Index, IndexName, Model};
+
+/// The size of an index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IndexSize {
+    pub sources: usize,
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+impl IndexSize {
+    fn of(index: &Index) -> IndexSize {
+        IndexSize {
+            sources: index.sources.len(),
+            lines: index.line_count,
+            bytes: index.byte_count,
+        }
+    }
+}
+
+impl std::fmt::Display for IndexSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} lines from {} sources", self.lines, self.sources)
+    }
+}
+
+/// The change of an index between an old and a new model.
+#[derive(Debug, PartialEq)]
+pub enum IndexChange {
+    Added(IndexSize),
+    Removed(IndexSize),
+    Changed(IndexSize, IndexSize),
+    Unchanged(IndexSize),
+}
+
+impl std::fmt::Display for IndexChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexChange::Added(size) => write!(f, "added, {}", size),
+            IndexChange::Removed(size) => write!(f, "removed, {}", size),
+            IndexChange::Changed(old, new) => write!(
+                f,
+                "{:+} lines ({} -> {}), {:+} sources",
+                new.lines as i64 - old.lines as i64,
+                old.lines,
+                new.lines,
+                new.sources as i64 - old.sources as i64
+            ),
+            IndexChange::Unchanged(size) => write!(f, "unchanged, {}", size),
+        }
+    }
+}
+
+impl Model {
+    /// Compare the indexes with the ones of a newer model, sorted by name.
+    pub fn diff(&self, new: &Model) -> Vec<(IndexName, IndexChange)> {
+        let mut changes: Vec<(IndexName, IndexChange)> = self
+            .indexes
+            .iter()
+            .map(|(name, index)| {
+                let old = IndexSize::of(index);
+                let change = match new.indexes.get(name).map(IndexSize::of) {
+                    None => IndexChange::Removed(old),
+                    Some(new) if new == old => IndexChange::Unchanged(old),
+                    Some(new) => IndexChange::Changed(old, new),
+                };
+                (name.clone(), change)
+            })
+            .chain(
+                new.indexes
+                    .iter()
+                    .filter(|(name, _)| !self.indexes.contains_key(name))
+                    .map(|(name, index)| (name.clone(), IndexChange::Added(IndexSize::of(index)))),
+            )
+            .collect();
+        changes.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        changes
+    }
+}
+
+#[test]
+fn test_model_diff() {
+    let old = crate::train_memory(vec![
+        ("service.log", "INFO service started\n"),
+        ("worker.log", "INFO worker ready\n"),
+        ("db.log", "INFO db ready\n"),
+    ]);
+    let new = crate::train_memory(vec![
+        (
+            "service.log",
+            "INFO service started\nINFO service stopped\n",
+        ),
+        ("worker.log", "INFO worker ready\n"),
+        ("api.log", "INFO api ready\n"),
+    ]);
+    let changes: Vec<String> = old
+        .diff(&new)
+        .iter()
+        .map(|(name, change)| format!("{}: {}", name, change))
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            "api.log: added, 1 lines from 1 sources",
+            "db.log: removed, 1 lines from 1 sources",
+            "service.log: +1 lines (1 -> 2), +0 sources",
+            "worker.log: unchanged, 1 lines from 1 sources"
+        ]
+    );
+}
@@ -0,0 +1,388 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the CircleCI job content.
+//! The artifacts and the baselines are discovered with the v2 API: the baseline is the same job
+//! of the latest successful workflow on the default branch. The step outputs are only available
+//! with the v1.1 API, they are loaded in memory.
+//! The private projects require a token, set with the CIRCLECI_TOKEN environment variable.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+/// The CircleCI API url.
+const API_URL: &str = "https://circleci.com/api/";
+
+lazy_static::lazy_static! {
+    /// The CircleCI API token, set with the CIRCLECI_TOKEN environment variable.
+    static ref CIRCLECI_TOKEN: Option<String> = std::env::var("CIRCLECI_TOKEN").ok();
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    api: Url,
+    /// The project slug, e.g. `gh/org/repo`.
+    pub slug: String,
+    pub number: u64,
+    pub name: String,
+    pub workflow: String,
+    pub status: String,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.slug, self.number)
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiJob {
+    number: u64,
+    name: String,
+    status: String,
+    latest_workflow: ApiWorkflow,
+}
+
+#[derive(Deserialize)]
+struct ApiWorkflow {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ApiProject {
+    vcs_info: ApiVcsInfo,
+}
+
+#[derive(Deserialize)]
+struct ApiVcsInfo {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct ApiItems<T> {
+    items: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct ApiWorkflowRun {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ApiWorkflowJob {
+    name: String,
+    job_number: Option<u64>,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ApiArtifact {
+    path: String,
+    url: Url,
+}
+
+#[derive(Deserialize)]
+struct ApiBuild {
+    #[serde(default)]
+    steps: Vec<ApiStep>,
+}
+
+#[derive(Deserialize)]
+struct ApiStep {
+    name: String,
+    #[serde(default)]
+    actions: Vec<ApiAction>,
+}
+
+#[derive(Deserialize)]
+struct ApiAction {
+    output_url: Option<Url>,
+}
+
+#[derive(Deserialize)]
+struct ApiOutput {
+    message: String,
+}
+
+impl Job {
+    fn url(&self, path: &str) -> Result<Url> {
+        self.api.join(path).context("Can't create circleci api url")
+    }
+
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        let project: ApiProject =
+            get_json(&self.api, &self.url(&format!("v2/project/{}", self.slug))?)?;
+        let url = Url::parse_with_params(
+            self.url(&format!(
+                "v2/insights/{}/workflows/{}",
+                self.slug, self.workflow
+            ))?
+            .as_str(),
+            [("branch", project.vcs_info.default_branch.as_str())],
+        )
+        .context("Can't create query url")?;
+        tracing::info!(url = url.as_str(), "Discovering baselines for {}", self);
+        let runs: ApiItems<ApiWorkflowRun> = get_json(&self.api, &url)?;
+        for run in runs.items.iter().filter(|run| run.status == "success") {
+            let jobs: ApiItems<ApiWorkflowJob> = get_json(
+                &self.api,
+                &self.url(&format!("v2/workflow/{}/job", run.id))?,
+            )?;
+            let baseline = jobs.items.into_iter().find_map(|job| match job.job_number {
+                Some(number) if job.name == self.name && number != self.number => Some(Job {
+                    number,
+                    status: job.status,
+                    ..self.clone()
+                }),
+                _ => None,
+            });
+            if let Some(baseline) = baseline {
+                return Ok(vec![Content::CircleCi(Box::new(baseline))]);
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Couldn't find a successful {} job",
+            self.name
+        ))
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let mut sources = self.get_steps()?;
+        let artifacts: ApiItems<ApiArtifact> = get_json(
+            &self.api,
+            &self.url(&format!(
+                "v2/project/{}/{}/artifacts",
+                self.slug, self.number
+            ))?,
+        )?;
+        for artifact in artifacts.items {
+            // The relative path is the artifact path
+            let prefix = artifact
+                .url
+                .as_str()
+                .strip_suffix(artifact.path.as_str())
+                .map(|base| base.len())
+                .unwrap_or(0);
+            sources.push(Source::Remote(prefix, artifact.url));
+        }
+        Ok(sources)
+    }
+
+    /// Load the step outputs, named `steps/<step name>`.
+    fn get_steps(&self) -> Result<Vec<Source>> {
+        let build: ApiBuild = get_json(
+            &self.api,
+            &self.url(&format!(
+                "v1.1/project/{}/{}",
+                v1_slug(&self.slug),
+                self.number
+            ))?,
+        )?;
+        let mut sources = Vec::new();
+        for step in build.steps {
+            let mut data = String::new();
+            for url in step
+                .actions
+                .iter()
+                .filter_map(|action| action.output_url.as_ref())
+            {
+                let outputs: Vec<ApiOutput> = get_json(url, url)?;
+                for output in outputs {
+                    data.push_str(&output.message);
+                }
+            }
+            if !data.is_empty() {
+                sources.push(Source::Memory(
+                    format!("steps/{}", step.name),
+                    crate::memory::MemoryData(data.into()),
+                ));
+            }
+        }
+        Ok(sources)
+    }
+}
+
+/// The v1.1 API uses the full vcs name.
+fn v1_slug(slug: &str) -> String {
+    match slug.split_once('/') {
+        Some(("gh", rest)) => format!("github/{}", rest),
+        Some(("bb", rest)) => format!("bitbucket/{}", rest),
+        _ => slug.to_string(),
+    }
+}
+
+fn get_json<T: serde::de::DeserializeOwned>(api: &Url, url: &Url) -> Result<T> {
+    let reader = crate::reader::from_url(api, url)?;
+    match serde_json::from_reader(reader).context("Can't decode circleci api") {
+        Ok(x) => Ok(x),
+        Err(e) => crate::reader::drop_url(api, url).map_or_else(Err, |_| Err(e)),
+    }
+}
+
+/// Returns the project slug and the job number of a job url, such as:
+/// https://app.circleci.com/pipelines/github/org/repo/42/workflows/uuid/jobs/123
+/// https://circleci.com/gh/org/repo/123
+fn get_circleci_job(url: &Url) -> Option<(String, u64)> {
+    let vcs = |name: &str| match name {
+        "github" | "gh" => Some("gh"),
+        "bitbucket" | "bb" => Some("bb"),
+        _ => None,
+    };
+    let segments = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    match (url.host_str()?, segments.as_slice()) {
+        ("app.circleci.com", ["pipelines", kind, org, repo, _, "workflows", _, "jobs", number]) => {
+            Some((
+                format!("{}/{}/{}", vcs(kind)?, org, repo),
+                number.parse().ok()?,
+            ))
+        }
+        ("circleci.com", [kind, org, repo, number]) => Some((
+            format!("{}/{}/{}", vcs(kind)?, org, repo),
+            number.parse().ok()?,
+        )),
+        _ => None,
+    }
+}
+
+/// Add the token to the CircleCI API requests.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    let is_api = url.host_str() == Some("circleci.com") && url.path().starts_with("/api/");
+    match &*CIRCLECI_TOKEN {
+        Some(token) if is_api => request.header("Circle-Token", token),
+        _ => request,
+    }
+}
+
+fn new_job(api: Url, slug: String, number: u64) -> Result<Content> {
+    let url = api
+        .join(&format!("v2/project/{}/job/{}", slug, number))
+        .context("Can't create job url")?;
+    let job: ApiJob = get_json(&api, &url)?;
+    Ok(Content::CircleCi(Box::new(Job {
+        api,
+        slug,
+        number: job.number,
+        name: job.name,
+        workflow: job.latest_workflow.name,
+        status: job.status,
+    })))
+}
+
+impl Content {
+    pub fn from_circleci_url(url: &Url) -> Option<Result<Content>> {
+        get_circleci_job(url).map(|(slug, number)| {
+            Url::parse(API_URL)
+                .context("Can't create circleci api url")
+                .and_then(|api| new_job(api, slug, number))
+        })
+    }
+}
+
+#[test]
+fn test_circleci_url() {
+    let assert_url = |url: &str, expected: Option<(&str, u64)>| {
+        assert_eq!(
+            get_circleci_job(&Url::parse(url).unwrap()),
+            expected.map(|(slug, number)| (slug.to_string(), number))
+        )
+    };
+    assert_url(
+        "https://app.circleci.com/pipelines/github/org/repo/42/workflows/8a1b/jobs/123",
+        Some(("gh/org/repo", 123)),
+    );
+    assert_url(
+        "https://circleci.com/gh/org/repo/123",
+        Some(("gh/org/repo", 123)),
+    );
+    assert_url("https://circleci.com/docs/", None);
+    assert_url("https://app.circleci.com/pipelines/github/org/repo", None);
+    assert_eq!(v1_slug("gh/org/repo"), "github/org/repo");
+}
+
+#[test]
+fn test_circleci_api() -> Result<()> {
+    use mockito::mock;
+    let api = Url::parse(&mockito::server_url())?.join("/api/")?;
+    let job_mock = mock("GET", "/api/v2/project/gh/org/repo/job/123")
+        .with_body(
+            r#"{"number": 123, "name": "test", "status": "failed",
+                "latest_workflow": {"id": "w2", "name": "build"}}"#,
+        )
+        .create();
+    let project_mock = mock("GET", "/api/v2/project/gh/org/repo")
+        .with_body(r#"{"vcs_info": {"default_branch": "main"}}"#)
+        .create();
+    let runs_mock = mock("GET", "/api/v2/insights/gh/org/repo/workflows/build")
+        .match_query(mockito::Matcher::UrlEncoded("branch".into(), "main".into()))
+        .with_body(
+            r#"{"items": [{"id": "w1", "status": "failed"}, {"id": "w0", "status": "success"}]}"#,
+        )
+        .create();
+    let jobs_mock = mock("GET", "/api/v2/workflow/w0/job")
+        .with_body(
+            r#"{"items": [{"name": "lint", "job_number": 100, "status": "success"},
+                          {"name": "test", "job_number": 101, "status": "success"}]}"#,
+        )
+        .create();
+    let steps_mock = mock("GET", "/api/v1.1/project/github/org/repo/123")
+        .with_body(format!(
+            r#"{{"steps": [{{"name": "Run tests", "actions": [{{"output_url": "{}/output/1"}}]}}]}}"#,
+            mockito::server_url()
+        ))
+        .create();
+    let output_mock = mock("GET", "/output/1")
+        .with_body(r#"[{"message": "collected 42 items\r\n"}, {"message": "1 failed\r\n"}]"#)
+        .create();
+    let artifacts_mock = mock("GET", "/api/v2/project/gh/org/repo/123/artifacts")
+        .with_body(format!(
+            r#"{{"items": [{{"path": "logs/server.log", "url": "{}/artifacts/0/logs/server.log"}}]}}"#,
+            mockito::server_url()
+        ))
+        .create();
+
+    let content = new_job(api, "gh/org/repo".to_string(), 123)?;
+    match content.discover_baselines()?.as_slice() {
+        [Content::CircleCi(baseline)] => assert_eq!(baseline.number, 101),
+        baselines => panic!("Unexpected baselines {:?}", baselines),
+    }
+    let sources = match &content {
+        Content::CircleCi(job) => job.get_sources()?,
+        _ => panic!("Unexpected content {:?}", content),
+    };
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["steps/Run tests", "logs/server.log"]
+    );
+    for mock in &[
+        job_mock,
+        project_mock,
+        runs_mock,
+        jobs_mock,
+        steps_mock,
+        output_mock,
+        artifacts_mock,
+    ] {
+        mock.assert();
+    }
+    Ok(())
+}
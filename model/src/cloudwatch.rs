@@ -0,0 +1,324 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the AWS CloudWatch Logs content, for the inputs such as
+//! `cloudwatch:/aws/lambda/api?since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z`.
+//! The events of the log group are read with the FilterLogEvents API, optionally restricted to the
+//! `stream` names and to the `filter` pattern, as a single memory source, or one source per stream with `group=stream`.
+//! The baseline is the previous time window of the same duration.
+//! The requests are signed with the credentials of the s3 module, and AWS_ENDPOINT_URL_CLOUDWATCH_LOGS
+//! sets a custom endpoint.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+lazy_static::lazy_static! {
+    static ref ENDPOINT: Option<Url> = std::env::var("AWS_ENDPOINT_URL_CLOUDWATCH_LOGS")
+        .ok()
+        .and_then(|endpoint| Url::parse(&endpoint).ok());
+}
+
+/// The events of a log group.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogGroup {
+    pub name: String,
+    pub streams: Vec<String>,
+    /// The CloudWatch filter pattern.
+    pub filter: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub region: Option<String>,
+    /// Create one source per stream.
+    pub by_stream: bool,
+}
+
+impl std::fmt::Display for LogGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        for stream in &self.streams {
+            query.append_pair("stream", stream);
+        }
+        for (name, value) in [
+            ("filter", &self.filter),
+            ("since", &self.since),
+            ("until", &self.until),
+            ("region", &self.region),
+        ] {
+            if let Some(value) = value {
+                query.append_pair(name, value);
+            }
+        }
+        if self.by_stream {
+            query.append_pair("group", "stream");
+        }
+        let query = query.finish();
+        match query.is_empty() {
+            true => write!(f, "cloudwatch:{}", self.name),
+            false => write!(f, "cloudwatch:{}?{}", self.name, query),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FilterResponse {
+    #[serde(default)]
+    events: Vec<Event>,
+    next_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Event {
+    #[serde(default)]
+    log_stream_name: String,
+    timestamp: i64,
+    message: String,
+}
+
+/// Returns the milliseconds timestamp of a RFC3339 date.
+fn millis(date: &str) -> Result<i64> {
+    Ok(chrono::DateTime::parse_from_rfc3339(date)
+        .with_context(|| format!("Invalid date: {}", date))?
+        .timestamp_millis())
+}
+
+impl LogGroup {
+    fn region(&self) -> &str {
+        self.region
+            .as_deref()
+            .unwrap_or_else(|| crate::s3::region())
+    }
+
+    fn endpoint(&self) -> Result<Url> {
+        match &*ENDPOINT {
+            Some(endpoint) => Ok(endpoint.clone()),
+            None => Url::parse(&format!("https://logs.{}.amazonaws.com/", self.region()))
+                .context("Invalid region"),
+        }
+    }
+
+    /// The request body of a page, after the token of the previous page.
+    fn body(&self, next_token: Option<&str>) -> Result<Value> {
+        let mut body = json!({ "logGroupName": self.name, "interleaved": true });
+        if !self.streams.is_empty() {
+            body["logStreamNames"] = json!(self.streams);
+        }
+        if let Some(filter) = &self.filter {
+            body["filterPattern"] = json!(filter);
+        }
+        if let Some(since) = &self.since {
+            body["startTime"] = json!(millis(since)?);
+        }
+        if let Some(until) = &self.until {
+            body["endTime"] = json!(millis(until)?);
+        }
+        if let Some(next_token) = next_token {
+            body["nextToken"] = json!(next_token);
+        }
+        Ok(body)
+    }
+
+    fn filter_events(&self, url: &Url, body: &Value) -> Result<FilterResponse> {
+        let payload = serde_json::to_vec(body).context("Can't encode the request")?;
+        let headers = crate::s3::sign_service(
+            "logs",
+            self.region(),
+            "POST",
+            url,
+            &payload,
+            vec![
+                ("content-type", "application/x-amz-json-1.1".to_string()),
+                ("x-amz-target", "Logs_20140328.FilterLogEvents".to_string()),
+            ],
+        );
+        let _permit = crate::urls::LIMITER.acquire(url);
+        let request = headers.into_iter().fold(
+            crate::reader::CLIENT.post(url.clone()),
+            |request, (name, value)| request.header(name, value),
+        );
+        let resp = request
+            .body(payload)
+            .send()
+            .context("Can't filter log events")?;
+        let status = resp.status();
+        crate::urls::record_response(url, status.as_u16(), resp.url());
+        if !status.is_success() {
+            let failure = crate::errors::Failure::HttpStatus(url.clone(), status.as_u16());
+            return Err(anyhow::Error::new(failure).context(format!(
+                "FilterLogEvents failed: {}",
+                resp.text().unwrap_or_default().trim()
+            )));
+        }
+        serde_json::from_reader(resp).context("Can't decode the log events")
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        self.read_events(&self.endpoint()?)
+    }
+
+    fn read_events(&self, url: &Url) -> Result<Vec<Source>> {
+        let mut groups: BTreeMap<String, Vec<(i64, String)>> = BTreeMap::new();
+        let mut next_token: Option<String> = None;
+        loop {
+            let resp = self.filter_events(url, &self.body(next_token.as_deref())?)?;
+            for event in resp.events {
+                let group = match self.by_stream {
+                    true => event.log_stream_name,
+                    false => self.name.trim_start_matches('/').to_string(),
+                };
+                groups
+                    .entry(group)
+                    .or_default()
+                    .push((event.timestamp, event.message));
+            }
+            // The token is unchanged at the end of the events.
+            match resp.next_token {
+                Some(token) if next_token.as_ref() != Some(&token) => next_token = Some(token),
+                _ => break,
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(group, mut events)| {
+                events.sort_by_key(|(timestamp, _)| *timestamp);
+                let mut data = Vec::new();
+                for (_, message) in events {
+                    data.extend_from_slice(message.trim_end().as_bytes());
+                    data.push(b'\n');
+                }
+                Source::Memory(
+                    format!("cloudwatch/{}", group.replace('/', "_")),
+                    crate::memory::MemoryData(data.into()),
+                )
+            })
+            .collect())
+    }
+
+    /// The previous time window, with the same duration.
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        match crate::elasticsearch::previous_window(&self.since, &self.until) {
+            Some((since, until)) => Ok(vec![Content::CloudWatch(Box::new(LogGroup {
+                since: Some(since),
+                until: Some(until),
+                ..self.clone()
+            }))]),
+            None => Err(anyhow::anyhow!(
+                "Can't discover cloudwatch baselines without a since and until date, they need to be provided"
+            )),
+        }
+    }
+}
+
+impl Content {
+    /// Create the log group content of a `cloudwatch:` url.
+    pub fn from_cloudwatch_url(url: &Url) -> Result<Content> {
+        let name = crate::urls::decode(url.path());
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Missing log group name: {}", url));
+        }
+        let mut group = LogGroup {
+            name,
+            streams: Vec::new(),
+            filter: None,
+            since: None,
+            until: None,
+            region: None,
+            by_stream: false,
+        };
+        for (key, value) in url.query_pairs() {
+            let value = value.into_owned();
+            match key.as_ref() {
+                "stream" => group.streams.push(value),
+                "filter" => group.filter = Some(value),
+                "since" => group.since = Some(value),
+                "until" => group.until = Some(value),
+                "region" => group.region = Some(value),
+                "group" if value == "stream" => group.by_stream = true,
+                _ => return Err(anyhow::anyhow!("Unknown cloudwatch parameter: {}", key)),
+            }
+        }
+        Ok(Content::CloudWatch(Box::new(group)))
+    }
+}
+
+#[test]
+fn test_cloudwatch_url() -> Result<()> {
+    let url = Url::parse(
+        "cloudwatch:/aws/lambda/api?stream=a&filter=ERROR&since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z",
+    )?;
+    match Content::from_cloudwatch_url(&url)? {
+        Content::CloudWatch(group) => {
+            assert_eq!(group.name, "/aws/lambda/api");
+            assert_eq!(
+                group.to_string(),
+                "cloudwatch:/aws/lambda/api?stream=a&filter=ERROR&since=2022-03-15T10%3A00%3A00Z&until=2022-03-15T11%3A00%3A00Z"
+            );
+            let body = group.body(Some("token"))?;
+            assert_eq!(body["startTime"], 1647338400000i64);
+            assert_eq!(body["logStreamNames"], json!(["a"]));
+            match group.discover_baselines()?.as_slice() {
+                [Content::CloudWatch(baseline)] => {
+                    assert_eq!(baseline.since.as_deref(), Some("2022-03-15T09:00:00+00:00"));
+                }
+                baselines => panic!("Unexpected baselines {:?}", baselines),
+            }
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+    assert!(Content::from_cloudwatch_url(&Url::parse("cloudwatch:")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_cloudwatch_events() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let events_mock = mock("POST", "/")
+        .match_header("x-amz-target", "Logs_20140328.FilterLogEvents")
+        .match_body(Matcher::PartialJson(
+            json!({"logGroupName": "/aws/lambda/api"}),
+        ))
+        .with_body(
+            r#"{"events": [
+              {"logStreamName": "2022/03/15/b", "timestamp": 2, "message": "Timeout\n"},
+              {"logStreamName": "2022/03/15/a", "timestamp": 1, "message": "START RequestId"},
+              {"logStreamName": "2022/03/15/b", "timestamp": 1, "message": "Connection refused"}
+            ]}"#,
+        )
+        .create();
+    let group = LogGroup {
+        name: "/aws/lambda/api".to_string(),
+        streams: Vec::new(),
+        filter: None,
+        since: None,
+        until: None,
+        region: None,
+        by_stream: true,
+    };
+    let sources = group.read_events(&Url::parse(&mockito::server_url())?)?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["cloudwatch/2022_03_15_a", "cloudwatch/2022_03_15_b"]
+    );
+    match &sources[1] {
+        Source::Memory(_, data) => assert_eq!(data.0, "Connection refused\nTimeout\n"),
+        source => panic!("Unexpected source {:?}", source),
+    }
+    events_mock.assert();
+    Ok(())
+}
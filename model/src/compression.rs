@@ -0,0 +1,163 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module compresses the saved models, with gzip by default. The codec is detected on load,
+//! so that the models saved with any codec can be loaded.
+
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::sync::RwLock;
+
+use bincode::Options;
+
+/// The magic number of the zstd frames.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The magic number of the gzip streams.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The maximum number of bytes decoded from a model part, so that a corrupted length is refused
+/// instead of aborting the process with an allocation failure.
+const DECODE_LIMIT: u64 = 1 << 32;
+
+lazy_static::lazy_static! {
+    static ref COMPRESSION: RwLock<Compression> = RwLock::new(Compression::default());
+}
+
+/// Set the compression of the saved models.
+pub fn set_compression(compression: Compression) {
+    *COMPRESSION.write().unwrap() = compression;
+}
+
+pub(crate) fn get_compression() -> Compression {
+    *COMPRESSION.read().unwrap()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl std::str::FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            "none" => Ok(Codec::None),
+            _ => Err(anyhow::anyhow!(
+                "Unknown codec {}, expected gzip, zstd or none",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    codec: Codec,
+    level: i32,
+}
+
+impl Default for Compression {
+    /// The fast gzip compression.
+    fn default() -> Self {
+        Compression {
+            codec: Codec::Gzip,
+            level: 1,
+        }
+    }
+}
+
+impl Compression {
+    /// Use the codec with the level, or its default level.
+    pub fn new(codec: Codec, level: Option<i32>) -> Result<Compression> {
+        let (default, range) = match codec {
+            Codec::Gzip => (1, 0..=9),
+            Codec::Zstd => (
+                zstd::DEFAULT_COMPRESSION_LEVEL,
+                zstd::compression_level_range(),
+            ),
+            Codec::None => (0, 0..=0),
+        };
+        let level = level.unwrap_or(default);
+        if !range.contains(&level) {
+            return Err(anyhow::anyhow!(
+                "Invalid {:?} level {}, expected a value between {} and {}",
+                codec,
+                level,
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(Compression { codec, level })
+    }
+
+    /// Compress the data written by the serialize function.
+    pub(crate) fn encode<F>(&self, serialize: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&mut dyn Write) -> Result<()>,
+    {
+        match self.codec {
+            Codec::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(
+                    Vec::new(),
+                    flate2::Compression::new(self.level as u32),
+                );
+                serialize(&mut encoder)?;
+                encoder.finish().context("Can't compress")
+            }
+            Codec::Zstd => {
+                let mut encoder = zstd::Encoder::new(Vec::new(), self.level)?;
+                serialize(&mut encoder)?;
+                encoder.finish().context("Can't compress")
+            }
+            Codec::None => {
+                let mut data = Vec::new();
+                serialize(&mut data)?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Returns the decompressed reader of the data, using the codec magic number.
+pub(crate) fn decoder<'a>(data: &'a [u8]) -> Result<Box<dyn Read + 'a>> {
+    Ok(if data.starts_with(GZIP_MAGIC) {
+        Box::new(flate2::read::GzDecoder::new(data))
+    } else if data.starts_with(ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(data)?)
+    } else {
+        Box::new(data)
+    })
+}
+
+/// Decompress and deserialize the data written with bincode::serialize_into.
+pub(crate) fn deserialize<T: serde::de::DeserializeOwned>(data: &[u8]) -> Result<T> {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(DECODE_LIMIT)
+        .deserialize_from(decoder(data)?)
+        .map_err(|e| e.into())
+}
+
+#[test]
+fn test_compression() {
+    let payload = b"model data ".repeat(100);
+    for codec in [Codec::Gzip, Codec::Zstd, Codec::None] {
+        let compression = Compression::new(codec, None).unwrap();
+        let data = compression
+            .encode(|writer| writer.write_all(&payload).map_err(|e| e.into()))
+            .unwrap();
+        let mut decoded = Vec::new();
+        decoder(&data).unwrap().read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, payload, "{:?}", codec);
+    }
+    assert!(Compression::new(Codec::Zstd, Some(9)).is_ok());
+    assert!(Compression::new(Codec::Gzip, Some(12)).is_err());
+    assert!("brotli".parse::<Codec>().is_err());
+}
@@ -0,0 +1,234 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the container logs content, for the `docker://container-id` inputs.
+//! The logs are read through the engine API, using the DOCKER_HOST environment variable,
+//! e.g. `unix:///run/user/1000/podman/podman.sock` to use podman.
+//! The stdout and stderr streams are loaded as the `docker/stdout` and `docker/stderr` sources,
+//! so that they are compared with the same streams of the baseline container.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use url::Url;
+
+use crate::{Content, Source};
+
+/// The default engine socket.
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Container {
+    pub id: String,
+}
+
+impl std::fmt::Display for Container {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "docker://{}", self.id)
+    }
+}
+
+#[derive(Deserialize)]
+struct Inspect {
+    #[serde(rename = "Config")]
+    config: Config,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    #[serde(rename = "Tty", default)]
+    tty: bool,
+}
+
+impl Container {
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let inspect: Inspect =
+            serde_json::from_slice(&engine_get(&format!("/containers/{}/json", self.id))?)
+                .context("Can't decode container inspect")?;
+        let logs = engine_get(&format!(
+            "/containers/{}/logs?stdout=true&stderr=true",
+            self.id
+        ))?;
+        // The streams are multiplexed, unless the container uses a tty.
+        let streams = if inspect.config.tty {
+            vec![("docker/stdout", logs)]
+        } else {
+            let (stdout, stderr) = demux(&logs)?;
+            vec![("docker/stdout", stdout), ("docker/stderr", stderr)]
+        };
+        Ok(streams
+            .into_iter()
+            .map(|(name, data)| {
+                Source::Memory(name.to_string(), crate::memory::MemoryData(data.into()))
+            })
+            .collect())
+    }
+}
+
+/// Perform a GET request on the engine API.
+fn engine_get(path: &str) -> Result<Vec<u8>> {
+    let host = std::env::var("DOCKER_HOST").unwrap_or_else(|_| format!("unix://{}", DOCKER_SOCKET));
+    tracing::debug!("Requesting {}{}", host, path);
+    match host.strip_prefix("unix://") {
+        Some(socket) => unix_get(socket, path),
+        None => {
+            let url = Url::parse(&host.replacen("tcp://", "http://", 1))
+                .and_then(|url| url.join(path))
+                .context("Invalid DOCKER_HOST")?;
+            let resp = crate::reader::CLIENT
+                .get(url)
+                .send()
+                .context("Can't query the engine")?;
+            if !resp.status().is_success() {
+                return Err(anyhow::anyhow!("{}: {}", path, resp.status()));
+            }
+            Ok(resp.bytes().context("Can't read the engine")?.to_vec())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn unix_get(socket: &str, path: &str) -> Result<Vec<u8>> {
+    let mut stream = std::os::unix::net::UnixStream::connect(socket)
+        .with_context(|| format!("Can't connect to {}", socket))?;
+    // Use HTTP/1.0 so that the connection is closed after the response.
+    write!(stream, "GET {} HTTP/1.0\r\nHost: docker\r\n\r\n", path)
+        .context("Can't query the engine")?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .context("Can't read the engine")?;
+    parse_response(path, &response)
+}
+
+#[cfg(not(unix))]
+fn unix_get(_socket: &str, _path: &str) -> Result<Vec<u8>> {
+    Err(anyhow::anyhow!(
+        "Unix sockets are not supported, use a tcp DOCKER_HOST"
+    ))
+}
+
+/// Returns the body of a raw http response.
+fn parse_response(path: &str, response: &[u8]) -> Result<Vec<u8>> {
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("{}: invalid engine response", path))?;
+    let headers = String::from_utf8_lossy(&response[..header_end]).to_lowercase();
+    let body = &response[header_end + 4..];
+    let status = headers.split_whitespace().nth(1).unwrap_or("");
+    if !status.starts_with('2') {
+        return Err(anyhow::anyhow!(
+            "{}: {} {}",
+            path,
+            status,
+            String::from_utf8_lossy(body).trim()
+        ));
+    }
+    if headers.contains("transfer-encoding: chunked") {
+        dechunk(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
+
+/// Decode a chunked transfer-encoding body.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    loop {
+        let line_end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(|| anyhow::anyhow!("Invalid chunk"))?;
+        let size = std::str::from_utf8(&body[..line_end])
+            .ok()
+            .and_then(|size| usize::from_str_radix(size.split(';').next()?.trim(), 16).ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid chunk size"))?;
+        if size == 0 {
+            return Ok(result);
+        }
+        let chunk = body
+            .get(line_end + 2..line_end + 2 + size)
+            .ok_or_else(|| anyhow::anyhow!("Truncated chunk"))?;
+        result.extend_from_slice(chunk);
+        body = body.get(line_end + 4 + size..).unwrap_or(&[]);
+    }
+}
+
+/// Split the multiplexed stream into stdout and stderr. Each frame has a 8 bytes header:
+/// the stream type, 3 zero bytes and the big endian size of the payload.
+fn demux(mut data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 8 {
+            return Err(anyhow::anyhow!("Truncated stream header"));
+        }
+        let size = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let payload = data
+            .get(8..8 + size)
+            .ok_or_else(|| anyhow::anyhow!("Truncated stream frame"))?;
+        match data[0] {
+            2 => stderr.extend_from_slice(payload),
+            _ => stdout.extend_from_slice(payload),
+        }
+        data = &data[8 + size..];
+    }
+    Ok((stdout, stderr))
+}
+
+impl Content {
+    pub fn from_docker_url(url: &Url) -> Result<Content> {
+        let id = url
+            .host_str()
+            .filter(|id| !id.is_empty())
+            .ok_or_else(|| anyhow::anyhow!("Missing container id: {}", url))?;
+        Ok(Content::Docker(Box::new(Container { id: id.to_string() })))
+    }
+}
+
+#[test]
+fn test_docker_stream() {
+    let frame = |stream: u8, payload: &str| {
+        let mut frame = vec![stream, 0, 0, 0];
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(payload.as_bytes());
+        frame
+    };
+    let data = [
+        frame(1, "starting\n"),
+        frame(2, "warning: deprecated\n"),
+        frame(1, "ready\n"),
+    ]
+    .concat();
+    let (stdout, stderr) = demux(&data).unwrap();
+    assert_eq!(stdout, b"starting\nready\n");
+    assert_eq!(stderr, b"warning: deprecated\n");
+    assert!(demux(&data[..data.len() - 1]).is_err());
+
+    assert_eq!(
+        parse_response(
+            "/logs",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nlog \r\n5\r\nline\n\r\n0\r\n\r\n"
+        )
+        .unwrap(),
+        b"log line\n"
+    );
+    assert_eq!(
+        parse_response("/logs", b"HTTP/1.0 200 OK\r\n\r\nlog line\n").unwrap(),
+        b"log line\n"
+    );
+    assert!(parse_response("/logs", b"HTTP/1.0 404 Not Found\r\n\r\nno such container").is_err());
+
+    match Content::from_docker_url(&Url::parse("docker://4f66ad9a0b2e").unwrap()).unwrap() {
+        Content::Docker(container) => assert_eq!(container.to_string(), "docker://4f66ad9a0b2e"),
+        content => panic!("Unexpected content {}", content),
+    }
+}
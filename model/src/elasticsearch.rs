@@ -0,0 +1,351 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Elasticsearch and OpenSearch content, for the search urls such as
+//! `https://es.example.com:9200/logs-*/_search?q=service:nova&since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z`.
+//! The message field of the matching documents are read in the timestamp order, and they are
+//! grouped into memory sources named `elasticsearch/<index pattern>`, or by the value of the `group` field.
+//! The baseline is the previous time window of the same duration.
+//! Set ELASTICSEARCH_API_KEY to use an api key, or provide the basic auth credentials in the url.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+/// The number of documents per request.
+const PAGE_SIZE: usize = 1000;
+
+lazy_static::lazy_static! {
+    static ref API_KEY: Option<String> = std::env::var("ELASTICSEARCH_API_KEY").ok();
+}
+
+/// A search of the documents of an index pattern.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Search {
+    /// The `_search` url of the index pattern, without the query.
+    pub url: Url,
+    /// The query string filter.
+    pub query: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub message_field: String,
+    pub timestamp_field: String,
+    /// The field used to split the documents into sources.
+    pub group_field: Option<String>,
+}
+
+impl std::fmt::Display for Search {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut url = self.url.clone();
+        url.set_password(None).ok();
+        {
+            let mut query = url.query_pairs_mut();
+            for (name, value) in [
+                ("q", &self.query),
+                ("since", &self.since),
+                ("until", &self.until),
+                ("group", &self.group_field),
+            ] {
+                if let Some(value) = value {
+                    query.append_pair(name, value);
+                }
+            }
+        }
+        write!(f, "{}", url.as_str().trim_end_matches('?'))
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: Hits,
+}
+
+#[derive(Deserialize)]
+struct Hits {
+    hits: Vec<Hit>,
+}
+
+#[derive(Deserialize)]
+struct Hit {
+    #[serde(rename = "_source", default)]
+    source: Value,
+    #[serde(default)]
+    sort: Vec<Value>,
+}
+
+/// Returns the value of a dotted field, either nested or flattened.
+fn get_field<'a>(document: &'a Value, field: &str) -> Option<&'a Value> {
+    document.get(field).or_else(|| {
+        let (parent, child) = field.split_once('.')?;
+        get_field(document.get(parent)?, child)
+    })
+}
+
+fn field_string(document: &Value, field: &str) -> Option<String> {
+    match get_field(document, field)? {
+        Value::String(s) => Some(s.clone()),
+        Value::Null => None,
+        value => Some(value.to_string()),
+    }
+}
+
+impl Search {
+    /// The request body of a page, after the sort values of the previous page.
+    fn body(&self, search_after: Option<&[Value]>) -> Value {
+        let mut filter = Vec::new();
+        if self.since.is_some() || self.until.is_some() {
+            let mut range = serde_json::Map::new();
+            if let Some(since) = &self.since {
+                range.insert("gte".to_string(), json!(since));
+            }
+            if let Some(until) = &self.until {
+                range.insert("lt".to_string(), json!(until));
+            }
+            filter.push(json!({ "range": { self.timestamp_field.as_str(): range } }));
+        }
+        if let Some(query) = &self.query {
+            filter.push(json!({ "query_string": { "query": query } }));
+        }
+        let mut body = json!({
+            "size": PAGE_SIZE,
+            "query": { "bool": { "filter": filter } },
+            "sort": [{ self.timestamp_field.as_str(): "asc" }, { "_doc": "asc" }],
+        });
+        if let Some(search_after) = search_after {
+            body["search_after"] = json!(search_after);
+        }
+        body
+    }
+
+    fn search(&self, body: &Value) -> Result<SearchResponse> {
+        let _permit = crate::urls::LIMITER.acquire(&self.url);
+        let mut request = crate::reader::CLIENT
+            .post(self.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(body).context("Can't encode the search")?);
+        if let Some(api_key) = &*API_KEY {
+            request = request.header(
+                reqwest::header::AUTHORIZATION,
+                format!("ApiKey {}", api_key),
+            );
+        }
+        let resp = request.send().context("Can't search")?;
+        let status = resp.status();
+        crate::urls::record_response(&self.url, status.as_u16(), resp.url());
+        if !status.is_success() {
+            let failure = crate::errors::Failure::HttpStatus(self.url.clone(), status.as_u16());
+            return Err(anyhow::Error::new(failure).context(format!(
+                "Search failed: {}",
+                resp.text().unwrap_or_default().trim()
+            )));
+        }
+        serde_json::from_reader(resp).context("Can't decode the search response")
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let mut groups: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        let mut search_after: Option<Vec<Value>> = None;
+        loop {
+            let hits = self.search(&self.body(search_after.as_deref()))?.hits.hits;
+            for hit in &hits {
+                self.add_hit(&mut groups, hit);
+            }
+            match hits.last() {
+                Some(last) if hits.len() == PAGE_SIZE && !last.sort.is_empty() => {
+                    search_after = Some(last.sort.clone())
+                }
+                _ => break,
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(group, data)| {
+                Source::Memory(
+                    format!("elasticsearch/{}", group.replace('/', "_")),
+                    crate::memory::MemoryData(data.into()),
+                )
+            })
+            .collect())
+    }
+
+    /// The index pattern of the url, which is the same for the baselines.
+    fn index_pattern(&self) -> String {
+        self.url
+            .path_segments()
+            .and_then(|mut segments| segments.next())
+            .map(crate::urls::decode)
+            .unwrap_or_default()
+    }
+
+    fn add_hit(&self, groups: &mut BTreeMap<String, Vec<u8>>, hit: &Hit) {
+        let message = match field_string(&hit.source, &self.message_field) {
+            Some(message) => message,
+            None => return,
+        };
+        let group = self
+            .group_field
+            .as_ref()
+            .and_then(|field| field_string(&hit.source, field))
+            .unwrap_or_else(|| self.index_pattern());
+        let data = groups.entry(group).or_default();
+        if let Some(timestamp) = field_string(&hit.source, &self.timestamp_field) {
+            data.extend_from_slice(timestamp.as_bytes());
+            data.push(b' ');
+        }
+        data.extend_from_slice(message.trim_end().as_bytes());
+        data.push(b'\n');
+    }
+
+    /// The previous time window, with the same duration.
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        match previous_window(&self.since, &self.until) {
+            Some((since, until)) => Ok(vec![Content::Elasticsearch(Box::new(Search {
+                since: Some(since),
+                until: Some(until),
+                ..self.clone()
+            }))]),
+            None => Err(anyhow::anyhow!(
+                "Can't discover elasticsearch baselines without a since and until date, they need to be provided"
+            )),
+        }
+    }
+}
+
+/// Returns the time window of the same duration that ends at since, the dates are RFC3339.
+pub(crate) fn previous_window(
+    since: &Option<String>,
+    until: &Option<String>,
+) -> Option<(String, String)> {
+    let parse = |date: &Option<String>| {
+        date.as_deref()
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+    };
+    match (parse(since), parse(until)) {
+        (Some(since), Some(until)) if since < until => {
+            let baseline_since = since - (until - since);
+            Some((baseline_since.to_rfc3339(), since.to_rfc3339()))
+        }
+        _ => None,
+    }
+}
+
+impl Content {
+    /// Create the search content of a `_search` url.
+    pub fn from_elasticsearch_url(url: &Url) -> Option<Result<Content>> {
+        if !url.path().ends_with("/_search") {
+            return None;
+        }
+        let mut search_url = url.clone();
+        search_url.set_query(None);
+        let mut search = Search {
+            url: search_url,
+            query: None,
+            since: None,
+            until: None,
+            message_field: "message".to_string(),
+            timestamp_field: "@timestamp".to_string(),
+            group_field: None,
+        };
+        for (key, value) in url.query_pairs() {
+            let value = value.into_owned();
+            match key.as_ref() {
+                "q" => search.query = Some(value),
+                "since" => search.since = Some(value),
+                "until" => search.until = Some(value),
+                "message" => search.message_field = value,
+                "timestamp" => search.timestamp_field = value,
+                "group" => search.group_field = Some(value),
+                _ => {
+                    return Some(Err(anyhow::anyhow!(
+                        "Unknown elasticsearch parameter: {}",
+                        key
+                    )))
+                }
+            }
+        }
+        Some(Ok(Content::Elasticsearch(Box::new(search))))
+    }
+}
+
+#[test]
+fn test_elasticsearch_url() {
+    let url = Url::parse(
+        "https://es.example.com:9200/logs-*/_search?q=service:nova&since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z",
+    )
+    .unwrap();
+    match Content::from_elasticsearch_url(&url).unwrap().unwrap() {
+        Content::Elasticsearch(search) => {
+            assert_eq!(
+                search.url.as_str(),
+                "https://es.example.com:9200/logs-*/_search"
+            );
+            assert_eq!(search.query.as_deref(), Some("service:nova"));
+            assert_eq!(
+                search.body(None)["query"]["bool"]["filter"][0]["range"]["@timestamp"]["gte"],
+                "2022-03-15T10:00:00Z"
+            );
+            match search.discover_baselines().unwrap().as_slice() {
+                [Content::Elasticsearch(baseline)] => {
+                    assert_eq!(baseline.since.as_deref(), Some("2022-03-15T09:00:00+00:00"));
+                    assert_eq!(baseline.until.as_deref(), Some("2022-03-15T10:00:00+00:00"));
+                }
+                baselines => panic!("Unexpected baselines {:?}", baselines),
+            }
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+    assert!(
+        Content::from_elasticsearch_url(&Url::parse("https://example.com/logs").unwrap()).is_none()
+    );
+}
+
+#[test]
+fn test_elasticsearch_search() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let search_mock = mock("POST", "/logs/_search")
+        .match_body(Matcher::PartialJson(
+            json!({"query": {"bool": {"filter": [{"query_string": {"query": "level:error"}}]}}}),
+        ))
+        .with_body(
+            r#"{"hits": {"hits": [
+              {"_index": "logs", "_source": {"@timestamp": "2022-03-15T10:00:01Z", "message": "Connection refused\n", "kubernetes": {"pod": "api-1"}}, "sort": [1]},
+              {"_index": "logs", "_source": {"@timestamp": "2022-03-15T10:00:02Z", "message": "Timeout", "kubernetes": {"pod": "db-1"}}, "sort": [2]},
+              {"_index": "logs", "_source": {"@timestamp": "2022-03-15T10:00:03Z", "kubernetes": {"pod": "db-1"}}, "sort": [3]}
+            ]}}"#,
+        )
+        .create();
+    let search = Search {
+        url: Url::parse(&mockito::server_url())?.join("/logs/_search")?,
+        query: Some("level:error".to_string()),
+        since: None,
+        until: None,
+        message_field: "message".to_string(),
+        timestamp_field: "@timestamp".to_string(),
+        group_field: Some("kubernetes.pod".to_string()),
+    };
+    let sources = search.get_sources()?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["elasticsearch/api-1", "elasticsearch/db-1"]
+    );
+    match &sources[1] {
+        Source::Memory(_, data) => assert_eq!(data.0, "2022-03-15T10:00:02Z Timeout\n"),
+        source => panic!("Unexpected source {:?}", source),
+    }
+    search_mock.assert();
+    Ok(())
+}
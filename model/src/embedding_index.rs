@@ -0,0 +1,195 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a ChunkIndex implementation that compares the embeddings of the lines, computed
+//! with a small sentence embedding model, such as all-MiniLM-L6-v2 exported to ONNX. Unlike the hashed
+//! tokens, the embeddings of the error messages worded differently from the baseline lines are close.
+//! The model runs with the ONNX runtime library, loaded from the ORT_DYLIB_PATH environment variable.
+//! It needs the embedding feature.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use logreduce_index::FeaturesMatrix;
+use ort::session::Session;
+use ort::value::Tensor;
+use tokenizers::Tokenizer;
+
+/// The maximum number of tokens of a line, the following tokens are ignored.
+const MAX_TOKENS: usize = 128;
+
+/// The number of lines encoded together.
+const BATCH_SIZE: usize = 64;
+
+lazy_static::lazy_static! {
+    static ref ENCODER: RwLock<Option<Arc<Encoder>>> = RwLock::new(None);
+}
+
+/// Load the embedding model of the directory, which contains the `model.onnx` and the `tokenizer.json`.
+pub fn set_encoder(dir: &Path) -> Result<()> {
+    *ENCODER.write().unwrap() = Some(Arc::new(Encoder::load(dir)?));
+    Ok(())
+}
+
+/// A sentence embedding model.
+pub struct Encoder {
+    session: Mutex<Session>,
+    tokenizer: Tokenizer,
+}
+
+impl Encoder {
+    pub fn load(dir: &Path) -> Result<Encoder> {
+        let session = Session::builder()
+            .and_then(|builder| builder.commit_from_file(dir.join("model.onnx")))
+            .with_context(|| format!("{:?}: can't load the embedding model", dir))?;
+        let mut tokenizer = Tokenizer::from_file(dir.join("tokenizer.json"))
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("{:?}: can't load the tokenizer", dir))?;
+        tokenizer
+            .with_truncation(Some(tokenizers::TruncationParams {
+                max_length: MAX_TOKENS,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(None);
+        Ok(Encoder {
+            session: Mutex::new(session),
+            tokenizer,
+        })
+    }
+
+    /// Returns the embedding of each line, the mean of its token embeddings.
+    pub fn encode(&self, lines: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encodings = self
+            .tokenizer
+            .encode_batch(lines.to_vec(), true)
+            .map_err(anyhow::Error::msg)?;
+        let batch = encodings.len();
+        let seq = encodings.iter().map(|e| e.len()).max().unwrap_or(0);
+        // The inputs are padded with zeros, the attention mask excludes the padding.
+        let mut ids = vec![0i64; batch * seq];
+        let mut mask = vec![0i64; batch * seq];
+        let mut types = vec![0i64; batch * seq];
+        for (row, encoding) in encodings.iter().enumerate() {
+            for (pos, id) in encoding.get_ids().iter().enumerate() {
+                ids[row * seq + pos] = *id as i64;
+                mask[row * seq + pos] = encoding.get_attention_mask()[pos] as i64;
+                types[row * seq + pos] = encoding.get_type_ids()[pos] as i64;
+            }
+        }
+
+        let mut session = self.session.lock().unwrap();
+        let mut inputs = Vec::new();
+        for input in &session.inputs {
+            let data = match input.name.as_str() {
+                "input_ids" => &ids,
+                "attention_mask" => &mask,
+                "token_type_ids" => &types,
+                name => return Err(anyhow::anyhow!("Unknown model input {}", name)),
+            };
+            inputs.push((
+                input.name.clone(),
+                Tensor::from_array(([batch, seq], data.clone()))?,
+            ));
+        }
+        let outputs = session.run(inputs)?;
+        let (shape, data) = outputs[0].try_extract_tensor::<f32>()?;
+        match shape[..] {
+            // The model is already pooled.
+            [_, dim] => Ok(data.chunks(dim as usize).map(|v| v.to_vec()).collect()),
+            [_, _, dim] => {
+                let dim = dim as usize;
+                Ok((0..batch)
+                    .map(|row| {
+                        let mut embedding = vec![0.0; dim];
+                        for pos in (0..seq).filter(|pos| mask[row * seq + pos] == 1) {
+                            let token = &data[(row * seq + pos) * dim..][..dim];
+                            embedding.iter_mut().zip(token).for_each(|(e, v)| *e += v);
+                        }
+                        // The vectors are normalized by the search, the mean is not needed.
+                        embedding
+                    })
+                    .collect())
+            }
+            _ => Err(anyhow::anyhow!("Unexpected model output shape {:?}", shape)),
+        }
+    }
+}
+
+/// Returns the embeddings of the lines. When the lines can't be encoded, the error is logged and
+/// the lines get empty vectors, which are far from every line.
+fn embed(lines: &[String]) -> Vec<Vec<f32>> {
+    let encoder = match ENCODER.read().unwrap().clone() {
+        Some(encoder) => encoder,
+        None => {
+            tracing::error!("The embedding index needs an embedding model");
+            return vec![Vec::new(); lines.len()];
+        }
+    };
+    lines
+        .chunks(BATCH_SIZE)
+        .flat_map(|batch| {
+            encoder.encode(batch).unwrap_or_else(|e| {
+                tracing::error!("Can't encode the lines: {:#}", e);
+                vec![Vec::new(); batch.len()]
+            })
+        })
+        .collect()
+}
+
+/// A ChunkIndex implementation. The baselines are the normalized embeddings, one row per line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingIndex {
+    #[serde(with = "crate::quantization")]
+    baselines: Vec<FeaturesMatrix>,
+}
+
+pub fn new() -> super::ChunkIndex {
+    super::ChunkIndex::Embedding(EmbeddingIndex::from_matrices(Vec::new()))
+}
+
+impl EmbeddingIndex {
+    /// Create an index with baseline matrices, see portable.rs.
+    pub fn from_matrices(baselines: Vec<FeaturesMatrix>) -> EmbeddingIndex {
+        EmbeddingIndex { baselines }
+    }
+    pub fn add(&mut self, baselines: &[String]) {
+        let vectors = embed(baselines);
+        let dim = match self.baselines.first() {
+            Some(mat) => mat.cols(),
+            None => vectors.iter().map(|v| v.len()).max().unwrap_or(0),
+        };
+        self.baselines
+            .push(logreduce_index::vectors_mat(&vectors, dim))
+    }
+    pub fn search(&self, targets: &[String]) -> Vec<f32> {
+        logreduce_index::search_vectors_chunk(&self.baselines, &embed(targets))
+    }
+    pub fn search_groups(
+        &self,
+        targets: &[String],
+        group_of: impl Fn(usize) -> Option<usize>,
+        group_count: usize,
+    ) -> Vec<Vec<f32>> {
+        logreduce_index::search_vectors_chunk_groups(
+            &self.baselines,
+            group_of,
+            group_count,
+            &embed(targets),
+        )
+    }
+    pub fn append(&mut self, other: EmbeddingIndex) {
+        self.baselines.extend(other.baselines)
+    }
+    pub fn compact(&mut self) {
+        self.baselines = logreduce_index::compact_mats(
+            std::mem::take(&mut self.baselines),
+            super::hashing_index::COMPACT_ROWS,
+        )
+    }
+    pub fn matrices(&self) -> &[FeaturesMatrix] {
+        &self.baselines
+    }
+}
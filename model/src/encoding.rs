@@ -0,0 +1,162 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the detection of the text encoding, to read the logs of the Windows agents.
+//! The beginning of the content is inspected for a byte order mark or the zero bytes of the UTF-16 text,
+//! which is decoded lossily to UTF-8 before the lines are split. The other content is read per line:
+//! the valid UTF-8 lines are read as is, and only the invalid lines are decoded as latin-1, so that a
+//! stray byte does not change the decoding of the whole content.
+//! The `journalctl -o export` content is converted to log lines, see journal::ExportReader.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+use std::io::{BufRead, BufReader, Chain, Cursor, Read};
+
+use crate::journal::ExportReader;
+
+/// The size of the sample used to detect the encoding.
+const SAMPLE_SIZE: usize = 4096;
+
+/// Returns the UTF-16 encoding of the sample, or None for the UTF-8 and latin-1 content.
+pub fn detect(sample: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return Some(encoding);
+    }
+    // The UTF-16 text has a zero byte for each ascii character.
+    let pairs = sample.len() / 2;
+    if pairs > 0 {
+        let zeros = |offset: usize| {
+            sample
+                .chunks_exact(2)
+                .filter(|pair| pair[offset] == 0)
+                .count()
+        };
+        let (even, odd) = (zeros(0), zeros(1));
+        if odd * 2 > pairs && even * 8 < pairs {
+            return Some(UTF_16LE);
+        } else if even * 2 > pairs && odd * 8 < pairs {
+            return Some(UTF_16BE);
+        }
+    }
+    None
+}
+
+/// A reader that keeps the valid UTF-8 lines, and decodes the other lines as latin-1.
+pub struct LineDecoder<R: Read> {
+    reader: BufReader<R>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> LineDecoder<R> {
+    fn new(reader: R) -> LineDecoder<R> {
+        LineDecoder {
+            reader: BufReader::new(reader),
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for LineDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+            if std::str::from_utf8(&self.line).is_err() {
+                // The latin-1 text is a subset of windows-1252.
+                let (text, _) = WINDOWS_1252.decode_without_bom_handling(&self.line);
+                self.line = text.into_owned().into_bytes();
+            }
+        }
+        let size = buf.len().min(self.line.len() - self.pos);
+        buf[..size].copy_from_slice(&self.line[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+type Sampled<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// A reader that converts the content to UTF-8, the encoding is detected on the first read.
+pub enum Decoder<R: Read> {
+    Pending(Option<R>),
+    Utf8(Box<LineDecoder<Sampled<R>>>),
+    Decoded(Box<DecodeReaderBytes<Sampled<R>, Vec<u8>>>),
+    Journal(Box<ExportReader<BufReader<Sampled<R>>>>),
+}
+
+pub fn decode<R: Read>(reader: R) -> Decoder<R> {
+    Decoder::Pending(Some(reader))
+}
+
+impl<R: Read> Decoder<R> {
+    fn start(mut reader: R) -> std::io::Result<Decoder<R>> {
+        let mut sample = Vec::with_capacity(SAMPLE_SIZE);
+        (&mut reader)
+            .take(SAMPLE_SIZE as u64)
+            .read_to_end(&mut sample)?;
+        let encoding = detect(&sample);
+        let is_export = crate::journal::is_export(&sample);
+        let reader = Cursor::new(sample).chain(reader);
+        if is_export {
+            tracing::debug!("Reading journal export content");
+            return Ok(Decoder::Journal(Box::new(ExportReader::new(
+                BufReader::new(reader),
+            ))));
+        }
+        Ok(match encoding {
+            Some(encoding) => {
+                tracing::debug!("Decoding {} content", encoding.name());
+                Decoder::Decoded(Box::new(
+                    DecodeReaderBytesBuilder::new()
+                        .encoding(Some(encoding))
+                        .build(reader),
+                ))
+            }
+            None => Decoder::Utf8(Box::new(LineDecoder::new(reader))),
+        })
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Decoder::Pending(reader) => {
+                let reader = reader.take().expect("Pending reader");
+                *self = Decoder::start(reader)?;
+                self.read(buf)
+            }
+            Decoder::Utf8(reader) => reader.read(buf),
+            Decoder::Decoded(reader) => reader.read(buf),
+            Decoder::Journal(reader) => reader.read(buf),
+        }
+    }
+}
+
+#[test]
+fn test_decode() {
+    let utf16: Vec<u8> = "first line\r\nsecond line é\r\n"
+        .encode_utf16()
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    let mut with_bom = vec![0xff, 0xfe];
+    with_bom.extend_from_slice(&utf16);
+    for (data, expected) in [
+        (utf16, "first line\r\nsecond line é\r\n"),
+        (with_bom, "first line\r\nsecond line é\r\n"),
+        (b"caf\xe9 ouvert\n".to_vec(), "café ouvert\n"),
+        ("déjà vu\n".as_bytes().to_vec(), "déjà vu\n"),
+        (
+            b"caf\xe9 ouvert\nd\xc3\xa9j\xc3\xa0 vu".to_vec(),
+            "café ouvert\ndéjà vu",
+        ),
+    ] {
+        let mut content = String::new();
+        decode(&data[..]).read_to_string(&mut content).unwrap();
+        assert_eq!(content, expected);
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the known failures, attached to the error chains as a context so that
+//! the user interface can suggest a remediation.
+
+use std::path::PathBuf;
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Failure {
+    /// The baselines of the target can't be discovered.
+    MissingBaselines,
+    /// The url returned a client error status.
+    HttpStatus(Url, u16),
+    /// The model file can't be decoded, for example when it was saved by another version.
+    IncompatibleModel(PathBuf),
+    /// The model checksum or signature doesn't match.
+    UntrustedModel(PathBuf),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::MissingBaselines => write!(f, "Can't discover the baselines"),
+            Failure::HttpStatus(url, status) => write!(f, "{}: HTTP {}", url, status),
+            Failure::IncompatibleModel(path) => write!(f, "Can't load model {:?}", path),
+            Failure::UntrustedModel(path) => write!(f, "Can't trust model {:?}", path),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Returns the known failure of an error chain.
+pub fn find_failure(err: &anyhow::Error) -> Option<&Failure> {
+    // The anyhow downcast looks through the contexts.
+    err.downcast_ref::<Failure>()
+}
+
+#[test]
+fn test_find_failure() {
+    use anyhow::Context;
+    let err = Err::<(), _>(anyhow::anyhow!("Couldn't find a successful build"))
+        .context(Failure::MissingBaselines)
+        .context("Can't process the target")
+        .unwrap_err();
+    assert_eq!(find_failure(&err), Some(&Failure::MissingBaselines));
+    assert_eq!(find_failure(&anyhow::anyhow!("Empty sources")), None);
+
+    let url = Url::parse("https://example.com/logs/job-output.txt").unwrap();
+    let err: anyhow::Error = Failure::HttpStatus(url.clone(), 404).into();
+    assert_eq!(find_failure(&err), Some(&Failure::HttpStatus(url, 404)));
+    assert_eq!(
+        err.to_string(),
+        "https://example.com/logs/job-output.txt: HTTP 404"
+    );
+}
@@ -0,0 +1,169 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module lets the other crates provide their own ChunkIndex implementation, without changing
+//! this crate: the index implements the ExternalIndex trait, and its kind is registered with
+//! `register_index`, so that the saved models can be loaded. The external indexes are saved as their
+//! kind and the bytes returned by `ExternalIndex::save`.
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::ChunkIndex;
+
+/// A ChunkIndex implementation provided by another crate.
+pub trait ExternalIndex: Any + Send + Sync + std::fmt::Debug {
+    /// The name of the implementation, given to `register_index`.
+    fn kind(&self) -> &'static str;
+
+    /// The tokens of the line.
+    fn tokenize(&self, line: &str) -> String {
+        crate::hashing_index::tokenize(line)
+    }
+
+    /// Index a chunk of baseline lines.
+    fn add(&mut self, baselines: &[String]);
+
+    /// The distance of each target to its nearest baseline line, between 0.0 and 1.0.
+    fn search(&self, targets: &[String]) -> Vec<f32>;
+
+    /// The distances of the targets to each group of baseline rows, see the HashingIndex. The
+    /// default is None, and the targets are compared to every source.
+    fn search_groups(
+        &self,
+        _targets: &[String],
+        _group_of: &dyn Fn(usize) -> Option<usize>,
+        _group_count: usize,
+    ) -> Option<Vec<Vec<f32>>> {
+        None
+    }
+
+    /// Append the baselines of another index of the same kind, see `downcast`.
+    fn append(&mut self, other: Box<dyn ExternalIndex>);
+
+    /// Reduce the memory of the index once the training is done.
+    fn compact(&mut self) {}
+
+    /// The bytes of the saved model, given back to the loader of `register_index`.
+    fn save(&self) -> Result<Vec<u8>>;
+}
+
+/// Returns the index when it is of the type T, to implement ExternalIndex::append.
+pub fn downcast<T: ExternalIndex>(index: Box<dyn ExternalIndex>) -> Option<T> {
+    let index: Box<dyn Any> = index;
+    index.downcast().ok().map(|index| *index)
+}
+
+/// Load the bytes returned by ExternalIndex::save.
+pub type Loader = fn(&[u8]) -> Result<Box<dyn ExternalIndex>>;
+
+struct Registered {
+    new: fn() -> ChunkIndex,
+    load: Loader,
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<&'static str, Registered>> = RwLock::new(HashMap::new());
+}
+
+/// Register an index kind, with the constructor of an empty index and the loader of the saved ones.
+pub fn register_index(kind: &'static str, new: fn() -> ChunkIndex, load: Loader) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .insert(kind, Registered { new, load });
+}
+
+/// Returns the constructor of the registered kind.
+pub(crate) fn factory(kind: &str) -> Option<fn() -> ChunkIndex> {
+    REGISTRY.read().unwrap().get(kind).map(|r| r.new)
+}
+
+fn load(kind: &str, data: &[u8]) -> Result<Box<dyn ExternalIndex>> {
+    let load = REGISTRY
+        .read()
+        .unwrap()
+        .get(kind)
+        .map(|r| r.load)
+        .ok_or_else(|| anyhow::anyhow!("Unknown index kind {}, it is not registered", kind))?;
+    load(data)
+}
+
+/// The serde functions of the ChunkIndex::External variant.
+#[allow(clippy::borrowed_box)]
+pub(crate) fn serialize<S: Serializer>(
+    index: &Box<dyn ExternalIndex>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let data = index.save().map_err(serde::ser::Error::custom)?;
+    (index.kind(), data).serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn ExternalIndex>, D::Error> {
+    let (kind, data) = <(String, Vec<u8>)>::deserialize(deserializer)?;
+    load(&kind, &data).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Content, Model, OutputMode};
+
+    /// An index of the exact lines.
+    #[derive(Debug, Default, Serialize, Deserialize)]
+    struct ExactIndex {
+        lines: std::collections::HashSet<String>,
+    }
+
+    impl ExternalIndex for ExactIndex {
+        fn kind(&self) -> &'static str {
+            "exact"
+        }
+        fn add(&mut self, baselines: &[String]) {
+            self.lines.extend(baselines.iter().cloned())
+        }
+        fn search(&self, targets: &[String]) -> Vec<f32> {
+            targets
+                .iter()
+                .map(|line| if self.lines.contains(line) { 0.0 } else { 1.0 })
+                .collect()
+        }
+        fn append(&mut self, other: Box<dyn ExternalIndex>) {
+            if let Some(other) = downcast::<ExactIndex>(other) {
+                self.lines.extend(other.lines)
+            }
+        }
+        fn save(&self) -> Result<Vec<u8>> {
+            Ok(bincode::serialize(self)?)
+        }
+    }
+
+    fn new() -> ChunkIndex {
+        ChunkIndex::External(Box::new(ExactIndex::default()))
+    }
+
+    fn load(data: &[u8]) -> Result<Box<dyn ExternalIndex>> {
+        Ok(Box::new(bincode::deserialize::<ExactIndex>(data)?))
+    }
+
+    #[test]
+    fn test_external_index() {
+        register_index("exact", new, load);
+        let model = crate::train_memory_with(vec![("service.log", "service started\n")], new);
+        let mut data = Vec::new();
+        model.save_writer(&mut data).unwrap();
+        let path = std::path::Path::new("model.bin");
+        let model = Model::load_reader(&data[..], path).unwrap();
+
+        let target =
+            Content::from_memory(vec![("service.log", "service started\nservice crashed\n")]);
+        let report = model.report(OutputMode::Quiet, target).unwrap();
+        assert_eq!(report.total_anomaly_count, 1);
+        assert!(model.export_json(Vec::new()).is_err());
+    }
+}
@@ -38,6 +38,11 @@ impl Source {
         crate::reader::from_path(path).context("Failed to open file")
     }
 
+    pub fn stdin_open() -> Result<crate::reader::DecompressReader> {
+        tracing::debug!("Reading stdin");
+        crate::reader::from_stdin().context("Failed to read stdin")
+    }
+
     // A file source only has one source
     pub fn file_iter(&self) -> impl Iterator<Item = Result<Source>> {
         std::iter::once(Ok(self.clone()))
@@ -136,6 +141,9 @@ impl IndexName {
                 )
                 .trim_matches(|c| matches!(c, '/' | '.' | '_' | '-'))
                 .trim_end_matches(".gz")
+                .trim_end_matches(".zst")
+                .trim_end_matches(".xz")
+                .trim_end_matches(".bz2")
                 .to_string()
         };
         IndexName(model_name)
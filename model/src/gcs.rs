@@ -0,0 +1,214 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Google Cloud Storage content, for the `gs://bucket/prefix` inputs.
+//! The objects are listed with the json api, anonymously for the public CI buckets.
+//! Set GOOGLE_OAUTH_ACCESS_TOKEN to read a private bucket.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{Content, Source};
+
+/// The public GCS endpoint, used for the json api and the objects download.
+pub(crate) const GCS_URL: &str = "https://storage.googleapis.com/";
+
+lazy_static::lazy_static! {
+    static ref TOKEN: Option<String> = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN").ok();
+}
+
+/// A bucket prefix.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Prefix {
+    /// The storage url.
+    storage: Url,
+    pub bucket: String,
+    pub prefix: String,
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gs://{}/{}", self.bucket, self.prefix)
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ApiObject {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ApiObjects {
+    #[serde(default)]
+    pub items: Vec<ApiObject>,
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+/// The url of an object of the bucket, the object name is percent-encoded.
+pub(crate) fn object_url(storage: &Url, bucket: &str, name: &str) -> Result<Url> {
+    storage
+        .join(&format!("{}/{}", bucket, crate::s3::encode(name, false)))
+        .context("Can't create object url")
+}
+
+/// List the objects, or the directories when the delimiter is set, handling the pagination.
+pub(crate) fn list(
+    storage: &Url,
+    bucket: &str,
+    prefix: &str,
+    delimiter: Option<&str>,
+) -> Result<ApiObjects> {
+    let api = storage
+        .join(&format!("storage/v1/b/{}/o", bucket))
+        .context("Can't create gcs api url")?;
+    let mut result = ApiObjects {
+        items: Vec::new(),
+        prefixes: Vec::new(),
+        next_page_token: None,
+    };
+    loop {
+        let mut url = api.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("prefix", prefix);
+            if let Some(delimiter) = delimiter {
+                query.append_pair("delimiter", delimiter);
+            }
+            if let Some(token) = &result.next_page_token {
+                query.append_pair("pageToken", token);
+            }
+        }
+        let mut page: ApiObjects = get_json(storage, &url)?;
+        result.items.append(&mut page.items);
+        result.prefixes.append(&mut page.prefixes);
+        match page.next_page_token {
+            Some(token) => result.next_page_token = Some(token),
+            None => return Ok(result),
+        }
+    }
+}
+
+pub(crate) fn get_json<T: serde::de::DeserializeOwned>(base: &Url, url: &Url) -> Result<T> {
+    let reader = crate::reader::from_url(base, url)?;
+    match serde_json::from_reader(reader).context("Can't decode gcs api") {
+        Ok(x) => Ok(x),
+        Err(e) => crate::reader::drop_url(base, url).map_or_else(Err, |_| Err(e)),
+    }
+}
+
+impl Prefix {
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        // The relative paths start after the last directory of the prefix.
+        let dir = match self.prefix.rfind('/') {
+            Some(pos) => &self.prefix[..pos + 1],
+            None => "",
+        };
+        let prefix = object_url(&self.storage, &self.bucket, dir)?.as_str().len();
+        list(&self.storage, &self.bucket, &self.prefix, None)?
+            .items
+            .iter()
+            .filter(|object| !object.name.ends_with('/'))
+            .map(|object| {
+                Ok(Source::Remote(
+                    prefix,
+                    object_url(&self.storage, &self.bucket, &object.name)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Add the access token to the storage requests.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    match &*TOKEN {
+        Some(token) if url.host_str() == Some("storage.googleapis.com") => {
+            request.bearer_auth(token)
+        }
+        _ => request,
+    }
+}
+
+impl Content {
+    pub fn from_gcs_url(url: &Url) -> Result<Content> {
+        let bucket = url
+            .host_str()
+            .ok_or_else(|| anyhow::anyhow!("Missing bucket name: {}", url))?;
+        let storage = Url::parse(GCS_URL).context("Can't create gcs url")?;
+        let prefix = crate::urls::decode(url.path().trim_start_matches('/'));
+        Ok(Content::Gcs(Box::new(Prefix {
+            storage,
+            bucket: bucket.to_string(),
+            prefix,
+        })))
+    }
+}
+
+#[test]
+fn test_gcs_prefix() -> Result<()> {
+    use mockito::{mock, Matcher};
+    match Content::from_gcs_url(&Url::parse("gs://opendev-logs/periodic/42/a%20b")?)? {
+        Content::Gcs(prefix) => {
+            assert_eq!(prefix.bucket, "opendev-logs");
+            assert_eq!(prefix.prefix, "periodic/42/a b");
+            assert_eq!(prefix.to_string(), "gs://opendev-logs/periodic/42/a b");
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+
+    let storage = Url::parse(&mockito::server_url())?.join("/")?;
+    let objects_mock = mock("GET", "/storage/v1/b/logs/o")
+        .match_query(Matcher::Exact("prefix=builds%2F42%2F".into()))
+        .with_body(
+            r#"{"items": [{"name": "builds/42/job-output.txt.gz"}, {"name": "builds/42/logs/"}]}"#,
+        )
+        .create();
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    std::io::Write::write_all(&mut gz, b"log line\n")?;
+    let log_mock = mock("GET", "/logs/builds/42/job-output.txt.gz")
+        .with_header("content-type", "application/gzip")
+        .with_body(gz.finish()?)
+        .create();
+
+    let prefix = Prefix {
+        storage,
+        bucket: "logs".to_string(),
+        prefix: "builds/42/".to_string(),
+    };
+    let sources = prefix.get_sources()?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["job-output.txt.gz"]
+    );
+    let mut content = String::new();
+    match &sources[0] {
+        Source::Remote(prefix, url) => {
+            std::io::Read::read_to_string(&mut Source::url_open(*prefix, url)?, &mut content)?;
+        }
+        source => panic!("Unexpected source {:?}", source),
+    }
+    assert_eq!(content, "log line\n");
+    assert_eq!(
+        object_url(&prefix.storage, "logs", "builds/42/a b?#1.txt")?.path(),
+        "/logs/builds/42/a%20b%3F%231.txt"
+    );
+    objects_mock.assert();
+    log_mock.assert();
+    Ok(())
+}
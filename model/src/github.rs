@@ -0,0 +1,305 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the GitHub Actions workflow run content.
+//! The job logs are discovered with the GitHub API, and the baselines are the previous
+//! successful runs of the same workflow on the default branch.
+//! The logs download requires a token, set with the GITHUB_TOKEN environment variable.
+//! The GitHub Enterprise server is set with the GITHUB_URL environment variable.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+/// The public GitHub API host, the enterprise servers use the `/api/v3/` path.
+const API_HOST: &str = "api.github.com";
+
+lazy_static::lazy_static! {
+    /// The GitHub API token, set with the GITHUB_TOKEN environment variable.
+    static ref GITHUB_TOKEN: Option<String> = std::env::var("GITHUB_TOKEN").ok();
+
+    /// The GitHub Enterprise host, set with the GITHUB_URL environment variable.
+    static ref GITHUB_HOST: Option<String> = std::env::var("GITHUB_URL")
+        .ok()
+        .and_then(|url| Url::parse(&url).ok())
+        .and_then(|url| url.host_str().map(str::to_string));
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    /// The repository api url.
+    api: Url,
+    pub id: u64,
+    pub workflow_id: u64,
+    pub name: String,
+    pub branch: String,
+    pub conclusion: Option<String>,
+}
+
+impl std::fmt::Display for Run {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}actions/runs/{}", self.api.as_str(), self.id)
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiRepo {
+    default_branch: String,
+}
+
+#[derive(Deserialize)]
+struct ApiRun {
+    id: u64,
+    workflow_id: u64,
+    name: Option<String>,
+    head_branch: Option<String>,
+    conclusion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiRuns {
+    workflow_runs: Vec<ApiRun>,
+}
+
+#[derive(Deserialize)]
+struct ApiJob {
+    id: u64,
+    name: String,
+    conclusion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ApiJobs {
+    jobs: Vec<ApiJob>,
+}
+
+impl Run {
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        let repo: ApiRepo = get_json(&self.api, &self.api)?;
+        let url = Url::parse_with_params(
+            self.api
+                .join(&format!("actions/workflows/{}/runs", self.workflow_id))
+                .context("Can't create runs url")?
+                .as_str(),
+            [
+                ("branch", repo.default_branch.as_str()),
+                ("status", "success"),
+                ("per_page", "10"),
+            ],
+        )
+        .context("Can't create query url")?;
+        tracing::info!(url = url.as_str(), "Discovering baselines for {}", self);
+        let runs: ApiRuns = get_json(&self.api, &url)?;
+        Ok(runs
+            .workflow_runs
+            .into_iter()
+            .filter(|run| run.id != self.id)
+            // Keep the most recent run
+            .take(1)
+            .map(|run| new_content(self.api.clone(), run))
+            .collect())
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let url = self
+            .api
+            .join(&format!("actions/runs/{}/jobs?per_page=100", self.id))
+            .context("Can't create jobs url")?;
+        let jobs: ApiJobs = get_json(&self.api, &url)?;
+        jobs.jobs
+            .iter()
+            // The skipped and the running jobs don't have logs
+            .filter(|job| matches!(job.conclusion.as_deref(), Some(c) if c != "skipped"))
+            .map(|job| job_source(&self.api, job))
+            .collect()
+    }
+}
+
+/// The job logs source, the job name is set as the url fragment to be used as the relative path,
+/// so that the jobs are grouped by name.
+fn job_source(api: &Url, job: &ApiJob) -> Result<Source> {
+    let mut url = api
+        .join(&format!("actions/jobs/{}/logs", job.id))
+        .context("Can't create logs url")?;
+    let prefix = url.as_str().len() + 1;
+    url.set_fragment(Some(&job.name));
+    Ok(Source::Remote(prefix, url))
+}
+
+fn new_content(api: Url, run: ApiRun) -> Content {
+    Content::GithubActions(Box::new(Run {
+        api,
+        id: run.id,
+        workflow_id: run.workflow_id,
+        name: run.name.unwrap_or_default(),
+        branch: run.head_branch.unwrap_or_default(),
+        conclusion: run.conclusion,
+    }))
+}
+
+fn get_json<T: serde::de::DeserializeOwned>(api: &Url, url: &Url) -> Result<T> {
+    let reader = crate::reader::from_url(api, url)?;
+    match serde_json::from_reader(reader).context("Can't decode github api") {
+        Ok(x) => Ok(x),
+        Err(e) => crate::reader::drop_url(api, url).map_or_else(Err, |_| Err(e)),
+    }
+}
+
+/// Returns the repository api url and the run id of a workflow run url, such as:
+/// https://github.com/owner/repo/actions/runs/42
+fn get_github_api_url(url: &Url) -> Option<Result<(Url, u64)>> {
+    api_url(url, GITHUB_HOST.as_deref())
+}
+
+/// Like get_github_api_url, with the enterprise host.
+fn api_url(url: &Url, enterprise: Option<&str>) -> Option<Result<(Url, u64)>> {
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    match segments.as_slice() {
+        [owner, repo, "actions", "runs", run_id, ..] => {
+            let run_id = run_id.parse().ok()?;
+            let api = match url.host_str()? {
+                "github.com" => format!("https://{}/repos/{}/{}/", API_HOST, owner, repo),
+                host if Some(host) == enterprise => {
+                    format!("https://{}/api/v3/repos/{}/{}/", host, owner, repo)
+                }
+                _ => return None,
+            };
+            Some(
+                Url::parse(&api)
+                    .context("Can't create github api url")
+                    .map(|api| (api, run_id)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Add the token to the GitHub API requests.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    match &*GITHUB_TOKEN {
+        Some(token) if is_api(url, GITHUB_HOST.as_deref()) => request.bearer_auth(token),
+        _ => request,
+    }
+}
+
+/// Check if the url is on the public api host, or on the api path of the enterprise host.
+fn is_api(url: &Url, enterprise: Option<&str>) -> bool {
+    match url.host_str() {
+        Some(API_HOST) => true,
+        Some(host) => Some(host) == enterprise && url.path().starts_with("/api/v3/"),
+        None => false,
+    }
+}
+
+impl Content {
+    pub fn from_github_url(url: &Url) -> Option<Result<Content>> {
+        get_github_api_url(url).map(|res| {
+            res.and_then(|(api, run_id)| {
+                let url = api
+                    .join(&format!("actions/runs/{}", run_id))
+                    .context("Can't create run url")?;
+                get_json(&api, &url).map(|run| new_content(api, run))
+            })
+        })
+    }
+}
+
+#[test]
+fn test_github_url() {
+    let enterprise = Some("github.example.com");
+    let assert_url = |full, api, run_id| {
+        let url = Url::parse(full).unwrap();
+        let result = api_url(&url, enterprise).unwrap().unwrap();
+        assert_eq!(result, (Url::parse(api).unwrap(), run_id));
+    };
+    assert_url(
+        "https://github.com/logreduce/logreduce-rust/actions/runs/2066528420",
+        "https://api.github.com/repos/logreduce/logreduce-rust/",
+        2066528420,
+    );
+    assert_url(
+        "https://github.example.com/org/project/actions/runs/42/attempts/2",
+        "https://github.example.com/api/v3/repos/org/project/",
+        42,
+    );
+    assert!(api_url(
+        &Url::parse("https://github.com/org/project").unwrap(),
+        enterprise
+    )
+    .is_none());
+    let other = Url::parse("https://evil.example.com/org/project/actions/runs/42").unwrap();
+    assert!(api_url(&other, enterprise).is_none());
+
+    let is_api_url = |url| is_api(&Url::parse(url).unwrap(), enterprise);
+    assert!(is_api_url("https://api.github.com/repos/org/project/"));
+    assert!(is_api_url(
+        "https://github.example.com/api/v3/repos/org/project/"
+    ));
+    assert!(!is_api_url(
+        "https://evil.example.com/api/v3/repos/org/project/"
+    ));
+    assert!(!is_api_url("https://github.example.com/org/project/"));
+
+    let api = Url::parse("https://api.github.com/repos/org/project/").unwrap();
+    let source = job_source(
+        &api,
+        &ApiJob {
+            id: 42,
+            name: "build".to_string(),
+            conclusion: Some("failure".to_string()),
+        },
+    )
+    .unwrap();
+    assert_eq!(source.get_relative(), "build");
+    assert_eq!(crate::IndexName::from_source(&source).as_str(), "build");
+}
+
+#[test]
+fn test_github_api() -> Result<()> {
+    use mockito::mock;
+    let api = Url::parse(&mockito::server_url())?.join("/repos/org/project/")?;
+    let repo_mock = mock("GET", "/repos/org/project/")
+        .with_body(r#"{"default_branch": "main"}"#)
+        .create();
+    let runs_mock = mock("GET", "/repos/org/project/actions/workflows/7/runs")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "branch".to_string(),
+            "main".to_string(),
+        ))
+        .with_body(
+            r#"{"workflow_runs": [
+              {"id": 43, "workflow_id": 7, "name": "CI", "head_branch": "main", "conclusion": "success"},
+              {"id": 41, "workflow_id": 7, "name": "CI", "head_branch": "main", "conclusion": "success"}
+            ]}"#,
+        )
+        .create();
+
+    let run = Run {
+        api: api.clone(),
+        id: 43,
+        workflow_id: 7,
+        name: "CI".to_string(),
+        branch: "fix".to_string(),
+        conclusion: Some("failure".to_string()),
+    };
+    let baselines = run.discover_baselines()?;
+    match baselines.as_slice() {
+        [Content::GithubActions(baseline)] => assert_eq!(baseline.id, 41),
+        _ => panic!("Unexpected baselines {:?}", baselines),
+    }
+    repo_mock.assert();
+    runs_mock.assert();
+    Ok(())
+}
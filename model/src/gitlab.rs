@@ -0,0 +1,288 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the GitLab CI pipeline content.
+//! The job traces are discovered with the GitLab API, and the baselines are the latest
+//! successful pipelines of the same ref. A job url only inspects the jobs with the same name.
+//! The private projects require a token, set with the GITLAB_TOKEN environment variable. The token is
+//! only sent to gitlab.com, or to the self-managed server set with the GITLAB_URL environment variable.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+lazy_static::lazy_static! {
+    /// The GitLab API token, set with the GITLAB_TOKEN environment variable.
+    static ref GITLAB_TOKEN: Option<String> = std::env::var("GITLAB_TOKEN").ok();
+
+    /// The self-managed GitLab host, set with the GITLAB_URL environment variable.
+    static ref GITLAB_HOST: Option<String> = std::env::var("GITLAB_URL")
+        .ok()
+        .and_then(|url| Url::parse(&url).ok())
+        .and_then(|url| url.host_str().map(str::to_string));
+}
+
+/// The public GitLab host.
+const GITLAB_COM: &str = "gitlab.com";
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pipeline {
+    /// The project api url.
+    api: Url,
+    pub id: u64,
+    pub git_ref: String,
+    pub status: String,
+    /// Only inspect the jobs with this name.
+    pub job_name: Option<String>,
+}
+
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}pipelines/{}", self.api.as_str(), self.id)?;
+        if let Some(job_name) = &self.job_name {
+            write!(f, " ({})", job_name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiPipeline {
+    id: u64,
+    #[serde(rename = "ref")]
+    git_ref: String,
+    status: String,
+}
+
+#[derive(Deserialize)]
+struct ApiJobPipeline {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct ApiJob {
+    id: u64,
+    name: String,
+    status: String,
+    pipeline: ApiJobPipeline,
+}
+
+impl Pipeline {
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        let url = Url::parse_with_params(
+            self.api
+                .join("pipelines")
+                .context("Can't create pipelines url")?
+                .as_str(),
+            [
+                ("ref", self.git_ref.as_str()),
+                ("status", "success"),
+                ("per_page", "10"),
+            ],
+        )
+        .context("Can't create query url")?;
+        tracing::info!(url = url.as_str(), "Discovering baselines for {}", self);
+        let pipelines: Vec<ApiPipeline> = get_json(&self.api, &url)?;
+        Ok(pipelines
+            .into_iter()
+            .filter(|pipeline| pipeline.id != self.id)
+            // Keep the latest pipeline
+            .take(1)
+            .map(|pipeline| new_content(self.api.clone(), pipeline, self.job_name.clone()))
+            .collect())
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let url = self
+            .api
+            .join(&format!("pipelines/{}/jobs?per_page=100", self.id))
+            .context("Can't create jobs url")?;
+        let jobs: Vec<ApiJob> = get_json(&self.api, &url)?;
+        jobs.iter()
+            // The skipped and the pending jobs don't have traces
+            .filter(|job| matches!(job.status.as_str(), "success" | "failed" | "canceled"))
+            .filter(|job| match &self.job_name {
+                Some(name) => &job.name == name,
+                None => true,
+            })
+            .map(|job| job_source(&self.api, job))
+            .collect()
+    }
+}
+
+/// The job trace source, the job name is set as the url fragment to be used as the relative path,
+/// so that the jobs are grouped by name.
+fn job_source(api: &Url, job: &ApiJob) -> Result<Source> {
+    let mut url = api
+        .join(&format!("jobs/{}/trace", job.id))
+        .context("Can't create trace url")?;
+    let prefix = url.as_str().len() + 1;
+    url.set_fragment(Some(&job.name));
+    Ok(Source::Remote(prefix, url))
+}
+
+fn new_content(api: Url, pipeline: ApiPipeline, job_name: Option<String>) -> Content {
+    Content::GitlabPipeline(Box::new(Pipeline {
+        api,
+        id: pipeline.id,
+        git_ref: pipeline.git_ref,
+        status: pipeline.status,
+        job_name,
+    }))
+}
+
+fn get_json<T: serde::de::DeserializeOwned>(api: &Url, url: &Url) -> Result<T> {
+    let reader = crate::reader::from_url(api, url)?;
+    match serde_json::from_reader(reader).context("Can't decode gitlab api") {
+        Ok(x) => Ok(x),
+        Err(e) => crate::reader::drop_url(api, url).map_or_else(Err, |_| Err(e)),
+    }
+}
+
+/// The kind of GitLab CI url.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Pipeline(u64),
+    Job(u64),
+}
+
+/// Returns the project api url and the target of a pipeline or job url, such as:
+/// https://gitlab.com/group/project/-/pipelines/42
+fn get_gitlab_api_url(url: &Url) -> Option<Result<(Url, Target)>> {
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    let sep = segments.iter().position(|segment| *segment == "-")?;
+    let target = match segments[sep + 1..] {
+        ["pipelines", id, ..] => Target::Pipeline(id.parse().ok()?),
+        ["jobs", id, ..] => Target::Job(id.parse().ok()?),
+        _ => return None,
+    };
+    if sep == 0 {
+        return None;
+    }
+    let project = segments[..sep].join("%2F");
+    let mut api = url.clone();
+    api.set_query(None);
+    api.set_fragment(None);
+    api.set_path(&format!("/api/v4/projects/{}/", project));
+    Some(Ok((api, target)))
+}
+
+/// Add the token to the GitLab API requests. The token is sent as a bearer token, instead of the
+/// PRIVATE-TOKEN header, because the client removes it when a request is redirected to another host.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    match &*GITLAB_TOKEN {
+        Some(token) if is_api(url, GITLAB_HOST.as_deref()) => request.bearer_auth(token),
+        _ => request,
+    }
+}
+
+/// Check if the url is on the api path of gitlab.com or of the self-managed host.
+fn is_api(url: &Url, self_managed: Option<&str>) -> bool {
+    let is_host = |host| host == GITLAB_COM || Some(host) == self_managed;
+    url.host_str().is_some_and(is_host) && url.path().starts_with("/api/v4/")
+}
+
+impl Content {
+    pub fn from_gitlab_url(url: &Url) -> Option<Result<Content>> {
+        get_gitlab_api_url(url).map(|res| {
+            res.and_then(|(api, target)| {
+                let (pipeline_id, job_name) = match target {
+                    Target::Pipeline(id) => (id, None),
+                    Target::Job(id) => {
+                        let url = api
+                            .join(&format!("jobs/{}", id))
+                            .context("Can't create job url")?;
+                        let job: ApiJob = get_json(&api, &url)?;
+                        (job.pipeline.id, Some(job.name))
+                    }
+                };
+                let url = api
+                    .join(&format!("pipelines/{}", pipeline_id))
+                    .context("Can't create pipeline url")?;
+                get_json(&api, &url).map(|pipeline| new_content(api, pipeline, job_name))
+            })
+        })
+    }
+}
+
+#[test]
+fn test_gitlab_url() {
+    let assert_url = |full, api, target| {
+        let url = Url::parse(full).unwrap();
+        let result = get_gitlab_api_url(&url).unwrap().unwrap();
+        assert_eq!(result, (Url::parse(api).unwrap(), target));
+    };
+    assert_url(
+        "https://gitlab.com/gitlab-org/gitlab-runner/-/pipelines/42",
+        "https://gitlab.com/api/v4/projects/gitlab-org%2Fgitlab-runner/",
+        Target::Pipeline(42),
+    );
+    assert_url(
+        "https://gitlab.example.com/group/sub/project/-/jobs/1234?foo=bar",
+        "https://gitlab.example.com/api/v4/projects/group%2Fsub%2Fproject/",
+        Target::Job(1234),
+    );
+    assert!(get_gitlab_api_url(
+        &Url::parse("https://gitlab.com/group/project/-/issues/1").unwrap()
+    )
+    .is_none());
+
+    let is_api_url = |url| is_api(&Url::parse(url).unwrap(), Some("gitlab.example.com"));
+    assert!(is_api_url(
+        "https://gitlab.com/api/v4/projects/group%2Fproject/"
+    ));
+    assert!(is_api_url(
+        "https://gitlab.example.com/api/v4/projects/1/jobs/2/trace"
+    ));
+    assert!(!is_api_url("https://evil.example.com/api/v4/projects/1/"));
+    assert!(!is_api_url(
+        "https://gitlab.com/group/project/-/pipelines/42"
+    ));
+}
+
+#[test]
+fn test_gitlab_api() -> Result<()> {
+    use mockito::mock;
+    let base = Url::parse(&mockito::server_url())?;
+    let api_path = "/api/v4/projects/group%2Fproject/";
+    let job_mock = mock("GET", &*format!("{}jobs/7", api_path))
+        .with_body(r#"{"id": 7, "name": "unit", "status": "failed", "pipeline": {"id": 42}}"#)
+        .create();
+    let pipeline_mock = mock("GET", &*format!("{}pipelines/42", api_path))
+        .with_body(r#"{"id": 42, "ref": "main", "status": "failed"}"#)
+        .create();
+    let jobs_mock = mock("GET", &*format!("{}pipelines/42/jobs", api_path))
+        .match_query(mockito::Matcher::Any)
+        .with_body(
+            r#"[
+              {"id": 7, "name": "unit", "status": "failed", "pipeline": {"id": 42}},
+              {"id": 8, "name": "lint", "status": "success", "pipeline": {"id": 42}}
+            ]"#,
+        )
+        .create();
+
+    let content = Content::from_gitlab_url(&base.join("/group/project/-/jobs/7")?).unwrap()?;
+    let sources = content.get_sources()?;
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].get_relative(), "unit");
+    assert!(sources[0]
+        .as_str()
+        .ends_with("/api/v4/projects/group%2Fproject/jobs/7/trace#unit"));
+
+    job_mock.assert();
+    pipeline_mock.assert();
+    jobs_mock.assert();
+    Ok(())
+}
@@ -0,0 +1,67 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the checksum of the saved models: the sha256 of the compressed model is
+//! appended after the compressed stream, so that a truncated or corrupted model is refused on load.
+//! The models saved without the checksum are still accepted.
+
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+use crate::errors::Failure;
+
+/// The marker of the checksum trailer.
+const MAGIC: &[u8] = b"LOGREDUCE-SHA256";
+
+const TRAILER_SIZE: usize = MAGIC.len() + 32;
+
+/// Append the checksum to the compressed model.
+pub(crate) fn seal(mut data: Vec<u8>) -> Vec<u8> {
+    let digest = Sha256::digest(&data);
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&digest);
+    data
+}
+
+/// Verify the checksum, and returns the compressed model.
+pub(crate) fn unseal<'a>(data: &'a [u8], path: &Path) -> Result<&'a [u8]> {
+    if data.len() < TRAILER_SIZE {
+        return Ok(data);
+    }
+    let (payload, trailer) = data.split_at(data.len() - TRAILER_SIZE);
+    let (magic, digest) = trailer.split_at(MAGIC.len());
+    if magic != MAGIC {
+        tracing::debug!("{:?}: the model doesn't have a checksum", path);
+        return Ok(data);
+    }
+    if Sha256::digest(payload).as_slice() != digest {
+        return Err(
+            anyhow::anyhow!("the checksum doesn't match, the model is corrupted")
+                .context(Failure::UntrustedModel(path.to_path_buf())),
+        );
+    }
+    Ok(payload)
+}
+
+#[test]
+fn test_model_checksum() {
+    use crate::Model;
+    let model = crate::train_memory(vec![("service.log", "INFO service started\n")]);
+    let mut data = Vec::new();
+    model.save_writer(&mut data).unwrap();
+    assert_eq!(&data[data.len() - TRAILER_SIZE..][..MAGIC.len()], MAGIC);
+    let path = Path::new("model.bin");
+    assert!(Model::load_reader(&data[..], path).is_ok());
+
+    // The models saved without the checksum are accepted.
+    assert!(Model::load_reader(&data[..data.len() - TRAILER_SIZE], path).is_ok());
+
+    let mid = data.len() / 2;
+    data[mid] ^= 0xff;
+    let err = Model::load_reader(&data[..], path).unwrap_err();
+    assert_eq!(
+        crate::errors::find_failure(&err),
+        Some(&Failure::UntrustedModel(path.to_path_buf()))
+    );
+}
@@ -0,0 +1,258 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Jenkins build content.
+//! The console log and the artifacts are discovered with the Jenkins JSON API, and the baseline
+//! is the last successful build of the same job.
+//! The server is set with the JENKINS_URL environment variable, and the protected servers require the
+//! JENKINS_USER and JENKINS_TOKEN environment variables.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+lazy_static::lazy_static! {
+    /// The Jenkins server, set with the JENKINS_URL environment variable.
+    static ref JENKINS_URL: Option<Url> =
+        std::env::var("JENKINS_URL").ok().and_then(|url| Url::parse(&url).ok());
+
+    /// The Jenkins API credentials, set with the JENKINS_USER and JENKINS_TOKEN environment variables.
+    static ref JENKINS_AUTH: Option<(String, String)> =
+        match (std::env::var("JENKINS_USER"), std::env::var("JENKINS_TOKEN")) {
+            (Ok(user), Ok(token)) => Some((user, token)),
+            _ => None,
+        };
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Build {
+    /// The job url.
+    job: Url,
+    pub number: u64,
+    pub result: Option<String>,
+}
+
+impl std::fmt::Display for Build {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}/", self.job.as_str(), self.number)
+    }
+}
+
+#[derive(Deserialize)]
+struct ApiBuild {
+    number: u64,
+    result: Option<String>,
+    #[serde(default)]
+    artifacts: Vec<ApiArtifact>,
+}
+
+#[derive(Deserialize)]
+struct ApiArtifact {
+    #[serde(rename = "relativePath")]
+    relative_path: String,
+}
+
+impl Build {
+    fn url(&self) -> Result<Url> {
+        self.job
+            .join(&format!("{}/", self.number))
+            .context("Can't create build url")
+    }
+
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        let url = self
+            .job
+            .join("lastSuccessfulBuild/")
+            .context("Can't create baseline url")?;
+        tracing::info!(url = url.as_str(), "Discovering baselines for {}", self);
+        let build = get_build(&self.job, &url)?;
+        if build.number == self.number {
+            Err(anyhow::anyhow!("The last successful build is the target"))
+        } else {
+            Ok(vec![new_content(self.job.clone(), build)])
+        }
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let url = self.url()?;
+        let build = get_build(&self.job, &url)?;
+        let console = url
+            .join("consoleText")
+            .context("Can't create console url")?;
+        let mut sources = vec![Source::Remote(url.as_str().len(), console)];
+        let artifacts = url.join("artifact/").context("Can't create artifact url")?;
+        let prefix = artifacts.as_str().len();
+        for artifact in build.artifacts {
+            let url = artifacts
+                .join(&artifact.relative_path)
+                .context("Can't create artifact url")?;
+            sources.push(Source::Remote(prefix, url));
+        }
+        Ok(sources)
+    }
+}
+
+fn new_content(job: Url, build: ApiBuild) -> Content {
+    Content::Jenkins(Box::new(Build {
+        job,
+        number: build.number,
+        result: build.result,
+    }))
+}
+
+/// Get the build information, the build url is a directory such as: https://host/job/name/42/
+fn get_build(job: &Url, build: &Url) -> Result<ApiBuild> {
+    let url = build
+        .join("api/json?tree=number,result,artifacts[relativePath]")
+        .context("Can't create api url")?;
+    let reader = crate::reader::from_url(job, &url)?;
+    match serde_json::from_reader(reader).context("Can't decode jenkins api") {
+        Ok(x) => Ok(x),
+        Err(e) => crate::reader::drop_url(job, &url).map_or_else(Err, |_| Err(e)),
+    }
+}
+
+/// Returns the job url and the build number of a build url, such as:
+/// https://jenkins.example.com/job/folder/job/name/42/console
+/// The artifact urls are not matched, to analyze them as regular files.
+fn get_jenkins_job_url(url: &Url, server: Option<&Url>) -> Option<(Url, u64)> {
+    if !is_server(url, server) {
+        return None;
+    }
+    let segments = url.path_segments()?.collect::<Vec<_>>();
+    let pos = segments.iter().rposition(|segment| *segment == "job")?;
+    match segments[pos..] {
+        ["job", name, number] | ["job", name, number, "" | "console" | "consoleFull"]
+            if !name.is_empty() =>
+        {
+            let number = number.parse().ok()?;
+            let mut job = url.clone();
+            job.set_query(None);
+            job.set_fragment(None);
+            job.set_path(&format!("{}/", segments[..pos + 2].join("/")));
+            Some((job, number))
+        }
+        _ => None,
+    }
+}
+
+/// Check if the url is on the Jenkins server.
+fn is_server(url: &Url, server: Option<&Url>) -> bool {
+    server.is_some_and(|server| {
+        url.host_str() == server.host_str()
+            && url.port_or_known_default() == server.port_or_known_default()
+    })
+}
+
+/// Add the credentials to the Jenkins requests.
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    match &*JENKINS_AUTH {
+        Some((user, token)) if is_server(url, JENKINS_URL.as_ref()) => {
+            request.basic_auth(user, Some(token))
+        }
+        _ => request,
+    }
+}
+
+impl Content {
+    /// The build urls of the other hosts than JENKINS_URL are not matched.
+    pub fn from_jenkins_url(url: &Url) -> Option<Result<Content>> {
+        from_server_url(url, JENKINS_URL.as_ref())
+    }
+}
+
+fn from_server_url(url: &Url, server: Option<&Url>) -> Option<Result<Content>> {
+    get_jenkins_job_url(url, server).map(|(job, number)| {
+        let build = Build {
+            job,
+            number,
+            result: None,
+        };
+        get_build(&build.job, &build.url()?).map(|api| new_content(build.job, api))
+    })
+}
+
+#[test]
+fn test_jenkins_url() {
+    let assert_url = |full, job, number| {
+        let url = Url::parse(full).unwrap();
+        assert_eq!(
+            get_jenkins_job_url(&url, Some(&url)),
+            Some((Url::parse(job).unwrap(), number))
+        );
+    };
+    assert_url(
+        "https://jenkins.example.com/job/project/42/",
+        "https://jenkins.example.com/job/project/",
+        42,
+    );
+    assert_url(
+        "https://ci.example.com/jenkins/job/folder/job/project/7/console",
+        "https://ci.example.com/jenkins/job/folder/job/project/",
+        7,
+    );
+    let server = Url::parse("https://jenkins.example.com/").unwrap();
+    for url in &[
+        "https://jenkins.example.com/job/project/",
+        "https://jenkins.example.com/job/project/42/artifact/logs/test.log",
+        "https://logs.example.com/job/project/42/",
+    ] {
+        assert_eq!(
+            get_jenkins_job_url(&Url::parse(url).unwrap(), Some(&server)),
+            None
+        );
+    }
+    let url = Url::parse("https://jenkins.example.com/job/project/42/").unwrap();
+    assert_eq!(get_jenkins_job_url(&url, None), None);
+    assert!(!is_server(
+        &Url::parse("https://jenkins.example.com:8443/job/project/42/").unwrap(),
+        Some(&server)
+    ));
+}
+
+#[test]
+fn test_jenkins_api() -> Result<()> {
+    use mockito::mock;
+    let base = Url::parse(&mockito::server_url())?;
+    let build_mock = mock("GET", "/job/project/42/api/json")
+        .match_query(mockito::Matcher::Any)
+        .with_body(
+            r#"{"number": 42, "result": "FAILURE",
+                "artifacts": [{"relativePath": "logs/test.log"}]}"#,
+        )
+        .expect(2)
+        .create();
+    let baseline_mock = mock("GET", "/job/project/lastSuccessfulBuild/api/json")
+        .match_query(mockito::Matcher::Any)
+        .with_body(r#"{"number": 41, "result": "SUCCESS"}"#)
+        .create();
+
+    let content = from_server_url(&base.join("/job/project/42/console")?, Some(&base)).unwrap()?;
+    let sources = content.get_sources()?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["consoleText", "logs/test.log"]
+    );
+    match content.discover_baselines()?.as_slice() {
+        [Content::Jenkins(baseline)] => assert_eq!(baseline.number, 41),
+        baselines => panic!("Unexpected baselines {:?}", baselines),
+    }
+    build_mock.assert();
+    baseline_mock.assert();
+    Ok(())
+}
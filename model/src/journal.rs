@@ -0,0 +1,457 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the systemd journal content, for the `journal:` inputs such as
+//! `journal:sshd.service?since=2022-03-15 10:15:00&until=2022-03-15 11:15:00`.
+//! The entries are read with journalctl, and they are grouped by unit into memory sources
+//! named `journal/<unit>`, so that each unit gets its own index.
+//! The baseline is the previous boot.
+//!
+//! The files saved with `journalctl -o export`, such as the journal dumps of the sosreports, are
+//! detected by the encoding::Decoder, and their entries are read as `<date> <unit>: <message>` lines.
+
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+/// The journal entries selection.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Query {
+    pub units: Vec<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// The boot offset or id, e.g. `-1` for the previous boot.
+    pub boot: Option<String>,
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "journal:{}", self.units.join(","))?;
+        let params = [
+            ("since", &self.since),
+            ("until", &self.until),
+            ("boot", &self.boot),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| format!("{}={}", name, value)))
+        .collect::<Vec<_>>();
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    #[serde(rename = "__REALTIME_TIMESTAMP")]
+    timestamp: Option<String>,
+    #[serde(rename = "_SYSTEMD_UNIT")]
+    unit: Option<String>,
+    #[serde(rename = "SYSLOG_IDENTIFIER")]
+    identifier: Option<String>,
+    /// The message is an array of bytes when it is not valid utf-8.
+    #[serde(rename = "MESSAGE")]
+    message: Option<serde_json::Value>,
+}
+
+impl Query {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec!["--no-pager".to_string(), "--output=json".to_string()];
+        for unit in &self.units {
+            args.push(format!("--unit={}", unit));
+        }
+        if let Some(since) = &self.since {
+            args.push(format!("--since={}", since));
+        }
+        if let Some(until) = &self.until {
+            args.push(format!("--until={}", until));
+        }
+        if let Some(boot) = &self.boot {
+            args.push(format!("--boot={}", boot));
+        }
+        args
+    }
+
+    /// The entries of all the units in the time span after the start, such as `1h` or `30min`.
+    /// Without a start, the time span before now is selected.
+    pub fn from_range(start: Option<&str>, range: &str) -> Result<Query> {
+        let span = parse_span(range)?;
+        let (since, until) = match start {
+            None => (format!("-{}s", span.num_seconds()), None),
+            Some(start) => {
+                let since = chrono::NaiveDateTime::parse_from_str(start, DATE_FORMAT)
+                    .with_context(|| {
+                        format!("Invalid start {}, expected YYYY-MM-DD HH:MM:SS", start)
+                    })?;
+                let until = since + span;
+                (
+                    since.format(DATE_FORMAT).to_string(),
+                    Some(until.format(DATE_FORMAT).to_string()),
+                )
+            }
+        };
+        Ok(Query {
+            since: Some(since),
+            until,
+            ..Query::default()
+        })
+    }
+
+    /// The previous boot, with the same units.
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        let boot = match self.boot.as_deref() {
+            None | Some("0") => -1,
+            Some(boot) => match boot.parse::<i64>() {
+                Ok(offset) if offset <= 0 => offset - 1,
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "Can't discover the baseline of boot {}, it needs to be provided",
+                        boot
+                    ))
+                }
+            },
+        };
+        Ok(vec![Content::Journal(Box::new(Query {
+            units: self.units.clone(),
+            since: None,
+            until: None,
+            boot: Some(boot.to_string()),
+        }))])
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        tracing::debug!("Running journalctl {}", self.args().join(" "));
+        let mut child = std::process::Command::new("journalctl")
+            .args(self.args())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .context("Can't run journalctl")?;
+        let stdout = child.stdout.take().context("Can't read journalctl")?;
+        let sources = read_entries(std::io::BufReader::new(stdout));
+        let status = child.wait().context("Can't wait for journalctl")?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("journalctl failed: {}", status));
+        }
+        sources
+    }
+}
+
+/// The journalctl date format.
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Parse a time span made of a number and a unit: `s`, `min`, `h` or `d`.
+fn parse_span(range: &str) -> Result<chrono::Duration> {
+    let invalid = || {
+        anyhow::anyhow!(
+            "Invalid range {}, expected a span such as 1h or 30min",
+            range
+        )
+    };
+    let pos = range
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let count: i64 = range[..pos].parse().map_err(|_| invalid())?;
+    match range[pos..].trim() {
+        "s" => Ok(chrono::Duration::seconds(count)),
+        "m" | "min" => Ok(chrono::Duration::minutes(count)),
+        "h" => Ok(chrono::Duration::hours(count)),
+        "d" => Ok(chrono::Duration::days(count)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Format the `__REALTIME_TIMESTAMP` microseconds as a line prefix.
+fn format_timestamp(ts: &str) -> Option<String> {
+    let ts = ts.trim().parse::<i64>().ok()?;
+    let ts = chrono::Utc.timestamp_opt(ts / 1_000_000, 0).single()?;
+    Some(ts.format("%Y-%m-%d %H:%M:%S ").to_string())
+}
+
+/// Returns true when the sample is the beginning of a `journalctl -o export` output.
+pub fn is_export(sample: &[u8]) -> bool {
+    sample.starts_with(b"__CURSOR=") || sample.starts_with(b"__REALTIME_TIMESTAMP=")
+}
+
+/// The fields of an export entry.
+#[derive(Debug, Default)]
+struct ExportEntry {
+    timestamp: Option<String>,
+    unit: Option<String>,
+    identifier: Option<String>,
+    message: Option<Vec<u8>>,
+}
+
+impl ExportEntry {
+    fn set(&mut self, name: &[u8], value: Vec<u8>) {
+        let text = || String::from_utf8_lossy(&value).into_owned();
+        match name {
+            b"__REALTIME_TIMESTAMP" => self.timestamp = Some(text()),
+            b"_SYSTEMD_UNIT" => self.unit = Some(text()),
+            b"SYSLOG_IDENTIFIER" => self.identifier = Some(text()),
+            b"MESSAGE" => self.message = Some(value),
+            _ => {}
+        }
+    }
+}
+
+/// A reader that converts the `journalctl -o export` entries to log lines.
+pub struct ExportReader<R: BufRead> {
+    inner: R,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: BufRead> ExportReader<R> {
+    pub fn new(inner: R) -> ExportReader<R> {
+        ExportReader {
+            inner,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Read the next entry, the fields are either `NAME=value` lines, or a `NAME` line followed by
+    /// the little-endian 64 bits size of the binary value, and the entries are separated by a blank line.
+    fn read_entry(&mut self) -> std::io::Result<Option<ExportEntry>> {
+        let mut entry = ExportEntry::default();
+        let mut empty = true;
+        loop {
+            let mut field = Vec::new();
+            if self.inner.read_until(b'\n', &mut field)? == 0 {
+                return Ok(if empty { None } else { Some(entry) });
+            }
+            if field.last() == Some(&b'\n') {
+                field.pop();
+            }
+            if field.is_empty() {
+                if empty {
+                    continue;
+                }
+                return Ok(Some(entry));
+            }
+            empty = false;
+            match field.iter().position(|c| *c == b'=') {
+                Some(eq) => {
+                    let value = field.split_off(eq + 1);
+                    entry.set(&field[..eq], value);
+                }
+                None => {
+                    let mut size = [0; 8];
+                    self.inner.read_exact(&mut size)?;
+                    let size = u64::from_le_bytes(size);
+                    let mut value = Vec::new();
+                    (&mut self.inner).take(size).read_to_end(&mut value)?;
+                    if value.len() as u64 != size {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "truncated journal export field",
+                        ));
+                    }
+                    // Skip the field newline.
+                    self.inner.read_exact(&mut [0])?;
+                    entry.set(&field, value);
+                }
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for ExportReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            let entry = match self.read_entry()? {
+                Some(entry) => entry,
+                None => return Ok(0),
+            };
+            let message = match entry.message {
+                Some(message) => message,
+                None => continue,
+            };
+            if let Some(ts) = entry.timestamp.as_deref().and_then(format_timestamp) {
+                self.line.extend_from_slice(ts.as_bytes());
+            }
+            if let Some(unit) = entry.unit.or(entry.identifier) {
+                self.line.extend_from_slice(unit.as_bytes());
+                self.line.extend_from_slice(b": ");
+            }
+            self.line.extend_from_slice(&message);
+            self.line.push(b'\n');
+        }
+        let size = buf.len().min(self.line.len() - self.pos);
+        buf[..size].copy_from_slice(&self.line[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+/// Group the messages of the journalctl json output per unit.
+fn read_entries<R: BufRead>(reader: R) -> Result<Vec<Source>> {
+    let mut units: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line.context("Can't read journal entry")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(&line).context("Can't decode journal entry")?;
+        let message = match entry.message {
+            Some(serde_json::Value::String(message)) => message.into_bytes(),
+            Some(serde_json::Value::Array(bytes)) => bytes
+                .iter()
+                .filter_map(|b| b.as_u64().map(|b| b as u8))
+                .collect(),
+            _ => continue,
+        };
+        let unit = entry
+            .unit
+            .or(entry.identifier)
+            .unwrap_or_else(|| "unknown".to_string());
+        let data = units.entry(unit).or_default();
+        if let Some(ts) = entry.timestamp.as_deref().and_then(format_timestamp) {
+            data.extend_from_slice(ts.as_bytes());
+        }
+        data.extend_from_slice(&message);
+        data.push(b'\n');
+    }
+    Ok(units
+        .into_iter()
+        .map(|(unit, data)| {
+            Source::Memory(
+                format!("journal/{}", unit.replace('/', "_")),
+                crate::memory::MemoryData(data.into()),
+            )
+        })
+        .collect())
+}
+
+impl Content {
+    /// Create the journal content of a `journal:[UNIT,...][?since=..&until=..&boot=..]` url.
+    pub fn from_journal_url(url: &Url) -> Result<Content> {
+        let mut query = Query {
+            units: url
+                .path()
+                .split(',')
+                .map(|unit| crate::urls::decode(unit.trim()))
+                .filter(|unit| !unit.is_empty())
+                .collect(),
+            ..Query::default()
+        };
+        for (key, value) in url.query_pairs() {
+            let value = Some(value.into_owned());
+            match key.as_ref() {
+                "since" => query.since = value,
+                "until" => query.until = value,
+                "boot" => query.boot = value,
+                "unit" => query.units.extend(value),
+                _ => return Err(anyhow::anyhow!("Unknown journal parameter: {}", key)),
+            }
+        }
+        Ok(Content::Journal(Box::new(query)))
+    }
+}
+
+#[test]
+fn test_journal_url() {
+    let url = Url::parse("journal:sshd.service,crond.service?since=2022-03-15 10:15:00").unwrap();
+    match Content::from_journal_url(&url).unwrap() {
+        Content::Journal(query) => {
+            assert_eq!(query.units, vec!["sshd.service", "crond.service"]);
+            assert_eq!(query.since.as_deref(), Some("2022-03-15 10:15:00"));
+            assert_eq!(
+                query.to_string(),
+                "journal:sshd.service,crond.service?since=2022-03-15 10:15:00"
+            );
+            assert!(query
+                .args()
+                .contains(&"--since=2022-03-15 10:15:00".to_string()));
+            match query.discover_baselines().unwrap().as_slice() {
+                [Content::Journal(baseline)] => {
+                    assert_eq!(baseline.boot.as_deref(), Some("-1"));
+                    assert_eq!(baseline.since, None);
+                }
+                baselines => panic!("Unexpected baselines {:?}", baselines),
+            }
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+    assert!(Content::from_journal_url(&Url::parse("journal:?level=err").unwrap()).is_err());
+}
+
+#[test]
+fn test_journal_range() {
+    let query = Query::from_range(Some("2022-03-15 10:15:00"), "90min").unwrap();
+    assert_eq!(
+        query.to_string(),
+        "journal:?since=2022-03-15 10:15:00&until=2022-03-15 11:45:00"
+    );
+    let query = Query::from_range(None, "1h").unwrap();
+    assert_eq!(query.to_string(), "journal:?since=-3600s");
+    assert!(Query::from_range(None, "1 week").is_err());
+    assert!(Query::from_range(Some("yesterday"), "1h").is_err());
+}
+
+#[test]
+fn test_journal_entries() {
+    let output = r#"{"__REALTIME_TIMESTAMP":"1647339300000000","_SYSTEMD_UNIT":"sshd.service","MESSAGE":"Accepted publickey"}
+{"__REALTIME_TIMESTAMP":"1647339301000000","SYSLOG_IDENTIFIER":"kernel","MESSAGE":[111,111,112,115,255]}
+{"__REALTIME_TIMESTAMP":"1647339302000000","_SYSTEMD_UNIT":"sshd.service","MESSAGE":"Connection closed"}
+"#;
+    let sources = read_entries(output.as_bytes()).unwrap();
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["journal/kernel", "journal/sshd.service"]
+    );
+    match &sources[1] {
+        Source::Memory(_, data) => assert_eq!(
+            data.0,
+            "2022-03-15 10:15:00 Accepted publickey\n2022-03-15 10:15:02 Connection closed\n"
+        ),
+        source => panic!("Unexpected source {:?}", source),
+    }
+}
+
+#[test]
+fn test_journal_export() {
+    let mut export = b"__CURSOR=s=1;i=1\n__REALTIME_TIMESTAMP=1647339300000000\n_SYSTEMD_UNIT=sshd.service\nMESSAGE=Accepted publickey\n\n".to_vec();
+    export.extend_from_slice(
+        b"__CURSOR=s=1;i=2\n__REALTIME_TIMESTAMP=1647339301000000\n_BOOT_ID=42\n\n",
+    );
+    let message = b"Traceback\n  KeyError";
+    export.extend_from_slice(b"__CURSOR=s=1;i=3\n__REALTIME_TIMESTAMP=1647339302000000\nSYSLOG_IDENTIFIER=nova\nMESSAGE\n");
+    export.extend_from_slice(&(message.len() as u64).to_le_bytes());
+    export.extend_from_slice(message);
+    export.extend_from_slice(b"\n\n");
+    assert!(is_export(&export));
+    assert!(!is_export(b"MESSAGE=oops\n"));
+
+    let mut content = String::new();
+    crate::encoding::decode(&export[..])
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(
+        content,
+        "2022-03-15 10:15:00 sshd.service: Accepted publickey\n2022-03-15 10:15:02 nova: Traceback\n  KeyError\n"
+    );
+
+    let truncated = &export[..export.len() - 8];
+    assert!(ExportReader::new(truncated)
+        .read_to_string(&mut String::new())
+        .is_err());
+}
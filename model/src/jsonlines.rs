@@ -0,0 +1,128 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module supports the services that log one JSON object per line. The lines are tokenized
+//! from the level, the message and the other fields of the object, instead of its raw text, so
+//! that the order of the keys doesn't matter and the dynamic fields, such as the timestamps and
+//! the trace ids, only contribute their key. The reported anomalies keep the original JSON.
+
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The fields of the message, the first one found is used.
+const MESSAGE_KEYS: [&str; 5] = ["message", "msg", "@message", "log", "text"];
+
+/// The fields of the level, nested objects use a dotted key.
+const LEVEL_KEYS: [&str; 5] = ["level", "severity", "lvl", "loglevel", "log.level"];
+
+/// The fields whose value changes on every line.
+const DYNAMIC_KEYS: [&str; 16] = [
+    "time",
+    "timestamp",
+    "@timestamp",
+    "ts",
+    "date",
+    "datetime",
+    "id",
+    "pid",
+    "tid",
+    "thread",
+    "duration",
+    "elapsed",
+    "latency",
+    "took",
+    "uptime",
+    "caller",
+];
+
+static JSON_LINES: AtomicBool = AtomicBool::new(false);
+
+/// Parse the lines that are JSON objects.
+pub fn set_json_lines(enabled: bool) {
+    JSON_LINES.store(enabled, Ordering::Relaxed);
+}
+
+fn is_dynamic(key: &str) -> bool {
+    let name = key.rsplit('.').next().unwrap_or(key).to_lowercase();
+    DYNAMIC_KEYS.contains(&name.as_str()) || name.ends_with("_id")
+}
+
+/// Collect the scalar values per dotted key, the arrays only have their key.
+fn collect(
+    prefix: &str,
+    object: &Map<String, Value>,
+    fields: &mut BTreeMap<String, Option<String>>,
+) {
+    for (key, value) in object {
+        let key = match prefix {
+            "" => key.clone(),
+            prefix => format!("{}.{}", prefix, key),
+        };
+        let value = match value {
+            Value::Object(object) => {
+                collect(&key, object, fields);
+                continue;
+            }
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            Value::Array(_) | Value::Null => None,
+        };
+        fields.insert(key, value);
+    }
+}
+
+pub(crate) fn is_enabled() -> bool {
+    JSON_LINES.load(Ordering::Relaxed)
+}
+
+/// Returns the text to tokenize when the line is a JSON object: the `level=` field, the message,
+/// and the other fields sorted by key, with only the key of the dynamic fields.
+pub(crate) fn flatten(line: &str) -> Option<String> {
+    let line = line.trim();
+    if !line.starts_with('{') {
+        return None;
+    }
+    let object: Map<String, Value> = serde_json::from_str(line).ok()?;
+    let mut fields = BTreeMap::new();
+    collect("", &object, &mut fields);
+
+    let mut text = String::with_capacity(line.len());
+    let mut take = |keys: &[&str]| keys.iter().find_map(|key| fields.remove(*key).flatten());
+    if let Some(level) = take(&LEVEL_KEYS) {
+        text.push_str("level=");
+        text.push_str(&level);
+    }
+    if let Some(message) = take(&MESSAGE_KEYS) {
+        text.push(' ');
+        text.push_str(&message);
+    }
+    for (key, value) in fields {
+        text.push(' ');
+        text.push_str(&key);
+        if let Some(value) = value.filter(|_| !is_dynamic(&key)) {
+            text.push('=');
+            text.push_str(&value);
+        }
+    }
+    Some(text.trim_start().to_string())
+}
+
+#[test]
+fn test_json_lines() {
+    let line = r#"{"ts": "2022-04-01T10:03:00Z", "level": "error", "msg": "connection lost", "peer": "db", "request_id": "a1b2", "ctx": {"retry": 3}}"#;
+    assert_eq!(
+        flatten(line).unwrap(),
+        "level=error connection lost ctx.retry=3 peer=db request_id ts"
+    );
+    // The keys order and the dynamic values don't change the text.
+    let other = r#"{"request_id": "c3d4", "peer": "db", "msg": "connection lost", "ctx": {"retry": 3}, "level": "error", "ts": "2022-04-02T11:00:00Z"}"#;
+    assert_eq!(flatten(line), flatten(other));
+    assert_eq!(
+        crate::levels::parse_level(&flatten(line).unwrap()),
+        Some(crate::levels::Level::Error)
+    );
+    assert_eq!(flatten("INFO {not json}"), None);
+    assert_eq!(flatten("{not json}"), None);
+}
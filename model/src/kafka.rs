@@ -0,0 +1,301 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the experimental Kafka content, to tail a topic through a Kafka REST proxy,
+//! for the urls such as `kafka://rest-proxy.example.com:8082/app-logs?seconds=30&messages=10000`.
+//! A temporary consumer reads the new records of the topic until the duration or the number of messages
+//! is reached, and the record values are analyzed as a `kafka/<topic>` memory source, or one source
+//! per record key with `group=key`. The topic has no baseline, use a trained model.
+//! Use `kafka+http://` for a proxy without TLS, and `from=earliest` to read the topic from the beginning.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::{Content, Source};
+
+/// The content type of the binary embedded format.
+const BINARY_V2: &str = "application/vnd.kafka.binary.v2+json";
+const V2: &str = "application/vnd.kafka.v2+json";
+
+/// The records of a topic.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Topic {
+    /// The base url of the REST proxy.
+    pub url: Url,
+    pub name: String,
+    /// The maximum number of messages.
+    pub messages: usize,
+    /// The maximum duration of the consumption.
+    pub seconds: u64,
+    /// Read the topic from the earliest offset instead of the new records.
+    pub earliest: bool,
+    /// Create one source per record key.
+    pub by_key: bool,
+}
+
+impl std::fmt::Display for Topic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut url = self.url.clone();
+        url.set_password(None).ok();
+        write!(
+            f,
+            "{}{}?messages={}&seconds={}",
+            url, self.name, self.messages, self.seconds
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Consumer {
+    base_uri: Url,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    key: Option<String>,
+    value: Option<String>,
+}
+
+fn decode(value: &Option<String>) -> Result<Vec<u8>> {
+    use base64::Engine;
+    match value {
+        Some(value) => base64::engine::general_purpose::STANDARD
+            .decode(value)
+            .context("Invalid record encoding"),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn check(resp: reqwest::blocking::Response) -> Result<reqwest::blocking::Response> {
+    let status = resp.status();
+    crate::urls::record_response(resp.url(), status.as_u16(), resp.url());
+    if status.is_success() {
+        Ok(resp)
+    } else {
+        let failure = crate::errors::Failure::HttpStatus(resp.url().clone(), status.as_u16());
+        Err(anyhow::Error::new(failure).context(format!(
+            "Kafka REST proxy request failed: {}",
+            resp.text().unwrap_or_default().trim()
+        )))
+    }
+}
+
+impl Topic {
+    /// Create a consumer subscribed to the topic.
+    fn subscribe(&self) -> Result<Url> {
+        let group = format!("logreduce-{}", std::process::id());
+        let url = self
+            .url
+            .join(&format!("consumers/{}", group))
+            .context("Bad url")?;
+        let reset = if self.earliest { "earliest" } else { "latest" };
+        let resp = check(
+            crate::reader::CLIENT
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, V2)
+                .body(json!({"format": "binary", "auto.offset.reset": reset}).to_string())
+                .send()
+                .context("Can't create the consumer")?,
+        )?;
+        let consumer: Consumer =
+            serde_json::from_reader(resp).context("Can't decode the consumer")?;
+        let base_uri = consumer.base_uri;
+        let subscription = Url::parse(&format!("{}/subscription", base_uri))?;
+        let subscribed = crate::reader::CLIENT
+            .post(subscription)
+            .header(reqwest::header::CONTENT_TYPE, V2)
+            .body(json!({ "topics": [self.name] }).to_string())
+            .send()
+            .context("Can't subscribe")
+            .and_then(check);
+        match subscribed {
+            Ok(_) => Ok(base_uri),
+            Err(e) => {
+                self.unsubscribe(&base_uri);
+                Err(e)
+            }
+        }
+    }
+
+    fn unsubscribe(&self, base_uri: &Url) {
+        if let Err(e) = crate::reader::CLIENT
+            .delete(base_uri.clone())
+            .header(reqwest::header::CONTENT_TYPE, V2)
+            .send()
+        {
+            tracing::warn!("Can't delete the consumer {}: {}", base_uri, e);
+        }
+    }
+
+    fn poll(&self, base_uri: &Url) -> Result<Vec<Record>> {
+        let url = Url::parse(&format!("{}/records?timeout=1000", base_uri))?;
+        let resp = check(
+            crate::reader::CLIENT
+                .get(url)
+                .header(reqwest::header::ACCEPT, BINARY_V2)
+                .send()
+                .context("Can't read the records")?,
+        )?;
+        serde_json::from_reader(resp).context("Can't decode the records")
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let base_uri = self.subscribe()?;
+        let records = self.consume(&base_uri);
+        self.unsubscribe(&base_uri);
+        let mut groups: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        for record in records? {
+            let group = match self.by_key {
+                true => String::from_utf8_lossy(&decode(&record.key)?).into_owned(),
+                false => self.name.clone(),
+            };
+            let data = groups.entry(group).or_default();
+            let value = decode(&record.value)?;
+            data.extend_from_slice(value.strip_suffix(b"\n").unwrap_or(&value));
+            data.push(b'\n');
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(group, data)| {
+                Source::Memory(
+                    format!("kafka/{}", group.replace('/', "_")),
+                    crate::memory::MemoryData(data.into()),
+                )
+            })
+            .collect())
+    }
+
+    /// Poll the records until the limits are reached.
+    fn consume(&self, base_uri: &Url) -> Result<Vec<Record>> {
+        let deadline = Instant::now() + Duration::from_secs(self.seconds);
+        let mut records = Vec::new();
+        while records.len() < self.messages && Instant::now() < deadline {
+            records.extend(self.poll(base_uri)?);
+        }
+        records.truncate(self.messages);
+        Ok(records)
+    }
+}
+
+impl Content {
+    /// Create the topic content of a `kafka://` or `kafka+http://` url.
+    pub fn from_kafka_url(url: &Url) -> Result<Content> {
+        let scheme = match url.scheme() {
+            "kafka" => "https",
+            "kafka+http" => "http",
+            scheme => return Err(anyhow::anyhow!("Unknown kafka scheme: {}", scheme)),
+        };
+        let path = url.path().trim_matches('/');
+        let (prefix, name) = match path.rfind('/') {
+            Some(pos) => (&path[..pos + 1], &path[pos + 1..]),
+            None => ("", path),
+        };
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Missing kafka topic: {}", url));
+        }
+        let base = Url::parse(&format!(
+            "{}://{}/{}",
+            scheme,
+            &url[url::Position::BeforeUsername..url::Position::AfterPort],
+            prefix
+        ))
+        .with_context(|| format!("Bad url {}", url))?;
+        let mut topic = Topic {
+            url: base,
+            name: crate::urls::decode(name),
+            messages: 10000,
+            seconds: 10,
+            earliest: false,
+            by_key: false,
+        };
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "messages" => topic.messages = value.parse().context("Invalid messages")?,
+                "seconds" => topic.seconds = value.parse().context("Invalid seconds")?,
+                "from" if value == "earliest" => topic.earliest = true,
+                "from" if value == "latest" => topic.earliest = false,
+                "group" if value == "key" => topic.by_key = true,
+                _ => return Err(anyhow::anyhow!("Unknown kafka parameter: {}", key)),
+            }
+        }
+        Ok(Content::Kafka(Box::new(topic)))
+    }
+}
+
+#[test]
+fn test_kafka_url() -> Result<()> {
+    let url = Url::parse("kafka://proxy.example.com:8082/app-logs?seconds=30&from=earliest")?;
+    match Content::from_kafka_url(&url)? {
+        Content::Kafka(topic) => {
+            assert_eq!(topic.url.as_str(), "https://proxy.example.com:8082/");
+            assert_eq!(topic.name, "app-logs");
+            assert_eq!(topic.seconds, 30);
+            assert!(topic.earliest);
+            assert_eq!(
+                topic.to_string(),
+                "https://proxy.example.com:8082/app-logs?messages=10000&seconds=30"
+            );
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+    assert!(Content::from_kafka_url(&Url::parse("kafka://proxy.example.com/")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_kafka_records() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let server = mockito::server_url();
+    let base_uri = format!("{}/consumers/logreduce/instances/test", server);
+    let consumer_mock = mock("POST", Matcher::Regex("^/consumers/logreduce-".into()))
+        .with_body(json!({"instance_id": "test", "base_uri": base_uri}).to_string())
+        .create();
+    let subscription_mock = mock("POST", "/consumers/logreduce/instances/test/subscription")
+        .match_body(Matcher::Json(json!({"topics": ["app-logs"]})))
+        .with_status(204)
+        .create();
+    let records_mock = mock(
+        "GET",
+        "/consumers/logreduce/instances/test/records?timeout=1000",
+    )
+    .with_body(
+        // The values are "service started\n" and "ERROR service crashed"
+        r#"[{"topic": "app-logs", "key": null, "value": "c2VydmljZSBzdGFydGVkCg==", "partition": 0, "offset": 1},
+            {"topic": "app-logs", "key": null, "value": "RVJST1Igc2VydmljZSBjcmFzaGVk", "partition": 0, "offset": 2}]"#,
+    )
+    .create();
+    let delete_mock = mock("DELETE", "/consumers/logreduce/instances/test")
+        .with_status(204)
+        .create();
+
+    let url = server.replacen("http", "kafka+http", 1);
+    let mut topic = match Content::from_kafka_url(&Url::parse(&format!("{}/app-logs", url))?)? {
+        Content::Kafka(topic) => topic,
+        content => panic!("Unexpected content {}", content),
+    };
+    topic.messages = 2;
+    let sources = topic.get_sources()?;
+    match sources.as_slice() {
+        [Source::Memory(name, data)] => {
+            assert_eq!(name, "kafka/app-logs");
+            assert_eq!(data.0, "service started\nERROR service crashed\n");
+        }
+        sources => panic!("Unexpected sources {:?}", sources),
+    }
+    consumer_mock.assert();
+    subscription_mock.assert();
+    records_mock.assert();
+    delete_mock.assert();
+    Ok(())
+}
@@ -0,0 +1,344 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the layout of the saved models, so that their indexes can be loaded
+//! lazily: a big model trained for many jobs only deserializes the indexes that match the target.
+//!
+//! The layout is the MAGIC marker, the FORMAT version as a little endian u32, the length of the
+//! header as a little endian u64, the compressed header with the baselines and the index table, and
+//! then the index blobs, compressed separately. The format version is checked before anything is
+//! decoded, so that the models saved with another layout are refused with an error.
+//! The model is read and verified once, with its checksum and its signature, and then only the
+//! blobs of the indexes that match the target are decompressed and deserialized. The sha256 of each
+//! blob is also in the table, to verify them when they are decoded.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::compression::{self, Compression};
+use crate::params::TrainParams;
+use crate::{errors::Failure, lookup_or_single, Baselines, Index, IndexName, Model};
+
+/// The marker of the models saved with the index table.
+const MAGIC: &[u8] = b"LOGREDUCE-INDEXED";
+
+/// The version of the layout, to be increased when the serialized structs change.
+const FORMAT: u32 = 2;
+
+const HEADER_OFFSET: usize = MAGIC.len() + 4 + 8;
+
+/// The location of an index blob, relative to the end of the header.
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    name: IndexName,
+    offset: u64,
+    length: u64,
+    digest: Vec<u8>,
+}
+
+/// The created_at, version, baselines, training parameters and index table of the model.
+type Header = (SystemTime, String, Baselines, TrainParams, Vec<Entry>);
+
+/// Serialize the model with the index table.
+pub(crate) fn encode(model: &Model, compression: Compression) -> Result<Vec<u8>> {
+    let mut names: Vec<&IndexName> = model.indexes.keys().collect();
+    names.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut table = Vec::with_capacity(names.len());
+    let mut blobs = Vec::new();
+    for name in names {
+        let blob = compression.encode(|writer| {
+            bincode::serialize_into(writer, &model.indexes[name]).map_err(|e| e.into())
+        })?;
+        table.push(Entry {
+            name: name.clone(),
+            offset: blobs.len() as u64,
+            length: blob.len() as u64,
+            digest: Sha256::digest(&blob).to_vec(),
+        });
+        blobs.extend(blob);
+    }
+    let header = compression.encode(|writer| {
+        bincode::serialize_into(
+            writer,
+            &(
+                &model.created_at,
+                &model.version,
+                &model.baselines,
+                &model.params,
+                &table,
+            ),
+        )
+        .map_err(|e| e.into())
+    })?;
+    let mut data = Vec::with_capacity(HEADER_OFFSET + header.len() + blobs.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&FORMAT.to_le_bytes());
+    data.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    data.extend(header);
+    data.extend(blobs);
+    Ok(data)
+}
+
+fn decode_header(header: &[u8]) -> Result<Header> {
+    compression::deserialize(header).context("Invalid model header")
+}
+
+fn decode_index(blob: &[u8]) -> Result<Index> {
+    compression::deserialize(blob).context("Invalid model index")
+}
+
+/// Returns the header and the remaining blobs, after checking the format version.
+fn split(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    if !data.starts_with(MAGIC) {
+        return Err(anyhow::anyhow!(
+            "the model was saved before the index table: train it again"
+        ));
+    }
+    let format = data
+        .get(MAGIC.len()..MAGIC.len() + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| anyhow::anyhow!("Truncated model header"))?;
+    if format != FORMAT {
+        return Err(anyhow::anyhow!(
+            "the model format {} is not supported, expected {}: train it again",
+            format,
+            FORMAT
+        ));
+    }
+    let length = data
+        .get(MAGIC.len() + 4..HEADER_OFFSET)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+        .filter(|length| HEADER_OFFSET + length <= data.len())
+        .ok_or_else(|| anyhow::anyhow!("Truncated model header"))?;
+    Ok(data[HEADER_OFFSET..].split_at(length))
+}
+
+/// Deserialize all the indexes of the model.
+pub(crate) fn decode(data: &[u8]) -> Result<Model> {
+    let (header, blobs) = split(data)?;
+    let (created_at, version, baselines, params, table) = decode_header(header)?;
+    let mut indexes = HashMap::with_capacity(table.len());
+    for entry in table {
+        let blob = blobs
+            .get(entry.offset as usize..(entry.offset + entry.length) as usize)
+            .ok_or_else(|| anyhow::anyhow!("{}: truncated index", entry.name))?;
+        indexes.insert(entry.name, decode_index(blob)?);
+    }
+    Ok(Model {
+        created_at,
+        version,
+        baselines,
+        indexes,
+        params,
+        lazy: None,
+    })
+}
+
+/// The indexes of a model loaded with Model::load_lazy, decoded on the first lookup.
+#[derive(Debug)]
+pub(crate) struct LazyIndexes {
+    /// The verified model, and the offset of the blobs.
+    data: Vec<u8>,
+    offset: usize,
+    table: HashMap<IndexName, (Entry, OnceLock<Option<Index>>)>,
+}
+
+impl LazyIndexes {
+    fn new(data: Vec<u8>, offset: usize, table: Vec<Entry>) -> LazyIndexes {
+        let table = table
+            .into_iter()
+            .map(|entry| (entry.name.clone(), (entry, OnceLock::new())))
+            .collect();
+        LazyIndexes {
+            data,
+            offset,
+            table,
+        }
+    }
+
+    fn read(&self, entry: &Entry) -> Result<Index> {
+        let start = self.offset + entry.offset as usize;
+        let blob = self
+            .data
+            .get(start..start + entry.length as usize)
+            .ok_or_else(|| anyhow::anyhow!("Truncated index"))?;
+        if Sha256::digest(blob).as_slice() != entry.digest {
+            return Err(anyhow::anyhow!("the index checksum doesn't match"));
+        }
+        decode_index(blob)
+    }
+
+    /// Feed the checksums of the index blobs to a hasher, see Model::report_digest.
+    pub(crate) fn digest(&self, hasher: &mut Sha256) {
+        let mut entries: Vec<&Entry> = self.table.values().map(|(entry, _)| entry).collect();
+        entries.sort_by(|a, b| a.name.0.cmp(&b.name.0));
+        for entry in entries {
+            hasher.update(entry.name.as_str());
+            hasher.update(&entry.digest);
+        }
+    }
+
+    /// Get the index, like Model::get_index. An index that can't be read is logged and skipped.
+    pub(crate) fn get(&self, index_name: &IndexName) -> Option<&Index> {
+        let (entry, slot) = lookup_or_single(&self.table, index_name)?;
+        slot.get_or_init(|| {
+            tracing::debug!("{}: loading the index", entry.name);
+            self.read(entry)
+                .map_err(|e| tracing::error!("{}: can't load the index: {:#}", entry.name, e))
+                .ok()
+        })
+        .as_ref()
+    }
+}
+
+impl Model {
+    /// Load a model whose indexes are decoded when they are first used, to reduce the memory usage of
+    /// the models trained for many jobs. The lazy model can't be saved, use Model::load to modify it.
+    /// The file is read once, so that the checksum and the signature are verified over the decoded blobs.
+    pub fn load_lazy(path: &Path) -> Result<Model> {
+        let data = std::fs::read(path).context("Can't open file")?;
+        #[cfg(feature = "signing")]
+        crate::signing::verify_file(path, &data)?;
+        Model::load_lazy_data(data, path)
+    }
+
+    /// Like load_lazy, for a model downloaded in memory.
+    pub(crate) fn load_lazy_data(data: Vec<u8>, path: &Path) -> Result<Model> {
+        let payload_len = crate::integrity::unseal(&data, path)?.len();
+        tracing::info!(path = path.to_str(), "Loading provided model lazily");
+        let (header, _) = split(&data[..payload_len])
+            .with_context(|| Failure::IncompatibleModel(path.to_path_buf()))?;
+        let (created_at, version, baselines, params, table) = decode_header(header)
+            .with_context(|| Failure::IncompatibleModel(path.to_path_buf()))?;
+        let offset = HEADER_OFFSET + header.len();
+        let mut data = data;
+        data.truncate(payload_len);
+        Ok(Model::with_lazy(
+            created_at,
+            version,
+            baselines,
+            params,
+            LazyIndexes::new(data, offset, table),
+        ))
+    }
+
+    fn with_lazy(
+        created_at: SystemTime,
+        version: String,
+        baselines: Baselines,
+        params: TrainParams,
+        lazy: LazyIndexes,
+    ) -> Model {
+        if !crate::version::is_compatible(&version) {
+            tracing::warn!(
+                "the model was created by logreduce {}, this is {}: train it again",
+                version,
+                crate::version::VERSION
+            );
+        }
+        Model {
+            created_at,
+            version,
+            baselines,
+            indexes: HashMap::new(),
+            params,
+            lazy: Some(lazy),
+        }
+    }
+}
+
+#[test]
+fn test_lazy_model() {
+    use crate::{Content, OutputMode};
+    let model = crate::train_memory(vec![
+        ("service.log", "INFO service started\n"),
+        ("worker.log", "INFO worker ready\n"),
+    ]);
+    let mut data = Vec::new();
+    model.save_writer(&mut data).unwrap();
+    let path = std::env::temp_dir().join("logreduce-test-lazy.bin");
+    std::fs::write(&path, &data).unwrap();
+
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started\nERROR service crashed\n",
+        )])
+    };
+    let expected = model.report(OutputMode::Quiet, target()).unwrap();
+    for lazy in [
+        Model::load_lazy(&path).unwrap(),
+        Model::load_lazy_data(data.clone(), &path).unwrap(),
+    ] {
+        let report = lazy.report(OutputMode::Quiet, target()).unwrap();
+        assert_eq!(report.total_anomaly_count, expected.total_anomaly_count);
+        assert_eq!(lazy.params(), model.params());
+        let table = &lazy.lazy.as_ref().unwrap().table;
+        let is_loaded = |name: &str| table[&IndexName::from_path(name)].1.get().is_some();
+        assert!(is_loaded("service.log"));
+        assert!(!is_loaded("worker.log"));
+        assert!(lazy.save_writer(&mut Vec::new()).is_err());
+    }
+
+    // The corrupted models are refused before any index is used.
+    let mut corrupted = data.clone();
+    let last_blob = corrupted.len() - 64;
+    corrupted[last_blob] ^= 0xff;
+    std::fs::write(&path, &corrupted).unwrap();
+    let err = Model::load_lazy(&path).unwrap_err();
+    assert_eq!(
+        crate::errors::find_failure(&err),
+        Some(&Failure::UntrustedModel(path.clone()))
+    );
+
+    // The models saved before the index table are refused.
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    bincode::serialize_into(&mut encoder, &model).unwrap();
+    std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+    for err in [
+        Model::load(&path).unwrap_err(),
+        Model::load_lazy(&path).unwrap_err(),
+    ] {
+        assert_eq!(
+            crate::errors::find_failure(&err),
+            Some(&Failure::IncompatibleModel(path.clone()))
+        );
+    }
+}
+
+#[test]
+fn test_model_format() {
+    let model = crate::train_memory(vec![("service.log", "INFO service started\n")]);
+    let path = Path::new("model.bin");
+    let is_incompatible = |data: &[u8]| {
+        let err = Model::load_reader(data, path).unwrap_err();
+        crate::errors::find_failure(&err) == Some(&Failure::IncompatibleModel(path.to_path_buf()))
+    };
+
+    // The models saved with another format version are refused before decoding the header.
+    let mut data = encode(&model, Compression::default()).unwrap();
+    data[MAGIC.len()] += 1;
+    assert!(is_incompatible(&data));
+    assert!(is_incompatible(&data[..MAGIC.len() + 2]));
+
+    // The models saved by the first release, without the version and the index table, are
+    // refused with an error instead of a huge allocation.
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    bincode::serialize_into(
+        &mut encoder,
+        &(&model.created_at, &model.baselines, &model.indexes),
+    )
+    .unwrap();
+    assert!(is_incompatible(&encoder.finish().unwrap()));
+
+    // A corrupted length is an error.
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    bincode::serialize_into(&mut encoder, &(&model.created_at, u64::MAX / 4)).unwrap();
+    assert!(is_incompatible(&encoder.finish().unwrap()));
+}
@@ -0,0 +1,151 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module extracts the log level of the anomalies, from the usual level words such as
+//! `WARNING` or `[error]`, the `level=warn` fields, and the syslog `<PRI>` prefix.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Report;
+
+/// The number of leading words searched for a level.
+const MAX_WORDS: usize = 8;
+
+/// The log level of a line, ordered by severity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Notice,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl Level {
+    /// The level of a word, either in uppercase or from a level field.
+    fn from_word(word: &str) -> Option<Level> {
+        match word {
+            "TRACE" => Some(Level::Trace),
+            "DEBUG" | "DBG" => Some(Level::Debug),
+            "INFO" => Some(Level::Info),
+            "NOTICE" => Some(Level::Notice),
+            "WARN" | "WARNING" => Some(Level::Warning),
+            "ERROR" | "ERR" | "SEVERE" => Some(Level::Error),
+            "FATAL" | "CRITICAL" | "CRIT" | "ALERT" | "EMERG" | "PANIC" => Some(Level::Critical),
+            _ => None,
+        }
+    }
+
+    /// The level of a syslog priority, see RFC 5424.
+    fn from_priority(priority: u8) -> Level {
+        match priority % 8 {
+            0..=2 => Level::Critical,
+            3 => Level::Error,
+            4 => Level::Warning,
+            5 => Level::Notice,
+            6 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Level::from_word(&s.to_uppercase()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown level {}, expected trace, debug, info, notice, warning, error or critical",
+                s
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Notice => "notice",
+            Level::Warning => "warning",
+            Level::Error => "error",
+            Level::Critical => "critical",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Returns the level of a line, when it is found in the leading words.
+pub fn parse_level(line: &str) -> Option<Level> {
+    if let Some(priority) = line
+        .strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .and_then(|(priority, _)| priority.parse::<u8>().ok())
+    {
+        return Some(Level::from_priority(priority));
+    }
+    let mut words = line
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .take(MAX_WORDS);
+    let mut is_field = false;
+    words.find_map(|word| {
+        let level = match is_field {
+            // The value of a `level=` or `"severity":` field may be in lowercase.
+            true => Level::from_word(&word.to_uppercase()),
+            false => Level::from_word(word),
+        };
+        is_field = matches!(
+            word.to_lowercase().as_str(),
+            "level" | "lvl" | "severity" | "loglevel"
+        );
+        level
+    })
+}
+
+impl Report {
+    /// Remove the anomalies below the level, the anomalies without a level are kept.
+    pub fn filter_level(&mut self, min_level: Level) {
+        for log_report in &mut self.log_reports {
+            let count = log_report.anomalies.len();
+            log_report
+                .anomalies
+                .retain(|anomaly| anomaly.anomaly.level.is_none_or(|level| level >= min_level));
+            self.total_anomaly_count -= count - log_report.anomalies.len();
+        }
+        self.log_reports
+            .retain(|log_report| !log_report.anomalies.is_empty());
+    }
+}
+
+#[test]
+fn test_parse_level() {
+    let assert_level = |line: &str, expected: Option<Level>| {
+        assert_eq!(parse_level(line), expected, "{}", line);
+    };
+    assert_level(
+        "2022-04-01 10:03:00.123 | ERROR test failed",
+        Some(Level::Error),
+    );
+    assert_level("[10:04:00] [WARN] disk almost full", Some(Level::Warning));
+    assert_level(
+        "E0401 10:04:00 level=warn msg=retrying",
+        Some(Level::Warning),
+    );
+    assert_level(
+        r#"{"severity": "critical", "msg": "oom"}"#,
+        Some(Level::Critical),
+    );
+    assert_level("<11>Apr  1 10:04:00 host sshd: fatal", Some(Level::Error));
+    assert_level("Traceback (most recent call last)", None);
+    assert_level("the error count is 0", None);
+    assert_level("a b c d e f g h ERROR", None);
+    assert_eq!("warn".parse::<Level>().unwrap(), Level::Warning);
+    assert!("loud".parse::<Level>().is_err());
+    assert!(Level::Error > Level::Warning);
+}
@@ -0,0 +1,298 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Grafana Loki content, for the urls such as
+//! `loki://loki.example.com:3100/?query={app="nova"}&since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z`.
+//! The LogQL query result streams are read in the timestamp order, as a single `loki/query` memory source,
+//! or grouped by the value of the `group` label.
+//! The baseline is the previous time window of the same duration.
+//! Use `loki+http://` for a server without TLS. Set LOKI_TOKEN to use a bearer token, or provide the
+//! basic auth credentials in the url, and set LOKI_ORG_ID for a multi-tenant server.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::{Baselines, Content, Source};
+
+/// The number of entries per request.
+const PAGE_SIZE: usize = 5000;
+
+lazy_static::lazy_static! {
+    static ref TOKEN: Option<String> = std::env::var("LOKI_TOKEN").ok();
+    static ref ORG_ID: Option<String> = std::env::var("LOKI_ORG_ID").ok();
+}
+
+/// A LogQL query of a time range.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Query {
+    /// The base url of the Loki server.
+    pub url: Url,
+    pub query: String,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    /// The label used to split the streams into sources.
+    pub group_label: Option<String>,
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut url = self.url.clone();
+        url.set_password(None).ok();
+        {
+            let mut query = url.query_pairs_mut();
+            query.append_pair("query", &self.query);
+            for (name, value) in [
+                ("since", &self.since),
+                ("until", &self.until),
+                ("group", &self.group_label),
+            ] {
+                if let Some(value) = value {
+                    query.append_pair(name, value);
+                }
+            }
+        }
+        write!(f, "{}", url)
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryResponse {
+    data: QueryData,
+}
+
+#[derive(Deserialize)]
+struct QueryData {
+    result: Vec<Stream>,
+}
+
+#[derive(Deserialize)]
+struct Stream {
+    #[serde(default)]
+    stream: BTreeMap<String, String>,
+    /// The nanosecond timestamp and the line of the entries.
+    values: Vec<(String, String)>,
+}
+
+impl Query {
+    fn query_range_url(&self) -> Result<Url> {
+        let mut url = self.url.clone();
+        url.set_query(None);
+        url.path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("Bad url {}", self.url))?
+            .pop_if_empty()
+            .extend(&["loki", "api", "v1", "query_range"]);
+        Ok(url)
+    }
+
+    /// Request a page of entries, starting at the start date or nanosecond timestamp.
+    fn query_range(&self, url: &Url, start: Option<&str>) -> Result<Vec<Stream>> {
+        let mut url = url.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("query", &self.query)
+                .append_pair("limit", &PAGE_SIZE.to_string())
+                .append_pair("direction", "forward");
+            if let Some(start) = start {
+                query.append_pair("start", start);
+            }
+            if let Some(until) = &self.until {
+                query.append_pair("end", until);
+            }
+        }
+        let _permit = crate::urls::LIMITER.acquire(&url);
+        let mut request = crate::reader::CLIENT.get(url.clone());
+        if let Some(token) = &*TOKEN {
+            request = request.bearer_auth(token);
+        }
+        if let Some(org_id) = &*ORG_ID {
+            request = request.header("X-Scope-OrgID", org_id);
+        }
+        let resp = request.send().context("Can't query loki")?;
+        let status = resp.status();
+        crate::urls::record_response(&self.url, status.as_u16(), resp.url());
+        if !status.is_success() {
+            let failure = crate::errors::Failure::HttpStatus(self.url.clone(), status.as_u16());
+            return Err(anyhow::Error::new(failure).context(format!(
+                "Query failed: {}",
+                resp.text().unwrap_or_default().trim()
+            )));
+        }
+        let resp: QueryResponse =
+            serde_json::from_reader(resp).context("Can't decode the query response")?;
+        Ok(resp.data.result)
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let url = self.query_range_url()?;
+        let mut groups: BTreeMap<String, Vec<(u128, String)>> = BTreeMap::new();
+        let mut start = self.since.clone();
+        loop {
+            let mut count = 0;
+            let mut last = None;
+            for stream in self.query_range(&url, start.as_deref())? {
+                let group = self
+                    .group_label
+                    .as_ref()
+                    .and_then(|label| stream.stream.get(label).cloned())
+                    .unwrap_or_else(|| "query".to_string());
+                let entries = groups.entry(group).or_default();
+                for (timestamp, line) in stream.values {
+                    let timestamp: u128 = timestamp.parse().context("Invalid timestamp")?;
+                    count += 1;
+                    last = last.max(Some(timestamp));
+                    entries.push((timestamp, line));
+                }
+            }
+            match last {
+                // The next page starts after the last entry.
+                Some(last) if count >= PAGE_SIZE => start = Some((last + 1).to_string()),
+                _ => break,
+            }
+        }
+        Ok(groups
+            .into_iter()
+            .map(|(group, mut entries)| {
+                entries.sort_by_key(|(timestamp, _)| *timestamp);
+                let mut data = Vec::new();
+                for (_, line) in entries {
+                    data.extend_from_slice(line.trim_end().as_bytes());
+                    data.push(b'\n');
+                }
+                Source::Memory(
+                    format!("loki/{}", group.replace('/', "_")),
+                    crate::memory::MemoryData(data.into()),
+                )
+            })
+            .collect())
+    }
+
+    /// The previous time window, with the same duration.
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        match crate::elasticsearch::previous_window(&self.since, &self.until) {
+            Some((since, until)) => Ok(vec![Content::Loki(Box::new(Query {
+                since: Some(since),
+                until: Some(until),
+                ..self.clone()
+            }))]),
+            None => Err(anyhow::anyhow!(
+                "Can't discover loki baselines without a since and until date, they need to be provided"
+            )),
+        }
+    }
+}
+
+impl Content {
+    /// Create the query content of a `loki://` or `loki+http://` url.
+    pub fn from_loki_url(url: &Url) -> Result<Content> {
+        let scheme = match url.scheme() {
+            "loki" => "https",
+            "loki+http" => "http",
+            scheme => return Err(anyhow::anyhow!("Unknown loki scheme: {}", scheme)),
+        };
+        let mut base = Url::parse(&format!(
+            "{}://{}{}",
+            scheme,
+            &url[url::Position::BeforeUsername..url::Position::AfterPort],
+            url.path()
+        ))
+        .with_context(|| format!("Bad url {}", url))?;
+        base.set_query(None);
+        let mut query = None;
+        let mut since = None;
+        let mut until = None;
+        let mut group_label = None;
+        for (key, value) in url.query_pairs() {
+            let value = value.into_owned();
+            match key.as_ref() {
+                "query" => query = Some(value),
+                "since" => since = Some(value),
+                "until" => until = Some(value),
+                "group" => group_label = Some(value),
+                _ => return Err(anyhow::anyhow!("Unknown loki parameter: {}", key)),
+            }
+        }
+        Ok(Content::Loki(Box::new(Query {
+            url: base,
+            query: query.ok_or_else(|| anyhow::anyhow!("Missing loki query: {}", url))?,
+            since,
+            until,
+            group_label,
+        })))
+    }
+}
+
+#[test]
+fn test_loki_url() -> Result<()> {
+    let url = Url::parse(
+        r#"loki://loki.example.com:3100/?query={app="nova"}&since=2022-03-15T10:00:00Z&until=2022-03-15T11:00:00Z"#,
+    )?;
+    match Content::from_loki_url(&url)? {
+        Content::Loki(query) => {
+            assert_eq!(query.url.as_str(), "https://loki.example.com:3100/");
+            assert_eq!(query.query, r#"{app="nova"}"#);
+            assert_eq!(
+                query.query_range_url()?.as_str(),
+                "https://loki.example.com:3100/loki/api/v1/query_range"
+            );
+            match query.discover_baselines()?.as_slice() {
+                [Content::Loki(baseline)] => {
+                    assert_eq!(baseline.since.as_deref(), Some("2022-03-15T09:00:00+00:00"));
+                    assert_eq!(baseline.until.as_deref(), Some("2022-03-15T10:00:00+00:00"));
+                }
+                baselines => panic!("Unexpected baselines {:?}", baselines),
+            }
+        }
+        content => panic!("Unexpected content {}", content),
+    }
+    assert!(Content::from_loki_url(&Url::parse("loki://loki.example.com/")?).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_loki_query() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let query_mock = mock("GET", "/loki/api/v1/query_range")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("query".into(), r#"{app="nova"}"#.into()),
+            Matcher::UrlEncoded("direction".into(), "forward".into()),
+        ]))
+        .with_body(
+            r#"{"status": "success", "data": {"resultType": "streams", "result": [
+              {"stream": {"pod": "api-1"}, "values": [["1647338402000000000", "Timeout"], ["1647338401000000000", "Connection refused\n"]]},
+              {"stream": {"pod": "db-1"}, "values": [["1647338403000000000", "Started"]]}
+            ]}}"#,
+        )
+        .create();
+    let url = mockito::server_url().replacen("http", "loki+http", 1);
+    let content = Content::from_loki_url(&Url::parse(&format!(
+        "{}/?query=%7Bapp%3D%22nova%22%7D&group=pod",
+        url
+    ))?)?;
+    let sources = match content {
+        Content::Loki(query) => query.get_sources()?,
+        content => panic!("Unexpected content {}", content),
+    };
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["loki/api-1", "loki/db-1"]
+    );
+    match &sources[0] {
+        Source::Memory(_, data) => assert_eq!(data.0, "Connection refused\nTimeout\n"),
+        source => panic!("Unexpected source {:?}", source),
+    }
+    query_mock.assert();
+    Ok(())
+}
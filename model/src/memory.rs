@@ -0,0 +1,95 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides helpers to work with logs that are already loaded in memory.
+
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Content, Source};
+
+/// The in-memory log data. It is not serialized to keep the models and reports small,
+/// thus a loaded model or report only contains the log names.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryData(pub Bytes);
+
+impl Serialize for MemoryData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+impl<'de> Deserialize<'de> for MemoryData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        <()>::deserialize(deserializer).map(|_| MemoryData::default())
+    }
+}
+
+impl Content {
+    /// Create a Content from a list of (name, data), for example:
+    ///
+    /// ```rust
+    /// let content = logreduce_model::Content::from_memory(vec![("job-output.txt", "log line")]);
+    /// ```
+    pub fn from_memory<I, N, D>(logs: I) -> Content
+    where
+        I: IntoIterator<Item = (N, D)>,
+        N: Into<String>,
+        D: Into<Bytes>,
+    {
+        Content::Memory(
+            logs.into_iter()
+                .map(|(name, data)| (name.into(), MemoryData(data.into())))
+                .collect(),
+        )
+    }
+}
+
+impl Source {
+    pub fn memory_open(data: &MemoryData) -> Result<crate::reader::DecompressReader> {
+        Ok(crate::reader::from_memory(data.0.clone()))
+    }
+
+    pub fn memory_iter(logs: &[(String, MemoryData)]) -> impl Iterator<Item = Result<Source>> {
+        logs.iter()
+            .map(|(name, data)| Ok(Source::Memory(name.clone(), data.clone())))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+#[test]
+fn test_memory_content() {
+    let baseline = Content::from_memory(vec![(
+        "service.log",
+        (0..200)
+            .map(|idx| format!("INFO service started worker {}\n", idx))
+            .collect::<String>(),
+    )]);
+    let target = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started worker 42\nERROR Traceback oops\n",
+    )]);
+    let model = crate::Model::train(
+        crate::OutputMode::Quiet,
+        vec![baseline],
+        crate::hashing_index::new,
+    )
+    .unwrap();
+    let report = model.report(crate::OutputMode::Quiet, target).unwrap();
+    assert_eq!(report.total_line_count, 2);
+    assert_eq!(report.total_anomaly_count, 1);
+    assert_eq!(
+        report.log_reports[0].anomalies[0].anomaly.line,
+        "ERROR Traceback oops"
+    );
+
+    // The data is not serialized
+    let encoded = bincode::serialize(&report.target).unwrap();
+    let decoded: Content = bincode::deserialize(&encoded).unwrap();
+    assert_eq!(
+        decoded,
+        Content::Memory(vec![("service.log".into(), MemoryData::default())])
+    );
+}
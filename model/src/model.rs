@@ -4,22 +4,113 @@
 //! This library provides a model implementation for the [logreduce](https://github.com/logreduce/logreduce) project.
 //!
 //! This module dispatch the abstract Content and Source to their implementationm e.g. the files module.
+//!
+//! A trained or loaded Model is immutable and Send + Sync. A service can load it once
+//! and share it with an [std::sync::Arc] to serve concurrent reports:
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use logreduce_model::{Content, Model, OutputMode};
+//! use std::sync::Arc;
+//!
+//! let model = Arc::new(Model::load(std::path::Path::new("model.bin"))?);
+//! let workers = ["job1.log", "job2.log"].iter().map(|path| {
+//!     let model = model.clone();
+//!     let target = Content::from_path(std::path::Path::new(path));
+//!     std::thread::spawn(move || model.report(OutputMode::Quiet, target?))
+//! });
+//! for worker in workers.collect::<Vec<_>>() {
+//!     let report = worker.join().expect("report thread")?;
+//! }
+//! # Ok(()) }
+//! ```
+//!
+//! The reports should use the Quiet output mode, as the progress messages are not synchronized.
+//! Note that the reader cache, enabled with LOGREDUCE_CACHE, expects a single writer per url.
 
 use anyhow::{Context, Result};
 use itertools::Itertools;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant, SystemTime};
 use url::Url;
 
+pub mod accepted;
+pub mod annotations;
+pub mod anonymity;
+pub mod ansi;
+pub mod ansible;
+pub mod archive;
+#[cfg(feature = "cloud")]
+pub mod azure;
+pub mod changes;
+#[cfg(feature = "http")]
+pub mod circleci;
+#[cfg(feature = "cloud")]
+pub mod cloudwatch;
+pub mod compression;
+#[cfg(feature = "http")]
+pub mod docker;
+#[cfg(feature = "http")]
+pub mod elasticsearch;
+#[cfg(feature = "embedding")]
+pub mod embedding_index;
+pub mod encoding;
+pub mod errors;
+pub mod external;
 pub mod files;
+#[cfg(feature = "cloud")]
+pub mod gcs;
+#[cfg(feature = "http")]
+pub mod github;
+#[cfg(feature = "http")]
+pub mod gitlab;
+mod integrity;
+#[cfg(feature = "http")]
+pub mod jenkins;
+pub mod journal;
+pub mod jsonlines;
+#[cfg(feature = "http")]
+pub mod kafka;
+mod lazy;
+pub mod levels;
+#[cfg(feature = "http")]
+pub mod loki;
+pub mod memory;
+pub mod multiline;
+pub mod outliers;
+pub mod params;
+pub mod parquet;
+pub mod patterns;
+pub mod perf;
+pub mod portable;
+pub mod precompute;
 pub mod process;
+pub mod profiles;
+#[cfg(feature = "cloud")]
+pub mod prow;
+pub mod quantization;
+pub mod ranges;
 mod reader;
+#[cfg(feature = "cloud")]
+pub mod s3;
+pub mod sampling;
+pub mod sarif;
+pub mod selection;
+pub mod severity;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod storage;
 pub mod urls;
+pub mod usage;
+pub mod version;
+pub mod warmup;
+#[cfg(feature = "zuul")]
 pub mod zuul;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum OutputMode {
     // Print every steps
     Debug,
@@ -40,11 +131,27 @@ impl OutputMode {
 pub enum Input {
     Path(String),
     Url(String),
+    /// The `-` path.
+    Stdin,
 }
 
 impl Input {
     pub fn from_string(s: String) -> Input {
-        match s.starts_with("http") {
+        if s == "-" {
+            return Input::Stdin;
+        }
+        match s.starts_with("http")
+            || s.starts_with("file://")
+            || s.starts_with("s3://")
+            || s.starts_with("gs://")
+            || s.starts_with("journal:")
+            || s.starts_with("cloudwatch:")
+            || s.starts_with("docker://")
+            || s.starts_with("loki://")
+            || s.starts_with("loki+http://")
+            || s.starts_with("kafka://")
+            || s.starts_with("kafka+http://")
+        {
             true => Input::Url(s),
             false => Input::Path(s),
         }
@@ -56,7 +163,34 @@ impl Input {
 pub enum Content {
     File(Source),
     Directory(Source),
+    #[cfg(feature = "zuul")]
     Zuul(Box<zuul::Build>),
+    #[cfg(feature = "http")]
+    GithubActions(Box<github::Run>),
+    #[cfg(feature = "http")]
+    GitlabPipeline(Box<gitlab::Pipeline>),
+    #[cfg(feature = "http")]
+    Jenkins(Box<jenkins::Build>),
+    #[cfg(feature = "cloud")]
+    Prow(Box<prow::Job>),
+    #[cfg(feature = "http")]
+    CircleCi(Box<circleci::Job>),
+    #[cfg(feature = "cloud")]
+    S3(Box<s3::Prefix>),
+    #[cfg(feature = "cloud")]
+    Gcs(Box<gcs::Prefix>),
+    Journal(Box<journal::Query>),
+    #[cfg(feature = "http")]
+    Docker(Box<docker::Container>),
+    #[cfg(feature = "http")]
+    Elasticsearch(Box<elasticsearch::Search>),
+    #[cfg(feature = "http")]
+    Loki(Box<loki::Query>),
+    #[cfg(feature = "cloud")]
+    CloudWatch(Box<cloudwatch::LogGroup>),
+    #[cfg(feature = "http")]
+    Kafka(Box<kafka::Topic>),
+    Memory(Vec<(String, memory::MemoryData)>),
 }
 
 impl std::fmt::Display for Content {
@@ -64,7 +198,34 @@ impl std::fmt::Display for Content {
         match self {
             Content::File(src) => write!(f, "File({})", src),
             Content::Directory(src) => write!(f, "Directory({})", src),
+            #[cfg(feature = "zuul")]
             Content::Zuul(build) => write!(f, "Zuul({})", build),
+            #[cfg(feature = "http")]
+            Content::GithubActions(run) => write!(f, "GithubActions({})", run),
+            #[cfg(feature = "http")]
+            Content::GitlabPipeline(pipeline) => write!(f, "GitlabPipeline({})", pipeline),
+            #[cfg(feature = "http")]
+            Content::Jenkins(build) => write!(f, "Jenkins({})", build),
+            #[cfg(feature = "cloud")]
+            Content::Prow(job) => write!(f, "Prow({})", job),
+            #[cfg(feature = "http")]
+            Content::CircleCi(job) => write!(f, "CircleCi({})", job),
+            #[cfg(feature = "cloud")]
+            Content::S3(prefix) => write!(f, "S3({})", prefix),
+            #[cfg(feature = "cloud")]
+            Content::Gcs(prefix) => write!(f, "Gcs({})", prefix),
+            Content::Journal(query) => write!(f, "Journal({})", query),
+            #[cfg(feature = "http")]
+            Content::Docker(container) => write!(f, "Docker({})", container),
+            #[cfg(feature = "http")]
+            Content::Elasticsearch(search) => write!(f, "Elasticsearch({})", search),
+            #[cfg(feature = "http")]
+            Content::Loki(query) => write!(f, "Loki({})", query),
+            #[cfg(feature = "cloud")]
+            Content::CloudWatch(group) => write!(f, "CloudWatch({})", group),
+            #[cfg(feature = "http")]
+            Content::Kafka(topic) => write!(f, "Kafka({})", topic),
+            Content::Memory(logs) => write!(f, "Memory({})", logs.len()),
         }
     }
 }
@@ -74,6 +235,11 @@ impl std::fmt::Display for Content {
 pub enum Source {
     Local(usize, PathBuf),
     Remote(usize, url::Url),
+    Memory(String, memory::MemoryData),
+    /// The standard input, which can only be read once.
+    Stdin,
+    /// A tar archive member, named `<archive>/<member path>`, see archive.rs.
+    Member(Box<Source>, String),
 }
 
 impl std::fmt::Display for Source {
@@ -81,6 +247,9 @@ impl std::fmt::Display for Source {
         match self {
             Source::Local(_, _) => write!(f, "local: {}", self.get_relative()),
             Source::Remote(_, _) => write!(f, "remote: {}", self.get_relative()),
+            Source::Memory(_, _) => write!(f, "memory: {}", self.get_relative()),
+            Source::Stdin => write!(f, "stdin"),
+            Source::Member(_, _) => write!(f, "member: {}", self.get_relative()),
         }
     }
 }
@@ -90,6 +259,9 @@ impl Source {
         match self {
             Source::Local(base_len, path) => &path.to_str().unwrap_or("")[*base_len..],
             Source::Remote(base_len, url) => &url.as_str()[*base_len..],
+            Source::Memory(name, _) => name.as_str(),
+            Source::Stdin => "stdin",
+            Source::Member(_, name) => name.as_str(),
         }
     }
 
@@ -97,9 +269,43 @@ impl Source {
         match self {
             Source::Local(_, path) => path.to_str().unwrap_or(""),
             Source::Remote(_, url) => url.as_str(),
+            Source::Memory(name, _) => name.as_str(),
+            Source::Stdin => "-",
+            Source::Member(_, name) => name.as_str(),
         }
     }
 
+    /// Open the source content.
+    pub fn open(&self) -> Result<crate::reader::DecompressReader> {
+        match self {
+            Source::Local(_, path_buf) => Source::file_open(path_buf.as_path()),
+            Source::Remote(prefix, url) => Source::url_open(*prefix, url),
+            Source::Memory(_, data) => Source::memory_open(data),
+            Source::Stdin => Source::stdin_open(),
+            Source::Member(archive, name) => archive::member_open(archive, name),
+        }
+    }
+
+    /// Feed the source identity to a hasher.
+    /// Local files are identified by their size and modification time,
+    /// remote urls are expected to be immutable build artifacts.
+    fn digest(&self, hasher: &mut sha2::Sha256) -> Result<()> {
+        use sha2::Digest;
+        hasher.update(self.as_str());
+        match self {
+            Source::Local(_, path) => {
+                let meta = std::fs::metadata(path).context("Can't read file metadata")?;
+                hasher.update(meta.len().to_le_bytes());
+                hasher.update(format!("{:?}", meta.modified()?));
+            }
+            Source::Memory(_, data) => hasher.update(&data.0),
+            Source::Remote(_, _) => {}
+            Source::Stdin => return Err(anyhow::anyhow!("Can't identify the stdin content")),
+            Source::Member(archive, _) => archive.digest(hasher)?,
+        }
+        Ok(())
+    }
+
     fn is_valid(&self) -> bool {
         lazy_static::lazy_static! {
             static ref EXTS: Vec<String> = {
@@ -113,8 +319,6 @@ impl Source {
                     ".eot", ".otf", ".woff", ".woff2", ".ttf",
                     // config
                     ".yaml", ".ini", ".conf",
-                    // not relevant
-                    "job-output.json",
                     // binary data with known location
                     "cacerts",
                     "local/creds", "pacemaker/authkey",
@@ -136,12 +340,43 @@ impl Source {
 /// A list of nominal content, e.g. a successful build.
 type Baselines = Vec<Content>;
 
+/// The sources of a content, see Content::collect_selected_sources.
+struct SelectedSources {
+    sources: Vec<Source>,
+    /// The sources skipped with ReportOptions::skipped.
+    deselected: Vec<Source>,
+    /// The failed task of the job-output.json, so that it is only read once.
+    failed_task: Option<ansible::FailedTask>,
+}
+
+/// The result of the inspection of the sources of an index, see Model::report.
+#[derive(Default)]
+struct Inspection {
+    warning: Option<String>,
+    index_report: Option<IndexReport>,
+    log_reports: Vec<LogReport>,
+    read_errors: Vec<(Source, String)>,
+    empty_sources: Vec<Source>,
+    sampled_sources: Vec<Source>,
+    http_responses: Vec<urls::HttpResponse>,
+    file_count: usize,
+    line_count: usize,
+    baseline_matches: Vec<(usize, usize)>,
+}
+
 /// An archive of baselines that is used to search anomaly.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Model {
     created_at: SystemTime,
+    /// The version of the program that created the model.
+    version: String,
     baselines: Baselines,
     indexes: HashMap<IndexName, Index>,
+    /// The parameters of the training.
+    params: params::TrainParams,
+    /// The indexes that are not loaded yet, see Model::load_lazy.
+    #[serde(skip)]
+    lazy: Option<lazy::LazyIndexes>,
 }
 
 /// A LogModelName is an identifier that is used to group similar source.
@@ -163,6 +398,9 @@ impl IndexName {
     }
 }
 
+/// The default minimum number of baseline lines for an index to produce confident results.
+pub const DEFAULT_MIN_BASELINE_LINES: usize = 100;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Index {
     created_at: SystemTime,
@@ -171,13 +409,55 @@ pub struct Index {
     index: ChunkIndex,
     line_count: usize,
     byte_count: usize,
+    /// The end row and the baseline of each trained source, see process::set_attribution.
+    baseline_rows: Vec<(usize, usize)>,
+    /// The number of runs since the index last matched a target, see Model::record_run.
+    idle_runs: usize,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Anomaly {
     pub distance: f32,
+    /// The distance adjusted with the keyword weights, see severity::Keywords.
+    pub severity: f32,
     pub pos: usize,
     pub line: String,
+    /// The identical lines skipped after this one, see ChunkProcessor::set_repeats.
+    pub repeats: Option<Repeats>,
+    /// The log level of the line, see levels::parse_level.
+    pub level: Option<levels::Level>,
+}
+
+/// The number of skipped duplicates of an anomaly, and the position of the last one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Repeats {
+    pub count: usize,
+    pub last_pos: usize,
+}
+
+impl std::fmt::Display for Repeats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "repeated {} times, last at line {}",
+            self.count, self.last_pos
+        )
+    }
+}
+
+impl Anomaly {
+    /// The repeats note to append to the rendered line.
+    pub fn repeats_note(&self) -> String {
+        match self.repeats {
+            Some(repeats) => format!("  [{}]", repeats),
+            None => String::new(),
+        }
+    }
+
+    /// The position of the last line, after the folded lines of a multiline record.
+    pub fn end_pos(&self) -> usize {
+        self.pos + self.line.matches('\n').count()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -195,6 +475,7 @@ pub struct LogReport {
     pub anomalies: Vec<AnomalyContext>,
     pub source: Source,
     pub index_name: IndexName,
+    pub annotation: Option<annotations::Annotation>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -215,6 +496,8 @@ impl IndexReport {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Report {
     pub created_at: SystemTime,
+    /// The version of the program that created the report.
+    pub version: String,
     pub run_time: Duration,
     pub target: Content,
     pub baselines: Vec<Content>,
@@ -222,44 +505,123 @@ pub struct Report {
     pub index_reports: HashMap<IndexName, IndexReport>,
     pub index_errors: Vec<Vec<Source>>,
     pub read_errors: Vec<(Source, String)>,
+    pub empty_sources: Vec<Source>,
+    /// The oversized sources partially analyzed, see sampling::Sampling.
+    pub sampled_sources: Vec<Source>,
+    pub warnings: Vec<String>,
+    pub accepted_anomalies: Vec<accepted::AcceptedAnomaly>,
+    /// The task which failed the build, when it is known.
+    pub failed_task: Option<ansible::FailedTask>,
+    /// The number of inspected sources.
+    pub total_file_count: usize,
     pub total_line_count: usize,
     pub total_anomaly_count: usize,
+    pub usage: usage::ResourceUsage,
+    /// The http responses of the remote target sources.
+    pub http_responses: Vec<urls::HttpResponse>,
+    /// The lines matched by each baseline, see process::set_attribution.
+    pub baseline_matches: Vec<BaselineMatches>,
+    /// The target index names that matched an index of the model, see Model::record_run.
+    pub inspected_indexes: Vec<IndexName>,
+}
+
+/// The options of a report, which only apply to its target, see Model::report_with.
+#[derive(Debug)]
+pub struct ReportOptions {
+    /// The relative names of the target sources to skip, for example when they are deselected
+    /// with the `--interactive` option, see Source::get_relative.
+    pub skipped: BTreeSet<String>,
+    /// Only inspect the ranges of the target sources.
+    pub ranges: ranges::Ranges,
+    /// The results of the indexes trained with fewer lines are reported as low-confidence.
+    pub min_baseline_lines: usize,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        ReportOptions {
+            skipped: BTreeSet::new(),
+            ranges: ranges::Ranges::default(),
+            min_baseline_lines: DEFAULT_MIN_BASELINE_LINES,
+        }
+    }
+}
+
+/// The unique target lines matched by a baseline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BaselineMatches {
+    pub baseline: Content,
+    pub matched_lines: usize,
+    /// The lines that no other baseline matched.
+    pub exclusive_lines: usize,
 }
 
 impl Report {
     pub fn save(&self, path: &Path) -> Result<()> {
+        self.save_writer(std::fs::File::create(path).context("Can't create report file")?)
+    }
+
+    pub fn load(path: &Path) -> Result<Report> {
+        Report::load_reader(std::fs::File::open(path).context("Can't open report file")?)
+    }
+
+    /// Serialize the full report as JSON, to be consumed by other tools.
+    pub fn write_json<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self).context("Can't serialize report")
+    }
+
+    fn save_writer<W: std::io::Write>(&self, writer: W) -> Result<()> {
         bincode::serialize_into(
-            flate2::write::GzEncoder::new(
-                std::fs::File::create(path).context("Can't create report file")?,
-                flate2::Compression::fast(),
-            ),
+            flate2::write::GzEncoder::new(writer, flate2::Compression::fast()),
             self,
         )
         .context("Can't save report")
     }
 
-    pub fn load(path: &Path) -> Result<Report> {
-        bincode::deserialize_from(flate2::read::GzDecoder::new(
-            std::fs::File::open(path).context("Can't open report file")?,
-        ))
-        .context("Can't load report")
+    fn load_reader<R: std::io::Read>(reader: R) -> Result<Report> {
+        bincode::deserialize_from(flate2::read::GzDecoder::new(reader)).context("Can't load report")
     }
 }
 
 impl Index {
+    pub fn train(sources: &[Source], index: ChunkIndex) -> Result<Index> {
+        let sources: Vec<(usize, Source)> = sources.iter().map(|s| (0, s.clone())).collect();
+        Index::train_baselines(&sources, index, false)
+    }
+
+    /// Train the index with the sources of each baseline, to attribute the matches to the baselines.
+    /// The lines are deduplicated per baseline when `per_baseline` is set, otherwise the lines
+    /// shared by many baselines are only attributed to the first one.
     #[tracing::instrument(level = "debug", name = "Index::train", skip(index))]
-    pub fn train(sources: &[Source], mut index: ChunkIndex) -> Result<Index> {
+    fn train_baselines(
+        sources: &[(usize, Source)],
+        mut index: ChunkIndex,
+        per_baseline: bool,
+    ) -> Result<Index> {
         let created_at = SystemTime::now();
         let start_time = Instant::now();
         let mut trainer = process::ChunkTrainer::new(&mut index);
-        for source in sources {
-            let reader = match source {
-                Source::Local(_, path_buf) => Source::file_open(path_buf.as_path())?,
-                Source::Remote(prefix, url) => Source::url_open(*prefix, url)?,
-            };
-            trainer
+        let mut trained_sources = Vec::with_capacity(sources.len());
+        let mut baseline_rows = Vec::with_capacity(sources.len());
+        for (baseline, source) in sources {
+            if per_baseline
+                && baseline_rows
+                    .last()
+                    .is_some_and(|(_, last)| last != baseline)
+            {
+                trainer.forget_lines();
+            }
+            trainer.set_profile(profiles::profile_of(&IndexName::from_source(source)));
+            let reader = source.open()?;
+            if trainer
                 .add(reader)
                 .with_context(|| format!("Failed to load {}", source))?
+            {
+                trained_sources.push(source.clone());
+                baseline_rows.push((trainer.row_count, *baseline));
+            } else {
+                tracing::info!("{}: skipped: empty", source);
+            }
         }
         trainer.complete();
         let train_time = start_time.elapsed();
@@ -269,22 +631,195 @@ impl Index {
             line_count: trainer.line_count,
             byte_count: trainer.byte_count,
             index,
-            sources: sources.to_vec(),
+            sources: trained_sources,
+            baseline_rows,
+            idle_runs: 0,
         })
     }
 
+    /// When the index was trained.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// The number of recorded runs since the index last matched a target.
+    pub fn idle_runs(&self) -> usize {
+        self.idle_runs
+    }
+
+    /// Returns true when the index was trained without any content.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Returns a warning when the index doesn't have enough baseline lines to be trusted.
+    pub fn check_confidence(&self, index_name: &IndexName, min_lines: usize) -> Option<String> {
+        if self.line_count < min_lines {
+            Some(format!(
+                "results for {} are low-confidence: only {} baseline lines out of {}, add more baselines or train with longer logs",
+                index_name, self.line_count, min_lines
+            ))
+        } else {
+            None
+        }
+    }
+
     pub fn get_processor<'a>(
         &'a self,
         output_mode: OutputMode,
         source: &Source,
         skip_lines: &'a mut HashSet<String>,
+        ranges: &ranges::Ranges,
     ) -> Result<process::ChunkProcessor<crate::reader::DecompressReader>> {
         debug_or_progress(output_mode, &format!("Inspecting {}", source));
-        let fp = match source {
-            Source::Local(_, path_buf) => Source::file_open(path_buf.as_path()),
-            Source::Remote(prefix, url) => Source::url_open(*prefix, url),
-        }?;
-        Ok(process::ChunkProcessor::new(fp, &self.index, skip_lines))
+        let fp = source.open()?;
+        let (fp, sampled) = sampling::sample(fp);
+        let fp = ranges.restrict(source, fp);
+        let profile = profiles::profile_of(&IndexName::from_source(source));
+        let mut processor =
+            process::ChunkProcessor::new(fp, &self.index, skip_lines).with_profile(profile);
+        if process::is_attribution_enabled() && !self.baseline_rows.is_empty() {
+            let baseline_count = self.baseline_rows.iter().map(|(_, b)| b + 1).max();
+            processor =
+                processor.with_attribution(&self.baseline_rows, baseline_count.unwrap_or_default());
+        }
+        processor.sampled = sampled;
+        Ok(processor)
+    }
+
+    /// Create an empty window to learn the lines during a live session.
+    pub fn learning_window(&self) -> process::LearningWindow {
+        process::LearningWindow::new(self.index.factory())
+    }
+
+    /// Add the learned lines to the index, the small learned chunks are compacted.
+    pub fn learn(&mut self, window: process::LearningWindow) -> Result<()> {
+        for chunk in window.complete() {
+            self.index.append(chunk)?;
+        }
+        self.compact();
+        Ok(())
+    }
+
+    /// Merge the baseline chunks into fewer matrices, to improve the search locality
+    /// and to reduce the serialized size.
+    pub fn compact(&mut self) {
+        self.index.compact();
+    }
+
+    /// Add the baselines of another index of the same kind.
+    pub fn merge(&mut self, other: Index) -> Result<()> {
+        self.index.check_kind(&other.index)?;
+        self.train_time += other.train_time;
+        self.line_count += other.line_count;
+        self.byte_count += other.byte_count;
+        for source in other.sources {
+            if !self.sources.contains(&source) {
+                self.sources.push(source);
+            }
+        }
+        self.index.append(other.index)?;
+        self.idle_runs = self.idle_runs.min(other.idle_runs);
+        // The rows of the merged models can't be attributed to the baselines.
+        self.baseline_rows.clear();
+        self.compact();
+        Ok(())
+    }
+
+    /// Search the anomalies of a list of lines, without using a Source.
+    pub fn inspect_lines<'a, I>(
+        &'a self,
+        lines: I,
+    ) -> impl Iterator<Item = Result<AnomalyContext>> + 'a
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+        I::IntoIter: 'a,
+    {
+        process::ChunkProcessor::single(crate::reader::LinesReader::new(lines), &self.index)
+    }
+
+    /// Inspect the sources of the index, one after the other so that the duplicated anomalies are skipped.
+    fn inspect_sources(
+        &self,
+        output_mode: OutputMode,
+        index_name: &IndexName,
+        sources: &[Source],
+        baseline_count: usize,
+        options: &ReportOptions,
+    ) -> Inspection {
+        let mut inspection = Inspection {
+            warning: self.check_confidence(index_name, options.min_baseline_lines),
+            baseline_matches: vec![(0, 0); baseline_count],
+            ..Inspection::default()
+        };
+        if let Some(warning) = &inspection.warning {
+            tracing::warn!("{}", warning);
+        }
+        let mut skip_lines = HashSet::new();
+        for source in sources {
+            let start_time = Instant::now();
+            let mut anomalies = Vec::new();
+            let processor =
+                self.get_processor(output_mode, source, &mut skip_lines, &options.ranges);
+            if let Source::Remote(_, url) = source {
+                inspection
+                    .http_responses
+                    .push(urls::HttpResponse::take(url));
+            }
+            match processor {
+                Ok(mut processor) => {
+                    for anomaly in processor.by_ref() {
+                        match anomaly {
+                            Ok(anomaly) => anomalies.push(anomaly),
+                            Err(err) => {
+                                inspection
+                                    .read_errors
+                                    .push((source.clone(), format!("{}", err)));
+                                break;
+                            }
+                        }
+                    }
+                    processor.set_repeats(&mut anomalies);
+                    for (total, matches) in inspection
+                        .baseline_matches
+                        .iter_mut()
+                        .zip(&processor.baseline_matches)
+                    {
+                        total.0 += matches.0;
+                        total.1 += matches.1;
+                    }
+                    inspection.file_count += 1;
+                    inspection.line_count += processor.line_count;
+                    if processor.sampled {
+                        inspection.sampled_sources.push(source.clone());
+                    }
+                    if processor.is_empty() {
+                        inspection.empty_sources.push(source.clone());
+                    } else if !anomalies.is_empty() {
+                        if inspection.index_report.is_none() {
+                            inspection.index_report = Some(IndexReport::from_index(self));
+                        }
+                        inspection.log_reports.push(LogReport {
+                            test_time: start_time.elapsed(),
+                            anomalies,
+                            source: source.clone(),
+                            index_name: index_name.clone(),
+                            line_count: processor.line_count,
+                            byte_count: processor.byte_count,
+                            annotation: None,
+                        });
+                    }
+                }
+                Err(err) => {
+                    inspection
+                        .read_errors
+                        .push((source.clone(), format!("{}", err)));
+                    break;
+                }
+            }
+        }
+        inspection
     }
 
     #[tracing::instrument(level = "debug", name = "Index::inspect", skip(self, output_mode))]
@@ -293,8 +828,9 @@ impl Index {
         output_mode: OutputMode,
         source: &Source,
         skip_lines: &'a mut HashSet<String>,
+        ranges: &ranges::Ranges,
     ) -> Box<dyn Iterator<Item = Result<AnomalyContext>> + 'a> {
-        match self.get_processor(output_mode, source, skip_lines) {
+        match self.get_processor(output_mode, source, skip_lines, ranges) {
             Ok(processor) => Box::new(processor),
             // If the file can't be open, the first iterator result will be the error.
             Err(e) => Box::new(std::iter::once(Err(e))),
@@ -311,6 +847,7 @@ impl Content {
             Input::Url(url_str) => {
                 Content::from_url(Url::parse(&url_str).expect("Failed to parse url"))
             }
+            Input::Stdin => Ok(Content::File(Source::Stdin)),
         }
     }
 
@@ -325,57 +862,194 @@ impl Content {
                 Source::Remote(_, _) => Err(anyhow::anyhow!(
                     "Can't find remmote baselines, they need to be provided"
                 )),
+                Source::Memory(_, _) => Err(anyhow::anyhow!(
+                    "Can't find memory baselines, they need to be provided"
+                )),
+                Source::Stdin => Err(anyhow::anyhow!(
+                    "Can't find stdin baselines, they need to be provided"
+                )),
+                Source::Member(_, _) => Err(anyhow::anyhow!(
+                    "Can't find archive member baselines, they need to be provided"
+                )),
             },
             Content::Directory(_) => Err(anyhow::anyhow!(
                 "Can't discover directory baselines, they need to be provided",
             )),
+            #[cfg(feature = "zuul")]
             Content::Zuul(build) => build.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::GithubActions(run) => run.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::GitlabPipeline(pipeline) => pipeline.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::Jenkins(build) => build.discover_baselines(),
+            #[cfg(feature = "cloud")]
+            Content::Prow(job) => job.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::CircleCi(job) => job.discover_baselines(),
+            #[cfg(feature = "cloud")]
+            Content::S3(_) => Err(anyhow::anyhow!(
+                "Can't discover s3 baselines, they need to be provided",
+            )),
+            #[cfg(feature = "cloud")]
+            Content::Gcs(_) => Err(anyhow::anyhow!(
+                "Can't discover gcs baselines, they need to be provided",
+            )),
+            Content::Journal(query) => query.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::Docker(_) => Err(anyhow::anyhow!(
+                "Can't discover container baselines, they need to be provided",
+            )),
+            #[cfg(feature = "http")]
+            Content::Elasticsearch(search) => search.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::Loki(query) => query.discover_baselines(),
+            #[cfg(feature = "cloud")]
+            Content::CloudWatch(group) => group.discover_baselines(),
+            #[cfg(feature = "http")]
+            Content::Kafka(_) => Err(anyhow::anyhow!(
+                "Can't discover kafka baselines, use a trained model",
+            )),
+            Content::Memory(_) => Err(anyhow::anyhow!(
+                "Can't discover memory baselines, they need to be provided",
+            )),
         })
         .and_then(|baselines| match baselines.len() {
             0 => Err(anyhow::anyhow!("Empty discovered baselines")),
             _ => Ok(baselines),
         })
+        .context(errors::Failure::MissingBaselines)
     }
 
     /// Get the sources of log lines for this Content.
     #[tracing::instrument(level = "debug")]
     pub fn get_sources(&self) -> Result<Vec<Source>> {
-        self.get_sources_iter()
-            .filter(|source| {
-                source
-                    .as_ref()
-                    .map(|source| source.is_valid())
-                    .unwrap_or(true)
-            })
-            .collect::<Result<Vec<_>>>()
+        self.collect_sources()
             .and_then(|sources| match sources.len() {
                 0 => Err(anyhow::anyhow!("Empty sources")),
                 _ => Ok(sources),
             })
     }
 
+    /// Get the valid sources, which may be empty.
+    /// The job-output.json are replaced by their task outputs, see ansible::read_job_output,
+    /// and the tar archives by their members, see archive::member_sources.
+    fn collect_sources(&self) -> Result<Vec<Source>> {
+        self.collect_selected_sources(&ReportOptions::default())
+            .map(|selected| selected.sources)
+    }
+
+    /// Like collect_sources, without the sources skipped by the options, and with the failed task
+    /// of the job-output.json.
+    fn collect_selected_sources(&self, options: &ReportOptions) -> Result<SelectedSources> {
+        let mut sources = Vec::new();
+        let mut failed_task = None;
+        for source in self.get_sources_iter() {
+            let source = source?;
+            if ansible::is_job_output(&source) {
+                match ansible::read_job_output(&source) {
+                    Ok((mut tasks, task)) => {
+                        sources.append(&mut tasks);
+                        failed_task = failed_task.or(task);
+                    }
+                    Err(e) => tracing::warn!("{}: skipped: {:#}", source, e),
+                }
+            } else if archive::is_archive(&source) {
+                match archive::member_sources(&source) {
+                    Ok(mut members) => sources.append(&mut members),
+                    Err(e) => tracing::warn!("{}: skipped: {:#}", source, e),
+                }
+            } else if source.is_valid() {
+                sources.push(source);
+            }
+        }
+        let (sources, deselected) = sources
+            .into_iter()
+            .partition(|source| !options.is_skipped(source));
+        Ok(SelectedSources {
+            sources,
+            deselected,
+            failed_task,
+        })
+    }
+
     pub fn get_sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
         match self {
             Content::File(src) => Box::new(src.file_iter()),
             Content::Directory(src) => match src {
                 Source::Local(_, pathbuf) => Box::new(Source::dir_iter(pathbuf.as_path())),
+                #[cfg(feature = "cloud")]
+                Source::Remote(_, url) if azure::is_blob_url(url) => azure::sources_iter(url),
                 Source::Remote(_, url) => Box::new(Source::httpdir_iter(url)),
+                Source::Memory(_, _) | Source::Stdin | Source::Member(_, _) => {
+                    Box::new(src.file_iter())
+                }
             },
+            #[cfg(feature = "zuul")]
             Content::Zuul(build) => Box::new(build.sources_iter()),
+            #[cfg(feature = "http")]
+            Content::GithubActions(run) => run.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::GitlabPipeline(pipeline) => pipeline.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::Jenkins(build) => build.sources_iter(),
+            #[cfg(feature = "cloud")]
+            Content::Prow(job) => job.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::CircleCi(job) => job.sources_iter(),
+            #[cfg(feature = "cloud")]
+            Content::S3(prefix) => prefix.sources_iter(),
+            #[cfg(feature = "cloud")]
+            Content::Gcs(prefix) => prefix.sources_iter(),
+            Content::Journal(query) => query.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::Docker(container) => container.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::Elasticsearch(search) => search.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::Loki(query) => query.sources_iter(),
+            #[cfg(feature = "cloud")]
+            Content::CloudWatch(group) => group.sources_iter(),
+            #[cfg(feature = "http")]
+            Content::Kafka(topic) => topic.sources_iter(),
+            Content::Memory(logs) => Box::new(Source::memory_iter(logs)),
         }
     }
 
+    /// Group the sources by IndexName. A content without sources is skipped, unless they are all empty.
     pub fn group_sources(baselines: &[Content]) -> Result<HashMap<IndexName, Vec<Source>>> {
+        Ok(Content::group_baseline_sources(baselines)?
+            .into_iter()
+            .map(|(index_name, sources)| {
+                (
+                    index_name,
+                    sources.into_iter().map(|(_, source)| source).collect(),
+                )
+            })
+            .collect())
+    }
+
+    /// Like group_sources, with the position of the baseline of each source.
+    fn group_baseline_sources(
+        baselines: &[Content],
+    ) -> Result<HashMap<IndexName, Vec<(usize, Source)>>> {
         let mut groups = HashMap::new();
-        for baseline in baselines {
-            for source in baseline.get_sources()? {
+        for (baseline_pos, baseline) in baselines.iter().enumerate() {
+            let sources = baseline.collect_sources()?;
+            if sources.is_empty() {
+                tracing::warn!("{}: skipped: empty", baseline);
+            }
+            for source in sources {
                 groups
                     .entry(IndexName::from_source(&source))
                     .or_insert_with(Vec::new)
-                    .push(source);
+                    .push((baseline_pos, source));
             }
         }
-        Ok(groups)
+        match groups.len() {
+            0 => Err(anyhow::anyhow!("Empty sources")),
+            _ => Ok(groups),
+        }
     }
 }
 
@@ -388,111 +1062,380 @@ impl Model {
         mk_index: fn() -> ChunkIndex,
     ) -> Result<Model> {
         let created_at = SystemTime::now();
+        let baselines = outliers::filter_baselines(baselines, mk_index)?;
         let mut indexes = HashMap::new();
-        for (index_name, sources) in Content::group_sources(&baselines)?.drain() {
+        for (index_name, sources) in Content::group_baseline_sources(&baselines)?.drain() {
             debug_or_progress(
                 output_mode,
                 &format!(
                     "Loading index {} with {}",
                     index_name,
-                    sources.iter().format(", ")
+                    sources.iter().map(|(_, source)| source).format(", ")
                 ),
             );
-            let index = Index::train(&sources, mk_index())?;
-            indexes.insert(index_name, index);
+            let index =
+                Index::train_baselines(&sources, mk_index(), process::is_attribution_enabled())?;
+            if index.is_empty() {
+                tracing::warn!("{}: skipped: empty", index_name);
+            } else {
+                indexes.insert(index_name, index);
+            }
+        }
+        if indexes.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Empty baselines, all the sources are blank"
+            ));
         }
         Ok(Model {
             created_at,
+            version: version::VERSION.to_string(),
             baselines,
             indexes,
+            params: params::get_train_params(),
+            lazy: None,
         })
     }
 
+    /// Load a model, its signature is verified when a trusted key is set, see signing::set_trusted_key.
     pub fn load(path: &Path) -> Result<Model> {
+        let data = std::fs::read(path).context("Can't open file")?;
+        #[cfg(feature = "signing")]
+        crate::signing::verify_file(path, &data)?;
+        Model::load_reader(&data[..], path)
+    }
+
+    fn load_reader<R: std::io::Read>(mut reader: R, path: &Path) -> Result<Model> {
         tracing::info!(path = path.to_str(), "Loading provided model");
-        bincode::deserialize_from(flate2::read::GzDecoder::new(
-            std::fs::File::open(path).context("Can't open file")?,
-        ))
-        .context("Can't load model")
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .with_context(|| format!("Can't read {:?}", path))?;
+        let payload = integrity::unseal(&data, path)?;
+        let model = lazy::decode(payload)
+            .with_context(|| errors::Failure::IncompatibleModel(path.to_path_buf()))?;
+        if !version::is_compatible(&model.version) {
+            tracing::warn!(
+                "{:?} was created by logreduce {}, this is {}: train it again",
+                path,
+                model.version,
+                version::VERSION
+            );
+        }
+        Ok(model)
+    }
+
+    /// The version of the program that created the model.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// The parameters of the training, to search and to extend the model with the same parameters.
+    pub fn params(&self) -> params::TrainParams {
+        self.params
+    }
+
+    /// Returns true when the model was created more than max_age ago.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        SystemTime::now()
+            .duration_since(self.created_at)
+            .is_ok_and(|age| age > max_age)
     }
 
     pub fn save(&self, path: &Path) -> Result<()> {
         tracing::info!(path = path.to_str(), "Saving model");
-        bincode::serialize_into(
-            flate2::write::GzEncoder::new(
-                std::fs::File::create(path).context("Can't create file")?,
-                flate2::Compression::fast(),
-            ),
-            self,
-        )
-        .context("Can't save model")
+        self.save_writer(std::fs::File::create(path).context("Can't create file")?)
+    }
+
+    fn save_writer<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        if self.lazy.is_some() {
+            return Err(anyhow::anyhow!(
+                "Can't save a model loaded lazily, load it with Model::load"
+            ));
+        }
+        let data =
+            lazy::encode(self, compression::get_compression()).context("Can't save model")?;
+        writer
+            .write_all(&integrity::seal(data))
+            .context("Can't save model")
     }
 
     /// Get the matching index for a given Source.
     pub fn get_index<'a>(&'a self, index_name: &IndexName) -> Option<&'a Index> {
-        lookup_or_single(&self.indexes, index_name)
+        match &self.lazy {
+            Some(lazy) => lazy.get(index_name),
+            None => lookup_or_single(&self.indexes, index_name),
+        }
+    }
+
+    /// Compact the indexes, see Index::compact.
+    pub fn compact(&mut self) {
+        for index in self.indexes.values_mut() {
+            index.compact();
+        }
+    }
+
+    /// Combine the baselines and the indexes of another model, for example to use the models trained per job.
+    /// The models must be trained with the same parameters and the same index kind.
+    pub fn merge(&mut self, other: Model) -> Result<()> {
+        if self.params != other.params {
+            return Err(anyhow::anyhow!(
+                "Can't merge a model trained with {:?} into a model trained with {:?}",
+                other.params,
+                self.params
+            ));
+        }
+        let kind = |model: &Model| model.indexes.values().map(|i| i.index.kind()).min();
+        if let (Some(kind), Some(other_kind)) = (kind(self), kind(&other)) {
+            if kind != other_kind {
+                return Err(anyhow::anyhow!(
+                    "Can't merge a {} model into a {} model",
+                    other_kind,
+                    kind
+                ));
+            }
+        }
+        for (index_name, index) in &other.indexes {
+            if let Some(current) = self.indexes.get(index_name) {
+                current
+                    .index
+                    .check_kind(&index.index)
+                    .with_context(|| format!("{}: can't merge the index", index_name))?;
+            }
+        }
+        self.created_at = SystemTime::now();
+        for baseline in other.baselines {
+            if !self.baselines.contains(&baseline) {
+                self.baselines.push(baseline);
+            }
+        }
+        for (index_name, index) in other.indexes {
+            match self.indexes.get_mut(&index_name) {
+                Some(current) => current.merge(index)?,
+                None => {
+                    self.indexes.insert(index_name, index);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a run that inspected the given target indexes, to find the indexes that are no longer used.
+    pub fn record_run(&mut self, inspected: &[IndexName]) {
+        // Like get_index, the single index matches every target.
+        let single = self.indexes.len() == 1 && !inspected.is_empty();
+        for (index_name, index) in self.indexes.iter_mut() {
+            if single || inspected.contains(index_name) {
+                index.idle_runs = 0;
+            } else {
+                index.idle_runs += 1;
+            }
+        }
+    }
+
+    /// Remove the indexes matching the predicate, for example the stale ones. Returns the removed names.
+    pub fn prune(&mut self, predicate: impl Fn(&IndexName, &Index) -> bool) -> Vec<IndexName> {
+        let mut removed: Vec<IndexName> = self
+            .indexes
+            .iter()
+            .filter(|(index_name, index)| predicate(index_name, index))
+            .map(|(index_name, _)| index_name.clone())
+            .collect();
+        removed.sort_by(|a, b| a.0.cmp(&b.0));
+        for index_name in &removed {
+            self.indexes.remove(index_name);
+        }
+        removed
+    }
+
+    /// Add the lines learned during a live session to the matching index.
+    pub fn learn(&mut self, index_name: &IndexName, window: process::LearningWindow) -> Result<()> {
+        let name = if self.indexes.contains_key(index_name) || self.indexes.len() != 1 {
+            index_name.clone()
+        } else {
+            // Like get_index, use the single index.
+            self.indexes.keys().next().unwrap().clone()
+        };
+        match self.indexes.get_mut(&name) {
+            Some(index) => index.learn(window),
+            None => Ok(()),
+        }
+    }
+
+    /// Create the final report, or return the previous one when the same target was already analyzed with this model
+    /// and the same processing parameters. The reports are kept in the reader cache, thus this is only used when
+    /// the cache is enabled with LOGREDUCE_CACHE.
+    pub fn report_cached(&self, output_mode: OutputMode, target: Content) -> Result<Report> {
+        self.report_cached_with(output_mode, target, &ReportOptions::default())
+    }
+
+    /// Like report_cached, with the options of the target.
+    #[tracing::instrument(level = "debug", skip(output_mode, self, options))]
+    pub fn report_cached_with(
+        &self,
+        output_mode: OutputMode,
+        target: Content,
+        options: &ReportOptions,
+    ) -> Result<Report> {
+        if let Content::File(Source::Stdin) = target {
+            // The stdin can't be read twice to compute the digest.
+            return self.report_with(output_mode, target, options);
+        }
+        let digest = self.report_digest(output_mode, &target, options)?;
+        match crate::reader::CACHE.report_get(&digest) {
+            Some(path) => {
+                tracing::info!(digest = digest.as_str(), "Re-using cached report");
+                Report::load(&path)
+            }
+            None => {
+                let report = self.report_with(output_mode, target, options)?;
+                if !crate::reader::CACHE.is_full() {
+                    report.save_writer(crate::reader::CACHE.report_add(&digest)?)?;
+                }
+                Ok(report)
+            }
+        }
     }
 
-    /// Create the final report.
-    #[tracing::instrument(level = "debug", skip(output_mode, self))]
+    /// Compute the report identifier, using the program version, the model, the processing parameters
+    /// and the target sources.
+    fn report_digest(
+        &self,
+        output_mode: OutputMode,
+        target: &Content,
+        options: &ReportOptions,
+    ) -> Result<String> {
+        use sha2::{Digest, Sha256};
+        let mut hasher = Sha256::new();
+        hasher.update(version::VERSION);
+        bincode::serialize_into(&mut hasher, &self.baselines).context("Can't serialize model")?;
+        // The indexes are changed by learn, prune and merge without a new created_at.
+        match &self.lazy {
+            Some(lazy) => lazy.digest(&mut hasher),
+            None => {
+                let mut names: Vec<&IndexName> = self.indexes.keys().collect();
+                names.sort_by(|a, b| a.0.cmp(&b.0));
+                for name in names {
+                    hasher.update(name.as_str());
+                    bincode::serialize_into(&mut hasher, &self.indexes[name])
+                        .context("Can't serialize model")?;
+                }
+            }
+        }
+        hasher.update(report_params(output_mode, options));
+        for source in target.get_sources()? {
+            source.digest(&mut hasher)?;
+        }
+        Ok(format!("{:X}", hasher.finalize()))
+    }
+
+    /// Create the final report. This only borrows the model, so that it can be called concurrently.
     pub fn report(&self, output_mode: OutputMode, target: Content) -> Result<Report> {
+        self.report_with(output_mode, target, &ReportOptions::default())
+    }
+
+    /// Like report, with the options of the target. The options are given per report, so that
+    /// concurrent reports of the same model can use different options.
+    #[tracing::instrument(level = "debug", skip(output_mode, self, options))]
+    pub fn report_with(
+        &self,
+        output_mode: OutputMode,
+        target: Content,
+        options: &ReportOptions,
+    ) -> Result<Report> {
+        let _run = urls::ReportRun::start();
         let start_time = Instant::now();
+        let start_usage = usage::ResourceUsage::collect();
         let created_at = SystemTime::now();
         let mut index_reports = HashMap::new();
         let mut log_reports = Vec::new();
         let mut index_errors = Vec::new();
         let mut read_errors = Vec::new();
+        let mut empty_sources = Vec::new();
+        let mut sampled_sources = Vec::new();
+        let mut warnings = Vec::new();
+        let mut http_responses = Vec::new();
+        let mut total_file_count = 0;
         let mut total_line_count = 0;
         let mut total_anomaly_count = 0;
-        for (index_name, sources) in Content::group_sources(&[target.clone()])?.drain() {
-            let mut skip_lines = HashSet::new();
-            match self.get_index(&index_name) {
-                Some(index) => {
-                    for source in sources {
-                        let start_time = Instant::now();
-                        let mut anomalies = Vec::new();
-                        match index.get_processor(output_mode, &source, &mut skip_lines) {
-                            Ok(mut processor) => {
-                                for anomaly in processor.by_ref() {
-                                    match anomaly {
-                                        Ok(anomaly) => anomalies.push(anomaly),
-                                        Err(err) => {
-                                            read_errors.push((source.clone(), format!("{}", err)));
-                                            break;
-                                        }
-                                    }
-                                }
-                                total_line_count += processor.line_count;
-                                if !anomalies.is_empty() {
-                                    total_anomaly_count += anomalies.len();
-                                    if !index_reports.contains_key(&index_name) {
-                                        index_reports.insert(
-                                            index_name.clone(),
-                                            IndexReport::from_index(index),
-                                        );
-                                    }
-                                    log_reports.push(LogReport {
-                                        test_time: start_time.elapsed(),
-                                        anomalies,
-                                        source,
-                                        index_name: index_name.clone(),
-                                        line_count: processor.line_count,
-                                        byte_count: processor.byte_count,
-                                    });
-                                }
-                            }
-                            Err(err) => {
-                                read_errors.push((source.clone(), format!("{}", err)));
-                                break;
-                            }
+        let mut baseline_matches = vec![(0, 0); self.baselines.len()];
+        let mut inspected_indexes = Vec::new();
+        let SelectedSources {
+            sources,
+            deselected,
+            failed_task,
+        } = target.collect_selected_sources(options)?;
+        if sources.is_empty() {
+            return Err(anyhow::anyhow!("Empty sources"));
+        }
+        for source in deselected {
+            if let Source::Remote(_, url) = source {
+                http_responses.push(urls::HttpResponse::skipped(&url, "deselected"));
+            }
+        }
+        let groups: Vec<_> = sources
+            .into_iter()
+            .into_group_map_by(IndexName::from_source)
+            .into_iter()
+            .collect();
+        // The indexes are inspected in parallel, and the chunk searches of their sources share the same
+        // rayon thread pool, see process::search_chunk.
+        let inspections: Vec<_> = groups
+            .par_iter()
+            .map(|(index_name, sources)| {
+                self.get_index(index_name).map(|index| {
+                    index.inspect_sources(
+                        output_mode,
+                        index_name,
+                        sources,
+                        self.baselines.len(),
+                        options,
+                    )
+                })
+            })
+            .collect();
+        for ((index_name, sources), inspection) in groups.into_iter().zip(inspections) {
+            match inspection {
+                Some(inspection) => {
+                    if let Some(warning) = inspection.warning {
+                        warnings.push(warning);
+                    }
+                    if let Some(index_report) = inspection.index_report {
+                        index_reports.insert(index_name.clone(), index_report);
+                    }
+                    inspected_indexes.push(index_name);
+                    for (total, matches) in baseline_matches
+                        .iter_mut()
+                        .zip(&inspection.baseline_matches)
+                    {
+                        total.0 += matches.0;
+                        total.1 += matches.1;
+                    }
+                    total_file_count += inspection.file_count;
+                    total_line_count += inspection.line_count;
+                    total_anomaly_count += inspection
+                        .log_reports
+                        .iter()
+                        .map(|log_report| log_report.anomalies.len())
+                        .sum::<usize>();
+                    log_reports.extend(inspection.log_reports);
+                    read_errors.extend(inspection.read_errors);
+                    empty_sources.extend(inspection.empty_sources);
+                    sampled_sources.extend(inspection.sampled_sources);
+                    http_responses.extend(inspection.http_responses);
+                }
+                None => {
+                    for source in &sources {
+                        if let Source::Remote(_, url) = source {
+                            http_responses.push(urls::HttpResponse::skipped(url, "no baseline"));
                         }
                     }
+                    index_errors.push(sources)
                 }
-                None => index_errors.push(sources.clone()),
             }
         }
         Ok(Report {
             created_at,
+            version: version::VERSION.to_string(),
             run_time: start_time.elapsed(),
             target,
             baselines: self.baselines.clone(),
@@ -500,12 +1443,56 @@ impl Model {
             index_reports,
             index_errors,
             read_errors,
+            empty_sources,
+            sampled_sources,
+            warnings,
+            accepted_anomalies: Vec::new(),
+            failed_task,
+            total_file_count,
             total_line_count,
             total_anomaly_count,
+            usage: usage::ResourceUsage::collect().since(&start_usage),
+            http_responses,
+            baseline_matches: match process::is_attribution_enabled() {
+                true => self
+                    .baselines
+                    .iter()
+                    .zip(baseline_matches)
+                    .map(
+                        |(baseline, (matched_lines, exclusive_lines))| BaselineMatches {
+                            baseline: baseline.clone(),
+                            matched_lines,
+                            exclusive_lines,
+                        },
+                    )
+                    .collect(),
+                false => Vec::new(),
+            },
+            inspected_indexes,
         })
     }
 }
 
+/// The parameters that change the report of a model, see Model::report_digest.
+fn report_params(output_mode: OutputMode, options: &ReportOptions) -> String {
+    format!(
+        "{:?}",
+        (
+            output_mode,
+            params::get_train_params(),
+            profiles::Profile::global(),
+            profiles::get_profiles(),
+            process::get_chunk_boundary(),
+            process::is_attribution_enabled(),
+            sampling::get_sampling(),
+            options,
+            urls::get_tail_bytes(),
+            hnsw_index::get_ef_search(),
+            patterns::get_patterns(),
+        )
+    )
+}
+
 /// Helper function to debug
 pub fn debug_or_progress(output_mode: OutputMode, msg: &str) {
     match output_mode {
@@ -534,56 +1521,507 @@ fn lookup_or_single<'a, K: Eq + std::hash::Hash, V>(hm: &'a HashMap<K, V>, k: &K
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ChunkIndex {
     HashingTrick(hashing_index::HashingIndex),
+    TfIdf(tfidf_index::TfIdfIndex),
+    Lsh(lsh_index::LshIndex),
+    Hnsw(hnsw_index::HnswIndex),
     Noop,
+    /// An index provided by another crate, see external.rs.
+    External(#[serde(with = "external")] Box<dyn external::ExternalIndex>),
+    #[cfg(feature = "embedding")]
+    Embedding(embedding_index::EmbeddingIndex),
 }
 
 /// An API to work with chunks of logs instead of individual line.
 impl ChunkIndex {
     fn tokenize(&self, line: &str) -> String {
         match self {
-            ChunkIndex::HashingTrick(_) => hashing_index::tokenize(line),
+            ChunkIndex::HashingTrick(_)
+            | ChunkIndex::TfIdf(_)
+            | ChunkIndex::Lsh(_)
+            | ChunkIndex::Hnsw(_) => hashing_index::tokenize(line),
             ChunkIndex::Noop => noop_index::tokenize(line),
+            ChunkIndex::External(i) => i.tokenize(line),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => hashing_index::tokenize(line),
         }
     }
 
     fn add(&mut self, baselines: &[String]) {
         match self {
             ChunkIndex::HashingTrick(i) => i.add(baselines),
+            ChunkIndex::TfIdf(i) => i.add(baselines),
+            ChunkIndex::Lsh(i) => i.add(baselines),
+            ChunkIndex::Hnsw(i) => i.add(baselines),
             ChunkIndex::Noop => {}
+            ChunkIndex::External(i) => i.add(baselines),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.add(baselines),
         }
     }
 
     fn search(&self, targets: &[String]) -> Vec<f32> {
         match self {
             ChunkIndex::HashingTrick(i) => i.search(targets),
+            ChunkIndex::TfIdf(i) => i.search(targets),
+            ChunkIndex::Lsh(i) => i.search(targets),
+            ChunkIndex::Hnsw(i) => i.search(targets),
             ChunkIndex::Noop => noop_index::search(targets),
+            ChunkIndex::External(i) => i.search(targets),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.search(targets),
+        }
+    }
+
+    /// The distances of the targets to each group of rows, when the index supports it.
+    fn search_groups(
+        &self,
+        targets: &[String],
+        group_of: impl Fn(usize) -> Option<usize>,
+        group_count: usize,
+    ) -> Option<Vec<Vec<f32>>> {
+        match self {
+            ChunkIndex::HashingTrick(i) => Some(i.search_groups(targets, group_of, group_count)),
+            ChunkIndex::TfIdf(i) => Some(i.search_groups(targets, group_of, group_count)),
+            ChunkIndex::Lsh(i) => Some(i.search_groups(targets, group_of, group_count)),
+            ChunkIndex::Hnsw(i) => Some(i.search_groups(targets, group_of, group_count)),
+            ChunkIndex::Noop => None,
+            ChunkIndex::External(i) => i.search_groups(targets, &group_of, group_count),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => Some(i.search_groups(targets, group_of, group_count)),
+        }
+    }
+
+    /// Returns the constructor of an empty index of the same kind.
+    fn factory(&self) -> fn() -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(_) => hashing_index::new,
+            ChunkIndex::TfIdf(_) => tfidf_index::new,
+            ChunkIndex::Lsh(_) => lsh_index::new,
+            ChunkIndex::Hnsw(_) => hnsw_index::new,
+            ChunkIndex::Noop => noop_index::new,
+            ChunkIndex::External(i) => external::factory(i.kind()).unwrap_or_else(|| {
+                tracing::warn!("{}: the index kind is not registered", i.kind());
+                noop_index::new
+            }),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => embedding_index::new,
+        }
+    }
+
+    /// The name of the index implementation.
+    fn kind(&self) -> &'static str {
+        match self {
+            ChunkIndex::HashingTrick(_) => "hashing-trick",
+            ChunkIndex::TfIdf(_) => "tfidf",
+            ChunkIndex::Lsh(_) => "lsh",
+            ChunkIndex::Hnsw(_) => "hnsw",
+            ChunkIndex::Noop => "noop",
+            ChunkIndex::External(i) => i.kind(),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => "embedding",
+        }
+    }
+
+    /// Returns an error when the other index is of another kind, and can't be appended.
+    fn check_kind(&self, other: &ChunkIndex) -> Result<()> {
+        match self.kind() == other.kind() {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!(
+                "Can't append a {} index to a {} index",
+                other.kind(),
+                self.kind()
+            )),
+        }
+    }
+
+    fn append(&mut self, other: ChunkIndex) -> Result<()> {
+        self.check_kind(&other)?;
+        match (self, other) {
+            (ChunkIndex::HashingTrick(i), ChunkIndex::HashingTrick(other)) => i.append(other),
+            (ChunkIndex::TfIdf(i), ChunkIndex::TfIdf(other)) => i.append(other),
+            (ChunkIndex::Lsh(i), ChunkIndex::Lsh(other)) => i.append(other),
+            (ChunkIndex::Hnsw(i), ChunkIndex::Hnsw(other)) => i.append(other),
+            (ChunkIndex::External(i), ChunkIndex::External(other)) => i.append(other),
+            #[cfg(feature = "embedding")]
+            (ChunkIndex::Embedding(i), ChunkIndex::Embedding(other)) => i.append(other),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn compact(&mut self) {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.compact(),
+            ChunkIndex::TfIdf(i) => i.compact(),
+            ChunkIndex::Lsh(i) => i.compact(),
+            ChunkIndex::Hnsw(i) => i.compact(),
+            ChunkIndex::Noop => {}
+            ChunkIndex::External(i) => i.compact(),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.compact(),
+        }
+    }
+
+    fn prepare(&mut self) -> Option<hashing_index::Prepared> {
+        match self {
+            ChunkIndex::HashingTrick(i) => Some(i.prepare()),
+            _ => None,
+        }
+    }
+
+    fn set_prepared(&mut self, prepared: hashing_index::Prepared) {
+        if let ChunkIndex::HashingTrick(i) = self {
+            i.set_prepared(prepared)
         }
     }
 }
 
 pub mod hashing_index {
     use serde::{Deserialize, Serialize};
+
+    /// The maximum number of rows of the compacted matrices.
+    pub(crate) const COMPACT_ROWS: usize = 4096;
+
     /// A ChunkIndex implementation.
     #[derive(Debug, Serialize, Deserialize)]
     pub struct HashingIndex {
+        #[serde(with = "crate::quantization")]
+        baselines: Vec<logreduce_index::FeaturesMatrix>,
+        /// The features of each baseline, see HashingIndex::prepare.
+        #[serde(skip)]
+        filters: Vec<logreduce_index::FeatureFilter>,
+    }
+
+    /// The derived structures of a HashingIndex, see precompute.rs.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct Prepared {
         baselines: Vec<logreduce_index::FeaturesMatrix>,
+        filters: Vec<logreduce_index::FeatureFilter>,
     }
 
     pub fn new() -> super::ChunkIndex {
         super::ChunkIndex::HashingTrick(HashingIndex {
             baselines: Vec::new(),
+            filters: Vec::new(),
+        })
+    }
+
+    /// Create an index with baseline matrices, see portable.rs.
+    pub fn from_matrices(baselines: Vec<logreduce_index::FeaturesMatrix>) -> super::ChunkIndex {
+        super::ChunkIndex::HashingTrick(HashingIndex {
+            baselines,
+            filters: Vec::new(),
         })
     }
 
     pub fn tokenize(line: &str) -> String {
         logreduce_tokenizer::process(line)
     }
+
+    /// The dimension of the existing baselines, otherwise the one of the TrainParams.
+    pub(crate) fn dimension(baselines: &[logreduce_index::FeaturesMatrix]) -> usize {
+        baselines.first().map_or_else(
+            || crate::params::get_train_params().dimension,
+            |mat| mat.cols(),
+        )
+    }
     impl HashingIndex {
         pub fn add(&mut self, baselines: &[String]) {
-            self.baselines.push(logreduce_index::index_mat(baselines))
+            self.filters.clear();
+            self.baselines.push(logreduce_index::index_mat_dim(
+                baselines,
+                dimension(&self.baselines),
+            ))
+        }
+        pub fn search(&self, targets: &[String]) -> Vec<f32> {
+            if self.is_prepared() {
+                logreduce_index::search_mat_chunk_filtered(&self.baselines, &self.filters, targets)
+            } else {
+                logreduce_index::search_mat_chunk(&self.baselines, targets)
+            }
+        }
+        pub fn search_groups(
+            &self,
+            targets: &[String],
+            group_of: impl Fn(usize) -> Option<usize>,
+            group_count: usize,
+        ) -> Vec<Vec<f32>> {
+            logreduce_index::search_mat_chunk_groups(
+                &self.baselines,
+                group_of,
+                group_count,
+                targets,
+            )
+        }
+        pub fn append(&mut self, other: HashingIndex) {
+            self.filters.clear();
+            self.baselines.extend(other.baselines)
+        }
+        pub fn compact(&mut self) {
+            self.filters.clear();
+            self.baselines =
+                logreduce_index::compact_mats(std::mem::take(&mut self.baselines), COMPACT_ROWS)
+        }
+        pub(crate) fn is_prepared(&self) -> bool {
+            !self.filters.is_empty() && self.filters.len() == self.baselines.len()
+        }
+        /// Compact the baselines and compute their features filter.
+        pub fn prepare(&mut self) -> Prepared {
+            self.compact();
+            self.filters = self
+                .baselines
+                .iter()
+                .map(logreduce_index::FeatureFilter::new)
+                .collect();
+            Prepared {
+                baselines: self.baselines.clone(),
+                filters: self.filters.clone(),
+            }
+        }
+        /// Use the structures computed by a previous prepare.
+        pub fn set_prepared(&mut self, prepared: Prepared) {
+            self.baselines = prepared.baselines;
+            self.filters = prepared.filters;
+        }
+        pub fn chunk_count(&self) -> usize {
+            self.baselines.len()
+        }
+        pub fn matrices(&self) -> &[logreduce_index::FeaturesMatrix] {
+            &self.baselines
+        }
+    }
+}
+
+pub mod tfidf_index {
+    use serde::{Deserialize, Serialize};
+    use std::sync::OnceLock;
+
+    use logreduce_index::{FeaturesMatrix, Idf};
+
+    /// A ChunkIndex implementation that weights the hashed features by their inverse document frequency,
+    /// so that the boilerplate tokens shared by most of the baseline lines matter less.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct TfIdfIndex {
+        /// The unweighted baselines, like the HashingIndex ones.
+        #[serde(with = "crate::quantization")]
+        baselines: Vec<FeaturesMatrix>,
+        /// The idf of the baselines and the weighted baselines, computed on the first search.
+        #[serde(skip)]
+        weighted: OnceLock<(Idf, Vec<FeaturesMatrix>)>,
+    }
+
+    pub fn new() -> super::ChunkIndex {
+        super::ChunkIndex::TfIdf(TfIdfIndex::from_matrices(Vec::new()))
+    }
+
+    impl TfIdfIndex {
+        /// Create an index with baseline matrices, see portable.rs.
+        pub fn from_matrices(baselines: Vec<FeaturesMatrix>) -> TfIdfIndex {
+            TfIdfIndex {
+                baselines,
+                weighted: OnceLock::new(),
+            }
+        }
+        fn weighted(&self) -> &(Idf, Vec<FeaturesMatrix>) {
+            self.weighted.get_or_init(|| {
+                let idf = Idf::new(&self.baselines);
+                let weighted = self
+                    .baselines
+                    .iter()
+                    .map(|mat| idf.weigh_mat(mat))
+                    .collect();
+                (idf, weighted)
+            })
+        }
+        pub fn add(&mut self, baselines: &[String]) {
+            self.weighted.take();
+            self.baselines.push(logreduce_index::index_mat_dim(
+                baselines,
+                super::hashing_index::dimension(&self.baselines),
+            ))
         }
         pub fn search(&self, targets: &[String]) -> Vec<f32> {
-            logreduce_index::search_mat_chunk(&self.baselines, targets)
+            let (idf, weighted) = self.weighted();
+            idf.search_mat_chunk(weighted, targets)
+        }
+        pub fn search_groups(
+            &self,
+            targets: &[String],
+            group_of: impl Fn(usize) -> Option<usize>,
+            group_count: usize,
+        ) -> Vec<Vec<f32>> {
+            let (idf, weighted) = self.weighted();
+            idf.search_mat_chunk_groups(weighted, group_of, group_count, targets)
+        }
+        pub fn append(&mut self, other: TfIdfIndex) {
+            self.weighted.take();
+            self.baselines.extend(other.baselines)
+        }
+        pub fn compact(&mut self) {
+            self.weighted.take();
+            self.baselines = logreduce_index::compact_mats(
+                std::mem::take(&mut self.baselines),
+                super::hashing_index::COMPACT_ROWS,
+            )
+        }
+        pub fn matrices(&self) -> &[FeaturesMatrix] {
+            &self.baselines
+        }
+    }
+}
+
+pub mod lsh_index {
+    use serde::{Deserialize, Serialize};
+    use std::sync::OnceLock;
+
+    use logreduce_index::{FeaturesMatrix, Lsh};
+
+    /// A ChunkIndex implementation that only compares the lines with the baseline lines found in
+    /// a locality sensitive hashing table, instead of every baseline line. The distances are approximated:
+    /// a line that is far from every baseline line may get the 1.0 distance.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct LshIndex {
+        /// The baselines, like the HashingIndex ones.
+        #[serde(with = "crate::quantization")]
+        baselines: Vec<FeaturesMatrix>,
+        /// The hashing table of the baselines, computed on the first search.
+        #[serde(skip)]
+        lsh: OnceLock<Lsh>,
+    }
+
+    pub fn new() -> super::ChunkIndex {
+        super::ChunkIndex::Lsh(LshIndex::from_matrices(Vec::new()))
+    }
+
+    impl LshIndex {
+        /// Create an index with baseline matrices, see portable.rs.
+        pub fn from_matrices(baselines: Vec<FeaturesMatrix>) -> LshIndex {
+            LshIndex {
+                baselines,
+                lsh: OnceLock::new(),
+            }
+        }
+        fn lsh(&self) -> &Lsh {
+            self.lsh.get_or_init(|| Lsh::new(&self.baselines))
+        }
+        pub fn add(&mut self, baselines: &[String]) {
+            self.lsh.take();
+            self.baselines.push(logreduce_index::index_mat_dim(
+                baselines,
+                super::hashing_index::dimension(&self.baselines),
+            ))
+        }
+        pub fn search(&self, targets: &[String]) -> Vec<f32> {
+            self.lsh().search_mat_chunk(&self.baselines, targets)
+        }
+        pub fn search_groups(
+            &self,
+            targets: &[String],
+            group_of: impl Fn(usize) -> Option<usize>,
+            group_count: usize,
+        ) -> Vec<Vec<f32>> {
+            self.lsh()
+                .search_mat_chunk_groups(&self.baselines, group_of, group_count, targets)
+        }
+        pub fn append(&mut self, other: LshIndex) {
+            self.lsh.take();
+            self.baselines.extend(other.baselines)
+        }
+        pub fn compact(&mut self) {
+            self.lsh.take();
+            self.baselines = logreduce_index::compact_mats(
+                std::mem::take(&mut self.baselines),
+                super::hashing_index::COMPACT_ROWS,
+            )
+        }
+        pub fn matrices(&self) -> &[FeaturesMatrix] {
+            &self.baselines
+        }
+    }
+}
+
+pub mod hnsw_index {
+    use serde::{Deserialize, Serialize};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use logreduce_index::{FeaturesMatrix, Hnsw};
+
+    /// The default number of nearest rows searched in the graph.
+    pub const DEFAULT_EF_SEARCH: usize = 32;
+
+    static EF_SEARCH: AtomicUsize = AtomicUsize::new(DEFAULT_EF_SEARCH);
+
+    /// Set the number of nearest rows searched in the graph: a larger value improves the recall,
+    /// at the cost of a slower search.
+    pub fn set_ef_search(ef: usize) {
+        EF_SEARCH.store(ef.max(1), Ordering::Relaxed);
+    }
+
+    pub(crate) fn get_ef_search() -> usize {
+        EF_SEARCH.load(Ordering::Relaxed)
+    }
+
+    /// A ChunkIndex implementation that searches the nearest baseline lines in an HNSW graph,
+    /// instead of comparing the lines with every baseline line. The distances are the cosine distances
+    /// of the nearest lines found, like the HashingIndex, thus they are at least the exact distances.
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct HnswIndex {
+        /// The baselines, like the HashingIndex ones.
+        #[serde(with = "crate::quantization")]
+        baselines: Vec<FeaturesMatrix>,
+        /// The graph of the baselines rows, updated when the baselines are added.
+        graph: Hnsw,
+    }
+
+    pub fn new() -> super::ChunkIndex {
+        super::ChunkIndex::Hnsw(HnswIndex::from_matrices(Vec::new()))
+    }
+
+    impl HnswIndex {
+        /// Create an index with baseline matrices, see portable.rs.
+        pub fn from_matrices(baselines: Vec<FeaturesMatrix>) -> HnswIndex {
+            let mut graph = Hnsw::default();
+            graph.extend(&baselines);
+            HnswIndex { baselines, graph }
+        }
+        pub fn add(&mut self, baselines: &[String]) {
+            self.baselines.push(logreduce_index::index_mat_dim(
+                baselines,
+                super::hashing_index::dimension(&self.baselines),
+            ));
+            self.graph.extend(&self.baselines)
+        }
+        pub fn search(&self, targets: &[String]) -> Vec<f32> {
+            self.graph
+                .search_mat_chunk(&self.baselines, targets, EF_SEARCH.load(Ordering::Relaxed))
+        }
+        pub fn search_groups(
+            &self,
+            targets: &[String],
+            group_of: impl Fn(usize) -> Option<usize>,
+            group_count: usize,
+        ) -> Vec<Vec<f32>> {
+            self.graph.search_mat_chunk_groups(
+                &self.baselines,
+                group_of,
+                group_count,
+                targets,
+                EF_SEARCH.load(Ordering::Relaxed),
+            )
+        }
+        pub fn append(&mut self, other: HnswIndex) {
+            self.baselines.extend(other.baselines);
+            self.graph.extend(&self.baselines)
+        }
+        /// The rows keep their positions, thus the graph is still valid.
+        pub fn compact(&mut self) {
+            self.baselines = logreduce_index::compact_mats(
+                std::mem::take(&mut self.baselines),
+                super::hashing_index::COMPACT_ROWS,
+            )
+        }
+        pub fn matrices(&self) -> &[FeaturesMatrix] {
+            &self.baselines
         }
     }
 }
@@ -604,3 +2042,331 @@ pub mod noop_index {
         distances
     }
 }
+
+/// Train a model on the memory files with the hashing index, for the tests.
+#[cfg(test)]
+pub(crate) fn train_memory(files: Vec<(&str, &'static str)>) -> Model {
+    train_memory_with(files, hashing_index::new)
+}
+
+/// Train a model on the memory files with the given index, for the tests.
+#[cfg(test)]
+pub(crate) fn train_memory_with(
+    files: Vec<(&str, &'static str)>,
+    index: fn() -> ChunkIndex,
+) -> Model {
+    Model::train(OutputMode::Quiet, vec![Content::from_memory(files)], index).unwrap()
+}
+
+#[test]
+fn test_model_compact() {
+    // Unique lines made of letters, so that they are not deduplicated by the tokenizer.
+    let word = |idx: usize| -> String {
+        [idx / 676, (idx / 26) % 26, idx % 26]
+            .iter()
+            .map(|c| (b'a' + *c as u8) as char)
+            .collect()
+    };
+    let baseline = Content::from_memory(vec![(
+        "service.log",
+        (0..2000)
+            .map(|idx| format!("INFO service{} started", word(idx)))
+            .join("\n"),
+    )]);
+    let mut model = Model::train(OutputMode::Quiet, vec![baseline], hashing_index::new).unwrap();
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO serviceaab started\nERROR service crashed\n",
+        )])
+    };
+    let chunk_count = |model: &Model| match &model.indexes.values().next().unwrap().index {
+        ChunkIndex::HashingTrick(i) => i.chunk_count(),
+        _ => 0,
+    };
+    let before = model.report(OutputMode::Quiet, target()).unwrap();
+    assert!(chunk_count(&model) > 1);
+    model.compact();
+    assert_eq!(chunk_count(&model), 1);
+    let after = model.report(OutputMode::Quiet, target()).unwrap();
+    assert_eq!(before.total_anomaly_count, 1);
+    assert_eq!(after.total_anomaly_count, 1);
+    assert_eq!(
+        before.log_reports[0].anomalies[0].anomaly.distance,
+        after.log_reports[0].anomalies[0].anomaly.distance
+    );
+}
+
+#[test]
+fn test_model_merge() {
+    let mut model = train_memory(vec![("service.log", "INFO service started\n")]);
+    model
+        .merge(train_memory(vec![
+            ("service.log", "INFO service stopped\n"),
+            ("worker.log", "INFO worker ready\n"),
+        ]))
+        .unwrap();
+    assert_eq!(model.baselines.len(), 2);
+    assert_eq!(model.indexes.len(), 2);
+    let service = &model.indexes[&IndexName::from_path("service.log")];
+    assert_eq!(service.sources.len(), 2);
+    assert_eq!(service.line_count, 2);
+
+    let target = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO service stopped\nERROR service crashed\n",
+    )]);
+    let report = model.report(OutputMode::Quiet, target).unwrap();
+    assert_eq!(report.total_anomaly_count, 1);
+}
+
+#[test]
+fn test_model_merge_incompatible() {
+    let train = |index| train_memory_with(vec![("service.log", "INFO service started\n")], index);
+    let mut model = train(hashing_index::new);
+
+    // The models trained with another hashing dimension are refused.
+    let mut other = train(hashing_index::new);
+    other.params = params::TrainParams::new(Some(1 << 16), None).unwrap();
+    assert!(model.merge(other).is_err());
+
+    // The tfidf matrices can't be appended to the hashing index.
+    assert!(model.merge(train(tfidf_index::new)).is_err());
+    let index = model
+        .indexes
+        .get_mut(&IndexName::from_path("service.log"))
+        .unwrap();
+    assert!(index.index.append(tfidf_index::new()).is_err());
+    assert!(index.index.append(hashing_index::new()).is_ok());
+    assert_eq!(model.indexes.len(), 1);
+    assert_eq!(model.baselines.len(), 1);
+}
+
+#[test]
+fn test_model_prune() {
+    let mut model = train_memory(vec![
+        ("service.log", "INFO service started\n"),
+        ("worker.log", "INFO worker ready\n"),
+        ("legacy.log", "INFO legacy ready\n"),
+    ]);
+    let target = Content::from_memory(vec![("service.log", "ERROR service crashed\n")]);
+    let report = model.report(OutputMode::Quiet, target).unwrap();
+    assert_eq!(
+        report.inspected_indexes,
+        vec![IndexName::from_path("service.log")]
+    );
+    model.record_run(&report.inspected_indexes);
+    model.record_run(&[IndexName::from_path("worker.log")]);
+
+    let idle_runs = |name: &str| model.indexes[&IndexName::from_path(name)].idle_runs();
+    assert_eq!((idle_runs("service.log"), idle_runs("legacy.log")), (1, 2));
+    let removed = model.prune(|_, index| index.idle_runs() >= 2);
+    assert_eq!(removed, vec![IndexName::from_path("legacy.log")]);
+    assert_eq!(model.indexes.len(), 2);
+    assert!(model
+        .prune(|_, index| index.created_at() < SystemTime::UNIX_EPOCH)
+        .is_empty());
+}
+
+#[test]
+fn test_baseline_confidence() {
+    let model = train_memory(vec![("service.log", "INFO service started\n")]);
+    let target = || Content::from_memory(vec![("service.log", "ERROR service crashed\n")]);
+    let report = model.report(OutputMode::Quiet, target()).unwrap();
+    assert_eq!(report.warnings.len(), 1);
+    assert!(report.warnings[0].contains("add more baselines"));
+
+    let options = ReportOptions {
+        min_baseline_lines: 1,
+        ..ReportOptions::default()
+    };
+    let report = model
+        .report_with(OutputMode::Quiet, target(), &options)
+        .unwrap();
+    assert!(report.warnings.is_empty());
+}
+
+#[test]
+fn test_baseline_attribution() {
+    let baselines = vec![
+        Content::from_memory(vec![("service.log", "INFO service started\n")]),
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started\nERROR service crashed\n",
+        )]),
+    ];
+    let sources = Content::group_baseline_sources(&baselines).unwrap();
+    let sources = &sources[&IndexName::from_path("service.log")];
+    let shared = Index::train_baselines(sources, hashing_index::new(), false).unwrap();
+    assert_eq!(shared.baseline_rows, vec![(1, 0), (2, 1)]);
+    let index = Index::train_baselines(sources, hashing_index::new(), true).unwrap();
+    assert_eq!(index.baseline_rows, vec![(1, 0), (3, 1)]);
+
+    let target = "INFO service started\nERROR service crashed\nWARNING disk full\n";
+    let mut processor = process::ChunkProcessor::single(target.as_bytes(), &index.index)
+        .with_attribution(&index.baseline_rows, 2);
+    let anomalies: Vec<_> = processor.by_ref().collect::<Result<_>>().unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(processor.baseline_matches, vec![(1, 0), (2, 1)]);
+}
+
+#[test]
+fn test_shared_model() {
+    let model = std::sync::Arc::new(train_memory(vec![(
+        "service.log",
+        "INFO service started\n",
+    )]));
+    let workers = (0..4)
+        .map(|idx| {
+            let model = model.clone();
+            std::thread::spawn(move || {
+                let target = Content::from_memory(vec![(
+                    "service.log",
+                    format!("INFO service started\nERROR worker{} crashed\n", idx),
+                )]);
+                model.report(OutputMode::Quiet, target).unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap().total_anomaly_count, 1);
+    }
+}
+
+#[test]
+fn test_report_digest() {
+    let mut model = train_memory(vec![
+        ("service.log", "INFO service started\n"),
+        ("worker.log", "INFO worker ready\n"),
+    ]);
+    let target = Content::from_memory(vec![("service.log", "ERROR service crashed\n")]);
+    let digest = |model: &Model, output_mode| {
+        model
+            .report_digest(output_mode, &target, &ReportOptions::default())
+            .unwrap()
+    };
+    let before = digest(&model, OutputMode::Quiet);
+    assert_eq!(before, digest(&model, OutputMode::Quiet));
+    assert_ne!(before, digest(&model, OutputMode::Debug));
+    let options = ReportOptions {
+        skipped: std::iter::once("service.log".to_string()).collect(),
+        ..ReportOptions::default()
+    };
+    assert_ne!(
+        before,
+        model
+            .report_digest(OutputMode::Quiet, &target, &options)
+            .unwrap()
+    );
+
+    // The pruned model doesn't re-use the previous reports.
+    model.prune(|index_name, _| *index_name == IndexName::from_path("worker.log"));
+    assert_ne!(before, digest(&model, OutputMode::Quiet));
+}
+
+#[test]
+fn test_tfidf_model() {
+    let baseline = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO service listening\nINFO service ready\n",
+    )]);
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started\nINFO service crashed\n",
+        )])
+    };
+    let distance = |mk_index| {
+        let model = Model::train(OutputMode::Quiet, vec![baseline.clone()], mk_index).unwrap();
+        let report = model.report(OutputMode::Quiet, target()).unwrap();
+        assert_eq!(report.total_anomaly_count, 1);
+        assert_eq!(
+            report.log_reports[0].anomalies[0].anomaly.line,
+            "INFO service crashed"
+        );
+        report.log_reports[0].anomalies[0].anomaly.distance
+    };
+    // The tokens shared by all the baseline lines have a lower weight.
+    assert!(distance(tfidf_index::new) > distance(hashing_index::new));
+}
+
+#[test]
+fn test_lsh_model() {
+    let baseline = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO worker ready\n",
+    )]);
+    let model = Model::train(OutputMode::Quiet, vec![baseline], lsh_index::new).unwrap();
+    let target = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO worker ready\nERROR disk full\n",
+    )]);
+    let report = model.report(OutputMode::Quiet, target).unwrap();
+    assert_eq!(report.total_anomaly_count, 1);
+    assert_eq!(
+        report.log_reports[0].anomalies[0].anomaly.line,
+        "ERROR disk full"
+    );
+}
+
+#[test]
+fn test_hnsw_model() {
+    let baseline = Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO worker ready\n",
+    )]);
+    let model = Model::train(OutputMode::Quiet, vec![baseline.clone()], hnsw_index::new).unwrap();
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started\nINFO worker ready\nERROR disk full\n",
+        )])
+    };
+    let report = model.report(OutputMode::Quiet, target()).unwrap();
+    assert_eq!(report.total_anomaly_count, 1);
+    // The distances are comparable with the exact search.
+    let exact = Model::train(OutputMode::Quiet, vec![baseline], hashing_index::new)
+        .unwrap()
+        .report(OutputMode::Quiet, target())
+        .unwrap();
+    assert_eq!(
+        report.log_reports[0].anomalies[0].anomaly.distance,
+        exact.log_reports[0].anomalies[0].anomaly.distance
+    );
+}
+
+#[test]
+fn test_index_dimension() {
+    let baseline = logreduce_index::index_mat_dim(&["INFO service started".to_string()], 1024);
+    let mut index = hashing_index::from_matrices(vec![baseline]);
+    // The lines are added and searched with the dimension of the index.
+    index.add(&["INFO worker ready".to_string()]);
+    let distances = index.search(&[
+        "INFO worker ready".to_string(),
+        "ERROR disk full".to_string(),
+    ]);
+    assert!(distances[0] < 1e-6);
+    assert!(distances[1] > 0.5);
+    index.compact();
+    assert_eq!(index.search(&["INFO service started".to_string()]).len(), 1);
+}
+
+#[test]
+fn test_stale_model() {
+    let mut model = train_memory(vec![("service.log", "INFO service started\n")]);
+    let day = Duration::from_secs(24 * 3600);
+    assert!(!model.is_stale(day));
+    model.created_at -= 2 * day;
+    assert!(model.is_stale(day));
+}
+
+#[test]
+fn test_stdin_input() {
+    let content = Content::from_input(Input::from_string("-".to_string())).unwrap();
+    assert_eq!(content, Content::File(Source::Stdin));
+    assert_eq!(
+        IndexName::from_source(&Source::Stdin),
+        IndexName("stdin".to_string())
+    );
+    assert!(content.discover_baselines().is_err());
+}
@@ -0,0 +1,171 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module folds the continuation lines of the stack traces into a single record, so that a
+//! Java or Python trace is searched and reported as one anomaly instead of one per frame. The lines
+//! of a record are separated by a new line, and the record has the position of its first line.
+
+use bytes::BytesMut;
+use std::io::{Read, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use logreduce_iterator::{BytesLines, LogLine};
+
+use crate::profiles::Profile;
+
+/// The maximum number of lines of a record, the following lines start a new record.
+const MAX_RECORD_LINES: usize = 256;
+
+const TRACEBACK: &[u8] = b"Traceback (most recent call last):";
+
+static MULTILINE: AtomicBool = AtomicBool::new(false);
+
+/// Fold the continuation lines into the previous line when reading the sources.
+pub fn set_multiline(enabled: bool) {
+    MULTILINE.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    MULTILINE.load(Ordering::Relaxed)
+}
+
+/// The lines of a source.
+pub(crate) type Lines<R> = BytesLines<crate::encoding::Decoder<R>>;
+
+/// Returns the lines, or the records when the multiline mode of the profile is enabled.
+pub(crate) fn read_lines<R: Read>(read: R, profile: Profile) -> Records<Lines<R>> {
+    Records {
+        lines: BytesLines::new(crate::encoding::decode(read)),
+        peeked: None,
+        enabled: profile.multiline,
+        strip_ansi: profile.strip_ansi,
+    }
+}
+
+/// The number of source lines of a record.
+pub(crate) fn line_count(record: &[u8]) -> usize {
+    1 + record.iter().filter(|c| **c == b'\n').count()
+}
+
+/// Check if the line continues the previous one: an indented line, a java frame or cause.
+fn is_continuation(line: &[u8]) -> bool {
+    match line.first() {
+        Some(b' ') | Some(b'\t') => !line.iter().all(u8::is_ascii_whitespace),
+        _ => {
+            line.starts_with(b"at ") || line.starts_with(b"Caused by:") || line.starts_with(b"...")
+        }
+    }
+}
+
+pub struct Records<I: Iterator<Item = Result<LogLine>>> {
+    lines: I,
+    /// The next line, when it doesn't continue the current record.
+    peeked: Option<Result<LogLine>>,
+    pub(crate) enabled: bool,
+    /// Remove the ANSI escape sequences, see ansi.rs.
+    pub(crate) strip_ansi: bool,
+}
+
+impl<I: Iterator<Item = Result<LogLine>>> Records<I> {
+    fn next_line(&mut self) -> Option<Result<LogLine>> {
+        if let Some(line) = self.peeked.take() {
+            return Some(line);
+        }
+        let line = self.lines.next()?;
+        Some(match self.strip_ansi {
+            true => line.map(crate::ansi::strip_line),
+            false => line,
+        })
+    }
+
+    fn next_if(&mut self, is_next: impl Fn(&Result<LogLine>) -> bool) -> Option<Result<LogLine>> {
+        let line = self.next_line()?;
+        if is_next(&line) {
+            Some(line)
+        } else {
+            self.peeked = Some(line);
+            None
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<LogLine>>> Iterator for Records<I> {
+    type Item = Result<LogLine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (first, pos) = match self.next_line()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        if !self.enabled || first.trim_ascii().is_empty() {
+            return Some(Ok((first, pos)));
+        }
+        // The exception of a python traceback is the first line that is not indented.
+        let is_traceback = first.starts_with(TRACEBACK);
+        let is_next = |line: &Result<LogLine>| match line {
+            Ok((line, _)) => {
+                is_continuation(line) || (is_traceback && !line.trim_ascii().is_empty())
+            }
+            Err(_) => false,
+        };
+        let mut record: Option<BytesMut> = None;
+        for _ in 1..MAX_RECORD_LINES {
+            let line = match self.next_if(is_next) {
+                Some(Ok((line, _))) => line,
+                _ => break,
+            };
+            let record = record.get_or_insert_with(|| BytesMut::from(&first[..]));
+            record.extend_from_slice(b"\n");
+            record.extend_from_slice(&line);
+            if !is_continuation(&line) {
+                break;
+            }
+        }
+        Some(Ok((record.map_or(first, BytesMut::freeze), pos)))
+    }
+}
+
+#[cfg(test)]
+fn records(data: &str) -> Vec<LogLine> {
+    let lines = BytesLines::new(std::io::Cursor::new(data.to_string()));
+    Records {
+        lines,
+        peeked: None,
+        enabled: true,
+        strip_ansi: false,
+    }
+    .map(|line| line.unwrap())
+    .collect()
+}
+
+#[test]
+fn test_records() {
+    let java = [
+        "INFO starting",
+        "ERROR java.lang.IllegalStateException: oops",
+        "\tat com.example.Service.run(Service.java:42)",
+        "Caused by: java.io.IOException: disk full",
+        "\t... 3 more",
+        "INFO stopping",
+    ];
+    let lines = records(&java.join("\n"));
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[1], (bytes::Bytes::from(java[1..5].join("\n")), 2));
+    assert_eq!(line_count(&lines[1].0), 4);
+    assert_eq!(lines[2].1, 6);
+
+    let python = [
+        "Traceback (most recent call last):",
+        "  File \"a.py\", line 1, in <module>",
+        "KeyError: 'x'",
+        "INFO done",
+        "",
+        "  indented",
+    ];
+    let lines = records(&python.join("\n"));
+    assert_eq!(
+        lines.iter().map(|(_, pos)| *pos).collect::<Vec<_>>(),
+        vec![1, 4, 5, 6]
+    );
+    assert_eq!(lines[0].0, bytes::Bytes::from(python[..3].join("\n")));
+}
@@ -0,0 +1,182 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module cross-checks the baselines before the training: a baseline whose lines are mostly
+//! missing from the other baselines is likely a failed build labeled as a success, and it would
+//! hide the very anomalies that logreduce should report.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::process::{tokenize_line, ChunkTrainer, THRESHOLD};
+use crate::{Baselines, ChunkIndex, Content, Source};
+
+/// The minimum number of baselines to tell the odd one out.
+const MIN_BASELINES: usize = 3;
+
+/// The ratio of unmatched lines above which a baseline is dissimilar.
+const MIN_UNMATCHED_RATIO: f32 = 0.5;
+
+/// How many times the median unmatched ratio a bad baseline has.
+const MEDIAN_FACTOR: f32 = 2.0;
+
+static AUTO_DROP: AtomicBool = AtomicBool::new(false);
+
+/// Remove the bad baselines before the training, instead of only warning about them.
+pub fn set_auto_drop(enabled: bool) {
+    AUTO_DROP.store(enabled, Ordering::Relaxed);
+}
+
+/// The lines of a baseline that are not found in the other baselines.
+#[derive(Debug)]
+pub struct BaselineCheck {
+    pub baseline: Content,
+    /// The unique lines of the baseline.
+    pub line_count: usize,
+    pub unmatched_count: usize,
+}
+
+impl BaselineCheck {
+    pub fn unmatched_ratio(&self) -> f32 {
+        match self.line_count {
+            0 => 0.0,
+            count => self.unmatched_count as f32 / count as f32,
+        }
+    }
+}
+
+/// Read the unique tokenized lines of a baseline.
+fn read_lines(index: &ChunkIndex, baseline: &Content) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    let mut seen = HashSet::new();
+    for source in baseline.collect_sources()? {
+        // The stdin can't be read twice.
+        if source == Source::Stdin {
+            continue;
+        }
+        let reader = source.open()?;
+        let profile = crate::profiles::profile_of(&crate::IndexName::from_source(&source));
+        for line in crate::multiline::read_lines(reader, profile) {
+            let line = line.with_context(|| format!("Failed to read {}", source))?;
+            if String::from_utf8_lossy(&line.0[..]).trim().is_empty() {
+                continue;
+            }
+            let tokens = tokenize_line(index, &line.0[..], profile.json_lines);
+            if seen.insert(tokens.clone()) {
+                lines.push(tokens);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+/// Search the lines of each baseline in the other baselines.
+pub fn check_baselines(
+    baselines: &[Content],
+    mk_index: fn() -> ChunkIndex,
+) -> Result<Vec<BaselineCheck>> {
+    let mut indexes = Vec::with_capacity(baselines.len());
+    let mut baselines_lines = Vec::with_capacity(baselines.len());
+    for baseline in baselines {
+        let mut index = mk_index();
+        let lines = read_lines(&index, baseline)?;
+        let mut trainer = ChunkTrainer::new(&mut index);
+        lines
+            .iter()
+            .for_each(|line| trainer.add_tokens(line.clone()));
+        trainer.complete();
+        indexes.push(index);
+        baselines_lines.push(lines);
+    }
+    Ok(baselines
+        .iter()
+        .zip(&baselines_lines)
+        .enumerate()
+        .map(|(pos, (baseline, lines))| {
+            let mut distances = vec![1.0; lines.len()];
+            for (_, index) in indexes
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != pos)
+            {
+                for (distance, other) in distances.iter_mut().zip(index.search(lines)) {
+                    *distance = other.min(*distance);
+                }
+            }
+            BaselineCheck {
+                baseline: baseline.clone(),
+                line_count: lines.len(),
+                unmatched_count: distances.iter().filter(|d| **d > THRESHOLD).count(),
+            }
+        })
+        .collect())
+}
+
+/// Returns the positions of the baselines that are much more dissimilar than the median one.
+pub fn find_bad(checks: &[BaselineCheck]) -> Vec<usize> {
+    if checks.len() < MIN_BASELINES {
+        return Vec::new();
+    }
+    let mut ratios: Vec<f32> = checks.iter().map(BaselineCheck::unmatched_ratio).collect();
+    ratios.sort_by(|a, b| a.total_cmp(b));
+    let median = ratios[ratios.len() / 2];
+    checks
+        .iter()
+        .enumerate()
+        .filter(|(_, check)| {
+            let ratio = check.unmatched_ratio();
+            ratio > MIN_UNMATCHED_RATIO && ratio > median * MEDIAN_FACTOR
+        })
+        .map(|(pos, _)| pos)
+        .collect()
+}
+
+/// Warn about the bad baselines, and remove them when set_auto_drop is enabled.
+pub(crate) fn filter_baselines(
+    baselines: Baselines,
+    mk_index: fn() -> ChunkIndex,
+) -> Result<Baselines> {
+    if baselines.len() < MIN_BASELINES {
+        return Ok(baselines);
+    }
+    let checks = check_baselines(&baselines, mk_index)?;
+    let bad = find_bad(&checks);
+    let auto_drop = AUTO_DROP.load(Ordering::Relaxed);
+    for pos in &bad {
+        tracing::warn!(
+            "{}: bad baseline: {:.0}% of its lines are not found in the other baselines{}",
+            checks[*pos].baseline,
+            checks[*pos].unmatched_ratio() * 100.0,
+            if auto_drop { ", dropped" } else { "" }
+        );
+    }
+    Ok(baselines
+        .into_iter()
+        .enumerate()
+        .filter(|(pos, _)| !(auto_drop && bad.contains(pos)))
+        .map(|(_, baseline)| baseline)
+        .collect())
+}
+
+#[test]
+fn test_find_bad_baselines() {
+    let build = |status: &str| {
+        Content::from_memory(vec![(
+            "job-output.txt",
+            format!(
+                "INFO starting the job\nINFO installing the packages\nINFO running the tests\nINFO job {}\n",
+                status
+            ),
+        )])
+    };
+    let mislabeled = Content::from_memory(vec![(
+        "job-output.txt",
+        "Traceback (most recent call last)\nKeyError: 'config'\nFATAL controller unreachable\n",
+    )]);
+    let baselines = vec![build("passed"), mislabeled, build("succeeded")];
+    let checks = check_baselines(&baselines, crate::hashing_index::new).unwrap();
+    assert_eq!(checks[1].unmatched_count, 3);
+    assert_eq!(find_bad(&checks), vec![1]);
+    assert!(find_bad(&checks[..2]).is_empty());
+}
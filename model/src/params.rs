@@ -0,0 +1,93 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the training parameters, which are recorded in the model. A smaller hashing
+//! dimension reduces the memory of the indexes, at the cost of more tokens sharing the same feature,
+//! and a smaller chunk size reduces the memory of the search, at the cost of a slower search.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::sync::RwLock;
+
+/// The default number of unique lines indexed and searched together.
+pub const DEFAULT_CHUNK_SIZE: usize = 512;
+
+const DIMENSION_RANGE: RangeInclusive<usize> = 1024..=1 << 24;
+
+const CHUNK_SIZE_RANGE: RangeInclusive<usize> = 16..=1 << 16;
+
+lazy_static::lazy_static! {
+    static ref TRAIN_PARAMS: RwLock<TrainParams> = RwLock::new(TrainParams::default());
+}
+
+/// Set the parameters of the trained models and of the search.
+pub fn set_train_params(params: TrainParams) {
+    *TRAIN_PARAMS.write().unwrap() = params;
+}
+
+pub(crate) fn get_train_params() -> TrainParams {
+    *TRAIN_PARAMS.read().unwrap()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrainParams {
+    /// The number of hashed features of the lines.
+    pub dimension: usize,
+    /// The number of unique lines indexed and searched together.
+    pub chunk_size: usize,
+}
+
+impl Default for TrainParams {
+    fn default() -> Self {
+        TrainParams {
+            dimension: logreduce_index::DEFAULT_DIMENSION,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+fn check(name: &str, value: usize, range: RangeInclusive<usize>) -> Result<usize> {
+    if range.contains(&value) {
+        Ok(value)
+    } else {
+        Err(anyhow::anyhow!(
+            "Invalid {} {}, expected a value between {} and {}",
+            name,
+            value,
+            range.start(),
+            range.end()
+        ))
+    }
+}
+
+impl TrainParams {
+    /// Use the values, or the default ones.
+    pub fn new(dimension: Option<usize>, chunk_size: Option<usize>) -> Result<TrainParams> {
+        let default = TrainParams::default();
+        Ok(TrainParams {
+            dimension: check(
+                "dimension",
+                dimension.unwrap_or(default.dimension),
+                DIMENSION_RANGE,
+            )?,
+            chunk_size: check(
+                "chunk size",
+                chunk_size.unwrap_or(default.chunk_size),
+                CHUNK_SIZE_RANGE,
+            )?,
+        })
+    }
+}
+
+#[test]
+fn test_train_params() {
+    assert_eq!(
+        TrainParams::new(None, None).unwrap(),
+        TrainParams::default()
+    );
+    let params = TrainParams::new(Some(1 << 16), Some(128)).unwrap();
+    assert_eq!(params.dimension, 65536);
+    assert!(TrainParams::new(Some(10), None).is_err());
+    assert!(TrainParams::new(None, Some(0)).is_err());
+}
@@ -0,0 +1,655 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module reads the `message` column of the Parquet files, to analyze the logs archived by the
+//! data platforms. The column values are read as log lines by reader::from_path.
+//!
+//! The file metadata and the page headers are decoded with a minimal thrift compact protocol parser.
+//! The supported columns are the top-level strings, with the plain or the dictionary encoding, and
+//! the uncompressed, snappy, gzip or zstd pages.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::Read;
+
+const MAGIC: &[u8] = b"PAR1";
+
+/// The name of the column that contains the log lines.
+const MESSAGE_COLUMN: &[u8] = b"message";
+
+/// A thrift compact protocol value.
+#[derive(Debug)]
+enum Value {
+    Bool(bool),
+    Int(i64),
+    Binary(Vec<u8>),
+    List(Vec<Value>),
+    Struct(Fields),
+    /// The doubles and the maps, which are not used.
+    Other,
+}
+
+/// The struct fields, per field id.
+type Fields = BTreeMap<i16, Value>;
+
+fn int(fields: &Fields, id: i16) -> Option<i64> {
+    match fields.get(&id) {
+        Some(Value::Int(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+fn binary(fields: &Fields, id: i16) -> Option<&[u8]> {
+    match fields.get(&id) {
+        Some(Value::Binary(value)) => Some(value),
+        _ => None,
+    }
+}
+
+fn list(fields: &Fields, id: i16) -> &[Value] {
+    match fields.get(&id) {
+        Some(Value::List(values)) => values,
+        _ => &[],
+    }
+}
+
+fn structs(fields: &Fields, id: i16) -> impl Iterator<Item = &Fields> {
+    list(fields, id).iter().filter_map(|value| match value {
+        Value::Struct(fields) => Some(fields),
+        _ => None,
+    })
+}
+
+fn struct_(fields: &Fields, id: i16) -> Result<&Fields> {
+    match fields.get(&id) {
+        Some(Value::Struct(fields)) => Ok(fields),
+        _ => Err(anyhow::anyhow!("Missing parquet field {}", id)),
+    }
+}
+
+fn required(fields: &Fields, id: i16) -> Result<usize> {
+    int(fields, id)
+        .and_then(|value| usize::try_from(value).ok())
+        .ok_or_else(|| anyhow::anyhow!("Missing parquet field {}", id))
+}
+
+/// A reader of the thrift compact protocol, and of the parquet varints.
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(data: &'a [u8]) -> Parser<'a> {
+        Parser { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .context("Truncated parquet data")?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow::anyhow!("Invalid parquet varint"))
+    }
+
+    fn zigzag(&mut self) -> Result<i64> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn fields(&mut self) -> Result<Fields> {
+        let mut fields = BTreeMap::new();
+        let mut last_id = 0;
+        loop {
+            let header = self.byte()?;
+            if header == 0 {
+                return Ok(fields);
+            }
+            let id = match header >> 4 {
+                0 => self.zigzag()? as i16,
+                delta => last_id + delta as i16,
+            };
+            let value = match header & 0x0f {
+                // The struct booleans are stored in the field type.
+                1 => Value::Bool(true),
+                2 => Value::Bool(false),
+                kind => self.value(kind)?,
+            };
+            fields.insert(id, value);
+            last_id = id;
+        }
+    }
+
+    fn value(&mut self, kind: u8) -> Result<Value> {
+        Ok(match kind {
+            1 | 2 => Value::Bool(self.byte()? == 1),
+            3 => Value::Int(self.byte()? as i8 as i64),
+            4..=6 => Value::Int(self.zigzag()?),
+            7 => {
+                self.bytes(8)?;
+                Value::Other
+            }
+            8 => {
+                let len = self.varint()? as usize;
+                Value::Binary(self.bytes(len)?.to_vec())
+            }
+            9 | 10 => {
+                let header = self.byte()?;
+                let size = match header >> 4 {
+                    15 => self.varint()? as usize,
+                    size => size as usize,
+                };
+                let kind = header & 0x0f;
+                Value::List((0..size).map(|_| self.value(kind)).collect::<Result<_>>()?)
+            }
+            11 => {
+                let size = self.varint()? as usize;
+                let kinds = if size > 0 { self.byte()? } else { 0 };
+                for _ in 0..size {
+                    self.value(kinds >> 4)?;
+                    self.value(kinds & 0x0f)?;
+                }
+                Value::Other
+            }
+            12 => Value::Struct(self.fields()?),
+            kind => return Err(anyhow::anyhow!("Unknown thrift type {}", kind)),
+        })
+    }
+}
+
+/// Read a little-endian integer.
+fn le(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, byte| (value << 8) | *byte as usize)
+}
+
+/// Decompress a raw snappy block.
+fn snappy(data: &[u8]) -> Result<Vec<u8>> {
+    let mut input = Parser::new(data);
+    let size = input.varint()? as usize;
+    let mut output = Vec::new();
+    while input.pos < data.len() {
+        let tag = input.byte()?;
+        let (len, offset) = match tag & 3 {
+            0 => {
+                let len = match tag >> 2 {
+                    len @ 0..=59 => len as usize + 1,
+                    len => le(input.bytes(len as usize - 59)?) + 1,
+                };
+                output.extend_from_slice(input.bytes(len)?);
+                continue;
+            }
+            1 => (
+                ((tag >> 2) & 7) as usize + 4,
+                ((tag as usize >> 5) << 8) | input.byte()? as usize,
+            ),
+            2 => ((tag >> 2) as usize + 1, le(input.bytes(2)?)),
+            _ => ((tag >> 2) as usize + 1, le(input.bytes(4)?)),
+        };
+        if offset == 0 || offset > output.len() {
+            return Err(anyhow::anyhow!("Invalid snappy offset"));
+        }
+        // The copies can overlap their output.
+        let start = output.len() - offset;
+        for pos in start..start + len {
+            output.push(output[pos]);
+        }
+    }
+    match output.len() == size {
+        true => Ok(output),
+        false => Err(anyhow::anyhow!("Invalid snappy size")),
+    }
+}
+
+fn decompress(codec: i64, data: &[u8]) -> Result<Vec<u8>> {
+    match codec {
+        0 => Ok(data.to_vec()),
+        1 => snappy(data),
+        2 => {
+            let mut output = Vec::new();
+            flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut output)
+                .context("Can't decompress parquet page")?;
+            Ok(output)
+        }
+        6 => zstd::stream::decode_all(data).context("Can't decompress parquet page"),
+        codec => Err(anyhow::anyhow!(
+            "Unsupported parquet compression codec {}",
+            codec
+        )),
+    }
+}
+
+/// Decode the RLE and bit-packed hybrid encoding, used by the definition levels and the dictionary indexes.
+fn rle_hybrid(data: &[u8], bit_width: usize, count: usize) -> Result<Vec<usize>> {
+    if bit_width > 32 {
+        return Err(anyhow::anyhow!("Invalid parquet bit width {}", bit_width));
+    }
+    let mut input = Parser::new(data);
+    let mut values = Vec::new();
+    while values.len() < count {
+        let header = input.varint()? as usize;
+        let remaining = count - values.len();
+        if header & 1 == 0 {
+            let value = le(input.bytes(bit_width.div_ceil(8))?);
+            values.extend(std::iter::repeat_n(value, (header >> 1).min(remaining)));
+        } else {
+            let bytes = input.bytes((header >> 1).saturating_mul(bit_width))?;
+            let packed = bytes.len() * 8 / bit_width.max(1);
+            for idx in 0..packed.min(remaining) {
+                let offset = idx * bit_width;
+                let value = (0..bit_width).fold(0, |value, bit| {
+                    let pos = offset + bit;
+                    value | ((bytes[pos / 8] as usize >> (pos % 8)) & 1) << bit
+                });
+                values.push(value);
+            }
+            if packed == 0 {
+                return Err(anyhow::anyhow!("Invalid parquet bit-packed run"));
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Decode the plain encoded strings.
+fn plain_values(data: &[u8], count: usize) -> Result<Vec<&[u8]>> {
+    let mut input = Parser::new(data);
+    (0..count)
+        .map(|_| {
+            let len = le(input.bytes(4)?);
+            input.bytes(len)
+        })
+        .collect()
+}
+
+fn children(element: &Fields) -> usize {
+    int(element, 5).unwrap_or(0) as usize
+}
+
+/// The number of schema elements of a node and its descendants.
+fn subtree_size(schema: &[&Fields], idx: usize) -> usize {
+    let mut next = idx + 1;
+    for _ in 0..schema
+        .get(idx)
+        .map(|element| children(element))
+        .unwrap_or(0)
+    {
+        next += subtree_size(schema, next);
+    }
+    next - idx
+}
+
+/// The message column of the file schema, with its maximum definition level.
+fn message_column(metadata: &Fields) -> Result<usize> {
+    let schema: Vec<&Fields> = structs(metadata, 2).collect();
+    let root = schema.first().context("Empty parquet schema")?;
+    let mut idx = 1;
+    for _ in 0..children(root) {
+        let element = schema.get(idx).context("Invalid parquet schema")?;
+        if binary(element, 4) == Some(MESSAGE_COLUMN) && children(element) == 0 {
+            if int(element, 1) != Some(6) {
+                return Err(anyhow::anyhow!(
+                    "The parquet message column is not a string"
+                ));
+            }
+            return match int(element, 3) {
+                // required
+                Some(0) | None => Ok(0),
+                // optional
+                Some(1) => Ok(1),
+                _ => Err(anyhow::anyhow!("The parquet message column is repeated")),
+            };
+        }
+        idx += subtree_size(&schema, idx);
+    }
+    Err(anyhow::anyhow!("The parquet file has no message column"))
+}
+
+/// Append the values of a column chunk, one per line.
+fn read_chunk(data: &[u8], meta: &Fields, max_level: usize, lines: &mut Vec<u8>) -> Result<()> {
+    let codec = int(meta, 4).unwrap_or(0);
+    let num_values = required(meta, 5)?;
+    let mut pos = match int(meta, 11) {
+        Some(offset) if offset > 0 => offset as usize,
+        _ => required(meta, 9)?,
+    };
+    let mut dictionary = Vec::new();
+    let mut read = 0;
+    while read < num_values {
+        let mut parser = Parser::new(data.get(pos..).context("Invalid parquet page offset")?);
+        let header = parser.fields()?;
+        pos += parser.pos;
+        let page_size = required(&header, 3)?;
+        let page = data
+            .get(pos..pos.saturating_add(page_size))
+            .context("Truncated parquet page")?;
+        pos += page_size;
+
+        let (count, encoding, levels, values) = match int(&header, 1) {
+            // The dictionary page
+            Some(2) => {
+                let count = required(struct_(&header, 7)?, 1)?;
+                dictionary = plain_values(&decompress(codec, page)?, count)?
+                    .into_iter()
+                    .map(|value| value.to_vec())
+                    .collect();
+                continue;
+            }
+            // The data page, with the levels prefixed by their length
+            Some(0) => {
+                let page_header = struct_(&header, 5)?;
+                let page = decompress(codec, page)?;
+                let levels_len = match max_level {
+                    0 => 0,
+                    _ => 4 + le(page.get(..4).context("Truncated parquet levels")?),
+                };
+                let levels = page.get(4.min(levels_len)..levels_len).unwrap_or(&[]);
+                (
+                    required(page_header, 1)?,
+                    int(page_header, 2),
+                    levels.to_vec(),
+                    page.get(levels_len..).unwrap_or(&[]).to_vec(),
+                )
+            }
+            // The data page v2, with the uncompressed levels
+            Some(3) => {
+                let page_header = struct_(&header, 8)?;
+                let levels_len = required(page_header, 5)? + required(page_header, 6)?;
+                let levels = page.get(..levels_len).context("Truncated parquet levels")?;
+                let values = match page_header.get(&7) {
+                    Some(Value::Bool(false)) => page[levels_len..].to_vec(),
+                    _ => decompress(codec, &page[levels_len..])?,
+                };
+                let rep_len = required(page_header, 6)?;
+                (
+                    required(page_header, 1)?,
+                    int(page_header, 4),
+                    levels[rep_len..].to_vec(),
+                    values,
+                )
+            }
+            // The index pages are skipped
+            _ => continue,
+        };
+        read += count;
+
+        let present = match max_level {
+            0 => count,
+            _ => rle_hybrid(&levels, 1, count)?
+                .iter()
+                .filter(|level| **level == max_level)
+                .count(),
+        };
+        let mut push = |value: &[u8]| {
+            lines.extend_from_slice(value);
+            lines.push(b'\n');
+        };
+        match encoding {
+            Some(0) => plain_values(&values, present)?
+                .into_iter()
+                .for_each(&mut push),
+            // The plain and the rle dictionary
+            Some(2) | Some(8) => {
+                let bit_width = *values.first().context("Missing parquet bit width")? as usize;
+                for idx in rle_hybrid(&values[1..], bit_width, present)? {
+                    push(
+                        dictionary
+                            .get(idx)
+                            .context("Invalid parquet dictionary index")?,
+                    );
+                }
+            }
+            encoding => {
+                return Err(anyhow::anyhow!(
+                    "Unsupported parquet encoding {:?}",
+                    encoding
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the values of the message column, one per line.
+pub fn messages(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 12 || !data.starts_with(MAGIC) || !data.ends_with(MAGIC) {
+        return Err(anyhow::anyhow!("Not a parquet file"));
+    }
+    let footer_end = data.len() - 8;
+    let footer_start = footer_end
+        .checked_sub(le(&data[footer_end..footer_end + 4]))
+        .filter(|start| *start >= MAGIC.len())
+        .context("Invalid parquet footer")?;
+    let metadata = Parser::new(&data[footer_start..footer_end]).fields()?;
+    let max_level = message_column(&metadata)?;
+
+    let mut lines = Vec::new();
+    for row_group in structs(&metadata, 4) {
+        for column in structs(row_group, 1) {
+            let meta = struct_(column, 3)?;
+            let path: Vec<&[u8]> = list(meta, 3)
+                .iter()
+                .filter_map(|value| match value {
+                    Value::Binary(name) => Some(name.as_slice()),
+                    _ => None,
+                })
+                .collect();
+            if path == [MESSAGE_COLUMN] {
+                read_chunk(data, meta, max_level, &mut lines)?;
+            }
+        }
+    }
+    Ok(lines)
+}
+
+#[test]
+fn test_snappy() {
+    // A literal, and an overlapping copy with a 1-byte offset.
+    let data = [
+        12,
+        5 << 2,
+        b'E',
+        b'R',
+        b'R',
+        b'O',
+        b'R',
+        b' ',
+        (2 << 2) | 1,
+        6,
+    ];
+    assert_eq!(snappy(&data).unwrap(), b"ERROR ERROR ");
+    assert!(snappy(&[4, 0, b'a', 1 << 2 | 1, 9]).is_err());
+    assert_eq!(
+        rle_hybrid(&[4, 1, 3, 5], 1, 5).unwrap(),
+        vec![1, 1, 1, 0, 1]
+    );
+}
+
+#[test]
+fn test_parquet_messages() {
+    // A minimal thrift compact writer.
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            match value >> 7 {
+                0 => {
+                    out.push(value as u8);
+                    return out;
+                }
+                rest => {
+                    out.push((value as u8 & 0x7f) | 0x80);
+                    value = rest;
+                }
+            }
+        }
+    }
+    let int = |value: i64| (5, varint(((value << 1) ^ (value >> 63)) as u64));
+    let bin = |value: &[u8]| (8, [varint(value.len() as u64), value.to_vec()].concat());
+    let list = |kind: u8, items: Vec<Vec<u8>>| {
+        (
+            9,
+            [vec![(items.len() as u8) << 4 | kind], items.concat()].concat(),
+        )
+    };
+    let struct_ = |fields: Vec<(i16, (u8, Vec<u8>))>| {
+        let mut out = Vec::new();
+        let mut last = 0;
+        for (id, (kind, value)) in fields {
+            out.push(((id - last) as u8) << 4 | kind);
+            out.extend(value);
+            last = id;
+        }
+        out.push(0);
+        (12, out)
+    };
+    let plain = |values: &[&[u8]]| -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| {
+                [(value.len() as u32).to_le_bytes().to_vec(), value.to_vec()].concat()
+            })
+            .collect()
+    };
+
+    let mut file = b"PAR1".to_vec();
+    // An optional column with a null, in a plain data page.
+    let first_offset = file.len();
+    let levels = [2, 0, 0, 0, 3, 0b101];
+    let page = [&levels[..], &plain(&[b"first line", b"Traceback oops"])].concat();
+    let size = int(page.len() as i64);
+    let data_page = struct_(vec![(1, int(3)), (2, int(0)), (3, int(3)), (4, int(3))]);
+    file.extend(
+        struct_(vec![
+            (1, int(0)),
+            (2, size.clone()),
+            (3, size),
+            (5, data_page),
+        ])
+        .1,
+    );
+    file.extend(page);
+    let first_size = (file.len() - first_offset) as i64;
+    // A dictionary page and a data page v2, compressed with snappy.
+    let second_offset = file.len();
+    let dictionary = plain(&[b"disk full"]);
+    let page = [
+        vec![dictionary.len() as u8, (dictionary.len() as u8 - 1) << 2],
+        dictionary,
+    ]
+    .concat();
+    let dictionary_page = struct_(vec![(1, int(1)), (2, int(0))]);
+    file.extend(
+        struct_(vec![
+            (1, int(2)),
+            (2, int(13)),
+            (3, int(page.len() as i64)),
+            (7, dictionary_page),
+        ])
+        .1,
+    );
+    file.extend(page);
+    let data_offset = file.len();
+    let page = [4, 1, 3, 2 << 2, 1, 4, 0];
+    let data_page = struct_(vec![
+        (1, int(2)),
+        (2, int(0)),
+        (3, int(2)),
+        (4, int(8)),
+        (5, int(2)),
+        (6, int(0)),
+        (7, (1, vec![])),
+    ]);
+    file.extend(
+        struct_(vec![
+            (1, int(3)),
+            (2, int(5)),
+            (3, int(page.len() as i64)),
+            (8, data_page),
+        ])
+        .1,
+    );
+    file.extend(page);
+    let second_size = (file.len() - second_offset) as i64;
+
+    let meta = |codec: i64,
+                values: i64,
+                size: i64,
+                data_offset: usize,
+                dictionary_offset: Option<usize>| {
+        let mut fields = vec![
+            (1, int(6)),
+            (3, list(8, vec![bin(b"message").1])),
+            (4, int(codec)),
+            (5, int(values)),
+            (6, int(size)),
+            (7, int(size)),
+            (9, int(data_offset as i64)),
+        ];
+        if let Some(offset) = dictionary_offset {
+            fields.push((11, int(offset as i64)));
+        }
+        let column = struct_(vec![(2, int(data_offset as i64)), (3, struct_(fields))]);
+        struct_(vec![
+            (1, list(12, vec![column.1])),
+            (2, int(size)),
+            (3, int(values)),
+        ])
+        .1
+    };
+    let metadata = struct_(vec![
+        (1, int(1)),
+        (
+            2,
+            list(
+                12,
+                vec![
+                    struct_(vec![(4, bin(b"schema")), (5, int(2))]).1,
+                    struct_(vec![(1, int(2)), (3, int(0)), (4, bin(b"timestamp"))]).1,
+                    struct_(vec![(1, int(6)), (3, int(1)), (4, bin(b"message"))]).1,
+                ],
+            ),
+        ),
+        (3, int(5)),
+        (
+            4,
+            list(
+                12,
+                vec![
+                    meta(0, 3, first_size, first_offset, None),
+                    meta(1, 2, second_size, data_offset, Some(second_offset)),
+                ],
+            ),
+        ),
+    ])
+    .1;
+    file.extend(&metadata);
+    file.extend((metadata.len() as u32).to_le_bytes());
+    file.extend(b"PAR1");
+
+    assert_eq!(
+        String::from_utf8(messages(&file).unwrap()).unwrap(),
+        "first line\nTraceback oops\ndisk full\ndisk full\n"
+    );
+    assert!(messages(b"PAR1 not a parquet file PAR1").is_err());
+}
@@ -0,0 +1,66 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module extends the masking patterns of the tokenizer with a yaml file of regular
+//! expressions per token, tried before the default patterns, such as:
+//!
+//! ```yaml
+//! BUILD: ['^build-[0-9]+$']
+//! DATE: ['^[0-9]{2}\.[0-9]{2}\.[0-9]{4}$']
+//! ```
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+
+use logreduce_tokenizer::{add_pattern, Pattern};
+
+lazy_static::lazy_static! {
+    /// The loaded (token, regex), which change the tokens of the reports.
+    static ref LOADED: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+}
+
+/// The loaded patterns, to identify the reports, see Model::report_cached.
+pub(crate) fn get_patterns() -> Vec<(String, String)> {
+    LOADED.read().unwrap().clone()
+}
+
+/// Load the patterns, they need to be the same for the training and the inspection.
+pub fn load_patterns(path: &Path) -> Result<()> {
+    let file = std::fs::File::open(path).context("Can't open patterns")?;
+    let regexes: BTreeMap<String, Vec<String>> =
+        serde_yaml::from_reader(file).context("Can't read patterns")?;
+    // The patterns are checked first, so that a bad file doesn't change the catalog.
+    let patterns = regexes
+        .iter()
+        .flat_map(|(token, regexes)| regexes.iter().map(move |regex| (token, regex)))
+        .map(|(token, regex)| {
+            Pattern::new(token, regex).with_context(|| format!("Invalid {} pattern", token))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    patterns.into_iter().for_each(add_pattern);
+    LOADED.write().unwrap().extend(
+        regexes.into_iter().flat_map(|(token, regexes)| {
+            regexes.into_iter().map(move |regex| (token.clone(), regex))
+        }),
+    );
+    Ok(())
+}
+
+#[test]
+fn test_load_patterns() {
+    let path = std::env::temp_dir().join("logreduce-test-patterns.yaml");
+    std::fs::write(&path, "RELEASE: ['^zzrelease-[0-9]+$']\n").unwrap();
+    load_patterns(&path).unwrap();
+    assert_eq!(
+        logreduce_tokenizer::process("deploying zzrelease-42 now"),
+        "deploying %RELEASE"
+    );
+
+    assert!(get_patterns().contains(&("RELEASE".into(), "^zzrelease-[0-9]+$".into())));
+
+    std::fs::write(&path, "BAD: ['(unclosed']\n").unwrap();
+    assert!(load_patterns(&path).is_err());
+    assert!(!get_patterns().iter().any(|(token, _)| token == "BAD"));
+}
@@ -0,0 +1,29 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the performance counters of the processing phases.
+//! They are emitted as debug events of the `logreduce::perf` target, so that a subscriber
+//! can summarize them, see the cli `--debug-perf` option.
+
+use std::time::Duration;
+
+/// The target of the performance events.
+pub const TARGET: &str = "logreduce::perf";
+
+/// Emit the counters of a phase, such as `train` or `inspect`, for a single source or index.
+pub(crate) fn record(
+    phase: &'static str,
+    lines: usize,
+    bytes: usize,
+    chunks: usize,
+    elapsed: Duration,
+) {
+    tracing::debug!(
+        target: TARGET,
+        phase,
+        lines = lines as u64,
+        bytes = bytes as u64,
+        chunks = chunks as u64,
+        elapsed_us = elapsed.as_micros() as u64,
+    );
+}
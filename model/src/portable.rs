@@ -0,0 +1,243 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module exports the models to a documented JSON format, so that they can be inspected or
+//! produced by other tools, without depending on the bincode layout of this crate.
+//!
+//! The baseline matrices are stored in the compressed sparse row format: the rows are the
+//! normalized feature vectors of the baseline lines, and the columns are the hashed tokens.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::hnsw_index::HnswIndex;
+use crate::lsh_index::LshIndex;
+use crate::params::TrainParams;
+use crate::tfidf_index::TfIdfIndex;
+use crate::{hashing_index, noop_index, ChunkIndex, Content, Index, IndexName, Model, Source};
+
+const FORMAT: &str = "logreduce-model";
+
+/// The version of the schema, increased when the fields change.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct PortableModel {
+    format: String,
+    format_version: u32,
+    /// The seconds since the epoch.
+    created_at: f64,
+    version: String,
+    baselines: Vec<Content>,
+    indexes: BTreeMap<String, PortableIndex>,
+    /// The parameters of the training.
+    #[serde(default)]
+    params: TrainParams,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableIndex {
+    created_at: f64,
+    /// The seconds spent training the index.
+    train_time: f64,
+    line_count: usize,
+    byte_count: usize,
+    sources: Vec<Source>,
+    /// Either `hashing-trick`, `tfidf`, `lsh`, `hnsw`, `noop` or `embedding`.
+    kind: String,
+    matrices: Vec<PortableMatrix>,
+    /// The end row and the baseline position of each source.
+    #[serde(default)]
+    baseline_rows: Vec<(usize, usize)>,
+    /// The number of runs since the index last matched a target.
+    #[serde(default)]
+    idle_runs: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PortableMatrix {
+    rows: usize,
+    cols: usize,
+    indptr: Vec<usize>,
+    indices: Vec<usize>,
+    data: Vec<logreduce_index::F>,
+}
+
+fn epoch_secs(time: &SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn from_epoch_secs(secs: f64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs_f64(secs.max(0.0))
+}
+
+impl PortableMatrix {
+    fn from_matrix(mat: &logreduce_index::FeaturesMatrix) -> PortableMatrix {
+        PortableMatrix {
+            rows: mat.rows(),
+            cols: mat.cols(),
+            indptr: mat.indptr().raw_storage().to_vec(),
+            indices: mat.indices().to_vec(),
+            data: mat.data().to_vec(),
+        }
+    }
+
+    fn into_matrix(self) -> Result<logreduce_index::FeaturesMatrix> {
+        logreduce_index::FeaturesMatrix::try_new(
+            (self.rows, self.cols),
+            self.indptr,
+            self.indices,
+            self.data,
+        )
+        .map_err(|(_, _, _, e)| anyhow::anyhow!("Invalid matrix: {}", e))
+    }
+}
+
+impl PortableIndex {
+    fn from_index(index: &Index) -> Result<PortableIndex> {
+        let (kind, matrices) = match &index.index {
+            ChunkIndex::HashingTrick(i) => ("hashing-trick", i.matrices()),
+            ChunkIndex::TfIdf(i) => ("tfidf", i.matrices()),
+            ChunkIndex::Lsh(i) => ("lsh", i.matrices()),
+            ChunkIndex::Hnsw(i) => ("hnsw", i.matrices()),
+            ChunkIndex::Noop => ("noop", &[][..]),
+            ChunkIndex::External(i) => {
+                return Err(anyhow::anyhow!("The {} index can't be exported", i.kind()))
+            }
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => ("embedding", i.matrices()),
+        };
+        let matrices = matrices.iter().map(PortableMatrix::from_matrix).collect();
+        Ok(PortableIndex {
+            created_at: epoch_secs(&index.created_at),
+            train_time: index.train_time.as_secs_f64(),
+            line_count: index.line_count,
+            byte_count: index.byte_count,
+            sources: index.sources.clone(),
+            kind: kind.to_string(),
+            matrices,
+            baseline_rows: index.baseline_rows.clone(),
+            idle_runs: index.idle_runs,
+        })
+    }
+
+    fn into_index(self) -> Result<Index> {
+        let matrices = self
+            .matrices
+            .into_iter()
+            .map(PortableMatrix::into_matrix)
+            .collect::<Result<_>>()?;
+        let index = match self.kind.as_str() {
+            "hashing-trick" => hashing_index::from_matrices(matrices),
+            "tfidf" => ChunkIndex::TfIdf(TfIdfIndex::from_matrices(matrices)),
+            "lsh" => ChunkIndex::Lsh(LshIndex::from_matrices(matrices)),
+            // The graph is built again from the matrices.
+            "hnsw" => ChunkIndex::Hnsw(HnswIndex::from_matrices(matrices)),
+            "noop" => noop_index::new(),
+            #[cfg(feature = "embedding")]
+            "embedding" => ChunkIndex::Embedding(
+                crate::embedding_index::EmbeddingIndex::from_matrices(matrices),
+            ),
+            kind => return Err(anyhow::anyhow!("Unknown index kind: {}", kind)),
+        };
+        Ok(Index {
+            created_at: from_epoch_secs(self.created_at),
+            train_time: Duration::from_secs_f64(self.train_time.max(0.0)),
+            sources: self.sources,
+            index,
+            line_count: self.line_count,
+            byte_count: self.byte_count,
+            baseline_rows: self.baseline_rows,
+            idle_runs: self.idle_runs,
+        })
+    }
+}
+
+impl Model {
+    /// Write the model in the portable JSON format.
+    pub fn export_json<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let portable = PortableModel {
+            format: FORMAT.to_string(),
+            format_version: FORMAT_VERSION,
+            created_at: epoch_secs(&self.created_at),
+            version: self.version.clone(),
+            baselines: self.baselines.clone(),
+            indexes: self
+                .indexes
+                .iter()
+                .map(|(name, index)| {
+                    PortableIndex::from_index(index)
+                        .map(|index| (name.0.clone(), index))
+                        .with_context(|| format!("{}: can't export the index", name))
+                })
+                .collect::<Result<_>>()?,
+            params: self.params,
+        };
+        serde_json::to_writer(writer, &portable).context("Can't export model")
+    }
+
+    /// Read a model written in the portable JSON format.
+    pub fn import_json<R: std::io::Read>(reader: R) -> Result<Model> {
+        let portable: PortableModel =
+            serde_json::from_reader(reader).context("Can't import model")?;
+        if portable.format != FORMAT || portable.format_version > FORMAT_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported model format {} version {}",
+                portable.format,
+                portable.format_version
+            ));
+        }
+        Ok(Model {
+            created_at: from_epoch_secs(portable.created_at),
+            version: portable.version,
+            baselines: portable.baselines,
+            indexes: portable
+                .indexes
+                .into_iter()
+                .map(|(name, index)| Ok((IndexName(name), index.into_index()?)))
+                .collect::<Result<_>>()?,
+            params: portable.params,
+            lazy: None,
+        })
+    }
+}
+
+#[test]
+fn test_portable_model() {
+    let model = crate::train_memory(vec![
+        (
+            "service.log",
+            "INFO service started\nINFO service stopped\n",
+        ),
+        ("worker.log", "INFO worker ready\n"),
+    ]);
+    let mut json = Vec::new();
+    model.export_json(&mut json).unwrap();
+    let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(value["format_version"], FORMAT_VERSION);
+    assert_eq!(value["indexes"]["worker.log"]["kind"], "hashing-trick");
+
+    let imported = Model::import_json(&json[..]).unwrap();
+    assert!(model
+        .diff(&imported)
+        .iter()
+        .all(|(_, change)| matches!(change, crate::changes::IndexChange::Unchanged(_))));
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started\nERROR service crashed\n",
+        )])
+    };
+    let report = |model: &Model| model.report(crate::OutputMode::Quiet, target()).unwrap();
+    assert_eq!(
+        report(&model).log_reports[0].anomalies[0].anomaly.distance,
+        report(&imported).log_reports[0].anomalies[0]
+            .anomaly
+            .distance
+    );
+    assert!(Model::import_json(&br#"{"format": "other"}"#[..]).is_err());
+}
@@ -0,0 +1,174 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the precomputation cache of the models: the compacted baselines and
+//! their features filter are derived on first use, and saved next to the model, in a `.prepared`
+//! file, so that the next runs with the same model skip the computation.
+//! The cache is keyed by the model file hash, it is re-created when the model changes.
+//! The cache is not signed, thus it is skipped when a trusted key is set, see signing.rs.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{hashing_index, IndexName, Model};
+
+#[derive(Serialize, Deserialize)]
+struct Prepared {
+    model_digest: String,
+    indexes: HashMap<IndexName, hashing_index::Prepared>,
+}
+
+/// The location of the precomputation cache of a model.
+pub fn prepared_path(model_path: &Path) -> PathBuf {
+    let mut path = model_path.as_os_str().to_owned();
+    path.push(".prepared");
+    PathBuf::from(path)
+}
+
+fn model_digest(buf: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(crate::version::VERSION);
+    hasher.update(buf);
+    format!("{:X}", hasher.finalize())
+}
+
+impl Model {
+    /// Load a model and its precomputed structures, they are computed and saved when the cache is missing.
+    pub fn load_prepared(path: &Path) -> Result<Model> {
+        let buf = std::fs::read(path).context("Can't open file")?;
+        #[cfg(feature = "signing")]
+        crate::signing::verify_file(path, &buf)?;
+        // The unsigned cache can't replace the baselines of a verified model.
+        #[cfg(feature = "signing")]
+        let use_cache = crate::signing::trusted_key().is_none();
+        #[cfg(not(feature = "signing"))]
+        let use_cache = true;
+        Model::load_prepared_data(&buf, path, use_cache)
+    }
+
+    fn load_prepared_data(buf: &[u8], path: &Path, use_cache: bool) -> Result<Model> {
+        let mut model = Model::load_reader(buf, path)?;
+        if !use_cache {
+            tracing::debug!("Skipping the precomputed indexes of the signed model");
+            return Ok(model);
+        }
+        let digest = model_digest(buf);
+        let cache_path = prepared_path(path);
+        if !model.use_prepared(&cache_path, &digest) {
+            tracing::info!(path = cache_path.to_str(), "Precomputing the model indexes");
+            if let Err(e) = model.prepare(&cache_path, digest) {
+                tracing::warn!("Can't save the precomputed indexes: {:?}", e);
+            }
+        }
+        Ok(model)
+    }
+
+    /// Returns true when the cache matches the model.
+    fn use_prepared(&mut self, cache_path: &Path, digest: &str) -> bool {
+        let prepared: Prepared = match std::fs::File::open(cache_path)
+            .ok()
+            .and_then(|file| bincode::deserialize_from(flate2::read::GzDecoder::new(file)).ok())
+        {
+            Some(prepared) => prepared,
+            None => return false,
+        };
+        if prepared.model_digest != digest {
+            tracing::debug!("Discarding the outdated precomputed indexes");
+            return false;
+        }
+        for (index_name, index_prepared) in prepared.indexes {
+            if let Some(index) = self.indexes.get_mut(&index_name) {
+                index.index.set_prepared(index_prepared);
+            }
+        }
+        true
+    }
+
+    fn prepare(&mut self, cache_path: &Path, model_digest: String) -> Result<()> {
+        let indexes = self
+            .indexes
+            .iter_mut()
+            .filter_map(|(index_name, index)| {
+                index
+                    .index
+                    .prepare()
+                    .map(|prepared| (index_name.clone(), prepared))
+            })
+            .collect();
+        let prepared = Prepared {
+            model_digest,
+            indexes,
+        };
+        // Write to a temporary file so that a concurrent run does not read a partial cache.
+        let mut tmp_path = cache_path.as_os_str().to_owned();
+        tmp_path.push(format!(".{}", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tmp_path).context("Can't create file")?,
+            flate2::Compression::fast(),
+        );
+        bincode::serialize_into(&mut encoder, &prepared).context("Can't encode")?;
+        encoder.finish().context("Can't write")?;
+        std::fs::rename(&tmp_path, cache_path).context("Can't rename")
+    }
+}
+
+#[test]
+fn test_load_prepared() -> Result<()> {
+    use crate::{Content, OutputMode};
+    let dir = std::env::temp_dir().join(format!("logreduce-prepared-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let model_path = dir.join("model.bin");
+    let train = |line: &str| {
+        let baseline = Content::from_memory(vec![(
+            "service.log",
+            (0..100)
+                .map(|idx| format!("INFO service {} {}", line, idx))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )]);
+        Model::train(OutputMode::Quiet, vec![baseline], hashing_index::new)
+    };
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "INFO service started 42\nERROR service crashed\n",
+        )])
+    };
+    train("started")?.save(&model_path)?;
+    let expected = Model::load(&model_path)?.report(OutputMode::Quiet, target())?;
+
+    let cache_path = prepared_path(&model_path);
+    let prepared = Model::load_prepared(&model_path)?;
+    assert!(cache_path.exists());
+    let digest = model_digest(&std::fs::read(&model_path)?);
+    assert!(Model::load(&model_path)?.use_prepared(&cache_path, &digest));
+    let report = Model::load_prepared(&model_path)?.report(OutputMode::Quiet, target())?;
+    assert_eq!(report.total_anomaly_count, expected.total_anomaly_count);
+    assert_eq!(
+        prepared.report(OutputMode::Quiet, target())?.log_reports[0].anomalies[0]
+            .anomaly
+            .distance,
+        expected.log_reports[0].anomalies[0].anomaly.distance
+    );
+
+    // The cache is not used for the signed models.
+    let signed = Model::load_prepared_data(&std::fs::read(&model_path)?, &model_path, false)?;
+    assert!(signed.indexes.values().all(|index| match &index.index {
+        crate::ChunkIndex::HashingTrick(index) => !index.is_prepared(),
+        _ => false,
+    }));
+
+    // The cache is invalidated by a new model.
+    train("stopped")?.save(&model_path)?;
+    let digest = model_digest(&std::fs::read(&model_path)?);
+    assert!(!Model::load(&model_path)?.use_prepared(&cache_path, &digest));
+    Model::load_prepared(&model_path)?;
+    assert!(Model::load(&model_path)?.use_prepared(&cache_path, &digest));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
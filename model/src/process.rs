@@ -4,15 +4,190 @@
 //! This module provides the core utilities to use logreduce-index with Read objects.
 
 use anyhow::Result;
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Read;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
-use crate::{Anomaly, AnomalyContext, ChunkIndex};
+#[cfg(test)]
+use crate::params::DEFAULT_CHUNK_SIZE;
+use crate::profiles::Profile;
+use crate::{Anomaly, AnomalyContext, ChunkIndex, Repeats};
 use logreduce_iterator::LogLine;
 
-const THRESHOLD: logreduce_index::F = 0.3;
+pub(crate) const THRESHOLD: logreduce_index::F = 0.3;
 const CTX_DISTANCE: usize = 3;
-const CHUNK_SIZE: usize = 512;
+// The maximum distance of a learned line, well below the THRESHOLD.
+const LEARN_DISTANCE: logreduce_index::F = 0.1;
+// The maximum number of learned chunks kept in the window.
+const LEARN_CHUNKS: usize = 16;
+// The minimum number of unique lines of a search work item.
+const MIN_PART_TARGETS: usize = 64;
+
+lazy_static::lazy_static! {
+    /// The default chunk boundary, set with the LOGREDUCE_CHUNK_BOUNDARY environment variable.
+    static ref CHUNK_BOUNDARY: ChunkBoundary = match std::env::var("LOGREDUCE_CHUNK_BOUNDARY") {
+        Ok(value) => value.parse().unwrap_or_else(|e| {
+            tracing::warn!("{}", e);
+            ChunkBoundary::Lines
+        }),
+        Err(_) => ChunkBoundary::Lines,
+    };
+
+    /// The number of work items of a chunk search, set with the LOGREDUCE_SEARCH_THREADS environment variable,
+    /// 0 to use the size of the rayon thread pool.
+    static ref SEARCH_THREADS: usize = match std::env::var("LOGREDUCE_SEARCH_THREADS") {
+        Ok(value) => match value.parse() {
+            Ok(0) => rayon::current_num_threads(),
+            Ok(threads) => threads,
+            Err(e) => {
+                tracing::warn!("Invalid LOGREDUCE_SEARCH_THREADS {}: {}", value, e);
+                1
+            }
+        },
+        Err(_) => 1,
+    };
+}
+
+pub(crate) fn get_chunk_boundary() -> ChunkBoundary {
+    *CHUNK_BOUNDARY
+}
+
+static ATTRIBUTION: AtomicBool = AtomicBool::new(false);
+
+/// Attribute the matched lines to the baselines of the model, see ChunkProcessor::with_attribution.
+/// This is slower, as the filtered search is not used.
+pub fn set_attribution(enabled: bool) {
+    ATTRIBUTION.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn is_attribution_enabled() -> bool {
+    ATTRIBUTION.load(Ordering::Relaxed)
+}
+
+/// The baseline of an index row, using the end row of each trained source.
+fn baseline_of(baseline_rows: &[(usize, usize)], row: usize) -> Option<usize> {
+    baseline_rows
+        .get(baseline_rows.partition_point(|(end, _)| *end <= row))
+        .map(|(_, baseline)| *baseline)
+}
+
+/// Where the processor can end a chunk of lines to be searched.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChunkBoundary {
+    /// After a fixed number of unique lines.
+    Lines,
+    /// Before the next blank line, once the chunk is full.
+    BlankLine,
+    /// Before the next line starting with a timestamp, once the chunk is full.
+    Timestamp,
+}
+
+impl std::str::FromStr for ChunkBoundary {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "lines" => Ok(ChunkBoundary::Lines),
+            "blank-line" => Ok(ChunkBoundary::BlankLine),
+            "timestamp" => Ok(ChunkBoundary::Timestamp),
+            _ => Err(anyhow::anyhow!(
+                "Unknown chunk boundary {}, expected lines, blank-line or timestamp",
+                s
+            )),
+        }
+    }
+}
+
+impl ChunkBoundary {
+    // Returns true when the line starts a new logical record.
+    fn is_record_start(&self, line: &str, is_blank: bool) -> bool {
+        match self {
+            ChunkBoundary::Lines => false,
+            ChunkBoundary::BlankLine => is_blank,
+            ChunkBoundary::Timestamp => starts_with_timestamp(line),
+        }
+    }
+
+    // The maximum number of unique lines when waiting for a record boundary.
+    fn max_chunk_size(&self, chunk_size: usize) -> usize {
+        match self {
+            ChunkBoundary::Lines => chunk_size,
+            _ => chunk_size * 4,
+        }
+    }
+}
+
+/// Check if the line starts with a date (2022-03-23, 2022/03/23), a time (17:33:00)
+/// or a syslog date (Mar 23), optionally surrounded by a bracket.
+fn starts_with_timestamp(line: &str) -> bool {
+    let line = line.strip_prefix('[').unwrap_or(line).as_bytes();
+    let matches = |pattern: &[u8]| {
+        line.len() >= pattern.len()
+            && pattern.iter().zip(line).all(|(p, c)| match p {
+                b'd' => c.is_ascii_digit(),
+                b'-' => *c == b'-' || *c == b'/',
+                p => p == c,
+            })
+    };
+    const MONTHS: [&[u8]; 12] = [
+        b"Jan ", b"Feb ", b"Mar ", b"Apr ", b"May ", b"Jun ", b"Jul ", b"Aug ", b"Sep ", b"Oct ",
+        b"Nov ", b"Dec ",
+    ];
+    matches(b"dddd-dd-dd") || matches(b"dd:dd:dd") || MONTHS.iter().any(|m| line.starts_with(m))
+}
+
+/// Search the unique lines of a chunk, split in work items for the rayon thread pool so that a large source
+/// uses all the cores. The pool is shared with the other sources, see Model::report, and the distances are
+/// assembled in the order of the targets.
+fn search_chunk(index: &ChunkIndex, targets: &[String], parts: usize) -> Vec<f32> {
+    use rayon::prelude::*;
+    let parts = parts.min(targets.len() / MIN_PART_TARGETS).max(1);
+    if parts == 1 {
+        return index.search(targets);
+    }
+    targets
+        .par_chunks(targets.len().div_ceil(parts))
+        .flat_map_iter(|part| index.search(part))
+        .collect()
+}
+
+/// Tokenize a raw line. When the line is not valid utf-8, or when the tokenizer panics,
+/// the tokens are replaced by a hash of the bytes so that the rest of the source is processed.
+/// The lines of a multiline record are tokenized separately, and the JSON lines are flattened.
+pub(crate) fn tokenize_line(index: &ChunkIndex, line: &[u8], json_lines: bool) -> String {
+    let flatten = |line: &str| {
+        json_lines
+            .then(|| crate::jsonlines::flatten(line))
+            .flatten()
+    };
+    let tokenize_one = |line: &str| match flatten(line) {
+        Some(text) => index.tokenize(&text),
+        None => index.tokenize(line),
+    };
+    let tokenize = |raw_str: &str| {
+        if raw_str.contains('\n') {
+            let tokens: Vec<String> = raw_str.split('\n').map(tokenize_one).collect();
+            tokens.join(" ")
+        } else {
+            tokenize_one(raw_str)
+        }
+    };
+    let tokens = std::str::from_utf8(line).ok().and_then(|raw_str| {
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tokenize(raw_str))).ok()
+    });
+    match tokens {
+        Some(tokens) => tokens,
+        None => {
+            use sha2::Digest;
+            tracing::debug!("Can't tokenize line: {}", String::from_utf8_lossy(line));
+            crate::usage::tokenizer_fallback();
+            let digest = sha2::Sha256::digest(line);
+            format!("%RAW{:x}", digest)
+        }
+    }
+}
 
 /// Helper struct to manage indexing multiples readers.
 pub struct ChunkTrainer<'a> {
@@ -21,6 +196,13 @@ pub struct ChunkTrainer<'a> {
     baselines: Vec<String>,
     pub line_count: usize,
     pub byte_count: usize,
+    pub chunk_count: usize,
+    /// The number of rows added to the index.
+    pub row_count: usize,
+    chunk_size: usize,
+    /// The processing modes of the next readers, see profiles.rs.
+    profile: Profile,
+    start_time: Instant,
 }
 
 impl<'a> ChunkTrainer<'a> {
@@ -31,9 +213,19 @@ impl<'a> ChunkTrainer<'a> {
             baselines: Vec::new(),
             line_count: 0,
             byte_count: 0,
+            chunk_count: 0,
+            row_count: 0,
+            chunk_size: crate::params::get_train_params().chunk_size,
+            profile: Profile::global(),
+            start_time: Instant::now(),
         }
     }
 
+    /// Read the next readers with the modes of the profile.
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+    }
+
     /// Index a single reader
     pub fn single<R: Read>(index: &'a mut ChunkIndex, read: R) -> Result<()> {
         let mut trainer = ChunkTrainer::new(index);
@@ -42,41 +234,182 @@ impl<'a> ChunkTrainer<'a> {
         Ok(())
     }
 
-    pub fn add<R: Read>(&mut self, read: R) -> Result<()> {
-        for line in logreduce_iterator::BytesLines::new(read) {
+    /// Index the next lines even if they were already added, e.g. for another baseline.
+    pub fn forget_lines(&mut self) {
+        self.skip_lines.clear();
+    }
+
+    /// Index a reader, returns false when it only contains blank lines.
+    pub fn add<R: Read>(&mut self, read: R) -> Result<bool> {
+        let mut has_content = false;
+        for line in crate::multiline::read_lines(read, self.profile) {
             let line = line?;
-            let raw_str = std::str::from_utf8(&line.0[..])
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            self.line_count += 1;
+            let raw_str = String::from_utf8_lossy(&line.0[..]);
+            self.line_count += crate::multiline::line_count(&line.0);
             self.byte_count += line.0.len();
-            let tokens = self.index.tokenize(raw_str);
+            if raw_str.trim().is_empty() {
+                continue;
+            }
+            has_content = true;
+            let tokens = tokenize_line(self.index, &line.0[..], self.profile.json_lines);
+            self.add_tokens(tokens);
+        }
+        Ok(has_content)
+    }
 
-            if !self.skip_lines.contains(&tokens) {
-                self.skip_lines.insert(tokens.clone());
-                self.baselines.push(tokens);
+    /// Index a line that is already tokenized.
+    pub fn add_tokens(&mut self, tokens: String) {
+        if !self.skip_lines.contains(&tokens) {
+            self.skip_lines.insert(tokens.clone());
+            self.baselines.push(tokens);
+            self.row_count += 1;
 
-                if self.baselines.len() == CHUNK_SIZE {
-                    self.index.add(&self.baselines);
-                    self.baselines.clear();
-                }
+            if self.baselines.len() == self.chunk_size {
+                self.index.add(&self.baselines);
+                self.chunk_count += 1;
+                self.baselines.clear();
             }
         }
-        Ok(())
     }
 
     pub fn complete(&mut self) {
         if !self.baselines.is_empty() {
             self.index.add(&self.baselines);
+            self.chunk_count += 1;
+        }
+        crate::perf::record(
+            "train",
+            self.line_count,
+            self.byte_count,
+            self.chunk_count,
+            self.start_time.elapsed(),
+        );
+    }
+}
+
+/// The unique lines, either shared between processors or owned by a single processor.
+enum SkipLines<'a> {
+    Shared(&'a mut HashSet<String>),
+    Owned(HashSet<String>),
+}
+
+impl<'a> Deref for SkipLines<'a> {
+    type Target = HashSet<String>;
+    fn deref(&self) -> &Self::Target {
+        match self {
+            SkipLines::Shared(skip_lines) => skip_lines,
+            SkipLines::Owned(skip_lines) => skip_lines,
         }
     }
 }
 
+impl<'a> DerefMut for SkipLines<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            SkipLines::Shared(skip_lines) => skip_lines,
+            SkipLines::Owned(skip_lines) => skip_lines,
+        }
+    }
+}
+
+/// The lines learned during a live session, so that the index adapts to a slowly changing normal.
+/// The lines that are close, but not identical, to the baselines are added to a sliding window of chunks.
+pub struct LearningWindow {
+    mk_index: fn() -> ChunkIndex,
+    /// The learned chunks with their line count, oldest first.
+    chunks: VecDeque<(usize, ChunkIndex)>,
+    /// The learned lines waiting to be indexed.
+    pending: Vec<String>,
+    line_count: usize,
+    max_lines: usize,
+    max_distance: logreduce_index::F,
+    chunk_size: usize,
+}
+
+impl LearningWindow {
+    pub fn new(mk_index: fn() -> ChunkIndex) -> LearningWindow {
+        let chunk_size = crate::params::get_train_params().chunk_size;
+        LearningWindow::with_size(mk_index, chunk_size * LEARN_CHUNKS)
+    }
+
+    /// Learn all the lines that are not anomalies, without a size limit.
+    pub fn accepting(mk_index: fn() -> ChunkIndex) -> LearningWindow {
+        LearningWindow {
+            max_distance: THRESHOLD,
+            ..LearningWindow::with_size(mk_index, usize::MAX)
+        }
+    }
+
+    fn with_size(mk_index: fn() -> ChunkIndex, max_lines: usize) -> LearningWindow {
+        LearningWindow {
+            mk_index,
+            chunks: VecDeque::new(),
+            pending: Vec::new(),
+            line_count: 0,
+            max_lines,
+            max_distance: LEARN_DISTANCE,
+            chunk_size: crate::params::get_train_params().chunk_size,
+        }
+    }
+
+    /// The number of lines currently learned.
+    pub fn line_count(&self) -> usize {
+        self.line_count + self.pending.len()
+    }
+
+    /// Lower the distances of the targets that are close to a learned line.
+    fn search(&self, targets: &[String], distances: &mut [logreduce_index::F]) {
+        for (_, chunk) in &self.chunks {
+            for (distance, learned) in distances.iter_mut().zip(chunk.search(targets)) {
+                *distance = distance.min(learned);
+            }
+        }
+    }
+
+    fn learn(&mut self, targets: &[String], distances: &[logreduce_index::F]) {
+        for (target, distance) in targets.iter().zip(distances) {
+            if *distance > 0.0 && *distance < self.max_distance {
+                self.pending.push(target.clone());
+                if self.pending.len() == self.chunk_size {
+                    self.flush();
+                }
+            }
+        }
+    }
+
+    /// Index the pending lines and drop the oldest chunks that no longer fit in the window.
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut index = (self.mk_index)();
+        index.add(&self.pending);
+        self.line_count += self.pending.len();
+        self.chunks.push_back((self.pending.len(), index));
+        self.pending.clear();
+        while self.line_count > self.max_lines {
+            match self.chunks.pop_front() {
+                Some((count, _)) => self.line_count -= count,
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the learned chunks, oldest first.
+    pub fn complete(mut self) -> impl Iterator<Item = ChunkIndex> {
+        self.flush();
+        self.chunks.into_iter().map(|(_, chunk)| chunk)
+    }
+}
+
 /// Helper struct to manage the log lines and the unique tokenized lines.
 /// The goal is to perform the index search on unique lines, while keeping a
 /// buffer of the raw line to manage the surrounding context.
 pub struct ChunkProcessor<'a, R: Read> {
-    reader: logreduce_iterator::BytesLines<R>,
+    reader: crate::multiline::Records<crate::multiline::Lines<R>>,
     index: &'a ChunkIndex,
+    /// Tokenize the JSON lines from their fields, see jsonlines.rs.
+    json_lines: bool,
     /// The raw log line with their global position
     buffer: Vec<(logreduce_iterator::LogLine, usize)>,
     /// The target tokenized lines
@@ -90,13 +423,50 @@ pub struct ChunkProcessor<'a, R: Read> {
     /// The list of anomalies recently found.
     anomalies: VecDeque<AnomalyContext>,
     /// The list of unique log lines, to avoid searching a line twice.
-    skip_lines: &'a mut HashSet<String>,
+    skip_lines: SkipLines<'a>,
+    /// The position of the anomalies, per tokenized line.
+    anomaly_lines: HashMap<String, usize>,
+    /// The skipped duplicates of the anomalies, per anomaly position.
+    repeats: HashMap<usize, Repeats>,
+    /// The skipped duplicates of the current chunk, before the anomalies are known.
+    pending_repeats: HashMap<String, Repeats>,
     /// The current line coordinate.
     coord: usize,
     /// Total lines count
     pub line_count: usize,
     /// Total bytes count
     pub byte_count: usize,
+    /// Searched chunks count
+    pub chunk_count: usize,
+    /// Blank lines count
+    blank_count: usize,
+    /// The reader only has the head and the tail of the source, see sampling::sample.
+    pub sampled: bool,
+    /// Where the chunks end
+    boundary: ChunkBoundary,
+    /// The number of unique lines searched together, see TrainParams.
+    chunk_size: usize,
+    /// The optional lines learned on the fly
+    learning: Option<&'a mut LearningWindow>,
+    /// The number of lines read before the reader
+    line_offset: usize,
+    /// The end row and the baseline of each trained source, to attribute the matches.
+    baseline_rows: Option<&'a [(usize, usize)]>,
+    /// The matched and the exclusively matched unique lines, per baseline.
+    pub baseline_matches: Vec<(usize, usize)>,
+    start_time: Instant,
+}
+
+impl<'a, R: Read> Drop for ChunkProcessor<'a, R> {
+    fn drop(&mut self) {
+        crate::perf::record(
+            "inspect",
+            self.line_count,
+            self.byte_count,
+            self.chunk_count,
+            self.start_time.elapsed(),
+        );
+    }
 }
 
 impl<'a, R: Read> Iterator for ChunkProcessor<'a, R> {
@@ -121,28 +491,104 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         index: &'a ChunkIndex,
         skip_lines: &'a mut HashSet<String>,
     ) -> ChunkProcessor<'a, R> {
+        ChunkProcessor::with_skip_lines(read, index, SkipLines::Shared(skip_lines))
+    }
+
+    /// Process a single reader, without sharing the unique lines.
+    pub fn single(read: R, index: &'a ChunkIndex) -> ChunkProcessor<'a, R> {
+        ChunkProcessor::with_skip_lines(read, index, SkipLines::Owned(HashSet::new()))
+    }
+
+    fn with_skip_lines(
+        read: R,
+        index: &'a ChunkIndex,
+        skip_lines: SkipLines<'a>,
+    ) -> ChunkProcessor<'a, R> {
+        let chunk_size = crate::params::get_train_params().chunk_size;
+        let profile = Profile::global();
         ChunkProcessor {
-            reader: logreduce_iterator::BytesLines::new(read),
+            reader: crate::multiline::read_lines(read, profile),
             index,
+            json_lines: profile.json_lines,
             buffer: Vec::new(),
             left_overs: Vec::new(),
-            targets: Vec::with_capacity(CHUNK_SIZE),
-            targets_coord: Vec::with_capacity(CHUNK_SIZE),
+            targets: Vec::with_capacity(chunk_size),
+            targets_coord: Vec::with_capacity(chunk_size),
             current_anomaly: None,
             anomalies: VecDeque::new(),
             skip_lines,
+            anomaly_lines: HashMap::new(),
+            repeats: HashMap::new(),
+            pending_repeats: HashMap::new(),
             coord: 0,
             line_count: 0,
             byte_count: 0,
+            chunk_count: 0,
+            blank_count: 0,
+            sampled: false,
+            boundary: *CHUNK_BOUNDARY,
+            chunk_size,
+            learning: None,
+            line_offset: 0,
+            baseline_rows: None,
+            baseline_matches: Vec::new(),
+            start_time: Instant::now(),
         }
     }
 
+    /// Count the lines matched by each baseline, using the rows of Index::train_baselines.
+    pub fn with_attribution(
+        mut self,
+        baseline_rows: &'a [(usize, usize)],
+        baseline_count: usize,
+    ) -> ChunkProcessor<'a, R> {
+        self.baseline_rows = Some(baseline_rows);
+        self.baseline_matches = vec![(0, 0); baseline_count];
+        self
+    }
+
+    /// Align the chunks on logical records, so that the contexts are not split mid-record.
+    pub fn with_boundary(mut self, boundary: ChunkBoundary) -> ChunkProcessor<'a, R> {
+        self.boundary = boundary;
+        self
+    }
+
+    /// Fold the continuation lines of the stack traces, see multiline.rs.
+    pub fn with_multiline(mut self, enabled: bool) -> ChunkProcessor<'a, R> {
+        self.reader.enabled = enabled;
+        self
+    }
+
+    /// Read the source with the modes of the profile, see profiles.rs.
+    pub fn with_profile(mut self, profile: Profile) -> ChunkProcessor<'a, R> {
+        self.reader.enabled = profile.multiline;
+        self.reader.strip_ansi = profile.strip_ansi;
+        self.json_lines = profile.json_lines;
+        self
+    }
+
+    /// Search the lines learned by the window too, and learn the new lines close to the baselines.
+    pub fn with_learning(mut self, window: &'a mut LearningWindow) -> ChunkProcessor<'a, R> {
+        self.learning = Some(window);
+        self
+    }
+
+    /// Report the anomaly positions after the lines already read from the source.
+    pub fn with_line_offset(mut self, line_offset: usize) -> ChunkProcessor<'a, R> {
+        self.line_offset = line_offset;
+        self
+    }
+
+    /// Returns true when the processed reader only contained blank lines.
+    pub fn is_empty(&self) -> bool {
+        self.line_count == self.blank_count
+    }
+
     fn read_anomalies(&mut self) -> Result<()> {
         while let Some(line) = self.reader.next() {
             let line = line?;
-            let raw_str = std::str::from_utf8(&line.0[..])
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            self.line_count += 1;
+            let raw_str = String::from_utf8_lossy(&line.0[..]);
+            self.line_count += crate::multiline::line_count(&line.0);
             self.byte_count += line.0.len();
             self.coord += 1;
 
@@ -151,36 +597,62 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
                 break;
             }
 
+            // Blank lines are only kept as context
+            let is_blank = raw_str.trim().is_empty();
+            if is_blank {
+                self.blank_count += 1;
+            }
+
+            // Search the full chunk before a new record starts
+            let mut searched = false;
+            if self.targets.len() >= self.chunk_size
+                && self.boundary.is_record_start(&raw_str, is_blank)
+            {
+                self.do_search_anomalies();
+                searched = true;
+            }
+
             // Call the static method of the ChunkIndex trait
-            let tokens = self.index.tokenize(raw_str);
+            let tokens = tokenize_line(self.index, &line.0[..], self.json_lines);
 
-            // Keep in the buffer all the lines until we get CHUNK_SIZE unique lines
+            // Keep in the buffer all the lines until we get chunk_size unique lines
             self.buffer.push((line, self.coord));
 
-            if !self.skip_lines.contains(&tokens) {
+            if !is_blank && !self.skip_lines.contains(&tokens) {
                 self.skip_lines.insert(tokens.clone());
 
                 self.targets.push(tokens);
                 self.targets_coord.push(self.coord);
 
-                if self.targets.len() == CHUNK_SIZE {
+                if self.targets.len() == self.boundary.max_chunk_size(self.chunk_size) {
                     self.do_search_anomalies();
-                    if !self.anomalies.is_empty() {
-                        return Ok(());
-                    }
+                    searched = true;
                 }
-            } else if self.buffer.len() > CHUNK_SIZE * 10 {
-                // the source contains mostly duplicate line.
-                self.do_search_anomalies();
-                if !self.anomalies.is_empty() {
-                    return Ok(());
+            } else {
+                if !is_blank {
+                    self.count_repeat(&tokens);
                 }
+                if self.buffer.len() > self.chunk_size * 10 {
+                    // the source contains mostly duplicate line.
+                    self.do_search_anomalies();
+                    searched = true;
+                }
+            }
+            if searched && !self.anomalies.is_empty() {
+                return Ok(());
             }
         }
 
         // We reached the end of the file and the last chunk is not completed
         if !self.targets.is_empty() {
             self.do_search_anomalies();
+        } else {
+            // The remaining lines are already known, they are the after context of the last anomaly.
+            self.collect_after(0);
+        }
+        if let Some(window) = &mut self.learning {
+            // Make the lines learned from this reader available for the next one.
+            window.flush();
         }
         if let Some(anomaly) = &self.current_anomaly {
             // No more after context available
@@ -190,14 +662,77 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         Ok(())
     }
 
+    /// Count a skipped duplicate line.
+    fn count_repeat(&mut self, tokens: &str) {
+        let pos = self.coord + self.line_offset;
+        let repeats = match self.anomaly_lines.get(tokens) {
+            Some(anomaly_pos) => self.repeats.entry(*anomaly_pos).or_default(),
+            None => self.pending_repeats.entry(tokens.to_string()).or_default(),
+        };
+        repeats.count += 1;
+        repeats.last_pos = pos;
+    }
+
+    /// Set the skipped duplicates of the anomalies, once the reader is consumed.
+    pub fn set_repeats(&self, anomalies: &mut [AnomalyContext]) {
+        for anomaly in anomalies {
+            anomaly.anomaly.repeats = self.repeats.get(&anomaly.anomaly.pos).copied();
+        }
+    }
+
+    /// Search the targets distance to each baseline, and count the matches.
+    fn search_attributed(&mut self, baseline_rows: &[(usize, usize)]) -> Vec<f32> {
+        let count = self.baseline_matches.len();
+        // The rows without a baseline, e.g. of a merged model, are searched as an extra group.
+        let group_of = |row| {
+            baseline_of(baseline_rows, row)
+                .filter(|b| *b < count)
+                .or(Some(count))
+        };
+        let groups = match self.index.search_groups(&self.targets, group_of, count + 1) {
+            Some(groups) => groups,
+            None => return search_chunk(self.index, &self.targets, *SEARCH_THREADS),
+        };
+        groups
+            .iter()
+            .map(|distances| {
+                let matches: Vec<usize> = distances[..count]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, distance)| **distance <= THRESHOLD)
+                    .map(|(baseline, _)| baseline)
+                    .collect();
+                for baseline in &matches {
+                    self.baseline_matches[*baseline].0 += 1;
+                }
+                if let [baseline] = matches[..] {
+                    self.baseline_matches[baseline].1 += 1;
+                }
+                distances
+                    .iter()
+                    .fold(1.0, |acc: f32, distance| acc.min(*distance))
+            })
+            .collect()
+    }
+
     /// Helper function for the anomalies_from_reader implementation.
     fn do_search_anomalies(&mut self) {
-        let distances = self.index.search(&self.targets);
+        self.chunk_count += 1;
+        let mut distances = match self.baseline_rows {
+            Some(baseline_rows) => self.search_attributed(baseline_rows),
+            None => search_chunk(self.index, &self.targets, *SEARCH_THREADS),
+        };
+        if let Some(window) = &mut self.learning {
+            window.search(&self.targets, &mut distances);
+            window.learn(&self.targets, &distances);
+        }
 
         let mut buffer_pos = 0;
         let mut last_context_pos = 0;
 
-        for (distance, coord) in distances.iter().zip(self.targets_coord.iter()) {
+        for (target_idx, (distance, coord)) in
+            distances.iter().zip(self.targets_coord.iter()).enumerate()
+        {
             let is_anomaly = distance > &THRESHOLD;
 
             // The distances and coords are out of sync with the buffer, because they only contains unique line.
@@ -210,12 +745,12 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
 
                 if distance_found_in_buffer && is_anomaly {
                     // We found the target in the buffer, and it is an anomaly
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
+                    let raw_str = String::from_utf8_lossy(bytes).into_owned();
                     target_str = Some((raw_str, line_number));
                 } else if let Some(anomaly) = &mut self.current_anomaly {
                     // The buffer head is not anomaly, and we are still processing the last anomaly found.
                     // In that case, we add the log line to the after context.
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
+                    let raw_str = String::from_utf8_lossy(bytes).into_owned();
                     anomaly.after.push(raw_str);
                     if anomaly.after.len() >= CTX_DISTANCE as usize {
                         // The current anomaly is completed. TODO: try using std::mem::replace
@@ -247,13 +782,29 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
 
                 last_context_pos = buffer_pos;
 
+                let pos = *log_pos + self.line_offset;
+                let tokens = &self.targets[target_idx];
+                if let Some(repeats) = self.pending_repeats.remove(tokens) {
+                    self.repeats.insert(pos, repeats);
+                }
+                self.anomaly_lines.insert(tokens.clone(), pos);
+
                 self.current_anomaly = Some(AnomalyContext {
                     before,
                     after: Vec::new(),
                     anomaly: Anomaly {
                         distance: *distance,
-                        pos: *log_pos,
+                        severity: *distance,
+                        pos,
+                        level: crate::levels::parse_level(
+                            self.json_lines
+                                .then(|| crate::jsonlines::flatten(&log_line))
+                                .flatten()
+                                .as_deref()
+                                .unwrap_or(&log_line),
+                        ),
                         line: log_line,
+                        repeats: None,
                     },
                 });
             } else if is_anomaly {
@@ -264,11 +815,16 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
             }
         }
 
-        // Handle the last anomaly after context
+        self.collect_after(last_context_pos);
+        self.reset(last_context_pos)
+    }
+
+    /// Handle the last anomaly after context
+    fn collect_after(&mut self, last_context_pos: usize) {
         if let Some(anomaly) = &mut self.current_anomaly {
             if last_context_pos < self.buffer.len() {
                 for ((bytes, _), _) in &self.buffer[last_context_pos..] {
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
+                    let raw_str = String::from_utf8_lossy(bytes).into_owned();
                     anomaly.after.push(raw_str);
                     if anomaly.after.len() >= CTX_DISTANCE as usize {
                         // The current anomaly is completed. TODO: try using std::mem::replace
@@ -279,12 +835,12 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
                 }
             }
         }
-        self.reset(last_context_pos)
     }
 
     fn reset(&mut self, left_overs_pos: usize) {
         self.targets.clear();
         self.targets_coord.clear();
+        self.pending_repeats.clear();
 
         // Keep the buffer left over as potential prev context for the next anomaly.
         let min_left_overs_pos = if self.buffer.len() < CTX_DISTANCE {
@@ -296,7 +852,7 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         self.left_overs = self.buffer[max_left_overs_pos..]
             .iter()
             // TODO: use direct bytes -> str conversion.
-            .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes).unwrap())
+            .map(|((bytes, _), _)| String::from_utf8_lossy(bytes).into_owned())
             .collect();
         self.buffer.clear();
     }
@@ -322,7 +878,7 @@ fn collect_before(
     let mut before = buffer[before_context_pos..buffer_pos]
         .iter()
         // TODO: use direct bytes -> str conversion.
-        .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes).unwrap())
+        .map(|((bytes, _), _)| String::from_utf8_lossy(bytes).into_owned())
         .collect::<Vec<String>>();
     if before_context_pos == 0 && before.len() < CTX_DISTANCE {
         // The anomaly happens at the begining of the buffer
@@ -397,6 +953,35 @@ fn test_leftovers() {
     );
 }
 
+#[test]
+fn test_search_chunk() {
+    let mut index = crate::hashing_index::new();
+    let baseline: Vec<String> = (0..100)
+        .map(|idx| format!("INFO service{} started", idx))
+        .collect();
+    ChunkTrainer::single(&mut index, std::io::Cursor::new(baseline.join("\n"))).unwrap();
+
+    let targets: Vec<String> = (0..DEFAULT_CHUNK_SIZE)
+        .map(|idx| match idx % 3 {
+            0 => tokenize_line(
+                &index,
+                format!("INFO service{} started", idx).as_bytes(),
+                false,
+            ),
+            _ => tokenize_line(
+                &index,
+                format!("ERROR worker{} crashed", idx).as_bytes(),
+                false,
+            ),
+        })
+        .collect();
+    assert_eq!(search_chunk(&index, &targets, 4), index.search(&targets));
+    assert_eq!(
+        search_chunk(&index, &targets[..10], 4),
+        index.search(&targets[..10])
+    );
+}
+
 #[test]
 fn test_chunk_processor() {
     let mut index = crate::hashing_index::new();
@@ -436,8 +1021,11 @@ fn test_chunk_processor() {
             after: vec!["in-between line".to_string()],
             anomaly: Anomaly {
                 distance: 1.0,
+                severity: 1.0,
                 pos: 3,
                 line: "Traceback oops".to_string(),
+                repeats: None,
+                level: None,
             },
         },
         AnomalyContext {
@@ -445,8 +1033,11 @@ fn test_chunk_processor() {
             after: vec!["003: regular log line".to_string()],
             anomaly: Anomaly {
                 distance: 1.0,
+                severity: 1.0,
                 pos: 5,
                 line: "another Traceback".to_string(),
+                repeats: None,
+                level: None,
             },
         },
     ];
@@ -462,3 +1053,206 @@ fn test_chunk_processor() {
             assert_eq!(got.after, expected.after);
         });
 }
+
+#[test]
+fn test_empty_source() {
+    let mut index = crate::hashing_index::new();
+    let mut trainer = ChunkTrainer::new(&mut index);
+    assert!(!trainer.add(std::io::Cursor::new("")).unwrap());
+    assert!(!trainer.add(std::io::Cursor::new("  \n\t\n")).unwrap());
+    assert!(trainer.add(std::io::Cursor::new("\nlog line\n")).unwrap());
+    trainer.complete();
+
+    let mut skip_lines = HashSet::new();
+    let mut processor =
+        ChunkProcessor::new(std::io::Cursor::new(" \n \n"), &index, &mut skip_lines);
+    assert!(processor.next().is_none());
+    assert!(processor.is_empty());
+}
+
+#[test]
+fn test_tokenize_fallback() {
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(&mut index, "service started\nservice stopped\n".as_bytes()).unwrap();
+    let mut target = b"service started\ninvalid \xff\xfe bytes\nservice stopped\n".to_vec();
+    target.extend_from_slice(b"invalid \xff\xfe bytes\n");
+    let mut processor = ChunkProcessor::single(std::io::Cursor::new(target), &index);
+    let anomalies = processor.by_ref().collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(processor.line_count, 4);
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.pos, 2);
+    // The invalid UTF-8 lines are decoded as latin-1, see encoding::LineDecoder.
+    assert_eq!(anomalies[0].anomaly.line, "invalid ÿþ bytes");
+    assert_eq!(
+        tokenize_line(&index, b"invalid \xff", false),
+        tokenize_line(&index, b"invalid \xff", false)
+    );
+    assert_ne!(
+        tokenize_line(&index, b"invalid \xff", false),
+        tokenize_line(&index, b"invalid \xfe", false)
+    );
+}
+
+#[test]
+fn test_chunk_boundary() {
+    assert!(starts_with_timestamp("2022-03-23 17:33:00 INFO"));
+    assert!(starts_with_timestamp("[2022/03/23 17:33:00] INFO"));
+    assert!(starts_with_timestamp("17:33:00 INFO"));
+    assert!(starts_with_timestamp("Mar 23 17:33:00 host sshd"));
+    assert!(!starts_with_timestamp("  File foo.py line 42"));
+    assert!(!starts_with_timestamp("2022"));
+    assert_eq!(
+        "timestamp".parse::<ChunkBoundary>().unwrap(),
+        ChunkBoundary::Timestamp
+    );
+    assert!("oops".parse::<ChunkBoundary>().is_err());
+
+    // Unique lines made of letters, so that they are not deduplicated by the tokenizer.
+    let word = |idx: usize| -> String {
+        [idx / 676, (idx / 26) % 26, idx % 26]
+            .iter()
+            .map(|c| (b'a' + *c as u8) as char)
+            .collect()
+    };
+    let info_lines = (0..DEFAULT_CHUNK_SIZE - 2)
+        .map(|idx| format!("2022-01-01 INFO started worker{}", word(idx)))
+        .collect::<Vec<_>>();
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(&mut index, std::io::Cursor::new(info_lines.join("\n"))).unwrap();
+
+    // A trace that crosses the DEFAULT_CHUNK_SIZE unique lines
+    let data = info_lines
+        .iter()
+        .map(|s| s.as_str())
+        .chain([
+            "2022-01-01 ERROR Traceback",
+            "  File a.py",
+            "  File b.py",
+            "  KeyError",
+            info_lines[0].as_str(),
+        ])
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut skip_lines = HashSet::new();
+    let processor = ChunkProcessor::new(std::io::Cursor::new(data), &index, &mut skip_lines)
+        .with_boundary(ChunkBoundary::Timestamp);
+    let anomalies = processor.collect::<Result<Vec<_>>>().unwrap();
+    let lines = anomalies
+        .iter()
+        .map(|anomaly| anomaly.anomaly.pos)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            DEFAULT_CHUNK_SIZE - 1,
+            DEFAULT_CHUNK_SIZE,
+            DEFAULT_CHUNK_SIZE + 1,
+            DEFAULT_CHUNK_SIZE + 2
+        ]
+    );
+    assert_eq!(anomalies[0].before.len(), CTX_DISTANCE);
+    assert_eq!(anomalies[3].after, vec![info_lines[0].clone()]);
+}
+
+#[test]
+fn test_multiline_anomaly() {
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(
+        &mut index,
+        std::io::Cursor::new("INFO service started\nINFO service stopped\n"),
+    )
+    .unwrap();
+    let trace = [
+        "ERROR java.lang.IllegalStateException: oops",
+        "\tat com.example.Service.run(Service.java:42)",
+        "\tat com.example.Main.main(Main.java:7)",
+    ];
+    let data = format!(
+        "INFO service started\n{}\nINFO service stopped\n",
+        trace.join("\n")
+    );
+    let processor = ChunkProcessor::single(std::io::Cursor::new(data), &index).with_multiline(true);
+    let anomalies = processor.collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.line, trace.join("\n"));
+    assert_eq!(anomalies[0].anomaly.pos, 2);
+    assert_eq!(anomalies[0].after, vec!["INFO service stopped".to_string()]);
+}
+
+#[test]
+fn test_repeats() {
+    let word = |idx: usize| -> String {
+        [idx / 676, (idx / 26) % 26, idx % 26]
+            .iter()
+            .map(|c| (b'a' + *c as u8) as char)
+            .collect()
+    };
+    let info_lines = (0..DEFAULT_CHUNK_SIZE)
+        .map(|idx| format!("INFO started worker{}", word(idx)))
+        .collect::<Vec<_>>();
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(&mut index, std::io::Cursor::new(info_lines.join("\n"))).unwrap();
+
+    // The anomaly is repeated in the same chunk, and in the next one.
+    let error = "ERROR disk full";
+    let data = std::iter::once(error)
+        .chain(std::iter::once(info_lines[0].as_str()))
+        .chain(std::iter::once(error))
+        .chain(info_lines[1..].iter().map(|s| s.as_str()))
+        .chain(std::iter::once(error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut skip_lines = HashSet::new();
+    let mut processor = ChunkProcessor::new(std::io::Cursor::new(data), &index, &mut skip_lines);
+    let mut anomalies = processor.by_ref().collect::<Result<Vec<_>>>().unwrap();
+    processor.set_repeats(&mut anomalies);
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.pos, 1);
+    assert_eq!(
+        anomalies[0].anomaly.repeats,
+        Some(Repeats {
+            count: 2,
+            last_pos: DEFAULT_CHUNK_SIZE + 3
+        })
+    );
+    assert_eq!(
+        anomalies[0].anomaly.repeats_note(),
+        format!(
+            "  [repeated 2 times, last at line {}]",
+            DEFAULT_CHUNK_SIZE + 3
+        )
+    );
+}
+
+#[test]
+fn test_learning_window() {
+    let targets = ["INFO worker started".to_string()];
+    let mut window = LearningWindow::with_size(crate::hashing_index::new, 2);
+    window.learn(&targets, &[0.0]);
+    window.learn(&targets, &[0.5]);
+    assert_eq!(
+        window.line_count(),
+        0,
+        "Known lines and anomalies are not learned"
+    );
+
+    window.learn(&targets, &[0.05]);
+    window.flush();
+    assert_eq!(window.line_count(), 1);
+    let mut distances = vec![1.0];
+    window.search(&targets, &mut distances);
+    assert!(distances[0] < 0.01, "The learned line is now known");
+
+    // The oldest chunks slide out of the window.
+    let others = [
+        "DEBUG cache refreshed".to_string(),
+        "DEBUG queue drained".to_string(),
+    ];
+    window.learn(&others, &[0.05, 0.05]);
+    window.flush();
+    assert_eq!(window.line_count(), 2);
+    let mut distances = vec![1.0];
+    window.search(&targets, &mut distances);
+    assert!(distances[0] > 0.3);
+    assert_eq!(window.complete().count(), 1);
+}
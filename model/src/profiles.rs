@@ -0,0 +1,131 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module selects the processing modes of the sources per index name, so that the JSON logs,
+//! the console logs and the test reports of the same artifacts tree are each read appropriately.
+//! The profiles are loaded from a yaml file of filename patterns, the first matching one is used:
+//!
+//! ```yaml
+//! - pattern: '*.json'
+//!   json_lines: true
+//! - pattern: 'stestr*'
+//!   multiline: true
+//! - pattern: 'job-output.txt'
+//!   keep_ansi: true
+//! ```
+//!
+//! The patterns are matched against the index name, or its file name, and they support the `*` and
+//! `?` wildcards. The modes that are not set use the global settings. The syslog headers are always
+//! removed by the tokenizer, so the journal files don't need a profile.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::RwLock;
+
+use crate::IndexName;
+
+/// The processing modes of a source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Profile {
+    /// Fold the continuation lines, see multiline.rs.
+    pub multiline: bool,
+    /// Tokenize the JSON lines from their fields, see jsonlines.rs.
+    pub json_lines: bool,
+    /// Remove the ANSI escape sequences, see ansi.rs.
+    pub strip_ansi: bool,
+}
+
+impl Profile {
+    /// The modes of the global settings.
+    pub fn global() -> Profile {
+        Profile {
+            multiline: crate::multiline::is_enabled(),
+            json_lines: crate::jsonlines::is_enabled(),
+            strip_ansi: crate::ansi::is_enabled(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    pattern: String,
+    multiline: Option<bool>,
+    json_lines: Option<bool>,
+    keep_ansi: Option<bool>,
+}
+
+lazy_static::lazy_static! {
+    static ref RULES: RwLock<Vec<Rule>> = RwLock::new(Vec::new());
+}
+
+/// Load the profiles, they need to be the same for the training and the inspection.
+pub fn load_profiles(path: &Path) -> Result<()> {
+    let file = std::fs::File::open(path).context("Can't open profiles")?;
+    let rules: Vec<Rule> = serde_yaml::from_reader(file).context("Can't read profiles")?;
+    *RULES.write().unwrap() = rules;
+    Ok(())
+}
+
+/// The loaded profiles, see Model::report_digest.
+pub(crate) fn get_profiles() -> String {
+    format!("{:?}", *RULES.read().unwrap())
+}
+
+/// Check if the name matches the pattern, where `*` matches any text and `?` any character.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|pos| wildcard_match(rest, &name[pos..])),
+        Some((c, rest)) => match name.split_first() {
+            Some((n, name)) if *c == b'?' || c == n => wildcard_match(rest, name),
+            _ => false,
+        },
+    }
+}
+
+fn is_match(pattern: &str, index_name: &str) -> bool {
+    let file_name = index_name.rsplit('/').next().unwrap_or(index_name);
+    wildcard_match(pattern.as_bytes(), index_name.as_bytes())
+        || wildcard_match(pattern.as_bytes(), file_name.as_bytes())
+}
+
+/// Returns the modes of the sources of the index.
+pub fn profile_of(index_name: &IndexName) -> Profile {
+    let mut profile = Profile::global();
+    let rules = RULES.read().unwrap();
+    if let Some(rule) = rules
+        .iter()
+        .find(|rule| is_match(&rule.pattern, index_name.as_str()))
+    {
+        profile.multiline = rule.multiline.unwrap_or(profile.multiline);
+        profile.json_lines = rule.json_lines.unwrap_or(profile.json_lines);
+        profile.strip_ansi = rule.keep_ansi.map_or(profile.strip_ansi, |keep| !keep);
+    }
+    profile
+}
+
+#[test]
+fn test_profiles() {
+    assert!(is_match("*.json", "controller/events.json"));
+    assert!(is_match("stestr*", "logs/stestr_results.html"));
+    assert!(is_match("logs/*.txt", "logs/job-output.txt"));
+    assert!(is_match("job-?utput.txt", "job-output.txt"));
+    assert!(!is_match("*.json", "controller/events.json.txt"));
+    assert!(!is_match("journal*", "logs/syslog"));
+
+    let path = std::env::temp_dir().join("logreduce-test-profiles.yaml");
+    std::fs::write(&path, "- pattern: 'syslog'\n  syslog: true\n").unwrap();
+    assert!(load_profiles(&path).is_err());
+
+    std::fs::write(&path, "- pattern: 'zzprofile*.json'\n  json_lines: true\n").unwrap();
+    load_profiles(&path).unwrap();
+    let profile = profile_of(&IndexName::from_path("logs/zzprofile-events.json"));
+    assert!(profile.json_lines);
+    assert_eq!(profile.multiline, Profile::global().multiline);
+    assert_eq!(
+        profile_of(&IndexName::from_path("logs/events.json")),
+        Profile::global()
+    );
+}
@@ -0,0 +1,220 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Prow job content, for the Kubernetes CI.
+//! The build-log.txt and the artifacts are listed from the GCS bucket, and the baseline is
+//! the most recent successful build of the same job that is older than the target.
+//! See the gcs module for the bucket listing.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{gcs, Baselines, Content, Source};
+
+/// The number of previous builds checked for a successful result.
+const MAX_BASELINE_LOOKUP: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    /// The storage url.
+    storage: Url,
+    pub bucket: String,
+    /// The job directory, e.g. `logs/ci-kubernetes-e2e-gci-gce`.
+    pub job: String,
+    pub build_id: u64,
+}
+
+impl std::fmt::Display for Job {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gs://{}/{}", self.bucket, self.build_path())
+    }
+}
+
+#[derive(Deserialize)]
+struct Finished {
+    result: Option<String>,
+}
+
+impl Job {
+    fn build_path(&self) -> String {
+        format!("{}/{}/", self.job, self.build_id)
+    }
+
+    /// The url of an object of the bucket, see gcs::object_url.
+    fn object_url(&self, name: &str) -> Result<Url> {
+        gcs::object_url(&self.storage, &self.bucket, name)
+    }
+
+    pub fn discover_baselines(&self) -> Result<Baselines> {
+        tracing::info!("Discovering baselines for {}", self);
+        let mut build_ids = gcs::list(
+            &self.storage,
+            &self.bucket,
+            &format!("{}/", self.job),
+            Some("/"),
+        )?
+        .prefixes
+        .iter()
+        .filter_map(|prefix| {
+            prefix
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()?
+                .parse()
+                .ok()
+        })
+        .filter(|build_id| *build_id < self.build_id)
+        .collect::<Vec<u64>>();
+        build_ids.sort_unstable_by(|a, b| b.cmp(a));
+        for build_id in build_ids.into_iter().take(MAX_BASELINE_LOOKUP) {
+            let build = Job {
+                build_id,
+                ..self.clone()
+            };
+            let url = build.object_url(&format!("{}finished.json", build.build_path()))?;
+            match gcs::get_json::<Finished>(&self.storage, &url) {
+                Ok(finished) if finished.result.as_deref() == Some("SUCCESS") => {
+                    return Ok(vec![Content::Prow(Box::new(build))])
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("Skipping {}: {:#}", build, e),
+            }
+        }
+        Err(anyhow::anyhow!(
+            "Couldn't find a successful build of {}",
+            self.job
+        ))
+    }
+
+    pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        match self.get_sources() {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+
+    fn get_sources(&self) -> Result<Vec<Source>> {
+        let build_path = self.build_path();
+        let prefix = self.object_url(&build_path)?.as_str().len();
+        gcs::list(&self.storage, &self.bucket, &build_path, None)?
+            .items
+            .iter()
+            .filter(|object| !object.name.ends_with('/'))
+            .map(|object| Ok(Source::Remote(prefix, self.object_url(&object.name)?)))
+            .collect()
+    }
+}
+
+/// Returns the bucket, the job directory and the build id of a prow url, such as:
+/// https://prow.k8s.io/view/gs/kubernetes-jenkins/logs/ci-kubernetes-e2e-gci-gce/1234
+fn get_prow_job(url: &Url) -> Option<(String, String, u64)> {
+    let segments = url
+        .path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    match segments.as_slice() {
+        ["view", "gs" | "gcs", bucket, job @ .., build_id] if !job.is_empty() => {
+            Some((bucket.to_string(), job.join("/"), build_id.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+impl Content {
+    pub fn from_prow_url(url: &Url) -> Option<Result<Content>> {
+        get_prow_job(url).map(|(bucket, job, build_id)| {
+            Url::parse(gcs::GCS_URL)
+                .context("Can't create gcs url")
+                .map(|storage| {
+                    Content::Prow(Box::new(Job {
+                        storage,
+                        bucket,
+                        job,
+                        build_id,
+                    }))
+                })
+        })
+    }
+}
+
+#[test]
+fn test_prow_url() {
+    assert_eq!(
+        get_prow_job(
+            &Url::parse(
+                "https://prow.k8s.io/view/gs/kubernetes-jenkins/logs/ci-kubernetes-e2e-gci-gce/1234/"
+            )
+            .unwrap()
+        ),
+        Some((
+            "kubernetes-jenkins".to_string(),
+            "logs/ci-kubernetes-e2e-gci-gce".to_string(),
+            1234
+        ))
+    );
+    assert_eq!(
+        get_prow_job(&Url::parse("https://prow.k8s.io/view/gs/bucket/1234").unwrap()),
+        None
+    );
+    assert_eq!(
+        get_prow_job(&Url::parse("https://prow.k8s.io/?job=ci-kubernetes-e2e").unwrap()),
+        None
+    );
+}
+
+#[test]
+fn test_prow_gcs() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let storage = Url::parse(&mockito::server_url())?.join("/")?;
+    let builds_mock = mock("GET", "/storage/v1/b/bucket/o")
+        .match_query(Matcher::UrlEncoded("delimiter".into(), "/".into()))
+        .with_body(
+            r#"{"prefixes": ["logs/job/40/", "logs/job/41/", "logs/job/42/", "logs/job/43/"]}"#,
+        )
+        .create();
+    let newer_mock = mock("GET", "/bucket/logs/job/43/finished.json")
+        .with_body(r#"{"result": "SUCCESS"}"#)
+        .expect(0)
+        .create();
+    let failed_mock = mock("GET", "/bucket/logs/job/41/finished.json")
+        .with_body(r#"{"result": "FAILURE"}"#)
+        .create();
+    let success_mock = mock("GET", "/bucket/logs/job/40/finished.json")
+        .with_body(r#"{"result": "SUCCESS"}"#)
+        .create();
+    let objects_mock = mock("GET", "/storage/v1/b/bucket/o")
+        .match_query(Matcher::Exact("prefix=logs%2Fjob%2F42%2F".into()))
+        .with_body(r#"{"items": [{"name": "logs/job/42/build-log.txt"}], "nextPageToken": "next"}"#)
+        .create();
+    let next_mock = mock("GET", "/storage/v1/b/bucket/o")
+        .match_query(Matcher::UrlEncoded("pageToken".into(), "next".into()))
+        .with_body(r#"{"items": [{"name": "logs/job/42/artifacts/kubelet #1.log"}]}"#)
+        .create();
+
+    let job = Job {
+        storage,
+        bucket: "bucket".to_string(),
+        job: "logs/job".to_string(),
+        build_id: 42,
+    };
+    match job.discover_baselines()?.as_slice() {
+        [Content::Prow(baseline)] => assert_eq!(baseline.build_id, 40),
+        baselines => panic!("Unexpected baselines {:?}", baselines),
+    }
+    let sources = job.get_sources()?;
+    assert_eq!(
+        sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["build-log.txt", "artifacts/kubelet%20%231.log"]
+    );
+    builds_mock.assert();
+    newer_mock.assert();
+    failed_mock.assert();
+    success_mock.assert();
+    objects_mock.assert();
+    next_mock.assert();
+    Ok(())
+}
@@ -0,0 +1,99 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module quantizes the feature matrices of the saved models: each value is stored as a signed
+//! integer of a few bits with a scale per row, instead of a 32-bit float. This is disabled by default,
+//! and the models are restored with the float matrices on load, whatever the setting.
+
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use logreduce_index::{FeaturesMatrix, QuantizedMatrix};
+
+/// The bits of the quantized values, 0 when the quantization is disabled.
+static BITS: AtomicU8 = AtomicU8::new(0);
+
+/// Quantize the matrices of the saved models with the bits, between 2 and 8. Less bits give a smaller
+/// model, at the cost of less accurate distances.
+pub fn set_quantization(bits: Option<u8>) -> Result<()> {
+    let bits = bits.unwrap_or(0);
+    if bits == 1 || bits > 8 {
+        return Err(anyhow::anyhow!(
+            "Invalid quantization {} bits, expected a value between 2 and 8",
+            bits
+        ));
+    }
+    BITS.store(bits, Ordering::Relaxed);
+    Ok(())
+}
+
+#[derive(Serialize)]
+enum StoredRef<'a> {
+    Float(&'a [FeaturesMatrix]),
+    Quantized(Vec<QuantizedMatrix>),
+}
+
+/// The variants are the StoredRef ones.
+#[derive(Deserialize)]
+enum Stored {
+    Float(Vec<FeaturesMatrix>),
+    Quantized(Vec<QuantizedMatrix>),
+}
+
+fn stored(mats: &[FeaturesMatrix], bits: u8) -> StoredRef<'_> {
+    match bits {
+        0 => StoredRef::Float(mats),
+        bits => StoredRef::Quantized(
+            mats.iter()
+                .map(|mat| QuantizedMatrix::new(mat, bits))
+                .collect(),
+        ),
+    }
+}
+
+/// The serde functions of the index matrices, used with `#[serde(with = "crate::quantization")]`.
+pub(crate) fn serialize<S: Serializer>(
+    mats: &[FeaturesMatrix],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    stored(mats, BITS.load(Ordering::Relaxed)).serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<FeaturesMatrix>, D::Error> {
+    Stored::deserialize(deserializer).map(restore)
+}
+
+fn restore(stored: Stored) -> Vec<FeaturesMatrix> {
+    match stored {
+        Stored::Float(mats) => mats,
+        Stored::Quantized(mats) => mats.iter().map(|mat| mat.to_mat()).collect(),
+    }
+}
+
+#[test]
+fn test_quantization() {
+    let lines = (0..100)
+        .map(|n| format!("INFO service {} started on node {}", n % 7, n))
+        .collect::<Vec<_>>();
+    let mats = vec![logreduce_index::index_mat(&lines)];
+    let targets = vec![
+        "INFO service 3 started on node 42".to_string(),
+        "ERROR disk full".to_string(),
+    ];
+    let expected = logreduce_index::search_mat_chunk(&mats, &targets);
+
+    let float = bincode::serialize(&stored(&mats, 0)).unwrap();
+    for bits in [8, 4] {
+        let data = bincode::serialize(&stored(&mats, bits)).unwrap();
+        assert!(data.len() * 2 < float.len(), "{} bits", bits);
+        let restored = restore(bincode::deserialize(&data).unwrap());
+        let distances = logreduce_index::search_mat_chunk(&restored, &targets);
+        for (distance, expected) in distances.iter().zip(&expected) {
+            assert!((distance - expected).abs() < 1e-3, "{} bits", bits);
+        }
+    }
+    assert!(set_quantization(Some(12)).is_err());
+}
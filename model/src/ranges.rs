@@ -0,0 +1,221 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the source ranges, to only inspect a region of the target logs, for example
+//! to re-analyze a failure during a deep-dive debugging. The ranges are a yaml file that maps the
+//! source paths to a line range, or to a time range of the timestamped lines:
+//!
+//! ```yaml
+//! controller/nova-api.log: lines 1000-50000
+//! job-output.txt: time 2022-03-15 10:00:00 - 2022-03-15 11:00:00
+//! ```
+//!
+//! The lines outside of the range are read as blank lines, to keep the line numbers of the anomalies.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Read};
+use std::path::Path;
+
+use crate::reader::DecompressReader;
+use crate::warmup::parse_timestamp;
+use crate::Source;
+
+/// The region of a source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Range {
+    /// The first and last line numbers, starting at 1.
+    Lines(usize, usize),
+    /// The lines logged between the two dates, the lines without a timestamp belong to the previous line.
+    Time(chrono::NaiveDateTime, chrono::NaiveDateTime),
+}
+
+impl std::str::FromStr for Range {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Range> {
+        let invalid =
+            || anyhow::anyhow!("Invalid range: {}, expected `lines A-B` or `time A - B`", s);
+        match s.trim().split_once(' ') {
+            Some(("lines", range)) => {
+                let (first, last) = range.trim().split_once('-').ok_or_else(invalid)?;
+                let first = first.trim().parse().map_err(|_| invalid())?;
+                let last = last.trim().parse().map_err(|_| invalid())?;
+                Ok(Range::Lines(first, last))
+            }
+            Some(("time", range)) => {
+                let (since, until) = range.trim().split_once(" - ").ok_or_else(invalid)?;
+                let since = parse_timestamp(since.trim()).ok_or_else(invalid)?;
+                let until = parse_timestamp(until.trim()).ok_or_else(invalid)?;
+                Ok(Range::Time(since, until))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The ranges of the source paths.
+#[derive(Debug, Default)]
+pub struct Ranges {
+    ranges: Vec<(String, Range)>,
+}
+
+impl Ranges {
+    pub fn load(path: &Path) -> Result<Ranges> {
+        let file = std::fs::File::open(path).context("Can't open ranges")?;
+        Ranges::from_reader(file)
+    }
+
+    fn from_reader<R: Read>(reader: R) -> Result<Ranges> {
+        let entries: BTreeMap<String, String> =
+            serde_yaml::from_reader(reader).context("Can't read ranges")?;
+        let ranges = entries
+            .into_iter()
+            .map(|(path, range)| Ok((path.trim_start_matches('/').to_string(), range.parse()?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Ranges { ranges })
+    }
+
+    /// The range of a source, matching the end of its relative path.
+    pub fn find(&self, source: &Source) -> Option<Range> {
+        let name = source.get_relative();
+        self.ranges
+            .iter()
+            .find(|(path, _)| {
+                name == path
+                    || (name.ends_with(path.as_str())
+                        && name[..name.len() - path.len()].ends_with('/'))
+            })
+            .map(|(_, range)| *range)
+    }
+
+    /// Returns the reader restricted to the range of the source, see Index::get_processor.
+    pub(crate) fn restrict(&self, source: &Source, reader: DecompressReader) -> DecompressReader {
+        match self.find(source) {
+            Some(range) => DecompressReader::Ranged(Box::new(RangeReader::new(range, reader))),
+            None => reader,
+        }
+    }
+}
+
+/// A reader that blanks the lines outside of the range.
+pub struct RangeReader {
+    inner: std::io::BufReader<DecompressReader>,
+    range: Range,
+    line_number: usize,
+    in_range: bool,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl RangeReader {
+    fn new(range: Range, reader: DecompressReader) -> RangeReader {
+        RangeReader {
+            inner: std::io::BufReader::new(reader),
+            range,
+            line_number: 0,
+            in_range: false,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Returns false when the rest of the content is outside of the range.
+    fn keep_line(&mut self) -> Option<bool> {
+        self.line_number += 1;
+        match self.range {
+            Range::Lines(_, last) if self.line_number > last => None,
+            Range::Lines(first, _) => Some(self.line_number >= first),
+            Range::Time(since, until) => {
+                if let Some(ts) = parse_timestamp(&String::from_utf8_lossy(&self.line)) {
+                    self.in_range = since <= ts && ts <= until;
+                }
+                Some(self.in_range)
+            }
+        }
+    }
+}
+
+impl Read for RangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            if self.inner.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(0);
+            }
+            match self.keep_line() {
+                None => {
+                    self.line.clear();
+                    return Ok(0);
+                }
+                Some(false) if self.line.ends_with(b"\n") => self.line = b"\n".to_vec(),
+                Some(false) => self.line.clear(),
+                Some(true) => {}
+            }
+        }
+        let size = buf.len().min(self.line.len() - self.pos);
+        buf[..size].copy_from_slice(&self.line[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
+#[test]
+fn test_ranges() -> Result<()> {
+    let ranges = Ranges::from_reader(
+        "controller/nova-api.log: lines 2-3\njob-output.txt: time 2022-03-15 10:00:00 - 2022-03-15 11:00:00\n"
+            .as_bytes(),
+    )?;
+    let source = |name: &str| Source::Memory(name.into(), crate::memory::MemoryData("".into()));
+    assert_eq!(
+        ranges.find(&source("logs/controller/nova-api.log")),
+        Some(Range::Lines(2, 3))
+    );
+    assert_eq!(ranges.find(&source("logs/other-nova-api.log")), None);
+    assert!("lines 10".parse::<Range>().is_err());
+
+    let read = |range: Range, content: &'static str| {
+        let mut result = String::new();
+        RangeReader::new(range, crate::reader::from_memory(content.into()))
+            .read_to_string(&mut result)
+            .unwrap();
+        result
+    };
+    assert_eq!(
+        read(Range::Lines(2, 3), "first\nsecond\nthird\nfourth\n"),
+        "\nsecond\nthird\n"
+    );
+    let time = ranges.find(&source("job-output.txt")).unwrap();
+    assert_eq!(
+        read(
+            time,
+            "2022-03-15 09:59:00 setup\n2022-03-15 10:01:00 failure\n  traceback\n2022-03-15 11:01:00 cleanup\n"
+        ),
+        "\n2022-03-15 10:01:00 failure\n  traceback\n\n"
+    );
+
+    // The ranges only apply to the report that uses them.
+    use crate::{Content, OutputMode, ReportOptions};
+    let model = crate::train_memory(vec![("service.log", "INFO service started\n")]);
+    let target = || {
+        Content::from_memory(vec![(
+            "service.log",
+            "ERROR service crashed\nINFO service started\nERROR service failed\n",
+        )])
+    };
+    let options = ReportOptions {
+        ranges: Ranges::from_reader("service.log: lines 2-3\n".as_bytes())?,
+        ..ReportOptions::default()
+    };
+    let report = model.report_with(OutputMode::Quiet, target(), &options)?;
+    assert_eq!(report.total_anomaly_count, 1);
+    assert_eq!(report.log_reports[0].anomalies[0].anomaly.pos, 3);
+    assert_eq!(
+        model
+            .report(OutputMode::Quiet, target())?
+            .total_anomaly_count,
+        2
+    );
+    Ok(())
+}
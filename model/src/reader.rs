@@ -1,40 +1,137 @@
 // Copyright (C) 2022 Red Hat
 // SPDX-License-Identifier: Apache-2.0
 
-//! This module provides a transparent decompression reader.
+//! This module provides a transparent decompression reader, for the gzip, the zstd, the xz and the bzip2 files.
 
 use anyhow::{Context, Result};
 use std::io::Read;
 use std::path::Path;
+#[cfg(feature = "http")]
 use url::Url;
 
 use std::fs::File;
 
+use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
+use xz2::read::XzDecoder;
+
+/// The decoder of a zstd stream.
+type ZstDecoder<R> = zstd::stream::read::Decoder<'static, R>;
+
+/// The file extensions of the compressed content.
+#[cfg(feature = "http")]
+const COMPRESSED_EXTS: &[&str] = &[".gz", ".zst", ".xz", ".bz2"];
+
+/// The magic number of the zstd frames.
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// The magic number of the xz streams.
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// The magic number of the bzip2 streams.
+const BZIP2_MAGIC: &[u8] = b"BZh";
 
 // TODO: use a struct to pass these references.
 lazy_static::lazy_static! {
-    static ref CACHE: logreduce_cache::Cache = logreduce_cache::Cache::new().expect("Cache");
-    static ref CLIENT: reqwest::blocking::Client = reqwest::blocking::Client::builder()
+    pub(crate) static ref CACHE: logreduce_cache::Cache = logreduce_cache::Cache::from_env().expect("Cache");
+}
+
+#[cfg(feature = "http")]
+lazy_static::lazy_static! {
+    pub(crate) static ref CLIENT: reqwest::blocking::Client = reqwest::blocking::Client::builder()
+        .user_agent(crate::urls::USER_AGENT.as_str())
         .build()
         .expect("Client");
 
-    static ref USE_CACHE: bool = std::env::var("LOGREDUCE_CACHE").is_ok();
+    pub(crate) static ref USE_CACHE: bool = std::env::var("LOGREDUCE_CACHE").is_ok();
 }
 
 /// Handle remote object.
+#[cfg(feature = "http")]
 use reqwest::blocking::Response;
+#[cfg(feature = "http")]
 mod remote {
     use super::*;
 
-    pub fn get_url(url: &Url) -> Result<Response> {
-        CLIENT.get(url.clone()).send().context("Can't get url")
+    /// The permit of the host limiter, held until the response body is dropped.
+    pub type Permit = httpdir::Permit<'static>;
+
+    pub fn get_url(url: &Url) -> Result<(Response, Permit)> {
+        send_get(url, |request| request)
     }
 
+    /// Request the last bytes, without compression so that the range applies to the content.
+    pub fn get_tail(url: &Url, bytes: u64) -> Result<(Response, Permit)> {
+        send_get(url, |request| {
+            request
+                .header(reqwest::header::RANGE, format!("bytes=-{}", bytes))
+                .header(reqwest::header::ACCEPT_ENCODING, "identity")
+        })
+    }
+
+    fn send_get(
+        url: &Url,
+        with: impl Fn(reqwest::blocking::RequestBuilder) -> reqwest::blocking::RequestBuilder,
+    ) -> Result<(Response, Permit)> {
+        let (resp, permit) = with_mirrors(url, |url| {
+            if !crate::urls::is_allowed(url) {
+                return Err(anyhow::anyhow!("{} is disallowed by robots.txt", url));
+            }
+            let permit = crate::urls::LIMITER.acquire(url);
+            crate::urls::authorize(url, with(CLIENT.get(url.clone())))
+                .send()
+                .context("Can't get url")
+                .map(|resp| (resp, permit))
+        })?;
+        crate::urls::record_response(url, resp.status().as_u16(), resp.url());
+        if resp.status().is_client_error() {
+            Err(crate::errors::Failure::HttpStatus(url.clone(), resp.status().as_u16()).into())
+        } else {
+            Ok((resp, permit))
+        }
+    }
+
+    #[cfg(feature = "zuul")]
     pub fn head(url: &Url) -> Result<bool> {
-        let resp = CLIENT.head(url.clone()).send().context("Can't head url")?;
+        // The head response has no body, the permit is released once it is received.
+        let (resp, _permit) = with_mirrors(url, |url| {
+            if !crate::urls::is_allowed(url) {
+                return Err(anyhow::anyhow!("{} is disallowed by robots.txt", url));
+            }
+            let permit = crate::urls::LIMITER.acquire(url);
+            crate::urls::authorize(url, CLIENT.head(url.clone()))
+                .send()
+                .context("Can't head url")
+                .map(|resp| (resp, permit))
+        })?;
         Ok(resp.status().is_success())
     }
+
+    // Retry the request with the mirrors when the url is not available.
+    fn with_mirrors(
+        url: &Url,
+        send: impl Fn(&Url) -> Result<(Response, Permit)>,
+    ) -> Result<(Response, Permit)> {
+        let is_available = |result: &Result<(Response, Permit)>| match result {
+            Ok((resp, _)) => {
+                !(resp.status().is_server_error()
+                    || resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS)
+            }
+            Err(_) => false,
+        };
+        let result = send(url);
+        if is_available(&result) {
+            return result;
+        }
+        for mirror in crate::urls::mirror_urls(url) {
+            tracing::warn!("{}: failing over to {}", url, mirror);
+            let mirror_result = send(&mirror);
+            if is_available(&mirror_result) {
+                return mirror_result;
+            }
+        }
+        result
+    }
 }
 
 // allow large enum for gzdecoder, which are the most used
@@ -42,32 +139,99 @@ mod remote {
 pub enum DecompressReader {
     Flat(File),
     Gz(GzDecoder<File>),
-    // TODO: support BZIP2 compression
-    Remote(Response),
-    Cached(logreduce_cache::CacheReader<Response>),
+    Zst(Box<ZstDecoder<std::io::BufReader<File>>>),
+    Xz(Box<XzDecoder<File>>),
+    Bz2(Box<BzDecoder<File>>),
+    /// The response body, with the host limiter permit that is released when the body is dropped.
+    #[cfg(feature = "http")]
+    Remote(Response, httpdir::Permit<'static>),
+    #[cfg(feature = "http")]
+    Cached(
+        logreduce_cache::CacheReader<Response>,
+        httpdir::Permit<'static>,
+    ),
+    Memory(std::io::Cursor<bytes::Bytes>),
+    /// A gzip stream, such as a remote file served without a gzip content-encoding.
+    RemoteGz(Box<GzDecoder<std::io::BufReader<DecompressReader>>>),
+    /// A zstd stream, the http client does not decode the zstd content-encoding.
+    RemoteZst(Box<ZstDecoder<std::io::BufReader<DecompressReader>>>),
+    RemoteXz(Box<XzDecoder<std::io::BufReader<DecompressReader>>>),
+    RemoteBz2(Box<BzDecoder<std::io::BufReader<DecompressReader>>>),
+    Buffered(Box<std::io::BufReader<DecompressReader>>),
+    Stdin(std::io::Stdin),
+    Sampled(Box<crate::sampling::HeadTail>),
+    Ranged(Box<crate::ranges::RangeReader>),
+    /// The content of a tar archive member, see archive::member_open.
+    Member(Box<std::io::Take<DecompressReader>>),
+}
+
+impl DecompressReader {
+    /// The size of the content, when it is known before reading.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Flat(r) => r.metadata().ok().map(|meta| meta.len()),
+            #[cfg(feature = "http")]
+            Remote(r, _) => r.content_length(),
+            Memory(r) => Some(r.get_ref().len() as u64),
+            _ => None,
+        }
+    }
 }
 use DecompressReader::*;
 
 pub fn from_path(path: &Path) -> Result<DecompressReader> {
-    let fp = File::open(path)?;
     let extension = path.extension().unwrap_or_else(|| std::ffi::OsStr::new(""));
-    Ok(if extension == ".gz" {
+    if extension == "parquet" {
+        let lines = crate::parquet::messages(&std::fs::read(path)?)
+            .with_context(|| format!("Can't read {:?}", path))?;
+        return Ok(from_memory(lines.into()));
+    }
+    let fp = File::open(path)?;
+    Ok(if extension == "gz" {
         Gz(GzDecoder::new(fp))
+    } else if extension == "zst" {
+        Zst(Box::new(ZstDecoder::new(fp)?))
+    } else if extension == "xz" {
+        Xz(Box::new(XzDecoder::new(fp)))
+    } else if extension == "bz2" {
+        Bz2(Box::new(BzDecoder::new(fp)))
     } else {
         Flat(fp)
     })
 }
 
+#[cfg(feature = "http")]
+/// Returns true when the url content is compressed.
+fn is_compressed(url: &Url) -> bool {
+    COMPRESSED_EXTS.iter().any(|ext| url.path().ends_with(ext))
+}
+
+pub fn from_memory(data: bytes::Bytes) -> DecompressReader {
+    Memory(std::io::Cursor::new(data))
+}
+
+/// The stdin is not seekable, so the magic numbers are looked up in the buffer.
+pub fn from_stdin() -> Result<DecompressReader> {
+    maybe_decompress(Stdin(std::io::stdin()))
+}
+
+#[cfg(feature = "zuul")]
 pub fn head_url(base: &Url, url: &Url) -> Result<bool> {
     if *USE_CACHE {
         match CACHE.head(base, url) {
             Some(result) => {
                 tracing::debug!("Cache hit for {}", url);
+                crate::usage::cache_hit();
                 Ok(result)
             }
             None => {
                 tracing::debug!("Cache miss for {}", url);
-                CACHE.head_set(base, url, remote::head(url)?)
+                crate::usage::cache_miss();
+                let result = remote::head(url)?;
+                match CACHE.is_full() {
+                    true => Ok(result),
+                    false => CACHE.head_set(base, url, result),
+                }
             }
         }
     } else {
@@ -75,25 +239,102 @@ pub fn head_url(base: &Url, url: &Url) -> Result<bool> {
     }
 }
 
+#[cfg(feature = "http")]
 pub fn from_url(base: &Url, url: &Url) -> Result<DecompressReader> {
-    if *USE_CACHE {
+    let reader = if *USE_CACHE {
         match CACHE.remote_get(base, url) {
             Some(cache) => {
                 tracing::debug!("Cache hit for {}", url);
-                cache.map(Gz)
+                crate::usage::cache_hit();
+                crate::urls::record_cached(url);
+                Gz(cache?)
             }
             None => {
                 tracing::debug!("Cache miss for {}", url);
-                let resp = remote::get_url(url)?;
-                let cache = CACHE.remote_add(base, url, resp)?;
-                Ok(Cached(cache))
+                crate::usage::cache_miss();
+                let (resp, permit) = remote::get_url(url)?;
+                if CACHE.is_full() {
+                    tracing::debug!("Cache quota exceeded, not caching {}", url);
+                    Remote(resp, permit)
+                } else {
+                    Cached(CACHE.remote_add(base, url, resp)?, permit)
+                }
             }
         }
     } else {
-        Ok(Remote(remote::get_url(url)?))
+        let (resp, permit) = remote::get_url(url)?;
+        Remote(resp, permit)
+    };
+    decompress(url, reader)
+}
+
+#[cfg(feature = "http")]
+/// Read the last bytes of a url, starting after the first newline when the content is truncated.
+/// The compressed files can't be truncated, and the partial responses are not cached.
+pub fn from_url_tail(base: &Url, url: &Url, bytes: u64) -> Result<DecompressReader> {
+    use std::io::BufRead;
+    if is_compressed(url) {
+        return from_url(base, url);
+    }
+    let (resp, permit) = remote::get_tail(url, bytes)?;
+    let truncated = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT
+        && resp
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(range_start)
+            .map(|start| start > 0)
+            .unwrap_or(false);
+    let mut reader = std::io::BufReader::new(Remote(resp, permit));
+    if truncated {
+        tracing::debug!("{}: reading the last {} bytes", url, bytes);
+        reader
+            .read_until(b'\n', &mut Vec::new())
+            .context("Can't read url")?;
+    }
+    Ok(Buffered(Box::new(reader)))
+}
+
+#[cfg(feature = "http")]
+/// The first byte position of a `bytes start-end/size` content range.
+fn range_start(range: &str) -> Option<u64> {
+    range
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(feature = "http")]
+/// Decompress the .gz, .zst, .xz and .bz2 urls when their content starts with the magic number.
+/// The http client already decodes the content served with a gzip content-encoding.
+fn decompress(url: &Url, reader: DecompressReader) -> Result<DecompressReader> {
+    if !is_compressed(url) {
+        return Ok(reader);
     }
+    maybe_decompress(reader).context("Can't read url")
 }
 
+pub(crate) fn maybe_decompress(reader: DecompressReader) -> Result<DecompressReader> {
+    use std::io::BufRead;
+    let mut reader = std::io::BufReader::new(reader);
+    let header = reader.fill_buf()?;
+    Ok(if header.starts_with(&[0x1f, 0x8b]) {
+        RemoteGz(Box::new(GzDecoder::new(reader)))
+    } else if header.starts_with(ZSTD_MAGIC) {
+        RemoteZst(Box::new(ZstDecoder::with_buffer(reader)?))
+    } else if header.starts_with(XZ_MAGIC) {
+        RemoteXz(Box::new(XzDecoder::new(reader)))
+    } else if header.starts_with(BZIP2_MAGIC) {
+        RemoteBz2(Box::new(BzDecoder::new(reader)))
+    } else {
+        Buffered(Box::new(reader))
+    })
+}
+
+#[cfg(feature = "http")]
 pub fn drop_url(base: &Url, url: &Url) -> Result<()> {
     if *USE_CACHE {
         CACHE.remote_drop(base, url)
@@ -102,18 +343,115 @@ pub fn drop_url(base: &Url, url: &Url) -> Result<()> {
     }
 }
 
+/// A reader for a list of lines.
+pub struct LinesReader<I: Iterator> {
+    lines: I,
+    // The current line being read, with the new line separator.
+    current: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator> LinesReader<I>
+where
+    I::Item: AsRef<str>,
+{
+    pub fn new<L: IntoIterator<IntoIter = I>>(lines: L) -> LinesReader<I> {
+        LinesReader {
+            lines: lines.into_iter(),
+            current: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<I: Iterator> Read for LinesReader<I>
+where
+    I::Item: AsRef<str>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.current.len() {
+            match self.lines.next() {
+                Some(line) => {
+                    self.current.clear();
+                    self.current.extend_from_slice(line.as_ref().as_bytes());
+                    self.current.push(b'\n');
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.current.len() - self.pos);
+        buf[..size].copy_from_slice(&self.current[self.pos..self.pos + size]);
+        self.pos += size;
+        Ok(size)
+    }
+}
+
 impl Read for DecompressReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         // TODO: refactor using the enum_dispatch crate.
         match self {
             Flat(r) => r.read(buf),
             Gz(r) => r.read(buf),
-            Remote(r) => r.read(buf),
-            Cached(r) => r.read(buf),
+            Zst(r) => r.read(buf),
+            Xz(r) => r.read(buf),
+            Bz2(r) => r.read(buf),
+            #[cfg(feature = "http")]
+            Remote(r, _) => r.read(buf).map(crate::usage::downloaded),
+            #[cfg(feature = "http")]
+            Cached(r, _) => r.read(buf).map(crate::usage::downloaded),
+            Memory(r) => r.read(buf),
+            RemoteGz(r) => r.read(buf),
+            RemoteZst(r) => r.read(buf),
+            RemoteXz(r) => r.read(buf),
+            RemoteBz2(r) => r.read(buf),
+            Buffered(r) => r.read(buf),
+            Stdin(r) => r.read(buf),
+            Sampled(r) => r.read(buf),
+            Ranged(r) => r.read(buf),
+            Member(r) => r.read(buf),
         }
     }
 }
 
+#[test]
+fn test_lines_reader() {
+    let mut reader = LinesReader::new(vec!["first", "", "second line"]);
+    let mut buf = [0; 4];
+    let mut result = Vec::new();
+    loop {
+        match reader.read(&mut buf).unwrap() {
+            0 => break,
+            n => result.extend_from_slice(&buf[..n]),
+        }
+    }
+    assert_eq!(result, b"first\n\nsecond line\n");
+
+    let baseline = (0..10)
+        .map(|idx| format!("test {} passed", idx))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let index = crate::Index::train(
+        &[crate::Source::Memory(
+            "test-output".into(),
+            crate::memory::MemoryData(baseline.into()),
+        )],
+        crate::hashing_index::new(),
+    )
+    .unwrap();
+    let captured = vec![
+        "test 1 passed".to_string(),
+        "test 2 failed: assertion error".to_string(),
+    ];
+    let anomalies = index
+        .inspect_lines(&captured)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.pos, 2);
+    assert_eq!(anomalies[0].before, vec!["test 1 passed".to_string()]);
+}
+
 /*
 // Automatic decompressor implementation poc
 pub fn auto<R: Read + 'static>(mut reader: R) -> Result<Box<dyn Read>> {
@@ -131,3 +469,73 @@ pub fn auto<R: Read + 'static>(mut reader: R) -> Result<Box<dyn Read>> {
     })
 }
 */
+
+#[test]
+fn test_maybe_decompress() {
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    std::io::Write::write_all(&mut gz, b"compressed line\n").unwrap();
+    let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+    std::io::Write::write_all(&mut xz, b"xz line\n").unwrap();
+    let mut bz2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+    std::io::Write::write_all(&mut bz2, b"bzip2 line\n").unwrap();
+    for (data, expected) in [
+        (gz.finish().unwrap(), "compressed line\n"),
+        (
+            zstd::encode_all(&b"zstd line\n"[..], 0).unwrap(),
+            "zstd line\n",
+        ),
+        (xz.finish().unwrap(), "xz line\n"),
+        (bz2.finish().unwrap(), "bzip2 line\n"),
+        (b"plain line\n".to_vec(), "plain line\n"),
+    ] {
+        let mut content = String::new();
+        maybe_decompress(from_memory(data.into()))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, expected);
+    }
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_from_url_tail() -> Result<()> {
+    use mockito::{mock, Matcher};
+    let content = "first line\nsecond line\nlast line\n";
+    let base = Url::parse(&mockito::server_url())?;
+    let _tail_mock = mock("GET", "/tail/console.log")
+        .match_header("range", "bytes=-16")
+        .with_status(206)
+        .with_header("content-range", "bytes 17-32/33")
+        .with_body(&content[17..])
+        .create();
+    let _full_mock = mock("GET", "/tail/small.log")
+        .match_header("range", Matcher::Any)
+        .with_body(content)
+        .create();
+
+    let mut tail = String::new();
+    from_url_tail(&base, &base.join("/tail/console.log")?, 16)?.read_to_string(&mut tail)?;
+    assert_eq!(tail, "last line\n");
+
+    let mut full = String::new();
+    from_url_tail(&base, &base.join("/tail/small.log")?, 16)?.read_to_string(&mut full)?;
+    assert_eq!(full, content);
+    assert_eq!(range_start("bytes 0-32/33"), Some(0));
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_from_url_permit() -> Result<()> {
+    let base = Url::parse(&mockito::server_url())?;
+    let url = base.join("/permit/console.log")?;
+    let _mock = mockito::mock("GET", "/permit/console.log")
+        .with_body("first line\n")
+        .create();
+    // The host slot is held until the body is read.
+    let mut reader = from_url(&base, &url)?;
+    assert!(crate::urls::LIMITER.active(&url) >= 1);
+    reader.read_to_end(&mut Vec::new())?;
+    Ok(())
+}
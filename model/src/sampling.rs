@@ -0,0 +1,163 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the head and tail sampling of the oversized sources: instead of
+//! reading a huge file entirely, only its first and last bytes are analyzed and the
+//! source is reported as partial.
+
+use std::io::{BufRead, Read};
+use std::sync::RwLock;
+
+use crate::reader::DecompressReader;
+
+/// The sampling of the sources larger than max_bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampling {
+    pub max_bytes: u64,
+    pub head_bytes: u64,
+    pub tail_bytes: u64,
+}
+
+impl Sampling {
+    /// Sample the first and last half of the limit.
+    pub fn new(max_bytes: u64) -> Sampling {
+        Sampling {
+            max_bytes,
+            head_bytes: max_bytes / 2,
+            tail_bytes: max_bytes - max_bytes / 2,
+        }
+    }
+
+    pub fn with_head_bytes(mut self, head_bytes: u64) -> Sampling {
+        self.head_bytes = head_bytes;
+        self
+    }
+
+    pub fn with_tail_bytes(mut self, tail_bytes: u64) -> Sampling {
+        self.tail_bytes = tail_bytes;
+        self
+    }
+
+    /// The number of bytes to skip between the head and the tail.
+    fn skip_bytes(&self, size: u64) -> Option<u64> {
+        if size > self.max_bytes {
+            size.checked_sub(self.head_bytes + self.tail_bytes)
+                .filter(|skip| *skip > 0)
+        } else {
+            None
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SAMPLING: RwLock<Option<Sampling>> = RwLock::new(None);
+}
+
+/// Enable the sampling of the inspected sources, see Model::get_processor.
+pub fn set_sampling(sampling: Option<Sampling>) {
+    if let Ok(mut current) = SAMPLING.write() {
+        *current = sampling;
+    }
+}
+
+/// Returns the reader sampled when its size exceeds the limit, and true when it is sampled.
+/// The sources without a known size, such as the compressed ones, are read entirely.
+pub(crate) fn sample(reader: DecompressReader) -> (DecompressReader, bool) {
+    sample_with(get_sampling(), reader)
+}
+
+pub(crate) fn get_sampling() -> Option<Sampling> {
+    SAMPLING.read().ok().and_then(|sampling| *sampling)
+}
+
+fn sample_with(sampling: Option<Sampling>, reader: DecompressReader) -> (DecompressReader, bool) {
+    match sampling.zip(reader.size()) {
+        Some((sampling, size)) => match sampling.skip_bytes(size) {
+            Some(skip) => (
+                DecompressReader::Sampled(Box::new(HeadTail {
+                    inner: std::io::BufReader::new(reader),
+                    head_left: sampling.head_bytes,
+                    skip,
+                    last: b'\n',
+                })),
+                true,
+            ),
+            None => (reader, false),
+        },
+        None => (reader, false),
+    }
+}
+
+/// A reader that skips the middle of the inner reader.
+pub struct HeadTail {
+    inner: std::io::BufReader<DecompressReader>,
+    head_left: u64,
+    skip: u64,
+    /// The last byte of the head.
+    last: u8,
+}
+
+impl Read for HeadTail {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.head_left > 0 {
+            let max = buf.len().min(self.head_left as usize);
+            let n = self.inner.read(&mut buf[..max])?;
+            if n == 0 {
+                self.head_left = 0;
+                self.skip = 0;
+            } else {
+                self.head_left -= n as u64;
+                self.last = buf[n - 1];
+            }
+            return Ok(n);
+        }
+        if self.skip > 0 {
+            // Skip one byte less to keep the first line of the tail when it is complete.
+            std::io::copy(
+                &mut (&mut self.inner).take(self.skip - 1),
+                &mut std::io::sink(),
+            )?;
+            self.skip = 0;
+            // Drop the partial first line of the tail, and end the last line of the head.
+            self.inner.read_until(b'\n', &mut Vec::new())?;
+            if self.last != b'\n' && !buf.is_empty() {
+                buf[0] = b'\n';
+                return Ok(1);
+            }
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn test_head_tail() {
+    let content = (0..100)
+        .map(|idx| format!("line {:02}\n", idx))
+        .collect::<String>();
+    let sampling = Sampling::new(20).with_head_bytes(16).with_tail_bytes(24);
+    assert_eq!(sampling.skip_bytes(10), None);
+    assert_eq!(sampling.skip_bytes(content.len() as u64), Some(760));
+
+    let read = |sampling: Sampling| {
+        let (mut reader, sampled) = sample_with(
+            Some(sampling),
+            crate::reader::from_memory(content.clone().into()),
+        );
+        assert!(sampled);
+        let mut result = String::new();
+        reader.read_to_string(&mut result).unwrap();
+        result
+    };
+    assert_eq!(
+        read(sampling),
+        "line 00\nline 01\nline 97\nline 98\nline 99\n"
+    );
+    // The partial lines are dropped from the tail and ended in the head.
+    assert_eq!(
+        read(sampling.with_head_bytes(12).with_tail_bytes(20)),
+        "line 00\nline\nline 98\nline 99\n"
+    );
+
+    let (_, sampled) = sample_with(None, crate::reader::from_memory(content.into()));
+    assert!(!sampled);
+}
@@ -0,0 +1,138 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the SARIF export of a report, to be used by code-scanning integrations.
+//! Each anomaly is a result located at the anomaly line, and the context is the surrounding region.
+
+use serde_json::{json, Value};
+
+use crate::levels::Level;
+use crate::{AnomalyContext, IndexName, Report, Source};
+
+const RULE_ID: &str = "logreduce/anomaly";
+
+impl Report {
+    /// Convert the report to a SARIF 2.1.0 log.
+    pub fn to_sarif(&self) -> Value {
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "logreduce",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": "https://github.com/logreduce/logreduce-rust",
+                        "rules": [{
+                            "id": RULE_ID,
+                            "shortDescription": {
+                                "text": "Log line not found in the baselines"
+                            },
+                        }],
+                    }
+                },
+                "results": self
+                    .log_reports
+                    .iter()
+                    .flat_map(|log_report| log_report
+                        .anomalies
+                        .iter()
+                        .map(move |anomaly| {
+                            let mut result = sarif_result(&log_report.source, anomaly);
+                            if let Some(annotation) = &log_report.annotation {
+                                result["properties"]["team"] = json!(annotation.team);
+                                result["properties"]["runbook"] = json!(annotation.runbook);
+                            }
+                            result
+                        }))
+                    .chain(self.accepted_anomalies.iter().map(|accepted| {
+                        let mut result = sarif_result(&accepted.source, &accepted.anomaly);
+                        result["suppressions"] = json!([{
+                            "kind": "external",
+                            "justification": accepted.comment.as_deref().unwrap_or(""),
+                        }]);
+                        result
+                    }))
+                    .collect::<Vec<_>>(),
+            }],
+        })
+    }
+}
+
+fn sarif_result(source: &Source, anomaly: &AnomalyContext) -> Value {
+    let level = match anomaly.anomaly.level {
+        Some(Level::Error) | Some(Level::Critical) => "error",
+        _ => "warning",
+    };
+    let context = anomaly
+        .before
+        .iter()
+        .chain(std::iter::once(&anomaly.anomaly.line))
+        .chain(anomaly.after.iter())
+        .map(|line| line.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    json!({
+        "ruleId": RULE_ID,
+        "level": level,
+        "rank": (anomaly.anomaly.distance * 100.0).clamp(0.0, 100.0),
+        "message": {
+            "text": anomaly.anomaly.line,
+        },
+        "locations": [{
+            "physicalLocation": {
+                "artifactLocation": {
+                    "uri": source.as_str(),
+                },
+                "region": {
+                    "startLine": anomaly.anomaly.pos,
+                    "snippet": {
+                        "text": anomaly.anomaly.line,
+                    },
+                },
+                "contextRegion": {
+                    "startLine": anomaly.anomaly.pos - anomaly.before.len(),
+                    "endLine": anomaly.anomaly.end_pos() + anomaly.after.len(),
+                    "snippet": {
+                        "text": context,
+                    },
+                },
+            },
+            "logicalLocations": [{
+                "name": IndexName::from_source(source).as_str(),
+            }],
+        }],
+        "partialFingerprints": {
+            "logreduce/v1": anomaly.anomaly.fingerprint(),
+        },
+        "properties": {
+            "distance": anomaly.anomaly.distance,
+        },
+    })
+}
+
+#[test]
+fn test_sarif() {
+    let model = crate::train_memory(vec![(
+        "service.log",
+        "INFO service started\nINFO service ready\n",
+    )]);
+    let target = crate::Content::from_memory(vec![(
+        "service.log",
+        "INFO service started\nERROR Traceback oops\nINFO service ready\n",
+    )]);
+    let sarif = model
+        .report(crate::OutputMode::Quiet, target)
+        .unwrap()
+        .to_sarif();
+
+    assert_eq!(sarif["version"], "2.1.0");
+    let results = sarif["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    let location = &results[0]["locations"][0]["physicalLocation"];
+    assert_eq!(location["artifactLocation"]["uri"], "service.log");
+    assert_eq!(location["region"]["startLine"], 2);
+    assert_eq!(location["contextRegion"]["startLine"], 1);
+    assert_eq!(location["contextRegion"]["endLine"], 3);
+    assert_eq!(results[0]["message"]["text"], "ERROR Traceback oops");
+}
@@ -0,0 +1,49 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the sources selection, to skip the irrelevant artifacts of the target,
+//! for example when they are deselected with the `--interactive` option.
+
+use crate::{ReportOptions, Source};
+
+impl ReportOptions {
+    /// Returns true when the source is skipped, see ReportOptions::skipped.
+    pub fn is_skipped(&self, source: &Source) -> bool {
+        self.skipped.contains(source.get_relative())
+    }
+}
+
+impl Source {
+    /// The size of the source, when it is known without reading it.
+    pub fn size_hint(&self) -> Option<u64> {
+        match self {
+            Source::Local(_, path) => std::fs::metadata(path).ok().map(|meta| meta.len()),
+            Source::Memory(_, data) => Some(data.0.len() as u64),
+            Source::Remote(_, _) | Source::Stdin | Source::Member(_, _) => None,
+        }
+    }
+}
+
+#[test]
+fn test_skipped_sources() {
+    let content = crate::Content::from_memory(vec![
+        ("job-output.txt", "build started\n"),
+        ("selection-debug.log", "noise\n"),
+    ]);
+    let options = ReportOptions {
+        skipped: std::iter::once("selection-debug.log".to_string()).collect(),
+        ..ReportOptions::default()
+    };
+    let selected = content.collect_selected_sources(&options).unwrap();
+    assert_eq!(
+        selected
+            .sources
+            .iter()
+            .map(|source| source.get_relative())
+            .collect::<Vec<_>>(),
+        vec!["job-output.txt"]
+    );
+    assert_eq!(selected.deselected.len(), 1);
+    assert_eq!(content.get_sources().unwrap().len(), 2);
+    assert_eq!(selected.sources[0].size_hint(), Some(14));
+}
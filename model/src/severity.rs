@@ -0,0 +1,160 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the anomaly severity, the distance adjusted with keyword weights.
+//! The default weights can be changed with a yaml file such as:
+//!
+//! ```yaml
+//! Traceback: 0.5
+//! WARNING: 0.1
+//! INFO: 0
+//! ```
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::{Anomaly, AnomalyContext, LogReport, Report};
+
+/// The default keyword weights.
+const DEFAULT_WEIGHTS: &[(&str, f32)] = &[
+    ("error", 0.2),
+    ("traceback", 0.3),
+    ("oom", 0.3),
+    ("panic", 0.3),
+    ("fatal", 0.3),
+    ("info", -0.2),
+    ("debug", -0.3),
+];
+
+/// The keyword weights, the keywords are matched as case-insensitive words.
+#[derive(Debug)]
+pub struct Keywords {
+    weights: BTreeMap<String, f32>,
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        Keywords {
+            weights: DEFAULT_WEIGHTS
+                .iter()
+                .map(|(keyword, weight)| (keyword.to_string(), *weight))
+                .collect(),
+        }
+    }
+}
+
+impl Keywords {
+    /// Load the weights, they are added to the default weights.
+    pub fn load(path: &Path) -> Result<Keywords> {
+        let file = std::fs::File::open(path).context("Can't open keywords")?;
+        let weights: BTreeMap<String, f32> =
+            serde_yaml::from_reader(file).context("Can't read keywords")?;
+        Ok(Keywords::default().with_weights(weights))
+    }
+
+    pub fn with_weights(mut self, weights: BTreeMap<String, f32>) -> Keywords {
+        self.weights.extend(
+            weights
+                .into_iter()
+                .map(|(keyword, weight)| (keyword.to_lowercase(), weight)),
+        );
+        self
+    }
+
+    /// The severity of an anomaly, between 0 and 1.
+    pub fn severity(&self, anomaly: &Anomaly) -> f32 {
+        let mut words = anomaly
+            .line
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>();
+        words.sort_unstable();
+        words.dedup();
+        let boost: f32 = words.iter().filter_map(|word| self.weights.get(word)).sum();
+        (anomaly.distance + boost).clamp(0.0, 1.0)
+    }
+}
+
+impl Report {
+    /// Set the severity of each anomaly and sort the log reports by their highest severity.
+    /// The anomalies logged during the failed task are raised.
+    pub fn classify(&mut self, keywords: &Keywords) {
+        for log_report in &mut self.log_reports {
+            for anomaly in &mut log_report.anomalies {
+                let mut severity = keywords.severity(&anomaly.anomaly);
+                if let Some(task) = &self.failed_task {
+                    if task.contains(&anomaly.anomaly) {
+                        severity = (severity + crate::ansible::FAILED_TASK_WEIGHT).min(1.0);
+                    }
+                }
+                anomaly.anomaly.severity = severity;
+            }
+        }
+        let max_severity = |log_report: &LogReport| {
+            log_report
+                .anomalies
+                .iter()
+                .map(|anomaly| anomaly.anomaly.severity)
+                .fold(0.0, f32::max)
+        };
+        self.log_reports.sort_by(|a, b| {
+            max_severity(b)
+                .partial_cmp(&max_severity(a))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    /// The most severe anomalies, with their log report.
+    pub fn top_anomalies(&self, count: usize) -> Vec<(&LogReport, &AnomalyContext)> {
+        let mut anomalies = self
+            .log_reports
+            .iter()
+            .flat_map(|log_report| {
+                log_report
+                    .anomalies
+                    .iter()
+                    .map(move |anomaly| (log_report, anomaly))
+            })
+            .collect::<Vec<_>>();
+        anomalies.sort_by(|(_, a), (_, b)| {
+            b.anomaly
+                .severity
+                .partial_cmp(&a.anomaly.severity)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        anomalies.truncate(count);
+        anomalies
+    }
+}
+
+#[test]
+fn test_severity() {
+    let anomaly = |line: &str| Anomaly {
+        distance: 0.5,
+        severity: 0.5,
+        pos: 1,
+        line: line.to_string(),
+        repeats: None,
+        level: None,
+    };
+    let keywords = Keywords::default();
+    let assert_severity = |keywords: &Keywords, line: &str, expected: f32| {
+        let severity = keywords.severity(&anomaly(line));
+        assert!((severity - expected).abs() < 1e-6, "{}: {}", line, severity);
+    };
+    assert_severity(&keywords, "connection refused", 0.5);
+    assert_severity(&keywords, "ERROR: connection refused", 0.7);
+    // The severity is capped and the keywords are counted once
+    assert_severity(&keywords, "ERROR error Traceback oops", 1.0);
+    assert_severity(&keywords, "DEBUG errors=0", 0.2);
+    assert_severity(&keywords, "kernel: room service", 0.5);
+
+    let keywords = keywords.with_weights(
+        vec![("Refused".to_string(), 0.1), ("ERROR".to_string(), 0.0)]
+            .into_iter()
+            .collect(),
+    );
+    assert_severity(&keywords, "ERROR: connection refused", 0.6);
+}
@@ -0,0 +1,187 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module verifies the detached signature of the models, so that a job only accepts the
+//! models published by a trusted pipeline. The signatures use the minisign format, next to the
+//! model in a `.minisig` file. Only the legacy signatures are supported, created with `minisign -S -l`,
+//! as the pre-hashed ones need blake2b.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::errors::Failure;
+use crate::storage::{LocalStorage, Storage};
+
+/// The minisign algorithm of the legacy signatures.
+const ALGORITHM: &[u8] = b"Ed";
+
+lazy_static::lazy_static! {
+    static ref TRUSTED_KEY: RwLock<Option<PublicKey>> = RwLock::new(None);
+}
+
+/// Only load the models signed by the key.
+pub fn set_trusted_key(key: Option<PublicKey>) {
+    *TRUSTED_KEY.write().unwrap() = key;
+}
+
+/// A minisign public key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublicKey {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+fn decode(line: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(line.trim())
+        .context("Invalid base64")
+}
+
+/// Returns the base64 line of a minisign file, skipping the untrusted comment.
+fn data_line(content: &str) -> Option<&str> {
+    content
+        .lines()
+        .find(|line| !line.trim().is_empty() && !line.starts_with("untrusted comment:"))
+}
+
+impl PublicKey {
+    /// Parse the content of a `minisign.pub` file, or its base64 line.
+    pub fn parse(content: &str) -> Result<PublicKey> {
+        let data = decode(data_line(content).unwrap_or(""))?;
+        if data.len() != 42 || &data[..2] != ALGORITHM {
+            return Err(anyhow::anyhow!("Invalid minisign public key"));
+        }
+        let mut key_id = [0; 8];
+        key_id.copy_from_slice(&data[2..10]);
+        let mut key = [0; 32];
+        key.copy_from_slice(&data[10..]);
+        Ok(PublicKey { key_id, key })
+    }
+
+    fn check(&self, message: &[u8], signature: &[u8]) -> bool {
+        ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, &self.key)
+            .verify(message, signature)
+            .is_ok()
+    }
+
+    /// Verify a `.minisig` signature of the data, including its trusted comment.
+    pub fn verify(&self, data: &[u8], minisig: &str) -> Result<()> {
+        let mut lines = minisig
+            .lines()
+            .filter(|line| !line.starts_with("untrusted comment:"));
+        let signature = decode(lines.next().unwrap_or(""))?;
+        let trusted_comment = lines
+            .next()
+            .and_then(|line| line.strip_prefix("trusted comment: "))
+            .ok_or_else(|| anyhow::anyhow!("Missing trusted comment"))?;
+        let global_signature = decode(lines.next().unwrap_or(""))?;
+        if signature.len() != 74 {
+            return Err(anyhow::anyhow!("Invalid signature"));
+        }
+        if &signature[..2] != ALGORITHM {
+            return Err(anyhow::anyhow!(
+                "Unsupported signature algorithm, sign the model with `minisign -S -l`"
+            ));
+        }
+        if signature[2..10] != self.key_id {
+            return Err(anyhow::anyhow!("The model is signed by another key"));
+        }
+        let signature = &signature[10..];
+        if !self.check(data, signature) {
+            return Err(anyhow::anyhow!("Invalid signature"));
+        }
+        let mut global = signature.to_vec();
+        global.extend_from_slice(trusted_comment.as_bytes());
+        if !self.check(&global, &global_signature) {
+            return Err(anyhow::anyhow!("Invalid trusted comment signature"));
+        }
+        Ok(())
+    }
+}
+
+/// The key set with set_trusted_key.
+pub(crate) fn trusted_key() -> Option<PublicKey> {
+    TRUSTED_KEY.read().unwrap().clone()
+}
+
+/// Verify the signature of an artifact, stored next to it.
+pub(crate) fn verify_artifact(
+    key: &PublicKey,
+    storage: &dyn Storage,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let untrusted = |err: anyhow::Error| err.context(Failure::UntrustedModel(PathBuf::from(name)));
+    let minisig = storage
+        .get(&format!("{}.minisig", name))?
+        .ok_or_else(|| untrusted(anyhow::anyhow!("The model is not signed")))?;
+    key.verify(data, &String::from_utf8_lossy(&minisig))
+        .map_err(untrusted)
+}
+
+/// Verify the signature of a local model, when a trusted key is set.
+pub(crate) fn verify_file(path: &Path, data: &[u8]) -> Result<()> {
+    let key = match trusted_key() {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    let storage = LocalStorage {
+        root: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    verify_artifact(&key, &storage, &name, data)
+}
+
+#[cfg(test)]
+fn sign(key_pair: &ring::signature::Ed25519KeyPair, key_id: &[u8], data: &[u8]) -> String {
+    let encode = |data: &[u8]| base64::engine::general_purpose::STANDARD.encode(data);
+    let signature = key_pair.sign(data);
+    let trusted_comment = "timestamp:1650000000\tfile:model.bin";
+    let mut global = signature.as_ref().to_vec();
+    global.extend_from_slice(trusted_comment.as_bytes());
+    format!(
+        "untrusted comment: signature\n{}\ntrusted comment: {}\n{}\n",
+        encode(&[ALGORITHM, key_id, signature.as_ref()].concat()),
+        trusted_comment,
+        encode(key_pair.sign(&global).as_ref())
+    )
+}
+
+#[test]
+fn test_signature() {
+    use ring::signature::KeyPair;
+    let rng = ring::rand::SystemRandom::new();
+    let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+    let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let key_id = b"logreduc";
+    let public = format!(
+        "untrusted comment: minisign public key\n{}\n",
+        base64::engine::general_purpose::STANDARD
+            .encode([ALGORITHM, key_id, key_pair.public_key().as_ref()].concat())
+    );
+    let key = PublicKey::parse(&public).unwrap();
+    let minisig = sign(&key_pair, key_id, b"model");
+    assert!(key.verify(b"model", &minisig).is_ok());
+    assert!(key.verify(b"other", &minisig).is_err());
+    assert!(key
+        .verify(b"model", &minisig.replace("model.bin", "other.bin"))
+        .is_err());
+    assert!(key
+        .verify(b"model", &sign(&key_pair, b"otherkey", b"model"))
+        .is_err());
+    assert!(PublicKey::parse("untrusted comment: bad\nRWQ=\n").is_err());
+
+    let dir = std::env::temp_dir().join("logreduce-test-signature");
+    std::fs::create_dir_all(&dir).unwrap();
+    let storage = crate::storage::LocalStorage { root: dir.clone() };
+    std::fs::write(dir.join("model.bin.minisig"), &minisig).unwrap();
+    assert!(verify_artifact(&key, &storage, "model.bin", b"model").is_ok());
+    let err = verify_artifact(&key, &storage, "model.bin", b"tampered").unwrap_err();
+    assert_eq!(
+        crate::errors::find_failure(&err),
+        Some(&Failure::UntrustedModel(PathBuf::from("model.bin")))
+    );
+    assert!(verify_artifact(&key, &storage, "unsigned.bin", b"model").is_err());
+}
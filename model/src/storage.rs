@@ -0,0 +1,381 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the storage of the artifacts, such as the models and the reports.
+//! A location is either a local path, a `s3://bucket/key` object, or a http url that accepts
+//! the GET and PUT requests, for example a WebDAV server.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "http")]
+use url::Url;
+
+use crate::{Model, Report};
+
+/// The interface of an artifacts storage.
+pub trait Storage: Send + Sync {
+    /// Returns the artifact data, or None when it does not exist.
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Create or replace an artifact.
+    fn put(&self, name: &str, data: Vec<u8>) -> Result<()>;
+
+    /// The path of the artifact when it is stored on the local filesystem.
+    fn local_path(&self, _name: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// A local directory.
+pub struct LocalStorage {
+    pub root: PathBuf,
+}
+
+impl Storage for LocalStorage {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.root.join(name);
+        match path.exists() {
+            true => std::fs::read(&path)
+                .with_context(|| format!("Can't read {:?}", path))
+                .map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.root.join(name);
+        std::fs::write(&path, data).with_context(|| format!("Can't write {:?}", path))
+    }
+
+    fn local_path(&self, name: &str) -> Option<PathBuf> {
+        Some(self.root.join(name))
+    }
+}
+
+/// A S3 bucket prefix, the requests are signed like the s3 sources.
+#[cfg(feature = "cloud")]
+pub struct S3Storage {
+    pub bucket: String,
+    pub prefix: String,
+}
+
+#[cfg(feature = "cloud")]
+impl Storage for S3Storage {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let url = crate::s3::object_url(&self.bucket, &format!("{}{}", self.prefix, name))?;
+        http_get(&url, sign_s3("GET", &url, b""))
+    }
+
+    fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        let url = crate::s3::object_url(&self.bucket, &format!("{}{}", self.prefix, name))?;
+        let headers = sign_s3("PUT", &url, &data);
+        http_put(&url, headers, data)
+    }
+}
+
+#[cfg(feature = "cloud")]
+fn sign_s3(method: &str, url: &Url, payload: &[u8]) -> Vec<(&'static str, String)> {
+    crate::s3::sign_service("s3", crate::s3::region(), method, url, payload, Vec::new())
+}
+
+/// A http directory.
+#[cfg(feature = "http")]
+pub struct HttpStorage {
+    pub base: Url,
+}
+
+#[cfg(feature = "http")]
+impl Storage for HttpStorage {
+    fn get(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        http_get(&self.url(name)?, Vec::new())
+    }
+
+    fn put(&self, name: &str, data: Vec<u8>) -> Result<()> {
+        http_put(&self.url(name)?, Vec::new(), data)
+    }
+}
+
+#[cfg(feature = "http")]
+impl HttpStorage {
+    fn url(&self, name: &str) -> Result<Url> {
+        self.base
+            .join(name)
+            .with_context(|| format!("Bad artifact name {}", name))
+    }
+}
+
+/// Get an artifact, the cached copy is re-used when the server validates its ETag or Last-Modified date.
+#[cfg(feature = "http")]
+fn http_get(url: &Url, headers: Vec<(&'static str, String)>) -> Result<Option<Vec<u8>>> {
+    let _permit = crate::urls::LIMITER.acquire(url);
+    let cached = match crate::reader::CACHE.artifact_get(url) {
+        Some(Ok(cached)) => Some(cached),
+        Some(Err(e)) => {
+            tracing::warn!("{}: invalid cached artifact: {:#}", url, e);
+            None
+        }
+        None => None,
+    };
+    let mut request = headers.into_iter().fold(
+        crate::reader::CLIENT.get(url.clone()),
+        |request, (name, value)| request.header(name, value),
+    );
+    if let Some(((etag, last_modified), _)) = &cached {
+        if let Some(etag) = etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let resp = request
+        .send()
+        .with_context(|| format!("Can't get {}", url))?;
+    match resp.status() {
+        reqwest::StatusCode::NOT_FOUND => Ok(None),
+        reqwest::StatusCode::NOT_MODIFIED if cached.is_some() => {
+            tracing::debug!("{}: using the cached copy", url);
+            Ok(cached.map(|(_, data)| data))
+        }
+        status if status.is_success() => {
+            let header = |name| {
+                resp.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string())
+            };
+            let validators = (
+                header(reqwest::header::ETAG),
+                header(reqwest::header::LAST_MODIFIED),
+            );
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut { resp }, &mut data)
+                .with_context(|| format!("Can't read {}", url))?;
+            if validators.0.is_some() || validators.1.is_some() {
+                if let Err(e) = crate::reader::CACHE.artifact_add(url, &validators, &data) {
+                    tracing::warn!("{}: can't cache the artifact: {:#}", url, e);
+                }
+            }
+            Ok(Some(data))
+        }
+        status => Err(anyhow::Error::new(crate::errors::Failure::HttpStatus(
+            url.clone(),
+            status.as_u16(),
+        ))),
+    }
+}
+
+#[cfg(feature = "http")]
+fn http_put(url: &Url, headers: Vec<(&'static str, String)>, data: Vec<u8>) -> Result<()> {
+    let _permit = crate::urls::LIMITER.acquire(url);
+    let resp = headers
+        .into_iter()
+        .fold(
+            crate::reader::CLIENT.put(url.clone()),
+            |request, (name, value)| request.header(name, value),
+        )
+        .body(data)
+        .send()
+        .with_context(|| format!("Can't put {}", url))?;
+    match resp.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(anyhow::Error::new(crate::errors::Failure::HttpStatus(
+            url.clone(),
+            status.as_u16(),
+        ))
+        .context(format!("Can't put {}", url))),
+    }
+}
+
+/// Returns the storage of a location, and the artifact name.
+pub fn locate(location: &str) -> Result<(Box<dyn Storage>, String)> {
+    if location.starts_with("s3://") || location.starts_with("http") {
+        locate_remote(location)
+    } else {
+        let path = Path::new(location);
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow::anyhow!("Missing artifact name: {}", location))?;
+        Ok((
+            Box::new(LocalStorage {
+                root: path.parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            }),
+            name.to_string_lossy().into_owned(),
+        ))
+    }
+}
+
+#[cfg(feature = "http")]
+fn locate_remote(location: &str) -> Result<(Box<dyn Storage>, String)> {
+    let url = Url::parse(location).with_context(|| format!("Bad location {}", location))?;
+    let (dir, name) = match url.path().rfind('/') {
+        Some(pos) => url.path().split_at(pos + 1),
+        None => ("", url.path()),
+    };
+    if name.is_empty() {
+        return Err(anyhow::anyhow!("Missing artifact name: {}", location));
+    }
+    if url.scheme() == "s3" {
+        locate_s3(&url, dir, name)
+    } else {
+        let base = url.join(dir).context("Bad location")?;
+        Ok((Box::new(HttpStorage { base }), crate::urls::decode(name)))
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn locate_remote(location: &str) -> Result<(Box<dyn Storage>, String)> {
+    Err(anyhow::anyhow!(
+        "Can't use {}, logreduce-model is built without the http feature",
+        location
+    ))
+}
+
+#[cfg(feature = "cloud")]
+fn locate_s3(url: &Url, dir: &str, name: &str) -> Result<(Box<dyn Storage>, String)> {
+    let bucket = url
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("Missing bucket: {}", url))?;
+    let prefix = crate::urls::decode(dir.trim_start_matches('/'));
+    Ok((
+        Box::new(S3Storage {
+            bucket: bucket.to_string(),
+            prefix,
+        }),
+        crate::urls::decode(name),
+    ))
+}
+
+#[cfg(all(feature = "http", not(feature = "cloud")))]
+fn locate_s3(url: &Url, _dir: &str, _name: &str) -> Result<(Box<dyn Storage>, String)> {
+    Err(anyhow::anyhow!(
+        "Can't use {}, logreduce-model is built without the cloud feature",
+        url
+    ))
+}
+
+/// Create or replace the artifact of a location.
+pub fn put_location(location: &str, data: Vec<u8>) -> Result<()> {
+    let (storage, name) = locate(location)?;
+    storage.put(&name, data)
+}
+
+impl Model {
+    /// Load the model of a location, or returns None when it does not exist yet.
+    /// The local models use the precomputation cache, see Model::load_prepared.
+    pub fn load_location(location: &str) -> Result<Option<Model>> {
+        Model::load_location_with(location, false)
+    }
+
+    /// Load a model from a location, its indexes are read when they are first used, see Model::load_lazy.
+    pub fn load_location_lazy(location: &str) -> Result<Option<Model>> {
+        Model::load_location_with(location, true)
+    }
+
+    fn load_location_with(location: &str, lazy: bool) -> Result<Option<Model>> {
+        let (storage, name) = locate(location)?;
+        match storage.local_path(&name) {
+            // The local models signature is verified when they are read, over the same bytes.
+            Some(path) if path.exists() => match lazy {
+                true => Model::load_lazy(&path).map(Some),
+                false => Model::load_prepared(&path).map(Some),
+            },
+            Some(_) => Ok(None),
+            None => match storage.get(&name)? {
+                Some(data) => {
+                    #[cfg(feature = "signing")]
+                    if let Some(key) = crate::signing::trusted_key() {
+                        crate::signing::verify_artifact(&key, &*storage, &name, &data)?;
+                    }
+                    match lazy {
+                        true => Model::load_lazy_data(data, Path::new(location)).map(Some),
+                        false => Model::load_reader(&data[..], Path::new(location)).map(Some),
+                    }
+                }
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Save the model to a location.
+    pub fn save_location(&self, location: &str) -> Result<()> {
+        tracing::info!(location, "Saving model");
+        let mut data = Vec::new();
+        self.save_writer(&mut data)?;
+        put_location(location, data)
+    }
+}
+
+impl Report {
+    /// Load the report of a location, or returns None when it does not exist.
+    pub fn load_location(location: &str) -> Result<Option<Report>> {
+        let (storage, name) = locate(location)?;
+        match storage.get(&name)? {
+            Some(data) => Report::load_reader(&data[..]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Save the report to a location.
+    pub fn save_location(&self, location: &str) -> Result<()> {
+        let mut data = Vec::new();
+        self.save_writer(&mut data)?;
+        put_location(location, data)
+    }
+}
+
+#[test]
+fn test_storage_locate() -> Result<()> {
+    #[cfg(feature = "cloud")]
+    {
+        let (_, name) = locate("s3://ci-models/nova/model.bin")?;
+        assert_eq!(name, "model.bin");
+    }
+    let (storage, name) = locate("models/nova.bin")?;
+    assert_eq!(name, "nova.bin");
+    assert_eq!(
+        storage.local_path(&name),
+        Some(PathBuf::from("models/nova.bin"))
+    );
+    assert!(locate("https://example.com/models/").is_err());
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_storage() -> Result<()> {
+    use mockito::mock;
+    let put_mock = mock("PUT", "/models/report.bin").with_status(201).create();
+    let missing_mock = mock("GET", "/models/missing.bin").with_status(404).create();
+    let location = format!("{}/models/report.bin", mockito::server_url());
+    let report = Report::load_location(&format!("{}/models/missing.bin", mockito::server_url()))?;
+    assert!(report.is_none());
+    put_location(&location, b"data".to_vec())?;
+    put_mock.assert();
+    missing_mock.assert();
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_storage_cache() -> Result<()> {
+    use mockito::mock;
+    let url = Url::parse(&format!("{}/models/cached.bin", mockito::server_url()))?;
+    crate::reader::CACHE.artifact_drop(&url)?;
+    let get_mock = mock("GET", "/models/cached.bin")
+        .match_header("if-none-match", mockito::Matcher::Missing)
+        .with_header("etag", "\"v1\"")
+        .with_body("model-v1")
+        .create();
+    let cached_mock = mock("GET", "/models/cached.bin")
+        .match_header("if-none-match", "\"v1\"")
+        .with_status(304)
+        .create();
+    let (storage, name) = locate(url.as_str())?;
+    assert_eq!(storage.get(&name)?, Some(b"model-v1".to_vec()));
+    assert_eq!(storage.get(&name)?, Some(b"model-v1".to_vec()));
+    get_mock.assert();
+    cached_mock.assert();
+    crate::reader::CACHE.artifact_drop(&url)?;
+    Ok(())
+}
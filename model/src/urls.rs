@@ -1,21 +1,379 @@
 // Copyright (C) 2022 Red Hat
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::{Context, Result};
+#[cfg(feature = "http")]
+use anyhow::Context;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "http")]
+use std::sync::Arc;
+use std::sync::Mutex;
 use url::Url;
 
 use crate::{Content, Source};
 
+#[cfg(feature = "http")]
 lazy_static::lazy_static! {
-    static ref CACHE: logreduce_cache::Cache = logreduce_cache::Cache::new().expect("Cache");
+    /// The per host limits, set with the LOGREDUCE_RATE_LIMIT (requests per second)
+    /// and the LOGREDUCE_MAX_CONNECTIONS environment variables.
+    pub(crate) static ref LIMITER: Arc<httpdir::HostLimiter> = Arc::new(httpdir::HostLimiter::new(
+        env_value("LOGREDUCE_RATE_LIMIT"),
+        env_value("LOGREDUCE_MAX_CONNECTIONS").unwrap_or(usize::MAX),
+    ));
+
+    /// The User-Agent, set with the LOGREDUCE_USER_AGENT environment variable.
+    pub(crate) static ref USER_AGENT: String = std::env::var("LOGREDUCE_USER_AGENT").unwrap_or_else(|_| format!(
+        "{}/{} (+https://github.com/logreduce/logreduce-rust)",
+        AGENT_NAME,
+        env!("CARGO_PKG_VERSION")
+    ));
+
+    /// Honor the robots.txt rules when the LOGREDUCE_ROBOTS environment variable is set.
+    static ref USE_ROBOTS: bool = std::env::var("LOGREDUCE_ROBOTS").is_ok();
+
+    /// The robots.txt rules per host, fetched once.
+    static ref ROBOTS: Mutex<HashMap<url::Origin, Arc<httpdir::Robots>>> = Mutex::new(HashMap::new());
+
+    /// The mirror prefixes, set with the LOGREDUCE_MIRRORS environment variable
+    /// using a space separated list of `primary-prefix=mirror-prefix`.
+    static ref MIRRORS: Vec<(String, String)> = parse_mirrors(
+        &std::env::var("LOGREDUCE_MIRRORS").unwrap_or_default()
+    );
+}
+
+/// The responses of the fetched urls, None when the url was read from the cache, see HttpResponse.
+#[derive(Default)]
+struct Responses {
+    /// The number of running reports, see ReportRun.
+    running: usize,
+    urls: HashMap<Url, Option<(u16, Url)>>,
+    /// The httpdir listings of the current run, shared by the baselines discovery and the sources fetching.
+    listings: HashMap<Url, Vec<Url>>,
+}
+
+impl Responses {
+    fn start(&mut self) {
+        self.running += 1;
+    }
+
+    fn finish(&mut self) {
+        self.running = self.running.saturating_sub(1);
+        if self.running == 0 {
+            self.urls.clear();
+            self.listings.clear();
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RESPONSES: Mutex<Responses> = Mutex::new(Responses::default());
+}
+
+/// Keep the responses and the listings while a report is running, they are cleared once the last
+/// report is done.
+pub(crate) struct ReportRun;
+
+impl ReportRun {
+    pub(crate) fn start() -> ReportRun {
+        if let Ok(mut responses) = RESPONSES.lock() {
+            responses.start();
+        }
+        ReportRun
+    }
+}
+
+impl Drop for ReportRun {
+    fn drop(&mut self) {
+        if let Ok(mut responses) = RESPONSES.lock() {
+            responses.finish();
+        }
+    }
+}
+
+/// The number of bytes read at the end of the remote sources, 0 to read them entirely.
+static TAIL_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Only read the last bytes of the remote sources, see reader::from_url_tail.
+pub fn set_tail_bytes(bytes: Option<u64>) {
+    TAIL_BYTES.store(bytes.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub(crate) fn get_tail_bytes() -> u64 {
+    TAIL_BYTES.load(Ordering::Relaxed)
+}
+
+/// The http response of a remote source, recorded in the report.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HttpResponse {
+    pub url: Url,
+    /// The status code, None when the source was not fetched.
+    pub status: Option<u16>,
+    /// The url after the redirects and the mirrors failover, when it differs.
+    pub final_url: Option<Url>,
+    /// The source was read from the local cache.
+    pub cached: bool,
+    /// Why the source was not fetched, when it was skipped.
+    pub skipped: Option<String>,
+}
+
+impl std::fmt::Display for HttpResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.status, &self.skipped) {
+            (Some(status), _) => write!(f, "{}: HTTP {}", self.url, status)?,
+            (None, _) if self.cached => write!(f, "{}: cached", self.url)?,
+            (None, Some(reason)) => write!(f, "{}: skipped, {}", self.url, reason)?,
+            (None, None) => write!(f, "{}: missing", self.url)?,
+        }
+        match &self.final_url {
+            Some(final_url) => write!(f, " from {}", final_url),
+            None => Ok(()),
+        }
+    }
+}
+
+impl HttpResponse {
+    /// Take the last response of the url, it is missing when the url was not fetched.
+    pub fn take(url: &Url) -> HttpResponse {
+        let response = RESPONSES
+            .lock()
+            .ok()
+            .and_then(|mut responses| responses.urls.remove(url));
+        let cached = matches!(response, Some(None));
+        let response = response.flatten();
+        HttpResponse {
+            url: url.clone(),
+            status: response.as_ref().map(|(status, _)| *status),
+            final_url: response
+                .map(|(_, final_url)| final_url)
+                .filter(|u| u != url),
+            cached,
+            skipped: None,
+        }
+    }
+
+    /// A source that was not fetched.
+    pub fn skipped(url: &Url, reason: &str) -> HttpResponse {
+        HttpResponse {
+            url: url.clone(),
+            status: None,
+            final_url: None,
+            cached: false,
+            skipped: Some(reason.to_string()),
+        }
+    }
+
+    /// Returns false for the error statuses, the redirections and the sources that were not fetched.
+    pub fn is_ok(&self) -> bool {
+        match self.status {
+            Some(status) => status < 400 && self.final_url.is_none(),
+            None => self.cached,
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn record_response(url: &Url, status: u16, final_url: &Url) {
+    if let Ok(mut responses) = RESPONSES.lock() {
+        responses
+            .urls
+            .insert(url.clone(), Some((status, final_url.clone())));
+    }
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn record_cached(url: &Url) {
+    if let Ok(mut responses) = RESPONSES.lock() {
+        responses.urls.insert(url.clone(), None);
+    }
+}
+
+/// Decode the percent-encoded characters.
+pub(crate) fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match (bytes[pos], s.get(pos + 1..pos + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                result.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+                pos += 3;
+            }
+            (b, _) => {
+                result.push(b);
+                pos += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+#[cfg(feature = "http")]
+fn parse_mirrors(value: &str) -> Vec<(String, String)> {
+    value
+        .split_whitespace()
+        .filter_map(|mirror| match mirror.split_once('=') {
+            Some((primary, mirror)) if !primary.is_empty() && !mirror.is_empty() => {
+                Some((primary.to_string(), mirror.to_string()))
+            }
+            _ => {
+                tracing::warn!("Ignoring invalid LOGREDUCE_MIRRORS value: {}", mirror);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Check the robots.txt rules before fetching a url, when LOGREDUCE_ROBOTS is set.
+#[cfg(feature = "http")]
+pub(crate) fn is_allowed(url: &Url) -> bool {
+    !*USE_ROBOTS || robots_allowed(url)
+}
+
+#[cfg(feature = "http")]
+fn robots_allowed(url: &Url) -> bool {
+    let origin = url.origin();
+    let cached = ROBOTS.lock().unwrap().get(&origin).cloned();
+    let robots = match cached {
+        Some(robots) => robots,
+        None => {
+            // The rules are fetched without holding the lock.
+            let robots = Arc::new(httpdir::Robots::fetch(
+                &crate::reader::CLIENT,
+                &LIMITER,
+                AGENT_NAME,
+                url,
+            ));
+            ROBOTS.lock().unwrap().insert(origin, robots.clone());
+            robots
+        }
+    };
+    robots.allowed(url)
+}
+
+/// The list of alternative urls to use when the url is not available.
+#[cfg(feature = "http")]
+pub(crate) fn mirror_urls(url: &Url) -> Vec<Url> {
+    mirrors_of(&MIRRORS, url)
+}
+
+#[cfg(feature = "http")]
+fn mirrors_of(mirrors: &[(String, String)], url: &Url) -> Vec<Url> {
+    mirrors
+        .iter()
+        .filter_map(|(primary, mirror)| {
+            let path = url.as_str().strip_prefix(primary.as_str())?;
+            Url::parse(&format!("{}{}", mirror, path)).ok()
+        })
+        .collect()
+}
+
+/// Add the credentials of the CI APIs to the request.
+#[cfg(feature = "http")]
+pub(crate) fn authorize(
+    url: &Url,
+    request: reqwest::blocking::RequestBuilder,
+) -> reqwest::blocking::RequestBuilder {
+    let request = crate::circleci::authorize(url, request);
+    let request = crate::github::authorize(url, request);
+    let request = crate::gitlab::authorize(url, request);
+    let request = crate::jenkins::authorize(url, request);
+    #[cfg(feature = "cloud")]
+    let request = {
+        let request = crate::azure::authorize(url, request);
+        let request = crate::gcs::authorize(url, request);
+        crate::s3::authorize(url, request)
+    };
+    request
+}
+
+/// The method of a request, for the signatures.
+#[cfg(feature = "cloud")]
+pub(crate) fn request_method(request: &reqwest::blocking::RequestBuilder) -> String {
+    request
+        .try_clone()
+        .and_then(|request| request.build().ok())
+        .map(|request| request.method().to_string())
+        .unwrap_or_else(|| "GET".to_string())
+}
+
+/// The name used to match the robots.txt rules.
+#[cfg(feature = "http")]
+const AGENT_NAME: &str = "logreduce";
+
+#[cfg(feature = "http")]
+fn env_value<T: std::str::FromStr>(name: &str) -> Option<T> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            tracing::warn!("Ignoring invalid {} value: {}", name, value);
+            None
+        }
+    }
 }
 
 impl Content {
     #[tracing::instrument(level = "debug")]
     pub fn from_url(url: Url) -> Result<Content> {
+        if url.scheme() == "journal" {
+            return Content::from_journal_url(&url);
+        }
+        if url.scheme() == "file" {
+            let path = url
+                .to_file_path()
+                .map_err(|()| anyhow::anyhow!("Bad file url {}", url))?;
+            return Content::from_path(&path);
+        }
+        #[cfg(feature = "cloud")]
+        if url.scheme() == "cloudwatch" {
+            return Content::from_cloudwatch_url(&url);
+        }
         if !url.has_authority() {
-            Err(anyhow::anyhow!("Bad url {}", url))
-        } else if let Some(content) = Content::from_zuul_url(&url) {
+            return Err(anyhow::anyhow!("Bad url {}", url));
+        }
+        Content::from_remote_url(url)
+    }
+
+    #[cfg(feature = "http")]
+    fn from_remote_url(url: Url) -> Result<Content> {
+        #[cfg(feature = "cloud")]
+        if url.scheme() == "s3" {
+            return Content::from_s3_url(&url);
+        } else if url.scheme() == "gs" {
+            return Content::from_gcs_url(&url);
+        }
+        if url.scheme() == "docker" {
+            return Content::from_docker_url(&url);
+        } else if url.scheme() == "loki" || url.scheme() == "loki+http" {
+            return Content::from_loki_url(&url);
+        } else if url.scheme() == "kafka" || url.scheme() == "kafka+http" {
+            return Content::from_kafka_url(&url);
+        }
+        #[cfg(feature = "zuul")]
+        if let Some(content) = Content::from_zuul_url(&url) {
+            return content;
+        }
+        if let Some(content) = Content::from_github_url(&url) {
+            return content;
+        } else if let Some(content) = Content::from_gitlab_url(&url) {
+            return content;
+        } else if let Some(content) = Content::from_jenkins_url(&url) {
+            return content;
+        }
+        #[cfg(feature = "cloud")]
+        if let Some(content) = Content::from_prow_url(&url) {
+            return content;
+        }
+        if let Some(content) = Content::from_circleci_url(&url) {
+            return content;
+        }
+        #[cfg(feature = "cloud")]
+        if let Some(content) = Content::from_azure_url(&url) {
+            return content;
+        }
+        if let Some(content) = Content::from_elasticsearch_url(&url) {
             content
         } else if url.as_str().ends_with('/') {
             Ok(Content::Directory(Source::Remote(0, url)))
@@ -23,33 +381,110 @@ impl Content {
             Ok(Content::File(Source::Remote(0, url)))
         }
     }
+
+    #[cfg(not(feature = "http"))]
+    fn from_remote_url(url: Url) -> Result<Content> {
+        Err(missing_http(&url))
+    }
+}
+
+/// The error of the remote urls when the http feature is disabled.
+#[cfg(not(feature = "http"))]
+fn missing_http(url: &Url) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Can't read {}, logreduce-model is built without the http feature",
+        url
+    )
+}
+
+#[cfg(not(feature = "http"))]
+impl Source {
+    pub fn url_open(_prefix: usize, url: &Url) -> Result<crate::reader::DecompressReader> {
+        Err(missing_http(url))
+    }
+
+    pub fn httpdir_iter(url: &Url) -> Box<dyn Iterator<Item = Result<Source>>> {
+        Box::new(std::iter::once(Err(missing_http(url))))
+    }
 }
 
+#[cfg(feature = "http")]
 impl Source {
+    // Crawl the url, using the mirrors when it fails.
+    fn httpdir_list(url: &Url) -> Result<Vec<Url>> {
+        let crawl = |url: &Url| {
+            let crawler = httpdir::Crawler::new()
+                .with_client(crate::reader::CLIENT.clone(), AGENT_NAME)
+                .with_limiter(LIMITER.clone());
+            if *USE_ROBOTS {
+                crawler.with_robots()
+            } else {
+                crawler
+            }
+            .list(url.clone())
+            .context("Can't list url")
+        };
+        let result = crawl(url);
+        if result.is_ok() {
+            return result;
+        }
+        for mirror in mirror_urls(url) {
+            tracing::warn!("{}: failing over to {}", url, mirror);
+            if let Ok(urls) = crawl(&mirror) {
+                // Keep the primary urls so that the sources are stable.
+                return urls
+                    .iter()
+                    .map(|u| match u.as_str().strip_prefix(mirror.as_str()) {
+                        Some(path) => url.join(path).context("Invalid mirror url"),
+                        None => Ok(u.clone()),
+                    })
+                    .collect();
+            }
+        }
+        result
+    }
+
     #[tracing::instrument(level = "debug")]
     pub fn url_open(prefix: usize, url: &Url) -> Result<crate::reader::DecompressReader> {
         tracing::debug!(url = url.as_str(), "Fetching url");
-        if prefix == 0 {
-            crate::reader::from_url(url, url)
+        let base = if prefix == 0 {
+            url.clone()
         } else {
-            crate::reader::from_url(&Url::parse(&url.as_str()[..42])?, url)
+            Url::parse(&url.as_str()[..42])?
+        };
+        match TAIL_BYTES.load(Ordering::Relaxed) {
+            0 => crate::reader::from_url(&base, url),
+            bytes => crate::reader::from_url_tail(&base, url, bytes),
+        }
+    }
+
+    /// List the url once per run, and once for all the runs when the LOGREDUCE_CACHE is enabled.
+    fn httpdir_listing(url: &Url) -> Result<Vec<Url>> {
+        if let Some(urls) = RESPONSES.lock().unwrap().listings.get(url) {
+            return Ok(urls.clone());
         }
+        let urls = match crate::reader::CACHE.httpdir_get(url) {
+            Some(res) if *crate::reader::USE_CACHE => res?,
+            _ => {
+                let urls = Source::httpdir_list(url)?;
+                if *crate::reader::USE_CACHE && !crate::reader::CACHE.is_full() {
+                    crate::reader::CACHE.httpdir_add(url, &urls)?;
+                }
+                urls
+            }
+        };
+        RESPONSES
+            .lock()
+            .unwrap()
+            .listings
+            .insert(url.clone(), urls.clone());
+        Ok(urls)
     }
 
     #[tracing::instrument(level = "debug")]
     pub fn httpdir_iter(url: &Url) -> Box<dyn Iterator<Item = Result<Source>>> {
         let base_len = url.as_str().trim_end_matches('/').len() + 1;
-        // TODO: fix the httpdir cache to work with iterator
-        let urls = match CACHE.httpdir_get(url) {
-            Some(res) => res,
-            None => httpdir::list(url.clone())
-                .context("Can't list url")
-                .and_then(|res| {
-                    CACHE.httpdir_add(url, &res)?;
-                    Ok(res)
-                }),
-        };
-        match urls {
+        match Source::httpdir_listing(url) {
             Ok(urls) => Box::new(
                 urls.into_iter()
                     .map(move |u| Ok(Source::Remote(base_len, u))),
@@ -58,3 +493,150 @@ impl Source {
         }
     }
 }
+
+#[cfg(feature = "http")]
+#[test]
+fn test_mirror_urls() {
+    let mirrors = parse_mirrors(
+        "https://logs.example.com/=https://mirror.example.org/logs/ invalid \
+         https://logs.example.com/=https://backup.example.net/",
+    );
+    assert_eq!(mirrors.len(), 2);
+    let url = Url::parse("https://logs.example.com/42/job-output.txt").unwrap();
+    assert_eq!(
+        mirrors_of(&mirrors, &url)
+            .iter()
+            .map(|u| u.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "https://mirror.example.org/logs/42/job-output.txt",
+            "https://backup.example.net/42/job-output.txt"
+        ]
+    );
+    assert!(mirrors_of(&mirrors, &Url::parse("https://other.example.com/").unwrap()).is_empty());
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_http_response() -> Result<()> {
+    use mockito::mock;
+    let server = Url::parse(&mockito::server_url())?;
+    let _old_mock = mock("GET", "/responses/old.log")
+        .with_status(302)
+        .with_header("location", "/responses/new.log")
+        .create();
+    let _new_mock = mock("GET", "/responses/new.log")
+        .with_body("log line\n")
+        .create();
+    let _missing_mock = mock("GET", "/responses/missing.log")
+        .with_status(404)
+        .create();
+
+    let run = ReportRun::start();
+    let old = server.join("/responses/old.log")?;
+    Source::url_open(0, &old)?;
+    let response = HttpResponse::take(&old);
+    assert_eq!(response.status, Some(200));
+    assert_eq!(response.final_url, Some(server.join("/responses/new.log")?));
+    assert!(!response.is_ok());
+    assert_eq!(
+        HttpResponse::take(&old).status,
+        None,
+        "The response is taken"
+    );
+
+    let missing = server.join("/responses/missing.log")?;
+    assert!(Source::url_open(0, &missing).is_err());
+    assert_eq!(
+        HttpResponse::take(&missing).to_string(),
+        format!("{}: HTTP 404", missing)
+    );
+
+    let unknown = server.join("/responses/unknown.log")?;
+    let response = HttpResponse::take(&unknown);
+    assert!(!response.is_ok());
+    assert_eq!(response.to_string(), format!("{}: missing", unknown));
+    let response = HttpResponse::skipped(&unknown, "no baseline");
+    assert!(!response.is_ok());
+    assert_eq!(
+        response.to_string(),
+        format!("{}: skipped, no baseline", unknown)
+    );
+
+    record_cached(&unknown);
+    assert!(HttpResponse::take(&unknown).is_ok());
+    drop(run);
+    Ok(())
+}
+
+#[test]
+fn test_run_listings() {
+    let url = Url::parse("https://logs.example.com/42/").unwrap();
+    let mut responses = Responses::default();
+    responses.start();
+    responses
+        .listings
+        .insert(url.clone(), vec![url.join("job-output.txt").unwrap()]);
+    responses.urls.insert(url.clone(), None);
+    responses.start();
+    responses.finish();
+    assert!(
+        responses.listings.contains_key(&url),
+        "A report is still running"
+    );
+    responses.finish();
+    assert!(responses.listings.is_empty());
+    assert!(responses.urls.is_empty());
+}
+
+#[test]
+fn test_file_url() -> Result<()> {
+    let dir = std::env::temp_dir().join("logreduce-test-file-url");
+    std::fs::create_dir_all(&dir)?;
+    let url = Url::from_directory_path(&dir).unwrap();
+    assert_eq!(
+        Content::from_input(crate::Input::from_string(url.to_string()))?,
+        Content::Directory(Source::Local(0, dir))
+    );
+    assert!(Content::from_url(Url::parse("file:///missing/logreduce")?).is_err());
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_robots_allowed() -> Result<()> {
+    use mockito::mock;
+    let server = Url::parse(&mockito::server_url())?;
+    let robots_mock = mock("GET", "/robots.txt")
+        .with_body("User-agent: *\nDisallow: /private/\n")
+        .expect(1)
+        .create();
+
+    assert!(robots_allowed(&server.join("/public/job-output.txt")?));
+    assert!(!robots_allowed(&server.join("/private/job-output.txt")?));
+    // The rules are fetched once per host.
+    robots_mock.assert();
+    Ok(())
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_httpdir_listing() -> Result<()> {
+    use mockito::mock;
+    let url = Url::parse(&mockito::server_url())?.join("/listing/")?;
+    let listing_mock = mock("GET", "/listing/")
+        .with_body(r#"<tr><td><a href="job-output.txt">job-output.txt</a></td></tr>"#)
+        .expect(1)
+        .create();
+
+    let list = || Source::httpdir_iter(&url).collect::<Result<Vec<_>>>();
+    let expected = vec![Source::Remote(
+        url.as_str().len(),
+        url.join("job-output.txt")?,
+    )];
+    assert_eq!(list()?, expected);
+    // The second listing is re-used.
+    assert_eq!(list()?, expected);
+    listing_mock.assert();
+    Ok(())
+}
@@ -0,0 +1,182 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the process resource usage summary.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+static DOWNLOADED_BYTES: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+static TOKENIZER_FALLBACKS: AtomicU64 = AtomicU64::new(0);
+
+/// Record the size of a remote read, returning it for convenience.
+#[cfg(feature = "http")]
+pub(crate) fn downloaded(size: usize) -> usize {
+    DOWNLOADED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    size
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn cache_hit() {
+    CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(feature = "http")]
+pub(crate) fn cache_miss() {
+    CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record a line that couldn't be tokenized, see process::tokenize_line.
+pub(crate) fn tokenizer_fallback() {
+    TOKENIZER_FALLBACKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// The resources used by the current process, or by a report, see ResourceUsage::since.
+/// When reports are created concurrently, the counters include the activity of all the reports.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub peak_rss: u64,
+    pub cpu_time: Duration,
+    pub downloaded_bytes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub tokenizer_fallbacks: u64,
+}
+
+impl ResourceUsage {
+    /// Collect the usage since the process started.
+    pub fn collect() -> ResourceUsage {
+        let (peak_rss, cpu_time) = rusage();
+        ResourceUsage {
+            peak_rss,
+            cpu_time,
+            downloaded_bytes: DOWNLOADED_BYTES.load(Ordering::Relaxed),
+            cache_hits: CACHE_HITS.load(Ordering::Relaxed),
+            cache_misses: CACHE_MISSES.load(Ordering::Relaxed),
+            tokenizer_fallbacks: TOKENIZER_FALLBACKS.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The usage since the start snapshot. The peak RSS is the process peak, it can't be
+    /// attributed to a report.
+    pub fn since(&self, start: &ResourceUsage) -> ResourceUsage {
+        ResourceUsage {
+            peak_rss: self.peak_rss,
+            cpu_time: self.cpu_time.saturating_sub(start.cpu_time),
+            downloaded_bytes: self.downloaded_bytes.saturating_sub(start.downloaded_bytes),
+            cache_hits: self.cache_hits.saturating_sub(start.cache_hits),
+            cache_misses: self.cache_misses.saturating_sub(start.cache_misses),
+            tokenizer_fallbacks: self
+                .tokenizer_fallbacks
+                .saturating_sub(start.tokenizer_fallbacks),
+        }
+    }
+
+    /// The ratio of remote objects served from the local cache.
+    pub fn cache_hit_ratio(&self) -> Option<f32> {
+        match self.cache_hits + self.cache_misses {
+            0 => None,
+            total => Some(self.cache_hits as f32 / total as f32),
+        }
+    }
+}
+
+impl std::fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "peak RSS {}, CPU time {:.2} sec, downloaded {}",
+            human_bytes(self.peak_rss),
+            self.cpu_time.as_secs_f32(),
+            human_bytes(self.downloaded_bytes)
+        )?;
+        if let Some(ratio) = self.cache_hit_ratio() {
+            write!(
+                f,
+                ", cache hits {}/{} ({:.0}%)",
+                self.cache_hits,
+                self.cache_hits + self.cache_misses,
+                ratio * 100.0
+            )?;
+        }
+        if self.tokenizer_fallbacks > 0 {
+            write!(
+                f,
+                ", {} lines hashed without tokenizer",
+                self.tokenizer_fallbacks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn human_bytes(size: u64) -> String {
+    let size = size as f64;
+    if size >= 1024.0 * 1024.0 * 1024.0 {
+        format!("{:.1} GB", size / (1024.0 * 1024.0 * 1024.0))
+    } else if size >= 1024.0 * 1024.0 {
+        format!("{:.1} MB", size / (1024.0 * 1024.0))
+    } else if size >= 1024.0 {
+        format!("{:.1} KB", size / 1024.0)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+#[cfg(unix)]
+fn rusage() -> (u64, Duration) {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    // Safety: getrusage fills the provided struct, which is zero initialized in case of failure.
+    let usage = unsafe {
+        libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr());
+        usage.assume_init()
+    };
+    let to_duration =
+        |tv: libc::timeval| Duration::new(tv.tv_sec as u64, (tv.tv_usec as u32) * 1000);
+    // ru_maxrss is reported in bytes on macos, and in kilobytes elsewhere.
+    let peak_rss = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64
+    } else {
+        usage.ru_maxrss as u64 * 1024
+    };
+    (
+        peak_rss,
+        to_duration(usage.ru_utime) + to_duration(usage.ru_stime),
+    )
+}
+
+#[cfg(not(unix))]
+fn rusage() -> (u64, Duration) {
+    (0, Duration::ZERO)
+}
+
+#[test]
+fn test_usage() {
+    let usage = ResourceUsage {
+        peak_rss: 42 * 1024 * 1024,
+        cpu_time: Duration::from_millis(1500),
+        downloaded_bytes: 2048,
+        cache_hits: 3,
+        cache_misses: 1,
+        tokenizer_fallbacks: 0,
+    };
+    assert_eq!(
+        format!("{}", usage),
+        "peak RSS 42.0 MB, CPU time 1.50 sec, downloaded 2.0 KB, cache hits 3/4 (75%)"
+    );
+    assert!(ResourceUsage::collect().peak_rss > 0);
+
+    let start = ResourceUsage {
+        cache_hits: 1,
+        cpu_time: Duration::from_millis(500),
+        ..usage.clone()
+    };
+    let report_usage = usage.since(&start);
+    assert_eq!(report_usage.peak_rss, usage.peak_rss);
+    assert_eq!(report_usage.cpu_time, Duration::from_secs(1));
+    assert_eq!(report_usage.downloaded_bytes, 0);
+    assert_eq!(report_usage.cache_hits, 2);
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the version of the program, which is recorded in the models and the reports,
+//! and the optional check for a newer release, enabled with LOGREDUCE_UPDATE_CHECK=1.
+//! The check is skipped in CI, when the CI environment variable is set, to avoid the network request.
+//! It needs the http feature.
+
+#[cfg(feature = "http")]
+use anyhow::{Context, Result};
+#[cfg(feature = "http")]
+use serde::Deserialize;
+#[cfg(feature = "http")]
+use std::time::Duration;
+
+/// The version of the program.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cfg(feature = "http")]
+const RELEASES_URL: &str = "https://api.github.com/repos/logreduce/logreduce-rust/releases/latest";
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Parse a `v1.2.3` version.
+fn parse(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim_start_matches('v').splitn(3, '.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    // Ignore the pre-release suffix, such as `3-rc1`.
+    let patch = parts
+        .next()
+        .map(|patch| {
+            patch
+                .split(|c: char| !c.is_ascii_digit())
+                .next()
+                .unwrap_or("")
+        })
+        .and_then(|patch| patch.parse().ok())
+        .unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// Returns true when the latest version is a newer major or minor release.
+pub fn is_much_newer(current: &str, latest: &str) -> bool {
+    match (parse(current), parse(latest)) {
+        (Some((major, minor, _)), Some((latest_major, latest_minor, _))) => {
+            (latest_major, latest_minor) > (major, minor)
+        }
+        _ => false,
+    }
+}
+
+/// Returns true when the versions have the same major and minor release.
+pub fn is_compatible(version: &str) -> bool {
+    !is_much_newer(version, VERSION) && !is_much_newer(VERSION, version)
+}
+
+#[cfg(feature = "http")]
+fn latest_release(url: &str) -> Result<String> {
+    let resp = crate::reader::CLIENT
+        .get(url)
+        .timeout(Duration::from_secs(2))
+        .send()
+        .context("Can't check the latest release")?
+        .error_for_status()
+        .context("Can't check the latest release")?;
+    let release: Release = serde_json::from_reader(resp).context("Can't decode the release")?;
+    Ok(release.tag_name)
+}
+
+/// Warn when a much newer release exists. This is disabled by default.
+#[cfg(feature = "http")]
+pub fn check_update() {
+    if std::env::var("LOGREDUCE_UPDATE_CHECK").as_deref() != Ok("1") || std::env::var("CI").is_ok()
+    {
+        return;
+    }
+    match latest_release(RELEASES_URL) {
+        Ok(latest) if is_much_newer(VERSION, &latest) => tracing::warn!(
+            "logreduce {} is available, this is {}: please update to keep the models compatible",
+            latest,
+            VERSION
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::debug!("{:#}", e),
+    }
+}
+
+#[test]
+fn test_versions() {
+    assert!(is_much_newer("0.1.0", "v0.2.0"));
+    assert!(is_much_newer("0.1.0", "1.0.0-rc1"));
+    assert!(!is_much_newer("0.1.0", "v0.1.7"));
+    assert!(!is_much_newer("0.2.0", "v0.1.0"));
+    assert!(!is_much_newer("0.1.0", "main"));
+    assert_eq!(parse("v1.2.3-rc1"), Some((1, 2, 3)));
+    assert!(is_compatible(VERSION));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn test_latest_release() -> Result<()> {
+    use mockito::mock;
+    let release_mock = mock("GET", "/releases/latest")
+        .with_body(r#"{"tag_name": "v0.2.0", "name": "logreduce 0.2.0"}"#)
+        .create();
+    let latest = latest_release(&format!("{}/releases/latest", mockito::server_url()))?;
+    assert_eq!(latest, "v0.2.0");
+    release_mock.assert();
+    Ok(())
+}
@@ -0,0 +1,241 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the self-baselining of a log, when no separate baseline exists:
+//! the beginning of the log is the baseline, and the remaining lines are inspected against it.
+//! The lines that are not anomalies are added to the baseline as the log is processed.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::process::{ChunkProcessor, ChunkTrainer, LearningWindow};
+use crate::{
+    usage, ChunkIndex, Content, IndexName, IndexReport, LogReport, Model, OutputMode, Report,
+    Source,
+};
+
+/// The part of the log used as the baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Warmup {
+    /// The first lines.
+    Lines(usize),
+    /// The lines logged since the first timestamp.
+    Duration(Duration),
+}
+
+impl Default for Warmup {
+    fn default() -> Self {
+        Warmup::Lines(1000)
+    }
+}
+
+impl Warmup {
+    /// Returns true when the line is the first one after the warmup.
+    fn is_over(
+        &self,
+        line_count: usize,
+        line: &str,
+        start: &mut Option<chrono::NaiveDateTime>,
+    ) -> bool {
+        match self {
+            Warmup::Lines(max) => line_count >= *max,
+            Warmup::Duration(duration) => match (parse_timestamp(line), *start) {
+                (Some(ts), Some(start)) => {
+                    ts.signed_duration_since(start).num_seconds() >= duration.as_secs() as i64
+                }
+                (Some(ts), None) => {
+                    *start = Some(ts);
+                    false
+                }
+                // Lines without a timestamp belong to the current record.
+                (None, _) => false,
+            },
+        }
+    }
+}
+
+/// Parse a leading date time (2022-03-23 17:33:00) or time (17:33:00), optionally surrounded by a bracket.
+pub(crate) fn parse_timestamp(line: &str) -> Option<chrono::NaiveDateTime> {
+    let line = line.strip_prefix('[').unwrap_or(line);
+    let parse = |s: &str| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok();
+    line.get(..19)
+        .and_then(|s| parse(&s.replace('/', "-").replacen('T', " ", 1)))
+        .or_else(|| {
+            line.get(..8)
+                .and_then(|s| parse(&format!("1970-01-01 {}", s)))
+        })
+}
+
+impl Model {
+    /// Create a report where each source is inspected against its own warmup lines.
+    #[tracing::instrument(level = "debug", skip(mk_index, output_mode))]
+    pub fn self_baseline(
+        output_mode: OutputMode,
+        target: Content,
+        warmup: Warmup,
+        mk_index: fn() -> ChunkIndex,
+    ) -> Result<Report> {
+        let _run = crate::urls::ReportRun::start();
+        let start_time = Instant::now();
+        let start_usage = usage::ResourceUsage::collect();
+        let created_at = SystemTime::now();
+        let mut index_reports = HashMap::new();
+        let mut log_reports = Vec::new();
+        let mut read_errors = Vec::new();
+        let mut empty_sources = Vec::new();
+        let mut http_responses = Vec::new();
+        let mut total_file_count = 0;
+        let mut total_line_count = 0;
+        let mut total_anomaly_count = 0;
+        for source in target.get_sources()? {
+            crate::debug_or_progress(output_mode, &format!("Self-baselining {}", source));
+            let result = self_baseline_source(&source, warmup, mk_index);
+            if let Source::Remote(_, url) = &source {
+                http_responses.push(crate::urls::HttpResponse::take(url));
+            }
+            match result {
+                Ok(log_report) => {
+                    total_file_count += 1;
+                    total_line_count += log_report.line_count;
+                    if log_report.line_count == 0 {
+                        empty_sources.push(source);
+                    } else if !log_report.anomalies.is_empty() {
+                        total_anomaly_count += log_report.anomalies.len();
+                        index_reports.insert(
+                            log_report.index_name.clone(),
+                            IndexReport {
+                                train_time: Duration::from_secs(0),
+                                sources: vec![source],
+                            },
+                        );
+                        log_reports.push(log_report);
+                    }
+                }
+                Err(err) => read_errors.push((source, format!("{:#}", err))),
+            }
+        }
+        Ok(Report {
+            created_at,
+            version: crate::version::VERSION.to_string(),
+            run_time: start_time.elapsed(),
+            baselines: vec![target.clone()],
+            target,
+            log_reports,
+            index_reports,
+            index_errors: Vec::new(),
+            read_errors,
+            empty_sources,
+            sampled_sources: Vec::new(),
+            warnings: Vec::new(),
+            accepted_anomalies: Vec::new(),
+            failed_task: None,
+            total_file_count,
+            total_line_count,
+            total_anomaly_count,
+            usage: usage::ResourceUsage::collect().since(&start_usage),
+            http_responses,
+            baseline_matches: Vec::new(),
+            inspected_indexes: Vec::new(),
+        })
+    }
+}
+
+fn self_baseline_source(
+    source: &Source,
+    warmup: Warmup,
+    mk_index: fn() -> ChunkIndex,
+) -> Result<LogReport> {
+    let start_time = Instant::now();
+    let reader = source.open()?;
+    let mut reader = std::io::BufReader::new(reader);
+
+    // Read the warmup lines, the first line after the warmup is kept for the processor.
+    let mut baseline = Vec::new();
+    let mut next_line = Vec::new();
+    let mut warmup_count = 0;
+    let mut start = None;
+    while reader
+        .read_until(b'\n', &mut next_line)
+        .context("Failed to read the warmup lines")?
+        > 0
+    {
+        if warmup.is_over(
+            warmup_count,
+            &String::from_utf8_lossy(&next_line),
+            &mut start,
+        ) {
+            break;
+        }
+        warmup_count += 1;
+        baseline.append(&mut next_line);
+    }
+
+    let mut index = mk_index();
+    let mut trainer = ChunkTrainer::new(&mut index);
+    trainer.add(std::io::Cursor::new(baseline))?;
+    trainer.complete();
+    let byte_count = trainer.byte_count;
+
+    let mut window = LearningWindow::accepting(mk_index);
+    let mut processor =
+        ChunkProcessor::single(std::io::Cursor::new(next_line).chain(reader), &index)
+            .with_line_offset(warmup_count)
+            .with_learning(&mut window);
+    let mut anomalies = processor.by_ref().collect::<Result<Vec<_>>>()?;
+    processor.set_repeats(&mut anomalies);
+    Ok(LogReport {
+        test_time: start_time.elapsed(),
+        line_count: warmup_count + processor.line_count,
+        byte_count: byte_count + processor.byte_count,
+        anomalies,
+        source: source.clone(),
+        index_name: IndexName::from_source(source),
+        annotation: None,
+    })
+}
+
+#[test]
+fn test_parse_timestamp() {
+    let ts = |s| parse_timestamp(s).map(|ts| ts.format("%F %T").to_string());
+    assert_eq!(
+        ts("2022-03-23T17:33:00.042Z INFO"),
+        Some("2022-03-23 17:33:00".to_string())
+    );
+    assert_eq!(
+        ts("[2022/03/23 17:33:00] INFO"),
+        Some("2022-03-23 17:33:00".to_string())
+    );
+    assert_eq!(ts("17:33:00 INFO"), Some("1970-01-01 17:33:00".to_string()));
+    assert_eq!(ts("  File foo.py line 42"), None);
+}
+
+#[test]
+fn test_self_baseline() {
+    let log = (0..20)
+        .map(|idx| format!("2022-03-23 17:{:02}:00 INFO service heartbeat", idx))
+        .chain(std::iter::once(
+            "2022-03-23 17:20:00 ERROR Traceback oops".to_string(),
+        ))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let target = || Content::from_memory(vec![("service.log", log.clone())]);
+    for warmup in [
+        Warmup::Lines(10),
+        Warmup::Duration(Duration::from_secs(10 * 60)),
+    ] {
+        let report = Model::self_baseline(
+            OutputMode::Quiet,
+            target(),
+            warmup,
+            crate::hashing_index::new,
+        )
+        .unwrap();
+        assert_eq!(report.total_line_count, 21);
+        assert_eq!(report.total_anomaly_count, 1, "{:?}", warmup);
+        let anomaly = &report.log_reports[0].anomalies[0];
+        assert_eq!(anomaly.anomaly.pos, 21);
+        assert_eq!(anomaly.before.len(), 3);
+    }
+}
@@ -10,6 +10,102 @@ use url::Url;
 
 use crate::{Baselines, Content, Source};
 
+lazy_static::lazy_static! {
+    /// The hosts to inspect, set with the LOGREDUCE_ZUUL_HOSTS environment variable.
+    static ref ZUUL_HOSTS: HostFilter = match std::env::var("LOGREDUCE_ZUUL_HOSTS") {
+        Ok(value) => value.parse().unwrap_or(HostFilter::All),
+        Err(_) => HostFilter::All,
+    };
+}
+
+/// Which inventory hosts logs are inspected.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HostFilter {
+    All,
+    /// The hosts that failed or were unreachable according to the play recap.
+    Failed,
+    Hosts(Vec<String>),
+}
+
+impl std::str::FromStr for HostFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "" | "all" => Ok(HostFilter::All),
+            "failed" => Ok(HostFilter::Failed),
+            hosts => Ok(HostFilter::Hosts(
+                hosts
+                    .split(',')
+                    .map(|host| host.trim().to_string())
+                    .collect(),
+            )),
+        }
+    }
+}
+
+impl HostFilter {
+    /// Returns the hosts to skip, when no host failed every hosts are kept.
+    fn skipped(&self, hosts: Vec<String>, failed: &[String]) -> Vec<String> {
+        match self {
+            HostFilter::All => Vec::new(),
+            HostFilter::Failed if failed.is_empty() => Vec::new(),
+            HostFilter::Failed => hosts
+                .into_iter()
+                .filter(|host| !failed.contains(host))
+                .collect(),
+            HostFilter::Hosts(keep) => hosts
+                .into_iter()
+                .filter(|host| !keep.contains(host))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Inventory {
+    all: InventoryGroup,
+}
+
+#[derive(Deserialize)]
+struct InventoryGroup {
+    #[serde(default)]
+    hosts: std::collections::BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Returns the hosts which failed or were unreachable in the PLAY RECAP lines, such as:
+/// `2022-04-01 10:00:00.000000 | controller : ok=12 changed=4 unreachable=0 failed=1 ...`
+fn failed_hosts<R: std::io::BufRead>(reader: R) -> Vec<String> {
+    lazy_static::lazy_static! {
+        static ref RECAP: regex::Regex = regex::Regex::new(
+            r"(?:^|\| )(\S+)\s+: ok=\d+\s+changed=\d+\s+unreachable=(\d+)\s+failed=(\d+)"
+        ).expect("Recap regex");
+    }
+    let mut hosts = Vec::new();
+    for line in reader.lines().map_while(Result::ok) {
+        if let Some(captures) = RECAP.captures(&line) {
+            let host = captures[1].to_string();
+            if (&captures[2] != "0" || &captures[3] != "0") && !hosts.contains(&host) {
+                hosts.push(host);
+            }
+        }
+    }
+    hosts
+}
+
+/// Returns the position of the source host, when the source is in a `<host>/` or a `logs/<host>/` directory.
+fn host_of(source: &Source, hosts: &[String]) -> Option<usize> {
+    let relative = source.get_relative();
+    let mut dirs = relative.split('/').collect::<Vec<_>>();
+    dirs.pop();
+    let position = |dir: &str| hosts.iter().position(|host| host == dir);
+    match dirs[..] {
+        ["logs", dir, ..] => position(dir).or_else(|| position("logs")),
+        [dir, ..] => position(dir),
+        [] => None,
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Build {
     api: Url,
@@ -121,8 +217,58 @@ impl Build {
             .collect())
     }
 
+    /// The build sources, followed by the sources of each inventory host that is not skipped.
     pub fn sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
-        Source::httpdir_iter(&self.log_url)
+        let (hosts, skipped) = match self.inventory_hosts() {
+            Ok(hosts) => hosts,
+            Err(e) if *ZUUL_HOSTS == HostFilter::All => {
+                tracing::debug!("{}: can't group the hosts: {:#}", self, e);
+                return Source::httpdir_iter(&self.log_url);
+            }
+            Err(e) => {
+                tracing::warn!("{}: keeping every hosts: {:#}", self, e);
+                return Source::httpdir_iter(&self.log_url);
+            }
+        };
+        if !skipped.is_empty() {
+            tracing::info!("{}: skipping the hosts {:?}", self, skipped);
+        }
+        let mut sources: Vec<_> = Source::httpdir_iter(&self.log_url)
+            .filter(|source| match source {
+                Ok(source) => host_of(source, &skipped).is_none(),
+                Err(_) => true,
+            })
+            .collect();
+        sources.sort_by_key(|source| match source {
+            Ok(source) => host_of(source, &hosts).map_or(0, |host| host + 1),
+            Err(_) => 0,
+        });
+        Box::new(sources.into_iter())
+    }
+
+    fn log_file(&self, path: &str) -> Result<crate::reader::DecompressReader> {
+        let mut base = self.log_url.clone();
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        let url = base.join(path).context("Can't create log url")?;
+        crate::reader::from_url(&base, &url)
+    }
+
+    /// The inventory hosts, and the ones to skip according to the LOGREDUCE_ZUUL_HOSTS filter.
+    fn inventory_hosts(&self) -> Result<(Vec<String>, Vec<String>)> {
+        let inventory: Inventory =
+            serde_yaml::from_reader(self.log_file("zuul-info/inventory.yaml")?)
+                .context("Can't decode inventory")?;
+        let hosts: Vec<String> = inventory.all.hosts.keys().cloned().collect();
+        let failed = match *ZUUL_HOSTS {
+            HostFilter::Failed => {
+                failed_hosts(std::io::BufReader::new(self.log_file("job-output.txt")?))
+            }
+            _ => Vec::new(),
+        };
+        let skipped = ZUUL_HOSTS.skipped(hosts.clone(), &failed);
+        Ok((hosts, skipped))
     }
 }
 
@@ -217,6 +363,60 @@ impl Content {
     }
 }
 
+#[test]
+fn test_zuul_hosts() {
+    let inventory: Inventory = serde_yaml::from_str(
+        r#"
+all:
+  hosts:
+    controller:
+      ansible_host: 192.0.2.1
+    compute1:
+      ansible_host: 192.0.2.2
+  vars:
+    zuul:
+      job: devstack
+"#,
+    )
+    .unwrap();
+    let hosts: Vec<String> = inventory.all.hosts.keys().cloned().collect();
+    assert_eq!(hosts, vec!["compute1", "controller"]);
+
+    let recap = "2022-04-01 10:00:00.000000 | PLAY RECAP\n\
+        2022-04-01 10:00:00.000000 | compute1                   : ok=12   changed=4    unreachable=0    failed=0    skipped=2\n\
+        2022-04-01 10:00:00.000000 | controller                 : ok=10   changed=3    unreachable=0    failed=1    skipped=2\n";
+    let failed = failed_hosts(recap.as_bytes());
+    assert_eq!(failed, vec!["controller"]);
+
+    let filter: HostFilter = "failed".parse().unwrap();
+    assert_eq!(filter.skipped(hosts.clone(), &failed), vec!["compute1"]);
+    assert!(
+        filter.skipped(hosts.clone(), &[]).is_empty(),
+        "Every hosts are kept when none failed"
+    );
+    let filter: HostFilter = "compute1, localhost".parse().unwrap();
+    assert_eq!(filter.skipped(hosts, &failed), vec!["controller"]);
+
+    let hosts = vec!["compute1".to_string(), "controller".to_string()];
+    let source = |path: &str| Source::Local(0, path.into());
+    assert_eq!(host_of(&source("compute1/logs/nova.log"), &hosts), Some(0));
+    assert_eq!(host_of(&source("logs/compute1/nova.log"), &hosts), Some(0));
+    assert_eq!(
+        host_of(&source("controller/logs/nova.log"), &hosts),
+        Some(1)
+    );
+    assert_eq!(
+        host_of(&source("logs/etc/compute1/nova.conf"), &hosts),
+        None
+    );
+    assert_eq!(
+        host_of(&source("controller/logs/compute1/nova.log"), &hosts),
+        Some(1)
+    );
+    assert_eq!(host_of(&source("logs/compute1"), &hosts), None);
+    assert_eq!(host_of(&source("job-output.txt"), &hosts), None);
+}
+
 #[test]
 fn test_zuul_url() {
     let assert_url = |full, base, uid: &str| {
@@ -0,0 +1,75 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a CSV export of the anomalies, with one row per anomaly.
+
+use std::fmt::Write;
+
+use crate::Result;
+
+pub fn render(report: &logreduce_model::Report) -> Result<String> {
+    let mut csv = String::new();
+    write_row(
+        &mut csv,
+        &[
+            "source",
+            "line_number",
+            "distance",
+            "level",
+            "line",
+            "fingerprint",
+            "team",
+            "runbook",
+        ],
+    )?;
+    for log_report in &report.log_reports {
+        let annotation = log_report.annotation.clone().unwrap_or_default();
+        for anomaly in &log_report.anomalies {
+            write_row(
+                &mut csv,
+                &[
+                    log_report.source.as_str(),
+                    &anomaly.anomaly.pos.to_string(),
+                    &format!("{:.3}", anomaly.anomaly.distance),
+                    &anomaly
+                        .anomaly
+                        .level
+                        .map_or_else(String::new, |level| level.to_string()),
+                    &anomaly.anomaly.line,
+                    &anomaly.anomaly.fingerprint(),
+                    annotation.team.as_deref().unwrap_or(""),
+                    annotation.runbook.as_deref().unwrap_or(""),
+                ],
+            )?;
+        }
+    }
+    Ok(csv)
+}
+
+fn write_row(csv: &mut String, fields: &[&str]) -> Result<()> {
+    for (idx, field) in fields.iter().enumerate() {
+        if idx > 0 {
+            csv.write_char(',')?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(csv, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            csv.write_str(field)?;
+        }
+    }
+    csv.write_str("\r\n")
+}
+
+#[test]
+fn test_write_row() {
+    let mut csv = String::new();
+    write_row(
+        &mut csv,
+        &["job-output.txt", "42", "0.900", "error: \"oops\", retrying"],
+    )
+    .unwrap();
+    assert_eq!(
+        csv,
+        "job-output.txt,42,0.900,\"error: \"\"oops\"\", retrying\"\r\n"
+    );
+}
@@ -0,0 +1,163 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a Markdown report, to be posted as a merge request comment.
+
+use itertools::Itertools;
+use std::fmt::Write;
+
+use crate::Result;
+
+pub fn render(report: &logreduce_model::Report) -> Result<String> {
+    let mut md = String::new();
+    writeln!(md, "## Logreduce of {}\n", report.target)?;
+    writeln!(
+        md,
+        "{} anomalies found in {} lines, compared with {}.\n",
+        report.total_anomaly_count,
+        report.total_line_count,
+        report.baselines.iter().format(", ")
+    )?;
+    if let Some(task) = &report.failed_task {
+        writeln!(md, "Failed task: `{}`\n", task)?;
+    }
+    for warning in &report.warnings {
+        writeln!(md, "> :warning: {}\n", warning)?;
+    }
+    if !report.empty_sources.is_empty() {
+        writeln!(
+            md,
+            "Skipped empty files: {}\n",
+            report.empty_sources.iter().format(", ")
+        )?;
+    }
+    if !report.sampled_sources.is_empty() {
+        writeln!(
+            md,
+            "Partially analyzed files, head and tail only: {}\n",
+            report.sampled_sources.iter().format(", ")
+        )?;
+    }
+    let http_responses = format!(
+        "{}",
+        report
+            .http_responses
+            .iter()
+            .filter(|resp| !resp.is_ok())
+            .format(", ")
+    );
+    if !http_responses.is_empty() {
+        writeln!(md, "HTTP responses: {}\n", http_responses)?;
+    }
+
+    if report.log_reports.is_empty() {
+        add_accepted(&mut md, &report.accepted_anomalies)?;
+        return Ok(md);
+    }
+    writeln!(md, "| File | Anomalies | Lines |")?;
+    writeln!(md, "| --- | ---: | ---: |")?;
+    for log_report in &report.log_reports {
+        writeln!(
+            md,
+            "| [{}]({}) | {} | {} |",
+            table_escape(log_report.source.get_relative()),
+            log_report.source.as_str().replace(' ', "%20"),
+            log_report.anomalies.len(),
+            log_report.line_count
+        )?;
+    }
+
+    for log_report in &report.log_reports {
+        writeln!(md, "\n### {}\n", log_report.source.get_relative())?;
+        if let Some(annotation) = &log_report.annotation {
+            writeln!(md, "{}\n", annotation)?;
+        }
+        add_anomalies(&mut md, &log_report.anomalies)?;
+    }
+    add_accepted(&mut md, &report.accepted_anomalies)?;
+    Ok(md)
+}
+
+fn add_accepted(
+    md: &mut String,
+    accepted: &[logreduce_model::accepted::AcceptedAnomaly],
+) -> Result<()> {
+    if accepted.is_empty() {
+        return Ok(());
+    }
+    writeln!(md, "\n### Accepted anomalies\n")?;
+    writeln!(md, "| File | Line | Anomaly | Comment |")?;
+    writeln!(md, "| --- | ---: | --- | --- |")?;
+    for accepted in accepted {
+        writeln!(
+            md,
+            "| {} | {} | `{}` | {} |",
+            table_escape(accepted.source.get_relative()),
+            accepted.anomaly.anomaly.pos,
+            table_escape(&accepted.anomaly.anomaly.line.replace('`', "'")),
+            table_escape(accepted.comment.as_deref().unwrap_or(""))
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the anomalies with their context, using one code block per contiguous lines.
+fn add_anomalies(md: &mut String, anomalies: &[logreduce_model::AnomalyContext]) -> Result<()> {
+    let mut blocks: Vec<Vec<String>> = Vec::new();
+    let mut last_pos = None;
+    for anomaly in anomalies {
+        let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
+        if last_pos != Some(starting_pos) {
+            blocks.push(Vec::new());
+        }
+        let block = blocks.last_mut().unwrap();
+        for (idx, line) in anomaly.before.iter().enumerate() {
+            block.push(format!("   {:4} | {}", starting_pos + 1 + idx, line));
+        }
+        block.push(format!(
+            "{:02.0} {:4} | {}{}",
+            anomaly.anomaly.distance * 99.0,
+            anomaly.anomaly.pos,
+            anomaly.anomaly.line,
+            anomaly.anomaly.repeats_note()
+        ));
+        for (idx, line) in anomaly.after.iter().enumerate() {
+            block.push(format!(
+                "   {:4} | {}",
+                anomaly.anomaly.end_pos() + 1 + idx,
+                line
+            ));
+        }
+        last_pos = Some(anomaly.anomaly.end_pos() + anomaly.after.len());
+    }
+
+    for block in blocks {
+        let fence = fence_for(&block);
+        writeln!(md, "{}\n{}\n{}", fence, block.iter().format("\n"), fence)?;
+    }
+    Ok(())
+}
+
+/// The code fence must be longer than any backtick sequence of the log lines.
+fn fence_for(lines: &[String]) -> String {
+    let longest = lines
+        .iter()
+        .flat_map(|line| line.split(|c| c != '`'))
+        .map(|ticks| ticks.len())
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn table_escape(value: &str) -> String {
+    value.replace('|', "\\|")
+}
+
+#[test]
+fn test_fence_for() {
+    assert_eq!(fence_for(&["ERROR oops".to_string()]), "```");
+    assert_eq!(
+        fence_for(&["```".to_string(), "run `cmd` ````".to_string()]),
+        "`````"
+    );
+}
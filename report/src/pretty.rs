@@ -0,0 +1,95 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a colored terminal report, formatted like a `git diff`.
+
+use std::fmt::Write;
+
+use crate::Result;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const CYAN: &str = "\x1b[36m";
+
+pub fn render(report: &logreduce_model::Report) -> Result<String> {
+    let mut out = String::new();
+    writeln!(out, "{}{}{}", BOLD, crate::summary::render(report)?, RESET)?;
+    for log_report in &report.log_reports {
+        writeln!(
+            out,
+            "{}--- {} ({} anomalies, {} model){}",
+            BOLD,
+            log_report.source,
+            log_report.anomalies.len(),
+            log_report.index_name,
+            RESET
+        )?;
+        if let Some(annotation) = &log_report.annotation {
+            writeln!(out, "{}{}{}", CYAN, annotation, RESET)?;
+        }
+        let mut last_pos = None;
+        for anomaly in &log_report.anomalies {
+            let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
+            if last_pos != Some(starting_pos) {
+                writeln!(out, "{}@@ line {} @@{}", CYAN, starting_pos + 1, RESET)?;
+            }
+            add_context(&mut out, starting_pos, &anomaly.before)?;
+            writeln!(
+                out,
+                "{}{:5} | {}{}{}",
+                severity_color(anomaly.anomaly.distance),
+                anomaly.anomaly.pos,
+                anomaly.anomaly.line,
+                anomaly.anomaly.repeats_note(),
+                RESET
+            )?;
+            add_context(&mut out, anomaly.anomaly.end_pos(), &anomaly.after)?;
+            last_pos = Some(anomaly.anomaly.end_pos() + anomaly.after.len());
+        }
+        writeln!(out)?;
+    }
+    for accepted in &report.accepted_anomalies {
+        writeln!(
+            out,
+            "{}accepted {}:{} | {} ({}){}",
+            DIM,
+            accepted.source,
+            accepted.anomaly.anomaly.pos,
+            accepted.anomaly.anomaly.line,
+            accepted.comment.as_deref().unwrap_or("no comment"),
+            RESET
+        )?;
+    }
+    writeln!(
+        out,
+        "{}{}: {} anomalies found in {} lines{}",
+        BOLD, report.target, report.total_anomaly_count, report.total_line_count, RESET
+    )?;
+    Ok(out)
+}
+
+fn add_context(out: &mut String, pos: usize, xs: &[String]) -> Result<()> {
+    for (idx, line) in xs.iter().enumerate() {
+        writeln!(out, "{}{:5} | {}{}", DIM, pos + 1 + idx, line, RESET)?;
+    }
+    Ok(())
+}
+
+/// The most distant lines are the most severe.
+fn severity_color(distance: f32) -> &'static str {
+    if distance >= 0.8 {
+        "\x1b[1;31m"
+    } else if distance >= 0.5 {
+        "\x1b[31m"
+    } else {
+        "\x1b[33m"
+    }
+}
+
+#[test]
+fn test_severity_color() {
+    assert_eq!(severity_color(1.0), "\x1b[1;31m");
+    assert_eq!(severity_color(0.6), "\x1b[31m");
+    assert_eq!(severity_color(0.35), "\x1b[33m");
+}
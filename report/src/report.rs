@@ -1,21 +1,61 @@
 // Copyright (C) 2022 Red Hat
 // SPDX-License-Identifier: Apache-2.0
 
+#[cfg(feature = "html")]
 use html_builder::*;
+#[cfg(feature = "html")]
 use itertools::Itertools;
+#[cfg(feature = "html")]
 use std::borrow::Cow;
+#[cfg(feature = "html")]
 use std::fmt::Write;
 
+mod csv;
+mod markdown;
+mod pretty;
+#[cfg(feature = "html")]
+mod standalone;
+mod summary;
+
 type Result<A> = core::result::Result<A, std::fmt::Error>;
 
+#[cfg(feature = "html")]
 pub fn render(report: &logreduce_model::Report) -> Result<String> {
     Ok(Html::from(report)?.render())
 }
 
+/// Render a self-contained report which can be browsed offline.
+#[cfg(feature = "html")]
+pub fn render_standalone(report: &logreduce_model::Report) -> Result<String> {
+    standalone::render(report)
+}
+
+/// Render the anomalies as CSV, with one row per anomaly.
+pub fn render_csv(report: &logreduce_model::Report) -> Result<String> {
+    csv::render(report)
+}
+
+/// Render the report as Markdown, to be posted as a merge request comment.
+pub fn render_markdown(report: &logreduce_model::Report) -> Result<String> {
+    markdown::render(report)
+}
+
+/// Render the anomalies with colors for the terminal.
+pub fn render_pretty(report: &logreduce_model::Report) -> Result<String> {
+    pretty::render(report)
+}
+
+/// Render the summary header of the terminal outputs.
+pub fn render_summary(report: &logreduce_model::Report) -> Result<String> {
+    summary::render(report)
+}
+
+#[cfg(feature = "html")]
 struct Html {
     buffer: Buffer,
 }
 
+#[cfg(feature = "html")]
 impl Html {
     fn from(report: &logreduce_model::Report) -> Result<Html> {
         let mut buffer = Buffer::new();
@@ -28,12 +68,14 @@ impl Html {
     }
 }
 
+#[cfg(feature = "html")]
 impl Html {
     fn render(self) -> String {
         self.buffer.finish()
     }
 }
 
+#[cfg(feature = "html")]
 fn table(parent: &mut Node, columns: Option<&[&str]>, rows: &[&[&str]]) -> Result<()> {
     let mut div = parent
         .div()
@@ -60,6 +102,7 @@ fn table(parent: &mut Node, columns: Option<&[&str]>, rows: &[&[&str]]) -> Resul
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn add_head(parent: &mut Node, title: &str) -> Result<()> {
     fn add_link(head: &mut Node, href: &str, integrity: &str) {
         head.link()
@@ -78,6 +121,7 @@ fn add_head(parent: &mut Node, title: &str) -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn add_body(parent: &mut Node, report: &logreduce_model::Report) -> Result<()> {
     fn add_script(body: &mut Node, href: &str, integrity: &str) {
         body.script()
@@ -98,13 +142,16 @@ fn add_body(parent: &mut Node, report: &logreduce_model::Report) -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn class_<'b>(node: &'b mut Node<'b>, tag: Cow<'static, str>, class: &str) -> Node<'b> {
     node.child(tag).attr(&format!("class=\"{}\"", class))
 }
+#[cfg(feature = "html")]
 fn div_<'b>(node: &'b mut Node<'b>, class: &str) -> Node<'b> {
     class_(node, std::borrow::Cow::Borrowed("div"), class)
 }
 
+#[cfg(feature = "html")]
 fn add_nav(body: &mut Node) -> Result<()> {
     let mut nav = body
         .nav()
@@ -154,6 +201,7 @@ fn add_nav(body: &mut Node) -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()> {
     let mut div = body
         .div()
@@ -172,7 +220,9 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
                 &format!("{}", report.baselines.iter().format(", ")),
             ],
             &["Created at", &render_time(&report.created_at)],
+            &["Created by", &format!("logreduce {}", report.version)],
             &["Run time", &format!("{:.2} sec", report.run_time.as_secs_f32())],
+            &["Resource usage", &format!("{}", report.usage)],
             &[
                 "Result",
                 &format!(
@@ -185,6 +235,40 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
         ],
     )?;
 
+    for warning in &report.warnings {
+        div.div()
+            .attr("class=\"alert alert-warning\"")
+            .write_str(warning)?;
+    }
+
+    if !report.empty_sources.is_empty() {
+        let empty_sources = format!("{}", report.empty_sources.iter().format(", "));
+        table(&mut div, None, &[&["Skipped: empty", &empty_sources]])?;
+    }
+
+    if !report.sampled_sources.is_empty() {
+        let sampled_sources = format!("{}", report.sampled_sources.iter().format(", "));
+        table(&mut div, None, &[&["Partial: head and tail only", &sampled_sources]])?;
+    }
+
+    let http_responses = format!("{}", report.http_responses.iter().filter(|resp| !resp.is_ok()).format(", "));
+    if !http_responses.is_empty() {
+        table(&mut div, None, &[&["HTTP responses", &http_responses]])?;
+    }
+
+    if !report.accepted_anomalies.is_empty() {
+        let positions = report.accepted_anomalies.iter().map(|accepted| {
+            format!("{}:{}", accepted.source.get_relative(), accepted.anomaly.anomaly.pos)
+        }).collect::<Vec<_>>();
+        let rows = report.accepted_anomalies.iter().zip(positions.iter()).map(|(accepted, pos)| [
+            pos.as_str(),
+            accepted.anomaly.anomaly.line.as_str(),
+            accepted.comment.as_deref().unwrap_or(""),
+        ]).collect::<Vec<_>>();
+        let rows = rows.iter().map(|row| &row[..]).collect::<Vec<_>>();
+        table(&mut div, Some(&["Accepted anomaly", "Line", "Comment"]), &rows)?;
+    }
+
     // Summary table
     // TODO: Anomaly count | Filename | Test time | Model
 
@@ -210,6 +294,7 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn render_content_report(
     list_group: &mut Node,
     log_report: &logreduce_model::LogReport,
@@ -285,6 +370,22 @@ fn render_content_report(
                         .write_str("file")?;
                 }
 
+                if let Some(annotation) = &log_report.annotation {
+                    let mut additional_item = pf_body
+                        .div()
+                        .attr("class=\"list-view-pf-additional-info-item\"");
+                    additional_item.span().attr("class=\"fa fa-users\"");
+                    if let Some(team) = &annotation.team {
+                        additional_item.write_str(team)?;
+                    }
+                    if let Some(runbook) = &annotation.runbook {
+                        additional_item
+                            .a()
+                            .attr(&format!("href=\"{}\"", runbook.replace('"', "&quot;")))
+                            .write_str(" runbook")?;
+                    }
+                }
+
                 {
                     let mut additional_item = pf_body
                         .div()
@@ -325,10 +426,12 @@ fn render_content_report(
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn model_anchor(index_name: &logreduce_model::IndexName) -> String {
     format!("#model_{}", index_name)
 }
 
+#[cfg(feature = "html")]
 fn render_context(loglines: &mut Node, pos: usize, xs: &[String]) -> Result<()> {
     for (idx, line) in xs.iter().enumerate() {
         loglines
@@ -338,6 +441,7 @@ fn render_context(loglines: &mut Node, pos: usize, xs: &[String]) -> Result<()>
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn render_lines(loglines: &mut Node, anomalies: &[logreduce_model::AnomalyContext]) -> Result<()> {
     let mut last_pos = None;
 
@@ -357,23 +461,28 @@ fn render_lines(loglines: &mut Node, anomalies: &[logreduce_model::AnomalyContex
             .pre()
             .attr(&format!("style=\"color: #{:2X}0000\"", color))
             .write_str(&format!(
-                "{:02} {:4} | {}",
-                dist, anomaly.anomaly.pos, anomaly.anomaly.line
+                "{:02} {:4} | {}{}",
+                dist,
+                anomaly.anomaly.pos,
+                anomaly.anomaly.line,
+                anomaly.anomaly.repeats_note()
             ))?;
 
-        render_context(loglines, anomaly.anomaly.pos, &anomaly.after)?;
+        render_context(loglines, anomaly.anomaly.end_pos(), &anomaly.after)?;
 
-        last_pos = Some(anomaly.anomaly.pos + anomaly.after.len());
+        last_pos = Some(anomaly.anomaly.end_pos() + anomaly.after.len());
     }
 
     Ok(())
 }
 
+#[cfg(feature = "html")]
 fn render_time(system_time: &std::time::SystemTime) -> String {
     let datetime: chrono::DateTime<chrono::offset::Utc> = (*system_time).into();
     datetime.format("%Y-%m-%d %T").to_string()
 }
 
+#[cfg(feature = "html")]
 const SCRIPTS: &[(&str, &str)] = &[
     (
         "https://code.jquery.com/jquery-3.3.1.min.js",
@@ -389,6 +498,7 @@ const SCRIPTS: &[(&str, &str)] = &[
     ),
 ];
 
+#[cfg(feature = "html")]
 const STYLES: &[(&str, &str)] = &[
     (
         "https://cdnjs.cloudflare.com/ajax/libs/patternfly/3.24.0/css/patternfly.min.css",
@@ -401,6 +511,7 @@ const STYLES: &[(&str, &str)] = &[
 ];
 
 /// A helper script to make the file list toggleable
+#[cfg(feature = "html")]
 static JS: &str = r#"
 $(document).ready(function(){
 $('#debugbtn').on('click', function(event) {$('[id=debuginfo]').toggle();});
@@ -419,6 +530,7 @@ $(".list-group-item-container .close").on("click", function (){
 })
 "#;
 
+#[cfg(feature = "html")]
 static LOGO: &str = concat!(
     "src=\"data:image/jpeg;base64,",
     "iVBORw0KGgoAAAANSUhEUgAAABcAAAAXBAMAAAASBMmTAAAAFVBMVEU6feU9geVjl+WMsOUicOXA",
@@ -0,0 +1,49 @@
+// The search and filter controls of the standalone report.
+(function () {
+  var search = document.getElementById("search");
+  var distance = document.getElementById("distance");
+  var distanceValue = document.getElementById("distance-value");
+  var files = document.getElementById("files");
+
+  function globRegExp(glob) {
+    var pattern = glob.replace(/[.+^${}()|[\]\\]/g, "\\$&").replace(/\*/g, ".*").replace(/\?/g, ".");
+    return new RegExp("(^|/)" + pattern + "$");
+  }
+
+  function update() {
+    var text = search.value.toLowerCase();
+    var minDistance = Number(distance.value);
+    var glob = files.value.trim() ? globRegExp(files.value.trim()) : null;
+    distanceValue.textContent = minDistance;
+    document.querySelectorAll("details[data-file]").forEach(function (log) {
+      var visible = 0;
+      var fileMatch = !glob || glob.test(log.dataset.file);
+      log.querySelectorAll(".anomaly-block").forEach(function (block) {
+        var show = fileMatch &&
+          Number(block.dataset.distance) >= minDistance &&
+          (!text || block.textContent.toLowerCase().indexOf(text) !== -1);
+        block.hidden = !show;
+        if (show) {
+          visible += 1;
+        }
+      });
+      log.hidden = visible === 0;
+      log.querySelector(".count").textContent = visible;
+    });
+  }
+
+  // Open and show the anomaly of the permalink.
+  function showTarget() {
+    var target = location.hash ? document.getElementById(location.hash.slice(1)) : null;
+    if (target) {
+      target.closest("details[data-file]").open = true;
+      target.scrollIntoView();
+    }
+  }
+
+  [search, distance, files].forEach(function (input) {
+    input.addEventListener("input", update);
+  });
+  window.addEventListener("hashchange", showTarget);
+  showTarget();
+})();
@@ -0,0 +1,318 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a self-contained html report, without external resources,
+//! so that it can be archived and browsed as a static file. An inline script provides the
+//! search and filter controls, and each anomaly has a permalink anchor.
+
+use html_builder::*;
+use itertools::Itertools;
+use std::borrow::Cow;
+use std::fmt::Write;
+
+use crate::Result;
+
+pub fn render(report: &logreduce_model::Report) -> Result<String> {
+    let mut buffer = Buffer::new();
+    {
+        let mut html = buffer.html().attr("lang='en'");
+        {
+            let mut head = html.head();
+            head.title()
+                .write_str(&escape(&format!("Logreduce of {}", report.target)))?;
+            head.meta().attr("charset='utf-8'");
+            head.style().write_str(include_str!("standalone.css"))?;
+        }
+        let mut body = html.body();
+        add_summary(&mut body, report)?;
+        add_controls(&mut body)?;
+        for (idx, log_report) in report.log_reports.iter().enumerate() {
+            add_log_report(
+                &mut body,
+                idx,
+                log_report,
+                report.index_reports.get(&log_report.index_name),
+                idx == 0,
+            )?;
+        }
+        body.script().write_str(include_str!("standalone.js"))?;
+    }
+    Ok(buffer.finish())
+}
+
+fn tag_<'b>(node: &'b mut Node, tag: &'static str, class: &str) -> Node<'b> {
+    node.child(Cow::Borrowed(tag))
+        .attr(&format!("class=\"{}\"", class))
+}
+
+fn add_summary(body: &mut Node, report: &logreduce_model::Report) -> Result<()> {
+    tag_(body, "h1", "title").write_str(&escape(&format!("Logreduce of {}", report.target)))?;
+
+    let mut table = body.table().attr("class=\"info\"");
+    let rows = [
+        (
+            "Baselines",
+            format!("{}", report.baselines.iter().format(", ")),
+        ),
+        ("Created at", crate::render_time(&report.created_at)),
+        ("Created by", format!("logreduce {}", report.version)),
+        (
+            "Run time",
+            format!("{:.2} sec", report.run_time.as_secs_f32()),
+        ),
+        ("Resource usage", format!("{}", report.usage)),
+        (
+            "Result",
+            format!(
+                "{} anomalies found in {} lines",
+                report.total_anomaly_count, report.total_line_count
+            ),
+        ),
+    ];
+    for (name, value) in rows.iter() {
+        let mut tr = table.tr();
+        tr.th().write_str(name)?;
+        tr.td().write_str(&escape(value))?;
+    }
+    if !report.empty_sources.is_empty() {
+        let mut tr = table.tr();
+        tr.th().write_str("Skipped: empty")?;
+        tr.td().write_str(&escape(&format!(
+            "{}",
+            report.empty_sources.iter().format(", ")
+        )))?;
+    }
+    for matches in &report.baseline_matches {
+        let mut tr = table.tr();
+        tr.th().write_str("Baseline matches")?;
+        tr.td().write_str(&escape(&format!(
+            "{} lines, {} exclusive: {}",
+            matches.matched_lines, matches.exclusive_lines, matches.baseline
+        )))?;
+    }
+    if !report.sampled_sources.is_empty() {
+        let mut tr = table.tr();
+        tr.th().write_str("Partial: head and tail only")?;
+        tr.td().write_str(&escape(&format!(
+            "{}",
+            report.sampled_sources.iter().format(", ")
+        )))?;
+    }
+    let http_responses = format!(
+        "{}",
+        report
+            .http_responses
+            .iter()
+            .filter(|resp| !resp.is_ok())
+            .format(", ")
+    );
+    if !http_responses.is_empty() {
+        let mut tr = table.tr();
+        tr.th().write_str("HTTP responses")?;
+        tr.td().write_str(&escape(&http_responses))?;
+    }
+
+    for warning in &report.warnings {
+        body.div()
+            .attr("class=\"warning\"")
+            .write_str(&escape(warning))?;
+    }
+    add_accepted(body, &report.accepted_anomalies)
+}
+
+/// The search and filter inputs, see standalone.js.
+fn add_controls(body: &mut Node) -> Result<()> {
+    let mut controls = body.div().attr("class=\"controls\"");
+    controls
+        .input()
+        .attr("id=\"search\" type=\"search\" placeholder=\"Search the anomalies\"");
+    controls
+        .input()
+        .attr("id=\"files\" type=\"text\" placeholder=\"File glob, e.g. *.log\"");
+    let mut label = controls.label();
+    label.write_str("Minimum distance ")?;
+    label
+        .input()
+        .attr("id=\"distance\" type=\"range\" min=\"0\" max=\"99\" value=\"0\"");
+    label.span().attr("id=\"distance-value\"").write_str("0")?;
+    Ok(())
+}
+
+fn add_accepted(
+    body: &mut Node,
+    accepted: &[logreduce_model::accepted::AcceptedAnomaly],
+) -> Result<()> {
+    if accepted.is_empty() {
+        return Ok(());
+    }
+    let mut details = tag_(body, "details", "log");
+    tag_(&mut details, "summary", "log-header")
+        .write_str(&format!("{} accepted anomalies", accepted.len()))?;
+    let mut table = details.table().attr("class=\"info\"");
+    for accepted in accepted {
+        let mut tr = table.tr();
+        tr.td().write_str(&escape(&format!(
+            "{}:{}",
+            accepted.source.get_relative(),
+            accepted.anomaly.anomaly.pos
+        )))?;
+        tr.td()
+            .pre()
+            .write_str(&escape(&accepted.anomaly.anomaly.line))?;
+        tr.td()
+            .write_str(&escape(accepted.comment.as_deref().unwrap_or("")))?;
+    }
+    Ok(())
+}
+
+fn add_log_report(
+    body: &mut Node,
+    log_idx: usize,
+    log_report: &logreduce_model::LogReport,
+    index_report: Option<&logreduce_model::IndexReport>,
+    open: bool,
+) -> Result<()> {
+    let mut details = tag_(body, "details", "log").attr(&format!(
+        "data-file=\"{}\"",
+        escape(log_report.source.get_relative())
+    ));
+    if open {
+        details = details.attr("open");
+    }
+    {
+        let mut summary = tag_(&mut details, "summary", "log-header");
+        summary
+            .span()
+            .attr("class=\"count\"")
+            .write_str(&format!("{}", log_report.anomalies.len()))?;
+        summary.write_str(&escape(log_report.source.get_relative()))?;
+        summary
+            .span()
+            .attr("class=\"model\"")
+            .write_str(&escape(&format!("{} model", log_report.index_name)))?;
+    }
+
+    {
+        let mut info = details.div().attr("class=\"log-info\"");
+        info.a()
+            .attr(&format!("href=\"{}\"", escape(log_report.source.as_str())))
+            .write_str("file")?;
+        if let Some(annotation) = &log_report.annotation {
+            add_annotation(&mut info, annotation)?;
+        }
+        if let Some(index_report) = index_report {
+            info.write_str(&escape(&format!(
+                " - baseline samples: {}",
+                index_report
+                    .sources
+                    .iter()
+                    .take(3)
+                    .map(|source| source.as_str())
+                    .format(", ")
+            )))?;
+        }
+    }
+
+    let mut loglines = details.div().attr("class=\"loglines\"");
+    let mut last_pos = None;
+    for anomaly in &log_report.anomalies {
+        let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
+        let id = format!("anomaly-{}-{}", log_idx, anomaly.anomaly.pos);
+        let mut block = tag_(&mut loglines, "div", "anomaly-block")
+            .attr(&format!("id=\"{}\"", id))
+            .attr(&format!(
+                "data-distance=\"{:.0}\"",
+                anomaly.anomaly.distance * 99.0
+            ));
+        if let Some(last_pos) = last_pos {
+            if last_pos != starting_pos {
+                block.hr();
+            }
+        }
+        add_context(&mut block, starting_pos, &anomaly.before, "before")?;
+        tag_(&mut block, "a", "permalink")
+            .attr(&format!("href=\"#{}\"", id))
+            .attr("title=\"Permalink\"")
+            .write_str("#")?;
+        block
+            .pre()
+            .attr("class=\"anomaly\"")
+            .attr(&format!(
+                "style=\"background-color: rgba(255, 0, 0, {:.2})\"",
+                anomaly.anomaly.distance * 0.5
+            ))
+            .attr(&format!(
+                "title=\"distance {:.2}\"",
+                anomaly.anomaly.distance
+            ))
+            .write_str(&escape(&format!(
+                "{:02.0} {:4} | {}{}",
+                anomaly.anomaly.distance * 99.0,
+                anomaly.anomaly.pos,
+                anomaly.anomaly.line,
+                anomaly.anomaly.repeats_note()
+            )))?;
+        add_context(
+            &mut block,
+            anomaly.anomaly.end_pos(),
+            &anomaly.after,
+            "after",
+        )?;
+        last_pos = Some(anomaly.anomaly.end_pos() + anomaly.after.len());
+    }
+    Ok(())
+}
+
+fn add_annotation(
+    info: &mut Node,
+    annotation: &logreduce_model::annotations::Annotation,
+) -> Result<()> {
+    if let Some(team) = &annotation.team {
+        info.write_str(" - owner: ")?;
+        info.strong().write_str(&escape(team))?;
+    }
+    if let Some(runbook) = &annotation.runbook {
+        info.write_str(" - ")?;
+        info.a()
+            .attr(&format!("href=\"{}\"", escape(runbook)))
+            .write_str("runbook")?;
+    }
+    Ok(())
+}
+
+/// The context lines are collapsed by default.
+fn add_context(loglines: &mut Node, pos: usize, xs: &[String], kind: &str) -> Result<()> {
+    if xs.is_empty() {
+        return Ok(());
+    }
+    let mut details = tag_(loglines, "details", "context");
+    tag_(&mut details, "summary", "context-header").write_str(&format!(
+        "{} lines {}",
+        xs.len(),
+        kind
+    ))?;
+    for (idx, line) in xs.iter().enumerate() {
+        details
+            .pre()
+            .write_str(&escape(&format!("   {:4} | {}", pos + 1 + idx, line)))?;
+    }
+    Ok(())
+}
+
+/// Escape the text and the attribute values, the html builder writes them verbatim.
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(
+        escape("<script>alert('x') && \"y\"</script>"),
+        "&lt;script&gt;alert(&#39;x&#39;) &amp;&amp; &quot;y&quot;&lt;/script&gt;"
+    );
+}
@@ -0,0 +1,74 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the summary header of the terminal outputs, so that the first screen
+//! shows the most severe anomalies.
+
+use std::fmt::Write;
+
+use crate::Result;
+
+/// The number of anomalies listed in the summary.
+const TOP_COUNT: usize = 5;
+
+/// The maximum length of the summary lines.
+const MAX_LINE_LEN: usize = 120;
+
+pub fn render(report: &logreduce_model::Report) -> Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{} files analyzed, {} with anomalies, {} anomalies found in {} lines ({:.1?})",
+        report.total_file_count,
+        report.log_reports.len(),
+        report.total_anomaly_count,
+        report.total_line_count,
+        report.run_time
+    )?;
+    if let Some(task) = &report.failed_task {
+        writeln!(out, "Failed task: {}", task)?;
+    }
+    if !report.baseline_matches.is_empty() {
+        writeln!(out, "Baseline matches:")?;
+    }
+    for matches in &report.baseline_matches {
+        writeln!(
+            out,
+            "  {} lines, {} exclusive: {}",
+            matches.matched_lines, matches.exclusive_lines, matches.baseline
+        )?;
+    }
+    let top = report.top_anomalies(TOP_COUNT);
+    if !top.is_empty() {
+        writeln!(out, "Top anomalies:")?;
+    }
+    for (log_report, anomaly) in top {
+        writeln!(
+            out,
+            "  {:02.0} {}:{} | {}{}",
+            anomaly.anomaly.severity * 99.0,
+            log_report.source.get_relative(),
+            anomaly.anomaly.pos,
+            truncate(&anomaly.anomaly.line),
+            match &report.failed_task {
+                Some(task) if task.contains(&anomaly.anomaly) => " (during the failed task)",
+                _ => "",
+            }
+        )?;
+    }
+    Ok(out)
+}
+
+fn truncate(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_LEN) {
+        Some((idx, _)) => format!("{}...", &line[..idx]),
+        None => line.to_string(),
+    }
+}
+
+#[test]
+fn test_truncate() {
+    assert_eq!(truncate("short line"), "short line");
+    let long = "é".repeat(MAX_LINE_LEN + 1);
+    assert_eq!(truncate(&long), format!("{}...", "é".repeat(MAX_LINE_LEN)));
+}
@@ -28,6 +28,8 @@
 //!            "2017-06-24 13:35:57,754 INFO:dlrn-build:DEBUG: writing output... [ 88%] configuration");
 //! tokens_eq!("tempest.lib.common.rest_client [req-b932e095-6706-4f5a-bd75-241c407a9d01 ] Request (main): 201 POST https://10.0.1.9/identity/v3/auth/tokens",
 //!            "tempest.lib.common.rest_client [req-08043549-3227-4c61-aa3b-9d02fc8437c3 ] Request (main): 201 POST https://104.130.217.34/identity/v3/auth/tokens");
+//! tokens_eq!("ts=2022-04-01T10:03:00Z level=info msg=\"request done\" duration=12ms",
+//!            "ts=2022-04-02T11:00:00Z level=info msg=\"request done\" duration=3.5s");
 //! ```
 //!
 //! TODO: decode json object and re-order the key to pass this test:
@@ -39,6 +41,7 @@
 use lazy_static::lazy_static;
 use regex::Regex;
 use regex::Split;
+use std::sync::RwLock;
 
 fn words(line: &str) -> Split {
     lazy_static! {
@@ -58,6 +61,49 @@ fn trim_quote_and_punctuation(word: &str) -> &str {
         })
 }
 
+/// Remove the syslog header, so that the tag and the message are the only features: the priority,
+/// the timestamp and the host of the RFC3164 lines (`<13>Mar 23 17:33:00 host sshd[42]: message`),
+/// of the RFC5424 lines (`<13>1 2022-03-23T17:33:00Z host sshd 42 - message`) and of the rsyslog
+/// high precision format.
+fn strip_syslog_header(line: &str) -> &str {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(concat!(
+            // The priority, and the version of the RFC5424 lines
+            r"^(<\d{1,3}>(\d{1,2} )?)?(",
+            // RFC3164
+            r"[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}",
+            // RFC5424 and rsyslog
+            r"|\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:\d{2})",
+            r") [^ ]+ "
+        ))
+        .unwrap();
+    }
+    match RE.find(line) {
+        Some(header) => &line[header.end()..],
+        None => line,
+    }
+}
+#[test]
+fn test_strip_syslog_header() {
+    assert_eq!(
+        strip_syslog_header("Mar  3 17:33:00 node-1 sshd[42]: Accepted publickey"),
+        "sshd[42]: Accepted publickey"
+    );
+    assert_eq!(
+        strip_syslog_header("<13>1 2022-03-23T17:33:00.003Z node-1 sshd 42 - Accepted publickey"),
+        "sshd 42 - Accepted publickey"
+    );
+    assert_eq!(
+        strip_syslog_header("2022-03-23T17:33:00.123456+00:00 node-1 kernel: oom"),
+        "kernel: oom"
+    );
+    assert_eq!(strip_syslog_header("Mar is a month"), "Mar is a month");
+    tokens_eq!(
+        "<30>Mar 23 17:33:00 node-1 systemd[1]: Started Session 42 of user zuul.",
+        "Apr  2 08:00:12 compute-0 systemd[1]: Started Session 7 of user zuul."
+    );
+}
+
 /// Apply global filter to skip specific lines.
 fn global_filter(line: &str) -> bool {
     lazy_static! {
@@ -366,30 +412,109 @@ mod re_tests {
     }
 }
 
-fn parse_literal(word: &str) -> Option<&str> {
-    if is_date(word) {
-        Some("%DATE")
-    } else if is_hash(word) {
-        Some("%HASH")
-    } else if is_uid(word) {
-        Some("%ID")
-    } else if is_cookie(word) {
-        Some("%COOKIE")
-    } else if is_uuid(word) {
-        Some("%UID")
-    } else if is_url(word) {
-        Some("%URL")
-    } else if is_random_path(word) {
-        Some("%PATH")
-    } else if is_refs(word) {
-        Some("%REF")
-    } else if is_base64(word) {
-        Some("%BASE64")
-    } else {
-        None
+/// The month names of the other languages: french, german, spanish and italian.
+const MONTHS: &str = concat!(
+    "(?i:^(",
+    "janvier|f[ée]vrier|mars|avril|mai|juin|juillet|ao[ûu]t|septembre|octobre|novembre|d[ée]cembre|",
+    "januar|februar|m[äa]rz|juni|juli|oktober|dezember|",
+    "enero|febrero|marzo|abril|mayo|junio|julio|agosto|septiembre|octubre|noviembre|diciembre|",
+    "gennaio|febbraio|aprile|maggio|giugno|luglio|settembre|ottobre|dicembre",
+    ")$)"
+);
+
+/// The epoch timestamps in seconds, milliseconds or fractional seconds, between 2001 and 2033.
+const EPOCH: &str = r"^1\d{9}(\d{3}|\.\d{1,6})?$";
+
+/// How a pattern of the catalog matches a word.
+pub enum Matcher {
+    /// A built-in check.
+    Builtin(fn(&str) -> bool),
+    /// A regular expression.
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn is_match(&self, word: &str) -> bool {
+        match self {
+            Matcher::Builtin(is_match) => is_match(word),
+            Matcher::Regex(regex) => regex.is_match(word),
+        }
+    }
+}
+
+/// A masking pattern: the words it matches are replaced by its token, such as `%DATE`.
+pub struct Pattern {
+    /// The replacement token.
+    pub token: String,
+    /// The matcher of the words.
+    pub matcher: Matcher,
+}
+
+impl Pattern {
+    /// Create a pattern from a regular expression, the token gets a `%` prefix.
+    pub fn new(token: &str, regex: &str) -> Result<Pattern, regex::Error> {
+        Ok(Pattern {
+            token: format!("%{}", token.trim_start_matches('%')),
+            matcher: Matcher::Regex(Regex::new(regex)?),
+        })
+    }
+
+    fn builtin(token: &str, is_match: fn(&str) -> bool) -> Pattern {
+        Pattern {
+            token: token.to_string(),
+            matcher: Matcher::Builtin(is_match),
+        }
     }
 }
 
+/// The default catalog, from the most specific pattern to the most general.
+fn default_catalog() -> Vec<Pattern> {
+    vec![
+        Pattern::builtin("%DATE", is_date),
+        Pattern::new("%DATE", MONTHS).unwrap(),
+        Pattern::new("%DATE", EPOCH).unwrap(),
+        Pattern::builtin("%HASH", is_hash),
+        Pattern::builtin("%ID", is_uid),
+        Pattern::builtin("%COOKIE", is_cookie),
+        Pattern::builtin("%UID", is_uuid),
+        Pattern::builtin("%URL", is_url),
+        Pattern::builtin("%PATH", is_random_path),
+        Pattern::builtin("%REF", is_refs),
+        Pattern::builtin("%BASE64", is_base64),
+    ]
+}
+
+lazy_static! {
+    static ref CATALOG: RwLock<Vec<Pattern>> = RwLock::new(default_catalog());
+}
+
+/// Add a pattern to the catalog, it is tried before the existing ones.
+/// The models need to be trained with the same patterns.
+pub fn add_pattern(pattern: Pattern) {
+    CATALOG.write().unwrap().insert(0, pattern);
+}
+
+/// Push the token of the first pattern matching the word.
+fn push_literal(word: &str, result: &mut String) -> bool {
+    let catalog = CATALOG.read().unwrap();
+    match catalog.iter().find(|p| p.matcher.is_match(word)) {
+        Some(pattern) => {
+            result.push_str(&pattern.token);
+            true
+        }
+        None => false,
+    }
+}
+#[test]
+fn test_catalog() {
+    tokens_eq!("rapport du 3 mars", "rapport du 12 juillet");
+    tokens_eq!("event at 1650000000123", "event at 1650000000.5");
+    let mut result = String::new();
+    assert!(push_literal("1650000000123", &mut result));
+    assert_eq!(result, "%DATE");
+    assert!(Pattern::new("BUILD", "(unclosed").is_err());
+}
+
 fn trim_pid(word: &str) -> Option<&str> {
     word.trim_end_matches(|c| c >= '0' && c <= '9')
         .strip_suffix('[')
@@ -417,14 +542,126 @@ fn test_push_error() {
     );
 }
 
+/// The logfmt keys whose value changes on every line, only their name is kept.
+const VOLATILE_KEYS: [&str; 10] = [
+    "t",
+    "ts",
+    "time",
+    "timestamp",
+    "date",
+    "duration",
+    "dur",
+    "elapsed",
+    "took",
+    "latency",
+];
+
+fn is_logfmt_key(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// The position of the closing quote, after an opening quote.
+fn closing_quote(value: &str) -> Option<usize> {
+    let mut escaped = false;
+    value.char_indices().find_map(|(pos, c)| {
+        let found = c == '"' && !escaped;
+        escaped = c == '\\' && !escaped;
+        found.then_some(pos)
+    })
+}
+
+/// Split a logfmt line like `level=info msg="server started" port=8080` in key and optional value pairs.
+/// The line must start with a pair and contain at least two of them.
+fn logfmt_pairs(line: &str) -> Option<Vec<(&str, Option<&str>)>> {
+    let mut pairs = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        let key_end = rest.find(['=', ' ']).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        if !is_logfmt_key(key) {
+            return None;
+        }
+        let value = match rest[key_end..].strip_prefix('=') {
+            Some(value) => {
+                let end = match value.strip_prefix('"') {
+                    Some(quoted) => closing_quote(quoted)? + 2,
+                    None => value.find(' ').unwrap_or(value.len()),
+                };
+                rest = &value[end..];
+                Some(value[..end].trim_matches('"'))
+            }
+            None if pairs.is_empty() => return None,
+            None => {
+                rest = &rest[key_end..];
+                None
+            }
+        };
+        pairs.push((key, value));
+        rest = rest.trim_start();
+    }
+    match pairs.iter().filter(|(_, value)| value.is_some()).count() {
+        0 | 1 => None,
+        _ => Some(pairs),
+    }
+}
+
+/// Tokenize the logfmt pairs, keeping every key name.
+fn process_logfmt(pairs: &[(&str, Option<&str>)]) -> String {
+    let mut result = String::new();
+    for (key, value) in pairs {
+        match value {
+            Some(value) => {
+                result.push_str(key);
+                result.push_str("%EQ ");
+                if VOLATILE_KEYS.contains(&key.to_lowercase().as_str()) {
+                    continue;
+                } else if is_key_for_id(key) {
+                    result.push_str("%VALUE_ID ");
+                } else {
+                    for word in words(value) {
+                        if do_process(word, &mut result) {
+                            result.push(' ')
+                        }
+                    }
+                }
+            }
+            None => {
+                if do_process(key, &mut result) {
+                    result.push(' ')
+                }
+            }
+        }
+    }
+    result.trim().to_string()
+}
+#[test]
+fn test_logfmt() {
+    assert_eq!(
+        process(r#"ts=2022-04-01T10:03:00Z level=error msg="connection lost" dur=1.2s trace_id=4bf92f3577b34da6 peer=database"#),
+        "ts%EQ level%EQ error error%A error%B error%C error%D msg%EQ connection lost dur%EQ trace_id%EQ %VALUE_ID peer%EQ database"
+    );
+    tokens_eq!(
+        r#"ts=2022-04-01T10:03:00Z level=info msg="request done" duration=12ms trace_id=4bf92f35"#,
+        r#"ts=2022-04-02T11:00:00Z level=info msg="request done" duration=3.5s trace_id=00f067aa"#
+    );
+    assert_eq!(
+        logfmt_pairs(r#"a=1 msg="quoted \" value" bare"#).unwrap()[1],
+        ("msg", Some(r#"quoted \" value"#))
+    );
+    assert_eq!(logfmt_pairs("Starting server port=8080 host=db"), None);
+    assert_eq!(logfmt_pairs("key=value only"), None);
+}
+
 /// The tokenizer main (recursive) function
 fn do_process(mut word: &str, result: &mut String) -> bool {
     word = trim_quote_and_punctuation(word);
     let mut added = true;
     // We try to process from the most specifics to the most general case
-    if let Some(token) = parse_literal(word) {
+    if push_literal(word, result) {
         // e.g. `February` or `sha256:...`
-        result.push_str(token)
     } else if is_error(word) {
         // e.g. `Traceback`
         push_error(word, result)
@@ -490,14 +727,18 @@ fn do_process(mut word: &str, result: &mut String) -> bool {
 
 /// The tokenizer entry point
 pub fn process(line: &str) -> String {
-    // Remove surrounding whitespaces
-    let line = line.trim();
+    // Remove surrounding whitespaces and the syslog header
+    let line = strip_syslog_header(line.trim());
 
     // check for global filter first
     if global_filter(line) {
         return "%GL_FILTER".to_string();
     }
 
+    if let Some(pairs) = logfmt_pairs(line) {
+        return process_logfmt(&pairs);
+    }
+
     // split the line into space separated words.
     let mut result = String::with_capacity(line.len());
     for word in words(line) {